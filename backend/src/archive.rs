@@ -0,0 +1,700 @@
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::crypto::{self, current_timestamp};
+use crate::erasure::{self, ErasureManager};
+use crate::journal;
+use crate::quota::{QuotaError, QuotaManager, Reservation};
+use crate::replication::ReplicationManager;
+use crate::storage::{BlobReader, BlobStore};
+use crate::types::{ArchiveBlob, ArchiveManifest, ImportReport, VaultItem};
+use crate::upload;
+
+/// Voce finale dell'archivio, scritta quando tutti i blob sono stati inviati
+const MANIFEST_NAME: &str = "manifest.json";
+
+/// Prefisso delle voci che contengono un blob dello store
+const BLOB_PREFIX: &str = "blobs/";
+
+const ARCHIVE_VERSION: u32 = 1;
+
+/// Limite del manifest letto in memoria durante l'import
+const MAX_MANIFEST_BYTES: u64 = 64 * 1024 * 1024;
+
+/// Sottodirectory di un ambiente con gli archivi in fase di import
+const IMPORT_DIR: &str = ".import";
+
+const BLOCK: usize = 512;
+
+/// Motivi per cui un archivio non viene importato
+#[derive(Debug)]
+pub enum ArchiveError {
+    /// Archivio malformato, troncato o senza manifest
+    Invalid(String),
+    /// I blob dell'archivio non stanno nella quota dell'ambiente
+    Quota(QuotaError),
+    Io(std::io::Error),
+}
+
+impl std::fmt::Display for ArchiveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ArchiveError::Invalid(reason) => write!(f, "invalid archive: {}", reason),
+            ArchiveError::Quota(e) => write!(f, "{}", e),
+            ArchiveError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<std::io::Error> for ArchiveError {
+    fn from(e: std::io::Error) -> Self {
+        match e.kind() {
+            ErrorKind::UnexpectedEof => ArchiveError::Invalid("truncated".to_string()),
+            _ => ArchiveError::Io(e),
+        }
+    }
+}
+
+fn is_blob_id(id: &str) -> bool {
+    !id.is_empty() && id.len() <= 64 && id.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Chiavi che un archivio può contenere: contenuti, anteprime e metadata
+fn is_archived_key(key: &str) -> bool {
+    is_metadata_key(key) || is_blob_id(key)
+}
+
+fn is_metadata_key(key: &str) -> bool {
+    key == journal::METADATA_KEY || journal::is_journal_key(key)
+}
+
+// ============== FORMATO ==============
+//
+// Un archivio è un file tar (ustar) leggibile con gli strumenti comuni: i blob
+// stanno in `blobs/<chiave>` e `manifest.json` è l'ultima voce, così l'export
+// procede in un solo passaggio calcolando gli hash mentre invia i blob.
+
+fn octal(field: &mut [u8], value: u64) {
+    let digits = field.len() - 1;
+    if value < 1u64 << (3 * digits) {
+        let text = format!("{:0width$o}", value, width = digits);
+        field[..digits].copy_from_slice(text.as_bytes());
+        field[digits] = 0;
+    } else {
+        // Codifica base-256 per le dimensioni oltre il limite ottale
+        field.fill(0);
+        field[0] = 0x80;
+        let bytes = value.to_be_bytes();
+        let len = field.len();
+        field[len - 8..].copy_from_slice(&bytes);
+    }
+}
+
+fn parse_number(field: &[u8]) -> Option<u64> {
+    if field.first().is_some_and(|b| b & 0x80 != 0) {
+        let mut value = u64::from(field[0] & 0x7f);
+        for b in &field[1..] {
+            value = value.checked_mul(256)?.checked_add(u64::from(*b))?;
+        }
+        return Some(value);
+    }
+    let text = std::str::from_utf8(field).ok()?;
+    let text = text.trim_matches(|c: char| c == '\0' || c == ' ');
+    if text.is_empty() {
+        return Some(0);
+    }
+    u64::from_str_radix(text, 8).ok()
+}
+
+fn header(name: &str, size: u64, mtime: u64) -> [u8; BLOCK] {
+    let mut block = [0u8; BLOCK];
+    block[..name.len()].copy_from_slice(name.as_bytes());
+    octal(&mut block[100..108], 0o644);
+    octal(&mut block[108..116], 0);
+    octal(&mut block[116..124], 0);
+    octal(&mut block[124..136], size);
+    octal(&mut block[136..148], mtime);
+    block[156] = b'0';
+    block[257..263].copy_from_slice(b"ustar\0");
+    block[263..265].copy_from_slice(b"00");
+
+    block[148..156].fill(b' ');
+    let checksum: u64 = block.iter().map(|b| u64::from(*b)).sum();
+    let text = format!("{:06o}\0 ", checksum);
+    block[148..156].copy_from_slice(text.as_bytes());
+    block
+}
+
+fn padding(size: u64) -> usize {
+    (BLOCK - (size % BLOCK as u64) as usize) % BLOCK
+}
+
+/// Scrive le voci dell'archivio una dopo l'altra
+struct ArchiveWriter<W> {
+    inner: W,
+}
+
+impl<W: AsyncWrite + Unpin> ArchiveWriter<W> {
+    /// Aggiunge una voce di `size` byte letti da `reader` e ne restituisce lo SHA-256
+    async fn append(&mut self, name: &str, size: u64, mut reader: BlobReader) -> std::io::Result<String> {
+        self.inner.write_all(&header(name, size, current_timestamp())).await?;
+
+        let mut hasher = Sha256::new();
+        let mut remaining = size;
+        let mut buf = vec![0u8; 64 * 1024];
+        while remaining > 0 {
+            let want = buf.len().min(remaining as usize);
+            let n = reader.read(&mut buf[..want]).await?;
+            if n == 0 {
+                return Err(std::io::Error::new(ErrorKind::UnexpectedEof, format!("{} changed while being archived", name)));
+            }
+            hasher.update(&buf[..n]);
+            self.inner.write_all(&buf[..n]).await?;
+            remaining -= n as u64;
+        }
+        self.inner.write_all(&[0u8; BLOCK][..padding(size)]).await?;
+        Ok(hex::encode(hasher.finalize()))
+    }
+
+    async fn finish(mut self) -> std::io::Result<()> {
+        self.inner.write_all(&[0u8; 2 * BLOCK]).await?;
+        self.inner.flush().await
+    }
+}
+
+/// Legge le voci di un archivio in ordine
+struct ArchiveReader<R> {
+    inner: R,
+}
+
+impl<R: AsyncRead + Unpin> ArchiveReader<R> {
+    /// Intestazione della prossima voce: (nome, dimensione, file regolare)
+    async fn next(&mut self) -> Result<Option<(String, u64, bool)>, ArchiveError> {
+        let mut block = [0u8; BLOCK];
+        self.inner.read_exact(&mut block).await?;
+        if block.iter().all(|b| *b == 0) {
+            return Ok(None);
+        }
+
+        let stored = parse_number(&block[148..156]).ok_or_else(|| ArchiveError::Invalid("bad header".to_string()))?;
+        let checksum: u64 = block.iter()
+            .enumerate()
+            .map(|(i, b)| if (148..156).contains(&i) { u64::from(b' ') } else { u64::from(*b) })
+            .sum();
+        if stored != checksum {
+            return Err(ArchiveError::Invalid("header checksum mismatch".to_string()));
+        }
+
+        let name_len = block[..100].iter().position(|b| *b == 0).unwrap_or(100);
+        let name = String::from_utf8_lossy(&block[..name_len]).to_string();
+        let size = parse_number(&block[124..136]).ok_or_else(|| ArchiveError::Invalid("bad entry size".to_string()))?;
+        let regular = matches!(block[156], b'0' | 0);
+        Ok(Some((name, size, regular)))
+    }
+
+    /// Copia il contenuto della voce corrente in `out` e ne restituisce lo SHA-256
+    async fn copy_to<W: AsyncWrite + Unpin>(&mut self, size: u64, out: &mut W) -> Result<String, ArchiveError> {
+        let mut hasher = Sha256::new();
+        let mut remaining = size;
+        let mut buf = vec![0u8; 64 * 1024];
+        while remaining > 0 {
+            let want = buf.len().min(remaining as usize);
+            let n = self.inner.read(&mut buf[..want]).await?;
+            if n == 0 {
+                return Err(ArchiveError::Invalid("truncated".to_string()));
+            }
+            hasher.update(&buf[..n]);
+            out.write_all(&buf[..n]).await?;
+            remaining -= n as u64;
+        }
+        self.skip(padding(size) as u64).await?;
+        Ok(hex::encode(hasher.finalize()))
+    }
+
+    async fn skip(&mut self, size: u64) -> Result<(), ArchiveError> {
+        let copied = tokio::io::copy(&mut (&mut self.inner).take(size), &mut tokio::io::sink()).await?;
+        if copied < size {
+            return Err(ArchiveError::Invalid("truncated".to_string()));
+        }
+        Ok(())
+    }
+}
+
+// ============== EXPORT ==============
+
+/// Esporta un intero ambiente: metadata.enc, journal, contenuti e anteprime,
+/// compresi i contenuti distribuiti in shard, che vengono ricostruiti
+pub async fn export_environment<W: AsyncWrite + Unpin>(
+    store: &dyn BlobStore,
+    erasure: &ErasureManager,
+    replication: &ReplicationManager,
+    env: &str,
+    out: W,
+) -> std::io::Result<()> {
+    let mut keys: Vec<String> = store.list(env).await?
+        .into_iter()
+        .map(|b| b.key)
+        .filter(|key| is_archived_key(key))
+        .collect();
+    keys.extend(erasure::sharded_ids(store, env).await?);
+    // metadata.enc per ultimo tra i blob, come nelle repliche
+    keys.sort_by_key(|key| (key == journal::METADATA_KEY, key.clone()));
+    keys.dedup();
+
+    let present: HashSet<&String> = keys.iter().collect();
+    let items: Vec<VaultItem> = completed_items(env).await?
+        .into_iter()
+        .filter(|item| present.contains(&item.content_id))
+        .collect();
+    write_archive(store, erasure, replication, env, &keys, items, out).await
+}
+
+/// Archivio con i soli elementi indicati (content_id), con le loro anteprime;
+/// gli ID sconosciuti vengono ignorati
+pub async fn export_items<W: AsyncWrite + Unpin>(
+    store: &dyn BlobStore,
+    erasure: &ErasureManager,
+    replication: &ReplicationManager,
+    env: &str,
+    ids: &[String],
+    out: W,
+) -> std::io::Result<()> {
+    let items: Vec<VaultItem> = completed_items(env).await?
+        .into_iter()
+        .filter(|item| ids.contains(&item.content_id))
+        .collect();
+    let keys: Vec<String> = items.iter()
+        .flat_map(|item| std::iter::once(item.content_id.clone()).chain(item.preview_id.clone()))
+        .collect();
+    write_archive(store, erasure, replication, env, &keys, items, out).await
+}
+
+/// Item degli upload completati di un ambiente (non quelli nell'inbox)
+async fn completed_items(env: &str) -> std::io::Result<Vec<VaultItem>> {
+    Ok(upload::list_uploads(env).await?
+        .into_iter()
+        .filter(|staged| staged.meta.wrapped_key.is_none())
+        .filter_map(|staged| staged.meta.item)
+        .collect())
+}
+
+async fn write_archive<W: AsyncWrite + Unpin>(
+    store: &dyn BlobStore,
+    erasure: &ErasureManager,
+    replication: &ReplicationManager,
+    env: &str,
+    keys: &[String],
+    items: Vec<VaultItem>,
+    out: W,
+) -> std::io::Result<()> {
+    let mut writer = ArchiveWriter { inner: out };
+    let mut blobs = Vec::new();
+
+    for key in keys {
+        let name = format!("{}{}", BLOB_PREFIX, key);
+        let (size, reader): (u64, BlobReader) = match store.stat(env, key).await {
+            Ok(stat) => (stat.size, store.open(env, key, None).await?),
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                match erasure.read(store, replication, env, key).await {
+                    Ok(Some(data)) => (data.len() as u64, Box::pin(std::io::Cursor::new(data))),
+                    // Eliminato nel frattempo
+                    Ok(None) => continue,
+                    Err(e) => return Err(std::io::Error::other(e.to_string())),
+                }
+            }
+            Err(e) => return Err(e),
+        };
+        let sha256 = writer.append(&name, size, reader).await?;
+        blobs.push(ArchiveBlob { key: key.clone(), size, sha256 });
+    }
+
+    let manifest = ArchiveManifest {
+        version: ARCHIVE_VERSION,
+        environment: env.to_string(),
+        created_at: current_timestamp(),
+        blobs,
+        items,
+    };
+    let data = serde_json::to_vec_pretty(&manifest).map_err(std::io::Error::other)?;
+    writer.append(MANIFEST_NAME, data.len() as u64, Box::pin(std::io::Cursor::new(data))).await?;
+    writer.finish().await
+}
+
+// ============== IMPORT ==============
+
+/// Archivio letto per intero e verificato, in attesa di essere scritto nello store
+pub struct StagedArchive {
+    dir: PathBuf,
+    manifest: ArchiveManifest,
+    /// Blob il cui hash corrisponde al manifest
+    verified: Vec<ArchiveBlob>,
+    corrupted: Vec<String>,
+    missing: Vec<String>,
+    /// Quota prenotata per i blob ricevuti, fino alla scrittura nello store
+    _reservations: Vec<Reservation>,
+}
+
+async fn remove_dir(dir: &Path) {
+    match tokio::fs::remove_dir_all(dir).await {
+        Err(e) if e.kind() != ErrorKind::NotFound => {
+            tracing::warn!("Cannot remove {}: {}", dir.display(), e);
+        }
+        _ => {}
+    }
+}
+
+/// Legge un archivio nei file temporanei dell'ambiente e verifica ogni blob
+/// con il manifest. Nulla viene scritto nello store finché non si chiama
+/// `commit`, quindi un archivio troncato non lascia import parziali. Ogni
+/// blob prenota la sua quota prima di essere ricevuto, e un archivio con
+/// voci che il manifest non elenca viene rifiutato.
+pub async fn stage<R: AsyncRead + Unpin>(
+    store: &dyn BlobStore,
+    quotas: &QuotaManager,
+    env: &str,
+    input: R,
+) -> Result<StagedArchive, ArchiveError> {
+    let dir = Path::new(upload::STAGING_ROOT)
+        .join(env)
+        .join(IMPORT_DIR)
+        .join(hex::encode(crypto::random_bytes::<8>()));
+    tokio::fs::create_dir_all(&dir).await?;
+
+    let mut reservations = Vec::new();
+    let admit = |size: u64| quotas.reserve(store, env, size);
+    match read_archive(&dir, input, admit, &mut reservations).await {
+        Ok((manifest, staged)) => {
+            let mut archive = StagedArchive {
+                dir,
+                manifest,
+                verified: Vec::new(),
+                corrupted: Vec::new(),
+                missing: Vec::new(),
+                _reservations: reservations,
+            };
+            for blob in &archive.manifest.blobs {
+                match staged.get(&blob.key) {
+                    Some((size, sha256)) if *size == blob.size && *sha256 == blob.sha256 => {
+                        archive.verified.push(blob.clone());
+                    }
+                    Some(_) => archive.corrupted.push(blob.key.clone()),
+                    None => archive.missing.push(blob.key.clone()),
+                }
+            }
+            Ok(archive)
+        }
+        Err(e) => {
+            remove_dir(&dir).await;
+            Err(e)
+        }
+    }
+}
+
+async fn read_archive<R, A, F>(
+    dir: &Path,
+    input: R,
+    admit: A,
+    reservations: &mut Vec<Reservation>,
+) -> Result<(ArchiveManifest, HashMap<String, (u64, String)>), ArchiveError>
+where
+    R: AsyncRead + Unpin,
+    A: Fn(u64) -> F,
+    F: std::future::Future<Output = Result<Reservation, QuotaError>>,
+{
+    let mut reader = ArchiveReader { inner: input };
+    let mut staged = HashMap::new();
+    let mut manifest: Option<ArchiveManifest> = None;
+
+    while let Some((name, size, regular)) = reader.next().await? {
+        if !regular {
+            reader.skip(size + padding(size) as u64).await?;
+            continue;
+        }
+        if name == MANIFEST_NAME {
+            if size > MAX_MANIFEST_BYTES {
+                return Err(ArchiveError::Invalid("manifest too large".to_string()));
+            }
+            let mut data = Vec::new();
+            reader.copy_to(size, &mut data).await?;
+            let parsed: ArchiveManifest = serde_json::from_slice(&data)
+                .map_err(|e| ArchiveError::Invalid(format!("bad manifest: {}", e)))?;
+            if parsed.version != ARCHIVE_VERSION {
+                return Err(ArchiveError::Invalid(format!("unsupported version {}", parsed.version)));
+            }
+            manifest = Some(parsed);
+            continue;
+        }
+
+        let Some(key) = name.strip_prefix(BLOB_PREFIX).filter(|key| is_archived_key(key)) else {
+            reader.skip(size + padding(size) as u64).await?;
+            continue;
+        };
+        if staged.contains_key(key) {
+            return Err(ArchiveError::Invalid(format!("duplicate entry {}", name)));
+        }
+        // Dopo il manifest si può già dire se la voce è attesa
+        if manifest.as_ref().is_some_and(|m| !m.blobs.iter().any(|b| b.key == key)) {
+            return Err(ArchiveError::Invalid(format!("entry {} is not in the manifest", name)));
+        }
+        reservations.push(admit(size).await.map_err(ArchiveError::Quota)?);
+        let mut file = tokio::fs::File::create(dir.join(key)).await?;
+        let sha256 = reader.copy_to(size, &mut file).await?;
+        file.flush().await?;
+        staged.insert(key.to_string(), (size, sha256));
+    }
+
+    let manifest = manifest.ok_or_else(|| ArchiveError::Invalid("no manifest".to_string()))?;
+    let listed: HashSet<&str> = manifest.blobs.iter().map(|b| b.key.as_str()).collect();
+    if let Some(key) = staged.keys().find(|key| !listed.contains(key.as_str())) {
+        return Err(ArchiveError::Invalid(format!("entry {}{} is not in the manifest", BLOB_PREFIX, key)));
+    }
+    Ok((manifest, staged))
+}
+
+/// SHA-256 di un blob già presente nello store
+async fn stored_hash(store: &dyn BlobStore, env: &str, key: &str) -> std::io::Result<String> {
+    let mut reader = store.open(env, key, None).await?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = reader.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Scrive nello store i blob verificati. Un blob già presente con lo stesso
+/// contenuto è riportato come identico, con un contenuto diverso come
+/// conflitto e non viene sovrascritto. I metadata (metadata.enc e journal)
+/// vengono importati solo in un ambiente che non ne ha ancora, perché
+/// mescolare due storie renderebbe illeggibili i delta.
+pub async fn commit(
+    store: &dyn BlobStore,
+    quotas: &QuotaManager,
+    env: &str,
+    archive: StagedArchive,
+) -> std::io::Result<ImportReport> {
+    let result = commit_blobs(store, env, &archive).await;
+    remove_dir(&archive.dir).await;
+    // La cache va aggiornata prima che le prenotazioni vengano rilasciate
+    quotas.invalidate(env).await;
+    result
+}
+
+async fn commit_blobs(store: &dyn BlobStore, env: &str, archive: &StagedArchive) -> std::io::Result<ImportReport> {
+    let mut report = ImportReport {
+        environment: env.to_string(),
+        source_environment: archive.manifest.environment.clone(),
+        corrupted: archive.corrupted.clone(),
+        missing: archive.missing.clone(),
+        ..Default::default()
+    };
+
+    let existing: HashSet<String> = store.list(env).await?.into_iter().map(|b| b.key).collect();
+    let has_metadata = existing.iter().any(|key| is_metadata_key(key));
+
+    // Journal prima di metadata.enc: un metadata.enc senza journal verrebbe
+    // preso per un ambiente precedente al journal
+    let mut verified: Vec<&ArchiveBlob> = archive.verified.iter().collect();
+    verified.sort_by_key(|b| (b.key == journal::METADATA_KEY, b.key.clone()));
+
+    let mut available = HashSet::new();
+    for blob in verified {
+        if existing.contains(&blob.key) {
+            if stored_hash(store, env, &blob.key).await? == blob.sha256 {
+                report.identical.push(blob.key.clone());
+                available.insert(blob.key.clone());
+            } else {
+                report.conflicts.push(blob.key.clone());
+            }
+            continue;
+        }
+        if has_metadata && is_metadata_key(&blob.key) {
+            report.conflicts.push(blob.key.clone());
+            continue;
+        }
+        store.put_file(env, &blob.key, &archive.dir.join(&blob.key)).await?;
+        report.imported.push(blob.key.clone());
+        available.insert(blob.key.clone());
+    }
+
+    // Gli item tornano tra gli upload completati, così cestino, condivisioni
+    // e GC li riconoscono come nell'ambiente di origine
+    for item in &archive.manifest.items {
+        if !available.contains(&item.content_id) || !is_blob_id(&item.id) {
+            continue;
+        }
+        if upload::record_item(env, item).await? {
+            report.items += 1;
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reader(data: &'static [u8]) -> BlobReader {
+        Box::pin(std::io::Cursor::new(data))
+    }
+
+    async fn sample_archive() -> Vec<u8> {
+        let mut data = Vec::new();
+        let mut writer = ArchiveWriter { inner: &mut data };
+        writer.append("blobs/ab01", 5, reader(b"hello")).await.unwrap();
+        writer.append("blobs/empty", 0, reader(b"")).await.unwrap();
+        writer.append(MANIFEST_NAME, 2, reader(b"{}")).await.unwrap();
+        writer.finish().await.unwrap();
+        data
+    }
+
+    #[test]
+    fn numbers_round_trip() {
+        for value in [0, 7, 0o644, 8u64.pow(11) - 1, 8u64.pow(11), u64::MAX >> 1] {
+            let mut field = [0u8; 12];
+            octal(&mut field, value);
+            assert_eq!(parse_number(&field), Some(value));
+        }
+        assert_eq!(parse_number(b"000644 \0"), Some(0o644));
+        assert_eq!(parse_number(b"\0\0\0\0"), Some(0));
+        assert_eq!(parse_number(b"9"), None);
+    }
+
+    #[tokio::test]
+    async fn tar_round_trip() {
+        let data = sample_archive().await;
+        assert_eq!(data.len() % BLOCK, 0);
+        // Intestazione, contenuto con padding, voce vuota, manifest, due blocchi finali
+        assert_eq!(data.len(), BLOCK * 7);
+        assert_eq!(&data[257..263], b"ustar\0");
+
+        let mut archive = ArchiveReader { inner: data.as_slice() };
+        let mut entries = Vec::new();
+        while let Some((name, size, regular)) = archive.next().await.unwrap() {
+            assert!(regular);
+            let mut content = Vec::new();
+            let sha256 = archive.copy_to(size, &mut content).await.unwrap();
+            assert_eq!(sha256, hex::encode(Sha256::digest(&content)));
+            entries.push((name, content));
+        }
+        assert_eq!(entries, [
+            ("blobs/ab01".to_string(), b"hello".to_vec()),
+            ("blobs/empty".to_string(), Vec::new()),
+            (MANIFEST_NAME.to_string(), b"{}".to_vec()),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn rejects_corrupted_and_truncated_archives() {
+        let mut data = sample_archive().await;
+        data[0] = b'c';
+        let mut archive = ArchiveReader { inner: data.as_slice() };
+        assert!(matches!(archive.next().await, Err(ArchiveError::Invalid(_))));
+
+        let data = sample_archive().await;
+        let mut archive = ArchiveReader { inner: &data[..BLOCK + 2] };
+        let (_, size, _) = archive.next().await.unwrap().unwrap();
+        assert!(matches!(archive.copy_to(size, &mut Vec::new()).await, Err(ArchiveError::Invalid(_))));
+    }
+
+    async fn archive_with(entries: &[(&str, &'static [u8])], listed: &[&str]) -> Vec<u8> {
+        let manifest = ArchiveManifest {
+            version: ARCHIVE_VERSION,
+            environment: "source".to_string(),
+            created_at: 0,
+            blobs: entries.iter()
+                .filter(|(key, _)| listed.contains(key))
+                .map(|(key, data)| ArchiveBlob {
+                    key: key.to_string(),
+                    size: data.len() as u64,
+                    sha256: hex::encode(Sha256::digest(data)),
+                })
+                .collect(),
+            items: Vec::new(),
+        };
+        let manifest = serde_json::to_vec(&manifest).unwrap();
+
+        let mut data = Vec::new();
+        let mut writer = ArchiveWriter { inner: &mut data };
+        for (key, content) in entries {
+            let name = format!("{}{}", BLOB_PREFIX, key);
+            writer.append(&name, content.len() as u64, reader(content)).await.unwrap();
+        }
+        let len = manifest.len() as u64;
+        writer.append(MANIFEST_NAME, len, Box::pin(std::io::Cursor::new(manifest))).await.unwrap();
+        writer.finish().await.unwrap();
+        data
+    }
+
+    struct Import {
+        store: crate::storage::TempStore,
+        quotas: QuotaManager,
+        env: String,
+        dir: PathBuf,
+    }
+
+    impl Import {
+        fn new(limit: u64) -> Self {
+            let env = format!("import-test-{}", hex::encode(crypto::random_bytes::<8>()));
+            let dir = std::env::temp_dir().join(&env);
+            std::fs::create_dir_all(&dir).unwrap();
+            Self {
+                store: crate::storage::TempStore::new(),
+                quotas: QuotaManager::new(crate::quota::QuotaConfig { env_bytes: Some(limit), ..Default::default() }),
+                env,
+                dir,
+            }
+        }
+
+        async fn read(&self, data: &[u8]) -> Result<HashMap<String, (u64, String)>, ArchiveError> {
+            let mut reservations = Vec::new();
+            let admit = |size| self.quotas.reserve(&*self.store, &self.env, size);
+            read_archive(&self.dir, data, admit, &mut reservations).await.map(|(_, staged)| staged)
+        }
+    }
+
+    impl Drop for Import {
+        fn drop(&mut self) {
+            std::fs::remove_dir_all(&self.dir).ok();
+        }
+    }
+
+    #[tokio::test]
+    async fn reads_listed_entries() {
+        let import = Import::new(1024);
+        let data = archive_with(&[("ab01", b"hello"), (journal::METADATA_KEY, b"meta")], &["ab01", journal::METADATA_KEY]).await;
+        let staged = import.read(&data).await.unwrap();
+        assert_eq!(staged["ab01"], (5, hex::encode(Sha256::digest(b"hello"))));
+        assert_eq!(std::fs::read(import.dir.join("ab01")).unwrap(), b"hello");
+    }
+
+    #[tokio::test]
+    async fn rejects_entries_missing_from_the_manifest() {
+        let import = Import::new(1024);
+        let data = archive_with(&[("ab01", b"hello"), ("cd02", b"extra")], &["ab01"]).await;
+        assert!(matches!(import.read(&data).await, Err(ArchiveError::Invalid(reason)) if reason.contains("cd02")));
+    }
+
+    #[tokio::test]
+    async fn checks_the_quota_before_each_blob() {
+        let import = Import::new(8);
+        let data = archive_with(&[("ab01", b"hello"), ("cd02", b"world")], &["ab01", "cd02"]).await;
+        assert!(matches!(import.read(&data).await, Err(ArchiveError::Quota(QuotaError::EnvironmentFull { used: 5, limit: 8 }))));
+        // Il secondo blob non è stato ricevuto
+        assert!(!import.dir.join("cd02").exists());
+    }
+
+    #[tokio::test]
+    async fn writer_detects_short_blobs() {
+        let mut writer = ArchiveWriter { inner: Vec::new() };
+        let err = writer.append("blobs/ab01", 10, reader(b"hello")).await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+}
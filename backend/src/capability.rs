@@ -0,0 +1,560 @@
+use axum::body::Bytes;
+use base64::{engine::general_purpose, Engine as _};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::io::ErrorKind;
+use tokio::sync::{Mutex, RwLock};
+
+use crate::crypto::{current_timestamp, random_bytes};
+use crate::storage::BlobStore;
+use crate::types::{CapabilityInfo, Role, SessionClaims};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Capability emesse per un ambiente, salvate nello store accanto ai blob
+pub const CAPABILITY_INDEX: &str = "capabilities.json";
+
+/// Prefisso dei token di capability (i token di sessione non lo hanno)
+pub const TOKEN_PREFIX: &str = "cap_";
+
+/// Errori nell'emissione e nella verifica delle capability
+#[derive(Debug)]
+pub enum CapabilityError {
+    /// Token malformato, catena di firme non valida o caveat non soddisfacibile
+    Invalid,
+    /// Capability revocata (o mai emessa da questo nodo)
+    Revoked,
+    Expired,
+    /// Caveat non riconosciuto o malformato in una richiesta di emissione
+    BadCaveat,
+    Io(std::io::Error),
+}
+
+impl std::fmt::Display for CapabilityError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CapabilityError::Invalid => write!(f, "invalid capability token"),
+            CapabilityError::Revoked => write!(f, "capability revoked"),
+            CapabilityError::Expired => write!(f, "capability expired"),
+            CapabilityError::BadCaveat => write!(f, "malformed caveat"),
+            CapabilityError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<std::io::Error> for CapabilityError {
+    fn from(e: std::io::Error) -> Self {
+        CapabilityError::Io(e)
+    }
+}
+
+/// Tipo di operazione richiesta da una route del vault
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    /// Lettura di blob, metadata e resoconti
+    Read,
+    /// Caricamento di nuovi contenuti
+    Upload,
+    /// Modifica di metadata, eliminazione e ripristino (include Upload)
+    Write,
+    /// Condivisioni, link, GC e svuotamento del cestino: mai delegabili
+    Admin,
+}
+
+impl Operation {
+    /// Ruolo minimo nell'ambiente per eseguire l'operazione
+    pub fn role(self) -> Role {
+        match self {
+            Operation::Read => Role::Reader,
+            Operation::Upload | Operation::Write => Role::Writer,
+            Operation::Admin => Role::Owner,
+        }
+    }
+}
+
+/// Restrizioni risultanti dai caveat di un token. Caveat ripetuti si
+/// sommano: ognuno può solo restringere ciò che i precedenti concedono.
+#[derive(Debug, Clone)]
+pub struct Caveats {
+    read: bool,
+    upload: bool,
+    write: bool,
+    /// Unici blob accessibili, se limitati
+    content: Option<HashSet<String>>,
+    /// Dimensione massima di ogni upload (contenuto + anteprima)
+    max_bytes: Option<u64>,
+    expires_at: Option<u64>,
+}
+
+impl Caveats {
+    /// Interpreta i caveat di un token legato all'ambiente `env`. Ogni caveat
+    /// ha la forma `chiave = valore`; uno sconosciuto rende il token inutilizzabile.
+    fn parse(env: &str, caveats: &[String]) -> Result<Self, CapabilityError> {
+        let mut parsed = Caveats {
+            read: true,
+            upload: true,
+            write: true,
+            content: None,
+            max_bytes: None,
+            expires_at: None,
+        };
+        for caveat in caveats {
+            let (key, value) = caveat.split_once('=').ok_or(CapabilityError::BadCaveat)?;
+            let value = value.trim();
+            if value.is_empty() {
+                return Err(CapabilityError::BadCaveat);
+            }
+            let list = || value.split(',').map(str::trim).filter(|v| !v.is_empty());
+            match key.trim() {
+                "environment" if value == env => {}
+                "expires" => {
+                    let t: u64 = value.parse().map_err(|_| CapabilityError::BadCaveat)?;
+                    parsed.expires_at = Some(parsed.expires_at.map_or(t, |e| e.min(t)));
+                }
+                "access" => {
+                    let mut granted = (false, false, false);
+                    for access in list() {
+                        match access {
+                            "read" => granted.0 = true,
+                            "upload" => granted.1 = true,
+                            "write" => granted.2 = true,
+                            _ => return Err(CapabilityError::BadCaveat),
+                        }
+                    }
+                    parsed.read &= granted.0;
+                    parsed.upload &= granted.1;
+                    parsed.write &= granted.2;
+                }
+                "content" => {
+                    let ids: HashSet<String> = list().map(str::to_string).collect();
+                    parsed.content = Some(match parsed.content {
+                        Some(previous) => previous.intersection(&ids).cloned().collect(),
+                        None => ids,
+                    });
+                }
+                "max_bytes" => {
+                    let n: u64 = value.parse().map_err(|_| CapabilityError::BadCaveat)?;
+                    parsed.max_bytes = Some(parsed.max_bytes.map_or(n, |m| m.min(n)));
+                }
+                _ => return Err(CapabilityError::BadCaveat),
+            }
+        }
+        Ok(parsed)
+    }
+
+    /// Vero se i caveat consentono l'operazione sui blob `content` (vuoto per
+    /// operazioni che non riguardano blob specifici) con `bytes` da caricare
+    pub fn permits(&self, op: Operation, content: &[String], bytes: u64) -> bool {
+        let allowed = match op {
+            Operation::Read => self.read,
+            Operation::Upload => self.upload || self.write,
+            Operation::Write => self.write,
+            Operation::Admin => false,
+        };
+        if !allowed {
+            return false;
+        }
+        if let Some(ids) = &self.content {
+            if content.is_empty() || !content.iter().all(|id| ids.contains(id)) {
+                return false;
+            }
+        }
+        self.max_bytes.is_none_or(|max| bytes <= max)
+    }
+}
+
+/// Contenuto di un token: identificatore, caveat in ordine e firma finale
+#[derive(Serialize, Deserialize)]
+struct TokenBody {
+    /// `{ambiente}:{id}`
+    identifier: String,
+    caveats: Vec<String>,
+    /// HMAC-SHA256 (hex) dell'ultimo anello della catena
+    signature: String,
+}
+
+/// Capability delegabili in stile macaroon.
+///
+/// La firma parte da un HMAC dell'identificatore con la chiave del nodo; ogni
+/// caveat aggiunge un anello `HMAC(firma precedente, caveat)`. Chi ha un token
+/// può quindi restringerlo offline aggiungendo caveat, ma non toglierne: solo
+/// il nodo può ricalcolare la catena dall'inizio. Una capability agisce con il
+/// ruolo corrente di chi l'ha emessa, ridotto dai caveat, e resta valida
+/// finché il suo ID è nell'indice dell'ambiente.
+pub struct CapabilityManager {
+    /// Chiave radice delle catene, derivata dalla chiave privata del nodo
+    root_key: [u8; 32],
+    /// Indici letti dallo store (ambiente -> capability)
+    cache: RwLock<HashMap<String, Vec<CapabilityInfo>>>,
+    /// Serializza le modifiche agli indici
+    lock: Mutex<()>,
+}
+
+impl CapabilityManager {
+    pub fn new(node_privkey: &str) -> Self {
+        let mut hasher = Sha256::new();
+        hasher.update(b"vault-capability:");
+        hasher.update(node_privkey.as_bytes());
+        Self {
+            root_key: hasher.finalize().into(),
+            cache: RwLock::new(HashMap::new()),
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Catena di HMAC fino all'ultimo caveat, da finalizzare o verificare
+    fn chain(&self, identifier: &str, caveats: &[String]) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.root_key).expect("HMAC accepts any key length");
+        mac.update(identifier.as_bytes());
+        for caveat in caveats {
+            let key = mac.finalize().into_bytes();
+            mac = HmacSha256::new_from_slice(&key).expect("HMAC accepts any key length");
+            mac.update(caveat.as_bytes());
+        }
+        mac
+    }
+
+    fn encode(&self, identifier: String, caveats: Vec<String>) -> String {
+        let signature = hex::encode(self.chain(&identifier, &caveats).finalize().into_bytes());
+        let body = serde_json::to_vec(&TokenBody { identifier, caveats, signature }).expect("token body serializes");
+        format!("{}{}", TOKEN_PREFIX, general_purpose::URL_SAFE_NO_PAD.encode(body))
+    }
+
+    async fn load(&self, store: &dyn BlobStore, env: &str) -> std::io::Result<Vec<CapabilityInfo>> {
+        if let Some(cached) = self.cache.read().await.get(env) {
+            return Ok(cached.clone());
+        }
+        let capabilities: Vec<CapabilityInfo> = match store.get(env, CAPABILITY_INDEX).await {
+            Ok(data) => serde_json::from_slice(&data).map_err(std::io::Error::other)?,
+            Err(e) if e.kind() == ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        self.cache.write().await.insert(env.to_string(), capabilities.clone());
+        Ok(capabilities)
+    }
+
+    async fn save(&self, store: &dyn BlobStore, env: &str, capabilities: Vec<CapabilityInfo>) -> std::io::Result<()> {
+        if capabilities.is_empty() {
+            match store.delete(env, CAPABILITY_INDEX).await {
+                Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        } else {
+            let data = serde_json::to_vec(&capabilities).map_err(std::io::Error::other)?;
+            store.put(env, CAPABILITY_INDEX, Bytes::from(data)).await?;
+        }
+        self.cache.write().await.insert(env.to_string(), capabilities);
+        Ok(())
+    }
+
+    /// Emette una capability per l'ambiente. Il primo caveat la lega
+    /// all'ambiente; seguono la scadenza, se indicata, e quelli richiesti.
+    pub async fn mint(
+        &self,
+        store: &dyn BlobStore,
+        env: &str,
+        issuer: &str,
+        label: String,
+        caveats: Vec<String>,
+        expires_at: Option<u64>,
+    ) -> Result<(String, CapabilityInfo), CapabilityError> {
+        let mut all = vec![format!("environment = {}", env)];
+        if let Some(t) = expires_at {
+            all.push(format!("expires = {}", t));
+        }
+        all.extend(caveats.into_iter().map(|c| c.trim().to_string()));
+        Caveats::parse(env, &all)?;
+
+        let info = CapabilityInfo {
+            id: hex::encode(random_bytes::<16>()),
+            issuer: issuer.to_string(),
+            label,
+            caveats: all.clone(),
+            created_at: current_timestamp(),
+            expires_at,
+        };
+
+        let _guard = self.lock.lock().await;
+        let mut capabilities = self.load(store, env).await?;
+        capabilities.push(info.clone());
+        self.save(store, env, capabilities).await?;
+
+        Ok((self.encode(format!("{}:{}", env, info.id), all), info))
+    }
+
+    /// Capability non scadute di un ambiente
+    pub async fn list(&self, store: &dyn BlobStore, env: &str, now: u64) -> std::io::Result<Vec<CapabilityInfo>> {
+        let mut capabilities = self.load(store, env).await?;
+        capabilities.retain(|c| c.expires_at.is_none_or(|t| t > now));
+        Ok(capabilities)
+    }
+
+    /// Revoca le capability indicate e restituisce gli ID revocati. Con
+    /// `issuer` vengono revocate solo quelle emesse da quella pubkey.
+    pub async fn revoke(
+        &self,
+        store: &dyn BlobStore,
+        env: &str,
+        ids: &[String],
+        issuer: Option<&str>,
+    ) -> std::io::Result<Vec<String>> {
+        let _guard = self.lock.lock().await;
+        let capabilities = self.load(store, env).await?;
+        let (revoked, remaining): (Vec<_>, Vec<_>) = capabilities
+            .into_iter()
+            .partition(|c| ids.contains(&c.id) && issuer.is_none_or(|p| c.issuer == p));
+        if !revoked.is_empty() {
+            self.save(store, env, remaining).await?;
+        }
+        Ok(revoked.into_iter().map(|c| c.id).collect())
+    }
+
+    /// Elimina dall'indice le capability scadute; restituisce quante ne ha rimosse
+    pub async fn expire(&self, store: &dyn BlobStore, env: &str, now: u64) -> std::io::Result<usize> {
+        let _guard = self.lock.lock().await;
+        let capabilities = self.load(store, env).await?;
+        let before = capabilities.len();
+        let remaining: Vec<_> = capabilities.into_iter().filter(|c| c.expires_at.is_none_or(|t| t > now)).collect();
+        let expired = before - remaining.len();
+        if expired > 0 {
+            self.save(store, env, remaining).await?;
+        }
+        Ok(expired)
+    }
+
+    /// Verifica la catena di un token e ne restituisce i claims (con la pubkey
+    /// di chi l'ha emessa) e le restrizioni dei caveat
+    pub async fn verify(&self, store: &dyn BlobStore, token: &str, now: u64) -> Result<(SessionClaims, Caveats), CapabilityError> {
+        let encoded = token.strip_prefix(TOKEN_PREFIX).ok_or(CapabilityError::Invalid)?;
+        let body: TokenBody = general_purpose::URL_SAFE_NO_PAD
+            .decode(encoded)
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .ok_or(CapabilityError::Invalid)?;
+        let signature = hex::decode(&body.signature).map_err(|_| CapabilityError::Invalid)?;
+        self.chain(&body.identifier, &body.caveats)
+            .verify_slice(&signature)
+            .map_err(|_| CapabilityError::Invalid)?;
+
+        let (env, id) = body.identifier.split_once(':').ok_or(CapabilityError::Invalid)?;
+        let caveats = Caveats::parse(env, &body.caveats).map_err(|_| CapabilityError::Invalid)?;
+        if caveats.expires_at.is_some_and(|t| t <= now) {
+            return Err(CapabilityError::Expired);
+        }
+        let info = self.load(store, env).await?
+            .into_iter()
+            .find(|c| c.id == id)
+            .ok_or(CapabilityError::Revoked)?;
+
+        Ok((claims(env, info, &caveats), caveats))
+    }
+
+    /// Credenziali S3 di una capability: l'access key è `{ambiente}-{id}`, il
+    /// secret è la firma della catena all'emissione (la stessa del token)
+    pub fn access_key(&self, env: &str, info: &CapabilityInfo) -> (String, String) {
+        let identifier = format!("{}:{}", env, info.id);
+        let secret = hex::encode(self.chain(&identifier, &info.caveats).finalize().into_bytes());
+        (format!("{}-{}", env, info.id), secret)
+    }
+
+    /// Risolve un'access key S3 nella capability da cui deriva. Restituisce il
+    /// secret con cui verificare la firma della richiesta, i claims e i caveat
+    /// dati all'emissione; quelli aggiunti dopo a un token non valgono per S3.
+    pub async fn resolve_access_key(
+        &self,
+        store: &dyn BlobStore,
+        access_key: &str,
+        now: u64,
+    ) -> Result<(String, SessionClaims, Caveats), CapabilityError> {
+        let (env, id) = access_key.split_once('-').ok_or(CapabilityError::Invalid)?;
+        let is_hex = |s: &str| !s.is_empty() && s.len() <= 64 && s.chars().all(|c| c.is_ascii_hexdigit());
+        if !is_hex(env) || !is_hex(id) {
+            return Err(CapabilityError::Invalid);
+        }
+        let info = self.load(store, env).await?
+            .into_iter()
+            .find(|c| c.id == id)
+            .ok_or(CapabilityError::Revoked)?;
+        let caveats = Caveats::parse(env, &info.caveats).map_err(|_| CapabilityError::Invalid)?;
+        if caveats.expires_at.is_some_and(|t| t <= now) {
+            return Err(CapabilityError::Expired);
+        }
+
+        let (_, secret) = self.access_key(env, &info);
+        Ok((secret, claims(env, info, &caveats), caveats))
+    }
+}
+
+/// Claims con cui agisce una capability: quelli di chi l'ha emessa
+fn claims(env: &str, info: CapabilityInfo, caveats: &Caveats) -> SessionClaims {
+    SessionClaims {
+        session_id: info.id,
+        pubkey: info.issuer,
+        environment: env.to_string(),
+        issued_at: info.created_at,
+        expires_at: caveats.expires_at.unwrap_or(u64::MAX),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::TempStore;
+
+    fn decode(token: &str) -> TokenBody {
+        let data = general_purpose::URL_SAFE_NO_PAD.decode(token.strip_prefix(TOKEN_PREFIX).unwrap()).unwrap();
+        serde_json::from_slice(&data).unwrap()
+    }
+
+    fn encode(body: &TokenBody) -> String {
+        format!("{}{}", TOKEN_PREFIX, general_purpose::URL_SAFE_NO_PAD.encode(serde_json::to_vec(body).unwrap()))
+    }
+
+    /// Aggiunge un caveat come fa il client, senza la chiave del nodo
+    fn attenuate(token: &str, caveat: &str) -> String {
+        let mut body = decode(token);
+        let mut mac = HmacSha256::new_from_slice(&hex::decode(&body.signature).unwrap()).unwrap();
+        mac.update(caveat.as_bytes());
+        body.signature = hex::encode(mac.finalize().into_bytes());
+        body.caveats.push(caveat.to_string());
+        encode(&body)
+    }
+
+    fn env() -> String {
+        hex::encode(random_bytes::<8>())
+    }
+
+    #[tokio::test]
+    async fn attenuation_only_narrows() {
+        let store = TempStore::new();
+        let env = env();
+        let manager = CapabilityManager::new("node-key");
+        let now = current_timestamp();
+        let (token, _) = manager
+            .mint(&*store, &env, "issuer", String::new(), vec!["access = read, upload".to_string()], Some(now + 100))
+            .await
+            .unwrap();
+
+        let (claims, caveats) = manager.verify(&*store, &token, now).await.unwrap();
+        assert_eq!(claims.environment, env);
+        assert!(caveats.permits(Operation::Upload, &[], 0));
+        assert!(!caveats.permits(Operation::Write, &[], 0));
+
+        // Un caveat che allarga viene intersecato con i precedenti
+        let token = attenuate(&token, "access = read, write");
+        let (_, caveats) = manager.verify(&*store, &token, now).await.unwrap();
+        assert!(caveats.permits(Operation::Read, &[], 0));
+        assert!(!caveats.permits(Operation::Upload, &[], 0));
+        assert!(!caveats.permits(Operation::Write, &[], 0));
+
+        let token = attenuate(&token, "content = a, b");
+        let token = attenuate(&token, "content = b, c");
+        let (_, caveats) = manager.verify(&*store, &token, now).await.unwrap();
+        assert!(caveats.permits(Operation::Read, &["b".to_string()], 0));
+        assert!(!caveats.permits(Operation::Read, &["a".to_string()], 0));
+        assert!(!caveats.permits(Operation::Read, &["c".to_string()], 0));
+        assert!(!caveats.permits(Operation::Read, &[], 0));
+
+        let token = attenuate(&token, "max_bytes = 100");
+        let token = attenuate(&token, "max_bytes = 1000");
+        let (_, caveats) = manager.verify(&*store, &token, now).await.unwrap();
+        assert!(caveats.permits(Operation::Read, &["b".to_string()], 100));
+        assert!(!caveats.permits(Operation::Read, &["b".to_string()], 101));
+
+        let extended = attenuate(&token, &format!("expires = {}", now + 1000));
+        let (claims, _) = manager.verify(&*store, &extended, now).await.unwrap();
+        assert_eq!(claims.expires_at, now + 100);
+        let shortened = attenuate(&token, &format!("expires = {}", now + 10));
+        let (claims, _) = manager.verify(&*store, &shortened, now).await.unwrap();
+        assert_eq!(claims.expires_at, now + 10);
+
+        // Un altro ambiente o un caveat sconosciuto rendono il token inutilizzabile
+        let moved = attenuate(&token, &format!("environment = {}", self::env()));
+        assert!(matches!(manager.verify(&*store, &moved, now).await, Err(CapabilityError::Invalid)));
+        let unknown = attenuate(&token, "admin = true");
+        assert!(matches!(manager.verify(&*store, &unknown, now).await, Err(CapabilityError::Invalid)));
+    }
+
+    #[tokio::test]
+    async fn caveats_cannot_be_changed_removed_or_reordered() {
+        let store = TempStore::new();
+        let env = env();
+        let manager = CapabilityManager::new("node-key");
+        let now = current_timestamp();
+        let (token, _) = manager.mint(&*store, &env, "issuer", String::new(), Vec::new(), None).await.unwrap();
+        let token = attenuate(&token, "access = read");
+        let token = attenuate(&token, "max_bytes = 10");
+        manager.verify(&*store, &token, now).await.unwrap();
+
+        let body = decode(&token);
+        let mut tampered = decode(&token);
+        tampered.caveats[1] = "access = read, write".to_string();
+        let mut removed = decode(&token);
+        removed.caveats.remove(1);
+        let mut reordered = decode(&token);
+        reordered.caveats.swap(1, 2);
+        let mut resigned = decode(&token);
+        resigned.signature = hex::encode([0u8; 32]);
+        for forged in [tampered, removed, reordered, resigned] {
+            assert!(matches!(manager.verify(&*store, &encode(&forged), now).await, Err(CapabilityError::Invalid)));
+        }
+
+        // Un altro nodo non riconosce la catena
+        let other = CapabilityManager::new("other-key");
+        assert!(matches!(other.verify(&*store, &encode(&body), now).await, Err(CapabilityError::Invalid)));
+        assert!(matches!(manager.verify(&*store, "not-a-token", now).await, Err(CapabilityError::Invalid)));
+    }
+
+    #[tokio::test]
+    async fn revoked_and_expired_tokens_are_rejected() {
+        let store = TempStore::new();
+        let env = env();
+        let manager = CapabilityManager::new("node-key");
+        let now = current_timestamp();
+
+        let (token, info) = manager.mint(&*store, &env, "issuer", String::new(), Vec::new(), None).await.unwrap();
+        assert!(manager.revoke(&*store, &env, std::slice::from_ref(&info.id), Some("someone-else")).await.unwrap().is_empty());
+        manager.verify(&*store, &token, now).await.unwrap();
+        assert_eq!(manager.revoke(&*store, &env, std::slice::from_ref(&info.id), Some("issuer")).await.unwrap(), vec![info.id]);
+        assert!(matches!(manager.verify(&*store, &token, now).await, Err(CapabilityError::Revoked)));
+
+        let (token, _) = manager.mint(&*store, &env, "issuer", String::new(), Vec::new(), Some(now + 10)).await.unwrap();
+        manager.verify(&*store, &token, now).await.unwrap();
+        assert!(matches!(manager.verify(&*store, &token, now + 10).await, Err(CapabilityError::Expired)));
+        let attenuated = attenuate(&token, &format!("expires = {}", now - 1));
+        assert!(matches!(manager.verify(&*store, &attenuated, now).await, Err(CapabilityError::Expired)));
+
+        assert_eq!(manager.expire(&*store, &env, now + 10).await.unwrap(), 1);
+        assert!(matches!(manager.verify(&*store, &token, now).await, Err(CapabilityError::Revoked)));
+    }
+
+    #[tokio::test]
+    async fn access_keys_resolve_only_live_capabilities() {
+        let store = TempStore::new();
+        let env = env();
+        let manager = CapabilityManager::new("node-key");
+        let now = current_timestamp();
+        let (token, info) = manager
+            .mint(&*store, &env, "issuer", String::new(), vec!["access = read".to_string()], None)
+            .await
+            .unwrap();
+
+        let (access_key, secret) = manager.access_key(&env, &info);
+        let (resolved, claims, caveats) = manager.resolve_access_key(&*store, &access_key, now).await.unwrap();
+        assert_eq!(resolved, secret);
+        assert_eq!(secret, decode(&token).signature);
+        assert_eq!(claims.pubkey, "issuer");
+        assert!(!caveats.permits(Operation::Upload, &[], 0));
+
+        let unknown = format!("{}-{}", env, hex::encode(random_bytes::<16>()));
+        assert!(matches!(manager.resolve_access_key(&*store, &unknown, now).await, Err(CapabilityError::Revoked)));
+        for malformed in ["", "no-dash-hex", &env, &format!("{}-../x", env)] {
+            assert!(matches!(manager.resolve_access_key(&*store, malformed, now).await, Err(CapabilityError::Invalid)));
+        }
+
+        manager.revoke(&*store, &env, &[info.id], None).await.unwrap();
+        assert!(matches!(manager.resolve_access_key(&*store, &access_key, now).await, Err(CapabilityError::Revoked)));
+    }
+}
@@ -0,0 +1,656 @@
+use rusqlite::{params, types::Type, Connection};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use crate::crypto::current_timestamp;
+use crate::erasure;
+use crate::inbox;
+use crate::storage::BlobStore;
+use crate::trash;
+use crate::types::{CatalogItem, ItemPage, ItemState, SearchPage, UploadMeta, UploadSession, VaultItem};
+use crate::upload;
+
+/// File del catalogo, accanto alle staging area degli ambienti
+pub const CATALOG_FILE: &str = "catalog.sqlite";
+
+/// Elementi per pagina quando il client non li indica, e massimo consentito
+pub const DEFAULT_PAGE: u64 = 100;
+pub const MAX_PAGE: u64 = 1000;
+
+/// Token dell'indice cieco accettati per elemento e per ricerca
+pub const MAX_ITEM_TOKENS: usize = 256;
+pub const MAX_QUERY_TOKENS: usize = 64;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS items (
+        environment TEXT NOT NULL,
+        id TEXT NOT NULL,
+        content_id TEXT NOT NULL,
+        preview_id TEXT,
+        item_type TEXT NOT NULL,
+        size INTEGER NOT NULL,
+        merkle_root TEXT,
+        chunk_size INTEGER,
+        state TEXT NOT NULL,
+        created_at INTEGER NOT NULL,
+        item TEXT NOT NULL,
+        PRIMARY KEY (environment, id)
+    );
+    CREATE INDEX IF NOT EXISTS items_content ON items (environment, content_id);
+    CREATE INDEX IF NOT EXISTS items_created ON items (environment, state, created_at);
+
+    CREATE TABLE IF NOT EXISTS item_tokens (
+        environment TEXT NOT NULL,
+        token TEXT NOT NULL,
+        item_id TEXT NOT NULL,
+        PRIMARY KEY (environment, token, item_id)
+    );
+    CREATE INDEX IF NOT EXISTS item_tokens_item ON item_tokens (environment, item_id);
+
+    CREATE TABLE IF NOT EXISTS uploads (
+        environment TEXT NOT NULL,
+        file_id TEXT NOT NULL,
+        content_id TEXT NOT NULL,
+        item_type TEXT NOT NULL,
+        size INTEGER NOT NULL,
+        chunk_size INTEGER NOT NULL,
+        total_chunks INTEGER NOT NULL,
+        inbox INTEGER NOT NULL,
+        started_at INTEGER NOT NULL,
+        last_activity INTEGER NOT NULL,
+        completed_at INTEGER,
+        PRIMARY KEY (environment, file_id)
+    );
+
+    CREATE TABLE IF NOT EXISTS upload_chunks (
+        environment TEXT NOT NULL,
+        file_id TEXT NOT NULL,
+        chunk INTEGER NOT NULL,
+        received_at INTEGER NOT NULL,
+        PRIMARY KEY (environment, file_id, chunk)
+    );
+";
+
+#[derive(Debug)]
+pub enum CatalogError {
+    Sqlite(rusqlite::Error),
+    Io(std::io::Error),
+}
+
+impl fmt::Display for CatalogError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CatalogError::Sqlite(e) => write!(f, "catalog database error: {}", e),
+            CatalogError::Io(e) => write!(f, "catalog rebuild failed: {}", e),
+        }
+    }
+}
+
+impl From<rusqlite::Error> for CatalogError {
+    fn from(e: rusqlite::Error) -> Self {
+        CatalogError::Sqlite(e)
+    }
+}
+
+impl From<std::io::Error> for CatalogError {
+    fn from(e: std::io::Error) -> Self {
+        CatalogError::Io(e)
+    }
+}
+
+/// Un token dell'indice cieco: HMAC in hex, anche troncato, da 16 a 64 cifre
+pub fn is_valid_search_token(token: &str) -> bool {
+    (16..=64).contains(&token.len()) && token.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// Token in minuscolo e senza duplicati; None se uno non è valido o sono troppi
+pub fn normalize_search_tokens(tokens: &[String], max: usize) -> Option<Vec<String>> {
+    if tokens.len() > max || !tokens.iter().all(|t| is_valid_search_token(t)) {
+        return None;
+    }
+    let mut tokens: Vec<String> = tokens.iter().map(|t| t.to_ascii_lowercase()).collect();
+    tokens.sort();
+    tokens.dedup();
+    Some(tokens)
+}
+
+fn state_name(state: ItemState) -> &'static str {
+    match state {
+        ItemState::Active => "active",
+        ItemState::Trashed => "trashed",
+        ItemState::Inbox => "inbox",
+    }
+}
+
+fn parse_state(name: &str) -> ItemState {
+    match name {
+        "trashed" => ItemState::Trashed,
+        "inbox" => ItemState::Inbox,
+        _ => ItemState::Active,
+    }
+}
+
+/// Catalogo SQLite degli elementi e delle sessioni di upload di ogni ambiente,
+/// per elencarli e contarli senza leggere la staging area. I meta.json restano
+/// la fonte di verità: un ambiente viene ricostruito dal filesystem la prima
+/// volta che lo si interroga in questo processo e dopo ogni `invalidate`,
+/// poi le scritture degli handler lo tengono allineato. SQLite è sincrono:
+/// ogni accesso gira in un thread di `spawn_blocking`, fuori dal runtime.
+pub struct Catalog {
+    db: Arc<Mutex<Db>>,
+}
+
+struct Db {
+    conn: Connection,
+    /// Ambienti già ricostruiti in questo processo
+    indexed: HashSet<String>,
+    /// Scritture per ambiente: una ricostruzione che ne vede cambiare il numero
+    /// può aver perso un aggiornamento e non marca l'ambiente come allineato
+    writes: HashMap<String, u64>,
+}
+
+/// Un upload letto dalla staging area durante una ricostruzione
+struct ScannedUpload {
+    file_id: String,
+    meta: UploadMeta,
+    chunks: Vec<usize>,
+    last_activity: u64,
+    /// Stato dell'elemento prodotto; None se non completato o eliminato definitivamente
+    state: Option<ItemState>,
+}
+
+impl Catalog {
+    pub fn open(path: &Path) -> Result<Self, CatalogError> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        Self::init(Connection::open(path)?)
+    }
+
+    /// Catalogo non persistente, se il file non può essere aperto
+    pub fn in_memory() -> Result<Self, CatalogError> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(conn: Connection) -> Result<Self, CatalogError> {
+        conn.execute_batch("PRAGMA journal_mode = WAL; PRAGMA synchronous = NORMAL;")?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self {
+            db: Arc::new(Mutex::new(Db {
+                conn,
+                indexed: HashSet::new(),
+                writes: HashMap::new(),
+            })),
+        })
+    }
+
+    /// Esegue `f` sul database in un thread bloccante
+    async fn with_db<T: Send + 'static>(&self, f: impl FnOnce(&mut Db) -> T + Send + 'static) -> T {
+        let db = self.db.clone();
+        tokio::task::spawn_blocking(move || f(&mut db.lock().unwrap_or_else(|e| e.into_inner())))
+            .await
+            .unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()))
+    }
+
+    /// Applica una scrittura. Una scrittura fallita non blocca l'upload (i
+    /// meta.json restano corretti): l'ambiente verrà ricostruito
+    async fn write(
+        &self,
+        env: &str,
+        what: &'static str,
+        f: impl FnOnce(&Connection, &str) -> rusqlite::Result<()> + Send + 'static,
+    ) {
+        let env = env.to_string();
+        self.with_db(move |db| {
+            *db.writes.entry(env.clone()).or_default() += 1;
+            if let Err(e) = f(&db.conn, &env) {
+                tracing::warn!("Catalog: failed to record {} in {}: {}", what, env, e);
+                db.indexed.remove(&env);
+            }
+        })
+        .await
+    }
+
+    /// Da chiamare dopo le modifiche alla staging area fatte senza passare dal
+    /// catalogo (GC, import, scadenze): l'ambiente verrà ricostruito
+    pub async fn invalidate(&self, env: &str) {
+        let env = env.to_string();
+        self.with_db(move |db| db.indexed.remove(&env)).await;
+    }
+
+    /// Registra un upload appena avviato
+    pub async fn record_upload(&self, env: &str, file_id: &str, meta: &UploadMeta) {
+        let (file_id, meta) = (file_id.to_string(), meta.clone());
+        self.write(env, "upload", move |conn, env| insert_upload(conn, env, &file_id, &meta, meta.created_at, None)).await;
+    }
+
+    /// Registra un chunk ricevuto
+    pub async fn record_chunk(&self, env: &str, file_id: &str, chunk: usize) {
+        let now = current_timestamp();
+        let file_id = file_id.to_string();
+        self.write(env, "chunk", move |conn, env| {
+            conn.execute(
+                "INSERT OR REPLACE INTO upload_chunks (environment, file_id, chunk, received_at) VALUES (?1, ?2, ?3, ?4)",
+                params![env, file_id, chunk, now],
+            )?;
+            conn.execute(
+                "UPDATE uploads SET last_activity = ?3 WHERE environment = ?1 AND file_id = ?2",
+                params![env, file_id, now],
+            )?;
+            Ok(())
+        }).await;
+    }
+
+    /// Registra l'elemento prodotto da un upload completato
+    pub async fn record_item(&self, env: &str, item: &VaultItem, created_at: u64, state: ItemState) {
+        let now = current_timestamp();
+        let item = item.clone();
+        self.write(env, "item", move |conn, env| {
+            let tx = conn.unchecked_transaction()?;
+            insert_item(&tx, env, &item, created_at, state)?;
+            tx.execute(
+                "UPDATE uploads SET completed_at = COALESCE(completed_at, ?3), last_activity = ?3
+                 WHERE environment = ?1 AND file_id = ?2",
+                params![env, item.id, now],
+            )?;
+            tx.execute(
+                "DELETE FROM upload_chunks WHERE environment = ?1 AND file_id = ?2",
+                params![env, item.id],
+            )?;
+            tx.commit()
+        }).await;
+    }
+
+    /// Cambia lo stato degli elementi con i content ID indicati
+    pub async fn set_state(&self, env: &str, content_ids: &[String], state: ItemState) {
+        let content_ids = content_ids.to_vec();
+        self.write(env, "item state", move |conn, env| {
+            for content_id in &content_ids {
+                conn.execute(
+                    "UPDATE items SET state = ?3 WHERE environment = ?1 AND content_id = ?2",
+                    params![env, content_id, state_name(state)],
+                )?;
+            }
+            Ok(())
+        }).await;
+    }
+
+    /// Toglie gli elementi il cui contenuto è stato eliminato definitivamente
+    pub async fn remove_items(&self, env: &str, content_ids: &[String]) {
+        let content_ids = content_ids.to_vec();
+        self.write(env, "removal", move |conn, env| {
+            for content_id in &content_ids {
+                conn.execute(
+                    "DELETE FROM item_tokens WHERE environment = ?1
+                     AND item_id IN (SELECT id FROM items WHERE environment = ?1 AND content_id = ?2)",
+                    params![env, content_id],
+                )?;
+                conn.execute(
+                    "DELETE FROM items WHERE environment = ?1 AND content_id = ?2",
+                    params![env, content_id],
+                )?;
+            }
+            Ok(())
+        }).await;
+    }
+
+    /// Toglie un upload e l'elemento prodotto, quando la staging area lo ha rimosso
+    pub async fn remove_upload(&self, env: &str, file_id: &str) {
+        let file_id = file_id.to_string();
+        self.write(env, "upload removal", move |conn, env| {
+            let tx = conn.unchecked_transaction()?;
+            tx.execute("DELETE FROM item_tokens WHERE environment = ?1 AND item_id = ?2", params![env, file_id])?;
+            tx.execute("DELETE FROM items WHERE environment = ?1 AND id = ?2", params![env, file_id])?;
+            tx.execute("DELETE FROM upload_chunks WHERE environment = ?1 AND file_id = ?2", params![env, file_id])?;
+            tx.execute("DELETE FROM uploads WHERE environment = ?1 AND file_id = ?2", params![env, file_id])?;
+            tx.commit()
+        }).await;
+    }
+
+    /// Ricostruisce l'ambiente dalla staging area e dallo store, se non è già allineato
+    pub async fn ensure_indexed(&self, store: &dyn BlobStore, env: &str) -> Result<(), CatalogError> {
+        let name = env.to_string();
+        let writes = self.with_db(move |db| {
+            (!db.indexed.contains(&name)).then(|| db.writes.get(&name).copied().unwrap_or(0))
+        }).await;
+        let Some(writes) = writes else {
+            return Ok(());
+        };
+
+        let scanned = scan(store, env).await?;
+
+        let env = env.to_string();
+        self.with_db(move |db| {
+            replace(&db.conn, &env, &scanned)?;
+            if db.writes.get(&env).copied().unwrap_or(0) == writes {
+                db.indexed.insert(env);
+            }
+            Ok(())
+        }).await
+    }
+
+    /// Una pagina di elementi, dal più recente
+    pub async fn items(
+        &self,
+        env: &str,
+        state: ItemState,
+        item_type: Option<&str>,
+        offset: u64,
+        limit: u64,
+    ) -> Result<ItemPage, CatalogError> {
+        let (env, item_type) = (env.to_string(), item_type.map(str::to_string));
+        self.with_db(move |db| Self::query_items(&db.conn, &env, state, item_type.as_deref(), offset, limit)).await
+    }
+
+    fn query_items(
+        conn: &Connection,
+        env: &str,
+        state: ItemState,
+        item_type: Option<&str>,
+        offset: u64,
+        limit: u64,
+    ) -> Result<ItemPage, CatalogError> {
+        let (total, total_bytes) = conn.query_row(
+            "SELECT COUNT(*), COALESCE(SUM(size), 0) FROM items
+             WHERE environment = ?1 AND state = ?2 AND (?3 IS NULL OR item_type = ?3)",
+            params![env, state_name(state), item_type],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+
+        let mut stmt = conn.prepare(
+            "SELECT item, state, created_at FROM items
+             WHERE environment = ?1 AND state = ?2 AND (?3 IS NULL OR item_type = ?3)
+             ORDER BY created_at DESC, id LIMIT ?4 OFFSET ?5",
+        )?;
+        let items = stmt
+            .query_map(params![env, state_name(state), item_type, limit, offset], |row| {
+                let item: VaultItem = serde_json::from_str(&row.get::<_, String>(0)?)
+                    .map_err(|e| rusqlite::Error::FromSqlConversionFailure(0, Type::Text, Box::new(e)))?;
+                let state = parse_state(&row.get::<_, String>(1)?);
+                // Nel cestino e nell'inbox l'anteprima non è raggiungibile con il suo ID
+                let preview_url = item.preview_id.as_ref()
+                    .filter(|_| state == ItemState::Active)
+                    .map(|preview_id| format!("/api/get_preview/{}/{}", env, preview_id));
+                Ok(CatalogItem {
+                    item,
+                    state,
+                    created_at: row.get(2)?,
+                    preview_url,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok(ItemPage { items, total, total_bytes, offset, limit })
+    }
+
+    /// Elementi che hanno in comune con la ricerca tutti i token (`match_all`)
+    /// o almeno uno; prima quelli con più token in comune, poi i più recenti
+    pub async fn search(
+        &self,
+        env: &str,
+        tokens: &[String],
+        match_all: bool,
+        state: ItemState,
+        offset: u64,
+        limit: u64,
+    ) -> Result<SearchPage, CatalogError> {
+        let (env, tokens) = (env.to_string(), tokens.to_vec());
+        self.with_db(move |db| Self::query_search(&db.conn, &env, &tokens, match_all, state, offset, limit)).await
+    }
+
+    fn query_search(
+        conn: &Connection,
+        env: &str,
+        tokens: &[String],
+        match_all: bool,
+        state: ItemState,
+        offset: u64,
+        limit: u64,
+    ) -> Result<SearchPage, CatalogError> {
+        let tokens_json = serde_json::Value::from(tokens).to_string();
+        let min_hits = if match_all { tokens.len() } else { 1 };
+        let matches = "SELECT i.id, i.created_at, COUNT(*) AS hits FROM item_tokens t
+             JOIN items i ON i.environment = t.environment AND i.id = t.item_id
+             WHERE t.environment = ?1 AND i.state = ?2 AND t.token IN (SELECT value FROM json_each(?3))
+             GROUP BY i.id HAVING hits >= ?4";
+
+        let total = conn.query_row(
+            &format!("SELECT COUNT(*) FROM ({})", matches),
+            params![env, state_name(state), tokens_json, min_hits],
+            |row| row.get(0),
+        )?;
+        let mut stmt = conn.prepare(&format!(
+            "{} ORDER BY hits DESC, i.created_at DESC, i.id LIMIT ?5 OFFSET ?6",
+            matches
+        ))?;
+        let ids = stmt
+            .query_map(params![env, state_name(state), tokens_json, min_hits, limit, offset], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<String>>>()?;
+
+        Ok(SearchPage { ids, total, offset, limit })
+    }
+
+    /// Sessioni di upload del vault (non le inbox), dalla più recente; quelle
+    /// completate solo se richiesto
+    pub async fn uploads(&self, env: &str, completed: bool) -> Result<Vec<UploadSession>, CatalogError> {
+        let env = env.to_string();
+        self.with_db(move |db| Self::query_uploads(&db.conn, &env, completed)).await
+    }
+
+    fn query_uploads(conn: &Connection, env: &str, completed: bool) -> Result<Vec<UploadSession>, CatalogError> {
+        let mut chunks: HashMap<String, Vec<usize>> = HashMap::new();
+        let mut stmt = conn.prepare(
+            "SELECT file_id, chunk FROM upload_chunks WHERE environment = ?1 ORDER BY file_id, chunk",
+        )?;
+        for row in stmt.query_map(params![env], |row| Ok((row.get::<_, String>(0)?, row.get::<_, usize>(1)?)))? {
+            let (file_id, chunk) = row?;
+            chunks.entry(file_id).or_default().push(chunk);
+        }
+
+        let mut stmt = conn.prepare(
+            "SELECT file_id, content_id, item_type, size, chunk_size, total_chunks, started_at, last_activity, completed_at
+             FROM uploads WHERE environment = ?1 AND inbox = 0 AND (?2 OR completed_at IS NULL)
+             ORDER BY started_at DESC, file_id",
+        )?;
+        let sessions = stmt
+            .query_map(params![env, completed], |row| {
+                let file_id: String = row.get(0)?;
+                let size: u64 = row.get(3)?;
+                let chunk_size: u64 = row.get(4)?;
+                let total_chunks: usize = row.get(5)?;
+                let completed_at: Option<u64> = row.get(8)?;
+
+                let received_chunks = if completed_at.is_some() {
+                    (0..total_chunks).collect()
+                } else {
+                    chunks.remove(&file_id).unwrap_or_default()
+                };
+                let received_bytes = received_chunks.iter()
+                    .map(|&i| chunk_size.min(size.saturating_sub(i as u64 * chunk_size)))
+                    .sum();
+
+                Ok(UploadSession {
+                    content_id: row.get(1)?,
+                    item_type: row.get(2)?,
+                    size,
+                    total_chunks,
+                    received_chunks,
+                    received_bytes,
+                    started_at: row.get(6)?,
+                    last_activity: row.get(7)?,
+                    completed_at,
+                    file_id,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok(sessions)
+    }
+}
+
+fn insert_upload(
+    conn: &Connection,
+    env: &str,
+    file_id: &str,
+    meta: &UploadMeta,
+    last_activity: u64,
+    completed_at: Option<u64>,
+) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO uploads
+         (environment, file_id, content_id, item_type, size, chunk_size, total_chunks, inbox, started_at, last_activity, completed_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+        params![
+            env,
+            file_id,
+            meta.content_id,
+            meta.item_type,
+            meta.size,
+            meta.chunk_size,
+            meta.total_chunks,
+            meta.wrapped_key.is_some(),
+            meta.created_at,
+            last_activity,
+            completed_at,
+        ],
+    )?;
+    Ok(())
+}
+
+fn insert_item(conn: &Connection, env: &str, item: &VaultItem, created_at: u64, state: ItemState) -> rusqlite::Result<()> {
+    let json = serde_json::to_string(item).map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+    conn.execute(
+        "INSERT OR REPLACE INTO items
+         (environment, id, content_id, preview_id, item_type, size, merkle_root, chunk_size, state, created_at, item)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+        params![
+            env,
+            item.id,
+            item.content_id,
+            item.preview_id,
+            item.item_type,
+            item.size,
+            item.merkle_root,
+            item.chunk_size,
+            state_name(state),
+            created_at,
+            json,
+        ],
+    )?;
+    conn.execute(
+        "DELETE FROM item_tokens WHERE environment = ?1 AND item_id = ?2",
+        params![env, item.id],
+    )?;
+    for token in &item.search_tokens {
+        conn.execute(
+            "INSERT OR IGNORE INTO item_tokens (environment, token, item_id) VALUES (?1, ?2, ?3)",
+            params![env, token, item.id],
+        )?;
+    }
+    Ok(())
+}
+
+/// Sostituisce le righe di un ambiente con quanto letto dal filesystem
+fn replace(conn: &Connection, env: &str, scanned: &[ScannedUpload]) -> rusqlite::Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    for table in ["items", "item_tokens", "uploads", "upload_chunks"] {
+        tx.execute(&format!("DELETE FROM {} WHERE environment = ?1", table), params![env])?;
+    }
+
+    for upload in scanned {
+        let completed_at = upload.meta.item.is_some().then_some(upload.last_activity);
+        insert_upload(&tx, env, &upload.file_id, &upload.meta, upload.last_activity, completed_at)?;
+        for &chunk in &upload.chunks {
+            tx.execute(
+                "INSERT INTO upload_chunks (environment, file_id, chunk, received_at) VALUES (?1, ?2, ?3, ?4)",
+                params![env, upload.file_id, chunk, upload.last_activity],
+            )?;
+        }
+        if let (Some(item), Some(state)) = (&upload.meta.item, upload.state) {
+            insert_item(&tx, env, item, upload.meta.created_at, state)?;
+        }
+    }
+    tx.commit()
+}
+
+/// Legge gli upload di un ambiente; lo stato di ogni elemento dipende da dove
+/// si trova il suo contenuto nello store
+async fn scan(store: &dyn BlobStore, env: &str) -> std::io::Result<Vec<ScannedUpload>> {
+    let blobs: HashSet<String> = store.list(env).await?.into_iter().map(|b| b.key).collect();
+    let sharded = erasure::sharded_ids(store, env).await?;
+
+    let mut scanned = Vec::new();
+    for staged in upload::list_uploads(env).await? {
+        let chunks = if staged.meta.item.is_none() {
+            let files = upload::staging_files(env, &staged.file_id);
+            upload::received_chunks(&files, &staged.meta).await.map(|(received, _)| received).unwrap_or_default()
+        } else {
+            Vec::new()
+        };
+
+        let state = staged.meta.item.as_ref().and_then(|item| {
+            let id = &item.content_id;
+            if staged.meta.wrapped_key.is_some() && blobs.contains(&inbox::inbox_key(id)) {
+                Some(ItemState::Inbox)
+            } else if blobs.contains(id) || sharded.contains(id) {
+                Some(ItemState::Active)
+            } else if blobs.contains(&trash::trashed_key(id)) {
+                Some(ItemState::Trashed)
+            } else {
+                None
+            }
+        });
+
+        scanned.push(ScannedUpload {
+            file_id: staged.file_id,
+            meta: staged.meta,
+            chunks,
+            last_activity: staged.last_activity,
+            state,
+        });
+    }
+    Ok(scanned)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(id: &str, tokens: &[&str]) -> VaultItem {
+        VaultItem {
+            id: id.to_string(),
+            encrypted_name: Vec::new(),
+            name_nonce: Vec::new(),
+            item_type: "photo".to_string(),
+            size: 10,
+            nonce: Vec::new(),
+            content_id: format!("c{}", id),
+            preview_id: None,
+            merkle_root: None,
+            chunk_hashes: Vec::new(),
+            chunk_size: None,
+            search_tokens: tokens.iter().map(|t| t.to_string()).collect(),
+        }
+    }
+
+    #[tokio::test]
+    async fn records_and_queries_off_the_runtime() {
+        let catalog = Catalog::in_memory().unwrap();
+        let (a, b) = ("a".repeat(32), "b".repeat(32));
+        catalog.record_item("env", &item("1", &[&a]), 1, ItemState::Active).await;
+        catalog.record_item("env", &item("2", &[&a, &b]), 2, ItemState::Active).await;
+
+        let page = catalog.items("env", ItemState::Active, None, 0, 10).await.unwrap();
+        assert_eq!(page.items.iter().map(|i| i.item.id.as_str()).collect::<Vec<_>>(), ["2", "1"]);
+        assert_eq!(page.total_bytes, 20);
+
+        let found = catalog.search("env", &[a.clone(), b.clone()], true, ItemState::Active, 0, 10).await.unwrap();
+        assert_eq!(found.ids, ["2"]);
+
+        catalog.set_state("env", &["c2".to_string()], ItemState::Trashed).await;
+        let found = catalog.search("env", std::slice::from_ref(&a), false, ItemState::Active, 0, 10).await.unwrap();
+        assert_eq!(found.ids, ["1"]);
+    }
+}
@@ -159,8 +159,6 @@ pub fn random_bytes<const N: usize>() -> [u8; N] {
 use ed25519_dalek::{
     SigningKey as Ed25519SigningKey, 
     VerifyingKey as Ed25519VerifyingKey, 
-    Signer as Ed25519Signer, 
-    Verifier as Ed25519Verifier
 };
 
 /// Genera una coppia di chiavi Ed25519
@@ -11,7 +11,7 @@ use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpStream;
 use tokio::sync::RwLock;
 use tokio::time::{interval, Duration};
-use tracing::{debug, error, info, warn};
+use tracing::{debug, info, warn};
 
 /// Gestisce la discovery e il mantenimento della rete peer
 pub struct DiscoveryManager {
@@ -25,10 +25,14 @@ pub struct DiscoveryManager {
     known_peers: Arc<RwLock<HashMap<String, KnownPeer>>>,
     /// Peer attualmente connessi
     connected_peers: Arc<RwLock<HashMap<String, PeerConnection>>>,
+    /// Trust tolto a ogni peer (pubkey -> totale), anche se non è tra i
+    /// conosciuti: viene applicato di nuovo quando il peer viene aggiunto
+    penalties: Arc<RwLock<HashMap<String, u8>>>,
 }
 
 /// Connessione attiva a un peer
 #[derive(Debug)]
+#[allow(dead_code)]
 pub struct PeerConnection {
     pub address: SocketAddr,
     pub x25519_pubkey: Option<[u8; 32]>,
@@ -47,6 +51,7 @@ impl DiscoveryManager {
             x25519_keypair,
             known_peers: Arc::new(RwLock::new(HashMap::new())),
             connected_peers: Arc::new(RwLock::new(HashMap::new())),
+            penalties: Arc::new(RwLock::new(HashMap::new())),
         }
     }
 
@@ -70,6 +75,7 @@ impl DiscoveryManager {
         // Avvia il loop di ping periodico
         let known_peers = self.known_peers.clone();
         let connected_peers = self.connected_peers.clone();
+        let penalties = self.penalties.clone();
         let node_config = self.node_config.clone();
         let ed25519_privkey = self.ed25519_privkey.clone();
         let x25519_pubkey = self.x25519_keypair.1;
@@ -140,14 +146,16 @@ impl DiscoveryManager {
                         };
                         let addr = format!("{}:{}", peer.node.address, port);
                         if let Ok(new_peers) = Self::request_peers(&addr, 10).await {
+                            let penalties = penalties.read().await.clone();
                             let mut peers = known_peers.write().await;
-                            for new_peer in new_peers {
+                            for mut new_peer in new_peers {
                                 if !peers.contains_key(&new_peer.node.pubkey) 
                                    && new_peer.node.pubkey != node_config.pubkey {
                                     info!("Discovered new peer: {} at {}", 
                                         new_peer.node.name.as_deref().unwrap_or("unknown"),
                                         new_peer.node.address
                                     );
+                                    new_peer.trust_score = penalized(&penalties, &new_peer.node.pubkey, new_peer.trust_score);
                                     peers.insert(new_peer.node.pubkey.clone(), new_peer);
                                 }
                             }
@@ -160,6 +168,7 @@ impl DiscoveryManager {
 
     /// Aggiunge i peer iniziali dalla configurazione
     async fn bootstrap_peers(&self) {
+        let penalties = self.penalties.read().await.clone();
         let mut peers = self.known_peers.write().await;
         
         for peer_config in &self.node_config.peers {
@@ -169,7 +178,7 @@ impl DiscoveryManager {
                 x25519_pubkey: None,
                 last_ping: 0,
                 latency_ms: None,
-                trust_score: 50, // Trust iniziale medio
+                trust_score: penalized(&penalties, &peer_config.pubkey, 50), // Trust iniziale medio
                 failed_attempts: 0,
             };
             peers.insert(peer_config.pubkey.clone(), known_peer);
@@ -185,9 +194,9 @@ impl DiscoveryManager {
     /// Invia un ping a un peer e misura la latenza
     async fn ping_peer(
         addr: &str,
-        node_config: &Node,
-        ed25519_privkey: &[u8],
-        x25519_pubkey: [u8; 32],
+        _node_config: &Node,
+        _ed25519_privkey: &[u8],
+        _x25519_pubkey: [u8; 32],
     ) -> Result<u32, Box<dyn std::error::Error + Send + Sync>> {
         let start = std::time::Instant::now();
         
@@ -293,7 +302,7 @@ impl DiscoveryManager {
                     .collect();
                 
                 // Ordina per trust score
-                result.sort_by(|a, b| b.trust_score.cmp(&a.trust_score));
+                result.sort_by_key(|p| std::cmp::Reverse(p.trust_score));
                 
                 Some(DiscoveryMessage::PeerList { peers: result })
             }
@@ -304,6 +313,7 @@ impl DiscoveryManager {
                 let sig_bytes = hex::decode(&node.signature).unwrap_or_default();
                 
                 if verify_signature_bytes(&pubkey_bytes, &node_data, &sig_bytes).is_ok() {
+                    let penalties = self.penalties.read().await.clone();
                     let mut peers = self.known_peers.write().await;
                     
                     let known_peer = KnownPeer {
@@ -311,7 +321,7 @@ impl DiscoveryManager {
                         x25519_pubkey: Some(x25519_pubkey),
                         last_ping: current_timestamp(),
                         latency_ms: None,
-                        trust_score: 30,
+                        trust_score: penalized(&penalties, &node.node.pubkey, 30),
                         failed_attempts: 0,
                     };
                     
@@ -353,6 +363,25 @@ impl DiscoveryManager {
         self.known_peers.read().await.values().cloned().collect()
     }
 
+    /// Vero se il nodo è tra i peer conosciuti
+    pub async fn is_known(&self, pubkey: &str) -> bool {
+        self.known_peers.read().await.contains_key(pubkey)
+    }
+
+    /// Abbassa il trust di un peer, ad esempio dopo un audit fallito. Vale
+    /// anche per i peer non ancora (o non più) conosciuti, e non si azzera
+    /// quando il peer si annuncia di nuovo.
+    pub async fn penalize(&self, pubkey: &str, amount: u8) {
+        {
+            let mut penalties = self.penalties.write().await;
+            let total = penalties.entry(pubkey.to_string()).or_default();
+            *total = total.saturating_add(amount);
+        }
+        if let Some(peer) = self.known_peers.write().await.get_mut(pubkey) {
+            peer.trust_score = peer.trust_score.saturating_sub(amount);
+        }
+    }
+
     /// Crea un annuncio firmato del nodo locale
     pub fn create_announcement(&self) -> (SignedNode, [u8; 32]) {
         let peer_node = PeerNode {
@@ -383,3 +412,42 @@ impl DiscoveryManager {
         (signed_node, self.x25519_keypair.1)
     }
 }
+
+/// Trust iniziale di un peer che viene aggiunto, meno le penalità già ricevute
+fn penalized(penalties: &HashMap<String, u8>, pubkey: &str, trust: u8) -> u8 {
+    trust.saturating_sub(penalties.get(pubkey).copied().unwrap_or(0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn penalties_outlive_the_peer_entry() {
+        let node: Node = serde_json::from_value(serde_json::json!({
+            "name": "test",
+            "pubkey": "self",
+            "address": "127.0.0.1",
+            "secure": false,
+            "version": null,
+            "peers": [{ "name": null, "pubkey": "peer", "address": "127.0.0.1", "port": 7000 }],
+            "ping_interval": 60,
+        }))
+        .unwrap();
+        let discovery = DiscoveryManager::new(node, Vec::new());
+
+        // Penalizzato prima di essere tra i conosciuti
+        discovery.penalize("peer", 10).await;
+        discovery.bootstrap_peers().await;
+        let trust = |peers: Vec<KnownPeer>| peers.iter().find(|p| p.node.pubkey == "peer").map(|p| p.trust_score);
+        assert_eq!(trust(discovery.get_known_peers().await), Some(40));
+
+        discovery.penalize("peer", 10).await;
+        assert_eq!(trust(discovery.get_known_peers().await), Some(30));
+
+        // Aggiunto di nuovo, non recupera il trust perso
+        discovery.known_peers.write().await.clear();
+        discovery.bootstrap_peers().await;
+        assert_eq!(trust(discovery.get_known_peers().await), Some(30));
+    }
+}
@@ -0,0 +1,252 @@
+use axum::{
+    body::Body,
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use axum::body::Bytes;
+use std::future::Future;
+use tokio_util::io::ReaderStream;
+
+use crate::storage::{BlobReader, BlobStore};
+
+/// Intervallo di byte richiesto (estremi inclusi)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl ByteRange {
+    pub fn len(&self) -> u64 {
+        self.end - self.start + 1
+    }
+}
+
+/// Esito del parsing dell'header Range
+#[derive(Debug, PartialEq)]
+pub enum RangeRequest {
+    /// Nessun Range (o Range non supportato): si serve l'intero blob
+    Full,
+    /// Singolo intervallo soddisfacibile
+    Partial(ByteRange),
+    /// Intervallo fuori dalla dimensione del blob
+    Unsatisfiable,
+}
+
+/// Interpreta un header `Range: bytes=...` per un blob di `size` byte.
+/// Sono supportati solo intervalli singoli; richieste multi-range ricevono
+/// l'intero blob, come consentito da RFC 9110.
+pub fn parse_range(value: &str, size: u64) -> RangeRequest {
+    let Some(spec) = value.trim().strip_prefix("bytes=") else {
+        return RangeRequest::Full;
+    };
+    if spec.contains(',') {
+        return RangeRequest::Full;
+    }
+    let Some((start, end)) = spec.trim().split_once('-') else {
+        return RangeRequest::Full;
+    };
+
+    let range = match (start.trim(), end.trim()) {
+        ("", "") => return RangeRequest::Full,
+        // bytes=-N: ultimi N byte
+        ("", suffix) => match suffix.parse::<u64>() {
+            Ok(0) => return RangeRequest::Unsatisfiable,
+            Ok(n) => ByteRange {
+                start: size.saturating_sub(n),
+                end: size.saturating_sub(1),
+            },
+            Err(_) => return RangeRequest::Full,
+        },
+        // bytes=N-: da N alla fine
+        (start, "") => match start.parse::<u64>() {
+            Ok(s) => ByteRange { start: s, end: size.saturating_sub(1) },
+            Err(_) => return RangeRequest::Full,
+        },
+        (start, end) => match (start.parse::<u64>(), end.parse::<u64>()) {
+            (Ok(s), Ok(e)) if s <= e => ByteRange { start: s, end: e.min(size.saturating_sub(1)) },
+            _ => return RangeRequest::Full,
+        },
+    };
+
+    if size == 0 || range.start >= size {
+        RangeRequest::Unsatisfiable
+    } else {
+        RangeRequest::Partial(range)
+    }
+}
+
+/// ETag forte per un blob immutabile identificato dal suo ID
+pub fn etag_for(blob_id: &str) -> String {
+    format!("\"{}\"", blob_id)
+}
+
+/// Verifica se un header If-None-Match corrisponde all'ETag
+fn etag_matches(header_value: &str, etag: &str) -> bool {
+    header_value.split(',').any(|tag| {
+        let tag = tag.trim();
+        tag == "*" || tag == etag || tag.strip_prefix("W/") == Some(etag)
+    })
+}
+
+fn not_modified(headers: &HeaderMap, etag: &str) -> bool {
+    headers.get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| etag_matches(v, etag))
+}
+
+/// Intervallo che verrà servito per un blob di `size` byte
+fn requested_range(headers: &HeaderMap, etag: &str, size: u64) -> RangeRequest {
+    let header_str = |name: header::HeaderName| headers.get(name).and_then(|v| v.to_str().ok());
+    // If-Range: il Range vale solo se il client ha ancora la stessa versione
+    let range_allowed = header_str(header::IF_RANGE).is_none_or(|v| v.trim() == etag);
+    match header_str(header::RANGE) {
+        Some(value) if range_allowed => parse_range(value, size),
+        _ => RangeRequest::Full,
+    }
+}
+
+/// Vero se la risposta a `headers` conterrà il primo o l'ultimo byte del
+/// blob: ogni download completo li scarica, anche se diviso in più range
+pub fn reaches_blob_edge(headers: &HeaderMap, blob_id: &str, size: u64) -> bool {
+    let etag = etag_for(blob_id);
+    if not_modified(headers, &etag) {
+        return false;
+    }
+    match requested_range(headers, &etag, size) {
+        RangeRequest::Full => true,
+        RangeRequest::Partial(range) => range.start == 0 || range.end + 1 >= size,
+        RangeRequest::Unsatisfiable => false,
+    }
+}
+
+/// Serve un blob immutabile in streaming, con supporto a Range, ETag e
+/// richieste condizionali (If-None-Match, If-Range)
+pub async fn serve_blob(
+    store: &dyn BlobStore,
+    env: &str,
+    blob_id: &str,
+    headers: &HeaderMap,
+) -> Result<Response, StatusCode> {
+    let size = match store.stat(env, blob_id).await {
+        Ok(stat) => stat.size,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            tracing::error!("Failed to stat blob {}/{}: {}", env, blob_id, e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    respond(blob_id, size, headers, |range| async move {
+        store.open(env, blob_id, range).await.map_err(|e| {
+            tracing::error!("Failed to open blob {}/{}: {}", env, blob_id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+    })
+    .await
+}
+
+/// Serve un blob già in memoria (ad esempio ricostruito dagli shard) con le
+/// stesse regole di `serve_blob`
+pub async fn serve_bytes(data: Bytes, blob_id: &str, headers: &HeaderMap) -> Result<Response, StatusCode> {
+    let size = data.len() as u64;
+    respond(blob_id, size, headers, |range| async move {
+        let data = match range {
+            Some((start, len)) => data.slice(start as usize..(start + len) as usize),
+            None => data,
+        };
+        Ok(Box::pin(std::io::Cursor::new(data)) as BlobReader)
+    })
+    .await
+}
+
+/// Risposta per un blob di `size` byte; `open` fornisce il contenuto, intero
+/// o nell'intervallo (inizio, lunghezza) richiesto
+async fn respond<F, Fut>(blob_id: &str, size: u64, headers: &HeaderMap, open: F) -> Result<Response, StatusCode>
+where
+    F: FnOnce(Option<(u64, u64)>) -> Fut,
+    Fut: Future<Output = Result<BlobReader, StatusCode>>,
+{
+    let etag = etag_for(blob_id);
+
+    if not_modified(headers, &etag) {
+        let mut resp = StatusCode::NOT_MODIFIED.into_response();
+        set_blob_headers(resp.headers_mut(), &etag);
+        return Ok(resp);
+    }
+
+    let mut resp = match requested_range(headers, &etag, size) {
+        RangeRequest::Full => {
+            let reader = open(None).await?;
+            let mut resp = Body::from_stream(ReaderStream::new(reader)).into_response();
+            resp.headers_mut().insert(header::CONTENT_LENGTH, HeaderValue::from(size));
+            resp
+        }
+        RangeRequest::Partial(range) => {
+            let reader = open(Some((range.start, range.len()))).await?;
+            let body = Body::from_stream(ReaderStream::new(reader));
+
+            let mut resp = (StatusCode::PARTIAL_CONTENT, body).into_response();
+            let content_range = format!("bytes {}-{}/{}", range.start, range.end, size);
+            resp.headers_mut().insert(header::CONTENT_LENGTH, HeaderValue::from(range.len()));
+            resp.headers_mut().insert(
+                header::CONTENT_RANGE,
+                HeaderValue::from_str(&content_range).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?,
+            );
+            resp
+        }
+        RangeRequest::Unsatisfiable => {
+            let mut resp = StatusCode::RANGE_NOT_SATISFIABLE.into_response();
+            let content_range = format!("bytes */{}", size);
+            resp.headers_mut().insert(
+                header::CONTENT_RANGE,
+                HeaderValue::from_str(&content_range).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?,
+            );
+            resp
+        }
+    };
+
+    set_blob_headers(resp.headers_mut(), &etag);
+    Ok(resp)
+}
+
+fn set_blob_headers(headers: &mut HeaderMap, etag: &str) {
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("application/octet-stream"));
+    headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("private, max-age=31536000, immutable"));
+    if let Ok(value) = HeaderValue::from_str(etag) {
+        headers.insert(header::ETAG, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_range(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::RANGE, HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    #[test]
+    fn ranges_reaching_either_end_are_downloads() {
+        assert!(reaches_blob_edge(&HeaderMap::new(), "ab", 100));
+        assert!(reaches_blob_edge(&with_range("bytes=0-9"), "ab", 100));
+        assert!(reaches_blob_edge(&with_range("bytes=90-"), "ab", 100));
+        assert!(reaches_blob_edge(&with_range("bytes=-10"), "ab", 100));
+        assert!(reaches_blob_edge(&with_range("bytes=50-500"), "ab", 100));
+        assert!(!reaches_blob_edge(&with_range("bytes=10-89"), "ab", 100));
+        assert!(!reaches_blob_edge(&with_range("bytes=100-"), "ab", 100));
+    }
+
+    #[test]
+    fn stale_if_range_serves_the_whole_blob() {
+        let mut headers = with_range("bytes=10-19");
+        headers.insert(header::IF_RANGE, HeaderValue::from_static("\"other\""));
+        assert!(reaches_blob_edge(&headers, "ab", 100));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::IF_NONE_MATCH, HeaderValue::from_str(&etag_for("ab")).unwrap());
+        assert!(!reaches_blob_edge(&headers, "ab", 100));
+    }
+}
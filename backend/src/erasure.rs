@@ -0,0 +1,616 @@
+use axum::body::Bytes;
+use rand::seq::SliceRandom;
+use reed_solomon_erasure::galois_8::ReedSolomon;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::io::ErrorKind;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+use crate::crypto::current_timestamp;
+use crate::discovery::DiscoveryManager;
+use crate::fsck;
+use crate::replication::{ReplicationManager, AUDIT_PENALTY};
+use crate::storage::BlobStore;
+use crate::trash;
+use crate::types::{KnownPeer, ShardLocation, ShardMap, VaultItem};
+use crate::upload;
+
+/// Mappe degli shard di un ambiente, salvate nello store accanto ai blob
+pub const SHARD_INDEX: &str = "shards.json";
+
+/// Ambiente di origine con cui gli shard viaggiano nelle richieste di replica
+pub const SHARD_ENVIRONMENT: &str = "shards";
+
+/// Configurazione della distribuzione in shard nel file node.json
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ErasureConfig {
+    /// Distribuisce i nuovi contenuti sui peer (le mappe esistenti vengono
+    /// comunque mantenute)
+    #[serde(default)]
+    pub enabled: bool,
+    /// Shard necessari per ricostruire un contenuto (k)
+    #[serde(default = "default_data_shards")]
+    pub data_shards: usize,
+    /// Shard di parità: quanti peer possono mancare (n - k)
+    #[serde(default = "default_parity_shards")]
+    pub parity_shards: usize,
+    /// I contenuti più piccoli restano in locale (byte)
+    #[serde(default = "default_min_blob_bytes")]
+    pub min_blob_bytes: u64,
+    /// I contenuti più grandi restano in locale: la ricostruzione avviene in memoria (byte)
+    #[serde(default = "default_max_blob_bytes")]
+    pub max_blob_bytes: u64,
+    /// Intervallo tra due passate di distribuzione e re-sharding (secondi)
+    #[serde(default = "default_interval_secs")]
+    pub interval_secs: u64,
+    /// Shard scaricati e verificati a ogni audit di un ambiente
+    #[serde(default = "default_audit_samples")]
+    pub audit_samples: usize,
+}
+
+impl Default for ErasureConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            data_shards: default_data_shards(),
+            parity_shards: default_parity_shards(),
+            min_blob_bytes: default_min_blob_bytes(),
+            max_blob_bytes: default_max_blob_bytes(),
+            interval_secs: default_interval_secs(),
+            audit_samples: default_audit_samples(),
+        }
+    }
+}
+
+fn default_data_shards() -> usize { 4 }
+fn default_parity_shards() -> usize { 2 }
+fn default_min_blob_bytes() -> u64 { 1024 * 1024 }
+fn default_max_blob_bytes() -> u64 { 64 * 1024 * 1024 }
+fn default_interval_secs() -> u64 { 3600 }
+fn default_audit_samples() -> usize { 8 }
+
+type TransportError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Errori nella ricostruzione di un contenuto
+#[derive(Debug)]
+pub enum ErasureError {
+    /// Meno shard integri raggiungibili di quelli necessari
+    Unavailable { available: usize, needed: usize },
+    Io(std::io::Error),
+}
+
+impl std::fmt::Display for ErasureError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ErasureError::Unavailable { available, needed } => {
+                write!(f, "only {} of {} required shards are reachable", available, needed)
+            }
+            ErasureError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<std::io::Error> for ErasureError {
+    fn from(e: std::io::Error) -> Self {
+        ErasureError::Io(e)
+    }
+}
+
+async fn load(store: &dyn BlobStore, env: &str) -> std::io::Result<Vec<ShardMap>> {
+    match store.get(env, SHARD_INDEX).await {
+        Ok(data) => serde_json::from_slice(&data).map_err(std::io::Error::other),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e),
+    }
+}
+
+async fn save(store: &dyn BlobStore, env: &str, maps: &[ShardMap]) -> std::io::Result<()> {
+    if maps.is_empty() {
+        return match store.delete(env, SHARD_INDEX).await {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        };
+    }
+    let data = serde_json::to_vec(maps).map_err(std::io::Error::other)?;
+    store.put(env, SHARD_INDEX, Bytes::from(data)).await
+}
+
+/// ID dei contenuti di un ambiente che esistono solo come shard sui peer
+pub async fn sharded_ids(store: &dyn BlobStore, env: &str) -> std::io::Result<HashSet<String>> {
+    Ok(load(store, env).await?.into_iter().map(|m| m.id).collect())
+}
+
+/// Chiave di uno shard nello store del peer
+fn shard_key(env: &str, content_id: &str, index: usize) -> String {
+    let digest = Sha256::digest(format!("{}:{}:{}", env, content_id, index).as_bytes());
+    hex::encode(&digest[..16])
+}
+
+fn shard_address(location: &ShardLocation) -> String {
+    format!("{}:{}", location.address, location.port)
+}
+
+/// Divide il contenuto in `k` shard di dati (l'ultimo completato con zeri) e
+/// aggiunge `m` shard di parità
+fn encode(data: &[u8], k: usize, m: usize) -> Result<Vec<Vec<u8>>, reed_solomon_erasure::Error> {
+    let rs = ReedSolomon::new(k, m)?;
+    let shard_size = data.len().div_ceil(k);
+    let mut shards: Vec<Vec<u8>> = (0..k + m)
+        .map(|i| {
+            let mut shard = if i < k {
+                data[(i * shard_size).min(data.len())..((i + 1) * shard_size).min(data.len())].to_vec()
+            } else {
+                Vec::new()
+            };
+            shard.resize(shard_size, 0);
+            shard
+        })
+        .collect();
+    rs.encode(&mut shards)?;
+    Ok(shards)
+}
+
+/// Ricostruisce tutti gli shard a partire da almeno `k` di essi
+fn rebuild(mut shards: Vec<Option<Vec<u8>>>, k: usize, m: usize) -> Result<Vec<Vec<u8>>, reed_solomon_erasure::Error> {
+    ReedSolomon::new(k, m)?.reconstruct(&mut shards)?;
+    Ok(shards.into_iter().map(Option::unwrap_or_default).collect())
+}
+
+/// Distribuzione dei contenuti sui peer conosciuti in shard Reed–Solomon
+/// k-di-n, così un gruppo di nodi piccoli può mettere in comune lo spazio.
+///
+/// Ogni contenuto completato viene diviso in `data_shards + parity_shards`
+/// shard posti su peer distinti del `DiscoveryManager`; la mappa viene
+/// salvata in `shards.json` prima di eliminare il blob locale. Le letture
+/// ricostruiscono il contenuto da qualsiasi `data_shards` shard integri; gli
+/// shard di peer spariti da `known_peers` vengono rigenerati su altri peer.
+/// Il trasporto è quello della replica.
+pub struct ErasureManager {
+    config: ErasureConfig,
+    /// Pubkey del nodo, che non può tenere shard propri
+    pubkey: String,
+    /// Serializza le modifiche a shards.json
+    lock: Mutex<()>,
+}
+
+impl ErasureManager {
+    pub fn new(config: ErasureConfig, pubkey: String) -> Self {
+        Self {
+            config,
+            pubkey,
+            lock: Mutex::new(()),
+        }
+    }
+
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.config.interval_secs.max(10))
+    }
+
+    /// Contenuti dell'ambiente distribuiti in shard
+    pub async fn list(&self, store: &dyn BlobStore, env: &str) -> std::io::Result<Vec<ShardMap>> {
+        load(store, env).await
+    }
+
+    /// Una passata su un ambiente: elimina gli shard dei contenuti che il
+    /// client non referenzia più, rigenera quelli di peer scomparsi e
+    /// distribuisce i nuovi contenuti. Restituisce quanti contenuti sono cambiati.
+    pub async fn distribute(
+        &self,
+        store: &dyn BlobStore,
+        replication: &ReplicationManager,
+        env: &str,
+        peers: &[KnownPeer],
+    ) -> std::io::Result<usize> {
+        let _guard = self.lock.lock().await;
+        let mut maps = load(store, env).await?;
+        let known: HashSet<&str> = peers.iter().map(|p| p.node.pubkey.as_str()).collect();
+        let candidates = self.candidates(peers);
+        let mut changed = 0;
+
+        if let Some(live) = fsck::load_live_set(store, env).await? {
+            let local: HashSet<String> = store.list(env).await?.into_iter().map(|b| b.key).collect();
+            let (stale, kept): (Vec<_>, Vec<_>) = maps.into_iter().partition(|m| {
+                !live.ids.contains(&m.id) && live.registered_at > m.created_at && !local.contains(&trash::trashed_key(&m.id))
+            });
+            maps = kept;
+            for map in stale {
+                Self::drop_shards(replication, &map).await;
+                changed += 1;
+            }
+        }
+
+        for map in &mut maps {
+            if map.shards.iter().all(|s| known.contains(s.peer.as_str())) {
+                continue;
+            }
+            match Self::reshard(replication, map, &candidates, &known).await {
+                Ok(0) => {}
+                Ok(n) => {
+                    tracing::info!("Re-sharded {}/{}: {} shards moved", env, map.id, n);
+                    changed += 1;
+                }
+                Err(e) => tracing::warn!("Re-sharding of {}/{} failed: {}", env, map.id, e),
+            }
+        }
+        save(store, env, &maps).await?;
+
+        let (k, m) = (self.config.data_shards, self.config.parity_shards);
+        if !self.config.enabled || candidates.len() < k + m {
+            return Ok(changed);
+        }
+        let sharded: HashSet<String> = maps.iter().map(|m| m.id.clone()).collect();
+        for staged in upload::list_uploads(env).await? {
+            let Some(item) = staged.meta.item else { continue };
+            if sharded.contains(&item.content_id) {
+                continue;
+            }
+            let Ok(stat) = store.stat(env, &item.content_id).await else { continue };
+            if stat.size < self.config.min_blob_bytes.max(1) || stat.size > self.config.max_blob_bytes {
+                continue;
+            }
+
+            let map = match self.shard(store, replication, env, item, &candidates[..k + m]).await {
+                Ok(map) => map,
+                Err(e) => {
+                    tracing::warn!("Sharding of {}/{} failed: {}", env, stat.key, e);
+                    continue;
+                }
+            };
+            maps.push(map);
+            save(store, env, &maps).await?;
+
+            // Solo ora che la mappa è salvata la copia locale non serve più
+            if let Err(e) = store.delete(env, &stat.key).await {
+                // Spostato nel cestino nel frattempo: gli shard non servono
+                if let Some(map) = maps.pop() {
+                    Self::drop_shards(replication, &map).await;
+                }
+                save(store, env, &maps).await?;
+                if e.kind() != ErrorKind::NotFound {
+                    return Err(e);
+                }
+                continue;
+            }
+            tracing::info!("Sharded {}/{} across {} peers", env, stat.key, k + m);
+            changed += 1;
+        }
+        Ok(changed)
+    }
+
+    /// Peer che possono ricevere shard, dal più affidabile
+    fn candidates<'a>(&self, peers: &'a [KnownPeer]) -> Vec<&'a KnownPeer> {
+        let mut candidates: Vec<&KnownPeer> = peers.iter()
+            .filter(|p| p.node.arson_port > 0 && p.node.pubkey != self.pubkey && p.failed_attempts < 3)
+            .collect();
+        candidates.sort_by(|a, b| {
+            b.trust_score.cmp(&a.trust_score)
+                .then(a.latency_ms.unwrap_or(u32::MAX).cmp(&b.latency_ms.unwrap_or(u32::MAX)))
+        });
+        candidates
+    }
+
+    /// Scarica un campione di shard dell'ambiente e ne verifica l'hash. I
+    /// peer che non restituiscono uno shard integro vengono penalizzati e i
+    /// loro shard rigenerati altrove. Restituisce quanti shard hanno fallito.
+    pub async fn audit(
+        &self,
+        store: &dyn BlobStore,
+        replication: &ReplicationManager,
+        discovery: &DiscoveryManager,
+        env: &str,
+    ) -> std::io::Result<usize> {
+        let _guard = self.lock.lock().await;
+        let mut maps = load(store, env).await?;
+        let samples: Vec<(usize, usize)> = {
+            let all: Vec<(usize, usize)> = maps.iter().enumerate()
+                .flat_map(|(m, map)| (0..map.shards.len()).map(move |s| (m, s)))
+                .collect();
+            all.choose_multiple(&mut rand::thread_rng(), self.config.audit_samples).copied().collect()
+        };
+        if samples.is_empty() {
+            return Ok(0);
+        }
+
+        // Peer che hanno fallito, per ciascuna mappa
+        let mut failed: HashMap<usize, HashSet<String>> = HashMap::new();
+        for (m, s) in samples {
+            let location = &maps[m].shards[s];
+            match replication.download(&shard_address(location), SHARD_ENVIRONMENT, &location.key).await {
+                Ok(data) if hex::encode(Sha256::digest(&data)) == location.hash => continue,
+                Ok(_) => tracing::warn!("Peer {} holds a corrupted shard of {}/{}", location.peer, env, maps[m].id),
+                Err(e) => tracing::warn!("Peer {} cannot serve a shard of {}/{}: {}", location.peer, env, maps[m].id, e),
+            }
+            discovery.penalize(&location.peer, AUDIT_PENALTY).await;
+            failed.entry(m).or_default().insert(location.peer.clone());
+        }
+        if failed.is_empty() {
+            return Ok(0);
+        }
+
+        let peers = discovery.get_known_peers().await;
+        let candidates = self.candidates(&peers);
+        let mut count = 0;
+        for (m, bad) in failed {
+            count += bad.len();
+            let known: HashSet<&str> = peers.iter()
+                .map(|p| p.node.pubkey.as_str())
+                .filter(|p| !bad.contains(*p))
+                .collect();
+            let map = &mut maps[m];
+            match Self::reshard(replication, map, &candidates, &known).await {
+                Ok(0) => {}
+                Ok(n) => tracing::info!("Re-sharded {}/{} after its audit: {} shards moved", env, map.id, n),
+                Err(e) => tracing::warn!("Re-sharding of {}/{} failed: {}", env, map.id, e),
+            }
+        }
+        save(store, env, &maps).await?;
+        Ok(count)
+    }
+
+    async fn shard(
+        &self,
+        store: &dyn BlobStore,
+        replication: &ReplicationManager,
+        env: &str,
+        item: VaultItem,
+        holders: &[&KnownPeer],
+    ) -> Result<ShardMap, TransportError> {
+        let (k, m) = (self.config.data_shards, self.config.parity_shards);
+        let data = store.get(env, &item.content_id).await?;
+        let size = data.len() as u64;
+        let shards = tokio::task::spawn_blocking(move || encode(&data, k, m)).await??;
+
+        let now = current_timestamp();
+        let mut map = ShardMap {
+            id: item.content_id.clone(),
+            item,
+            size,
+            data_shards: k,
+            parity_shards: m,
+            shard_size: shards[0].len() as u64,
+            shards: Vec::new(),
+            created_at: now,
+            updated_at: now,
+        };
+        for (index, (shard, holder)) in shards.into_iter().zip(holders).enumerate() {
+            let location = ShardLocation {
+                index,
+                key: shard_key(env, &map.id, index),
+                hash: hex::encode(Sha256::digest(&shard)),
+                peer: holder.node.pubkey.clone(),
+                address: holder.node.address.clone(),
+                port: holder.node.arson_port,
+            };
+            if let Err(e) = Self::store_shard(replication, &location, shard).await {
+                Self::drop_shards(replication, &map).await;
+                return Err(e);
+            }
+            map.shards.push(location);
+        }
+        Ok(map)
+    }
+
+    async fn store_shard(replication: &ReplicationManager, location: &ShardLocation, shard: Vec<u8>) -> Result<(), TransportError> {
+        let size = shard.len() as u64;
+        replication
+            .push(&shard_address(location), SHARD_ENVIRONMENT, &location.key, size, Box::pin(std::io::Cursor::new(shard)))
+            .await
+    }
+
+    /// Elimina gli shard di un contenuto; i peer irraggiungibili li tengono
+    async fn drop_shards(replication: &ReplicationManager, map: &ShardMap) {
+        for location in &map.shards {
+            if let Err(e) = replication.remove(&shard_address(location), SHARD_ENVIRONMENT, vec![location.key.clone()]).await {
+                tracing::debug!("Failed to drop shard {} from {}: {}", location.key, location.address, e);
+            }
+        }
+    }
+
+    /// Scarica shard integri finché bastano per la ricostruzione, saltando
+    /// quelli tenuti dai peer in `skip`, e li ricostruisce tutti
+    async fn reconstruct(
+        replication: &ReplicationManager,
+        map: &ShardMap,
+        skip: &HashSet<&str>,
+    ) -> Result<Vec<Vec<u8>>, ErasureError> {
+        let mut shards: Vec<Option<Vec<u8>>> = vec![None; map.data_shards + map.parity_shards];
+        let mut available = 0;
+        for location in &map.shards {
+            if available == map.data_shards {
+                break;
+            }
+            if skip.contains(location.peer.as_str()) || location.index >= shards.len() {
+                continue;
+            }
+            match replication.download(&shard_address(location), SHARD_ENVIRONMENT, &location.key).await {
+                Ok(data) if hex::encode(Sha256::digest(&data)) == location.hash => {
+                    shards[location.index] = Some(data);
+                    available += 1;
+                }
+                Ok(_) => tracing::warn!("Shard {} on {} is corrupted", location.key, location.address),
+                Err(e) => tracing::debug!("Shard {} unavailable on {}: {}", location.key, location.address, e),
+            }
+        }
+        if available < map.data_shards {
+            return Err(ErasureError::Unavailable { available, needed: map.data_shards });
+        }
+
+        let (k, m) = (map.data_shards, map.parity_shards);
+        tokio::task::spawn_blocking(move || rebuild(shards, k, m))
+            .await
+            .map_err(std::io::Error::other)?
+            .map_err(|e| ErasureError::Io(std::io::Error::other(format!("{:?}", e))))
+    }
+
+    fn join(map: &ShardMap, shards: Vec<Vec<u8>>) -> Bytes {
+        let mut data: Vec<u8> = shards.into_iter().take(map.data_shards).flatten().collect();
+        data.truncate(map.size as usize);
+        Bytes::from(data)
+    }
+
+    /// Rigenera gli shard dei peer scomparsi (o non più affidabili) su peer
+    /// che non ne hanno già uno; restituisce quanti ne ha spostati
+    async fn reshard(
+        replication: &ReplicationManager,
+        map: &mut ShardMap,
+        candidates: &[&KnownPeer],
+        known: &HashSet<&str>,
+    ) -> Result<usize, TransportError> {
+        let lost: Vec<usize> = (0..map.shards.len()).filter(|&i| !known.contains(map.shards[i].peer.as_str())).collect();
+        let holders: HashSet<&str> = map.shards.iter().map(|s| s.peer.as_str()).collect();
+        let replacements: Vec<&KnownPeer> = candidates.iter()
+            .filter(|p| !holders.contains(p.node.pubkey.as_str()))
+            .take(lost.len())
+            .copied()
+            .collect();
+        if replacements.len() < lost.len() {
+            tracing::debug!("Not enough peers to re-shard {}: {} needed", map.id, lost.len());
+            return Ok(0);
+        }
+
+        let skip: HashSet<&str> = lost.iter().map(|&i| map.shards[i].peer.as_str()).collect();
+        let shards = Self::reconstruct(replication, map, &skip).await.map_err(|e| e.to_string())?;
+
+        for (&i, holder) in lost.iter().zip(replacements) {
+            let location = ShardLocation {
+                peer: holder.node.pubkey.clone(),
+                address: holder.node.address.clone(),
+                port: holder.node.arson_port,
+                ..map.shards[i].clone()
+            };
+            Self::store_shard(replication, &location, shards[location.index].clone()).await?;
+            map.shards[i] = location;
+            map.updated_at = current_timestamp();
+        }
+        Ok(lost.len())
+    }
+
+    /// Ricostruisce un contenuto distribuito in shard; `None` se il contenuto
+    /// non è distribuito
+    pub async fn read(
+        &self,
+        store: &dyn BlobStore,
+        replication: &ReplicationManager,
+        env: &str,
+        content_id: &str,
+    ) -> Result<Option<Bytes>, ErasureError> {
+        let Some(map) = load(store, env).await?.into_iter().find(|m| m.id == content_id) else {
+            return Ok(None);
+        };
+        let shards = Self::reconstruct(replication, &map, &HashSet::new()).await?;
+        Ok(Some(Self::join(&map, shards)))
+    }
+
+    /// Riporta in locale i contenuti indicati ed elimina i loro shard, così
+    /// passano dal cestino come gli altri blob. Quelli non ricostruibili
+    /// restano distribuiti.
+    pub async fn unshard(
+        &self,
+        store: &dyn BlobStore,
+        replication: &ReplicationManager,
+        env: &str,
+        ids: &[String],
+    ) -> std::io::Result<()> {
+        let _guard = self.lock.lock().await;
+        let maps = load(store, env).await?;
+        if !maps.iter().any(|m| ids.contains(&m.id)) {
+            return Ok(());
+        }
+        let (selected, mut remaining): (Vec<_>, Vec<_>) = maps.into_iter().partition(|m| ids.contains(&m.id));
+
+        for map in selected {
+            let restored = match Self::reconstruct(replication, &map, &HashSet::new()).await {
+                Ok(shards) => store.put(env, &map.id, Self::join(&map, shards)).await.map_err(ErasureError::Io),
+                Err(e) => Err(e),
+            };
+            match restored {
+                Ok(()) => Self::drop_shards(replication, &map).await,
+                Err(e) => {
+                    tracing::warn!("Failed to bring {}/{} back from its shards: {}", env, map.id, e);
+                    remaining.push(map);
+                }
+            }
+        }
+        save(store, env, &remaining).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::replication::ReplicationConfig;
+
+    const K: usize = 4;
+    const M: usize = 2;
+
+    fn map(data: &[u8], shards: &[Vec<u8>]) -> ShardMap {
+        ShardMap {
+            id: "content".to_string(),
+            item: VaultItem {
+                id: "item".to_string(),
+                encrypted_name: Vec::new(),
+                name_nonce: Vec::new(),
+                item_type: "file".to_string(),
+                size: data.len(),
+                nonce: Vec::new(),
+                content_id: "content".to_string(),
+                preview_id: None,
+                merkle_root: None,
+                chunk_hashes: Vec::new(),
+                chunk_size: None,
+                search_tokens: Vec::new(),
+            },
+            size: data.len() as u64,
+            data_shards: K,
+            parity_shards: M,
+            shard_size: shards[0].len() as u64,
+            shards: shards.iter().enumerate().map(|(index, shard)| ShardLocation {
+                index,
+                key: shard_key("env", "content", index),
+                hash: hex::encode(Sha256::digest(shard)),
+                peer: format!("peer-{}", index),
+                address: "127.0.0.1".to_string(),
+                port: 1,
+            }).collect(),
+            created_at: 0,
+            updated_at: 0,
+        }
+    }
+
+    #[test]
+    fn content_survives_losing_parity_shards() {
+        // Lunghezza non multipla di K: l'ultimo shard di dati è completato con zeri
+        let data: Vec<u8> = (0..10_007u32).map(|i| (i * 31 % 251) as u8).collect();
+        let shards = encode(&data, K, M).unwrap();
+        assert_eq!(shards.len(), K + M);
+        let map = map(&data, &shards);
+
+        for lost in [vec![], vec![0], vec![K], vec![0, 1], vec![1, K + 1], vec![K, K + 1], vec![0, K - 1]] {
+            let partial = shards.iter().enumerate()
+                .map(|(i, s)| if lost.contains(&i) { None } else { Some(s.clone()) })
+                .collect();
+            let rebuilt = rebuild(partial, K, M).unwrap();
+            assert_eq!(rebuilt, shards, "lost {:?}", lost);
+            assert_eq!(ErasureManager::join(&map, rebuilt), data, "lost {:?}", lost);
+        }
+    }
+
+    #[tokio::test]
+    async fn losing_too_many_shards_is_unavailable() {
+        let data = vec![7u8; 4096];
+        let shards = encode(&data, K, M).unwrap();
+        let partial = shards.iter().enumerate().map(|(i, s)| (i > M).then(|| s.clone())).collect();
+        assert!(rebuild(partial, K, M).is_err());
+
+        // Gli shard dei peer rimasti non sono raggiungibili
+        let map = map(&data, &shards);
+        let replication = ReplicationManager::new(ReplicationConfig::default(), String::new(), String::new(), Vec::new());
+        let skip: HashSet<&str> = map.shards[..=M].iter().map(|s| s.peer.as_str()).collect();
+        match ErasureManager::reconstruct(&replication, &map, &skip).await {
+            Err(ErasureError::Unavailable { available: 0, needed: K }) => {}
+            other => panic!("expected Unavailable, got {:?}", other.map(|s| s.len())),
+        }
+    }
+}
@@ -0,0 +1,141 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::sync::{mpsc, RwLock};
+
+use crate::crypto::current_timestamp;
+use crate::types::{SessionClaims, VaultEvent, WsServerMessage};
+
+/// Connessione WebSocket iscritta agli eventi di un ambiente
+struct Subscriber {
+    connection: u64,
+    /// Sessione con cui si è iscritta: revocarla chiude l'iscrizione
+    session_id: String,
+    /// Utente della sessione, da escludere se lascia il team
+    pubkey: String,
+    /// Scadenza della sessione con cui si è iscritta
+    expires_at: u64,
+    tx: mpsc::UnboundedSender<WsServerMessage>,
+}
+
+/// Notifiche delle modifiche al vault verso le connessioni WebSocket.
+///
+/// Una connessione si iscrive a un ambiente con un token di sessione valido e
+/// riceve gli eventi emessi dagli handler del vault finché la sessione non
+/// scade o viene revocata, l'utente non lascia il team o la connessione non si
+/// chiude. Gli eventi portano solo ID e dati
+/// già cifrati, come le risposte delle API.
+#[derive(Default)]
+pub struct EventHub {
+    subscribers: RwLock<HashMap<String, Vec<Subscriber>>>,
+    next_connection: AtomicU64,
+}
+
+impl EventHub {
+    /// Identificativo per una nuova connessione WebSocket
+    pub fn connection_id(&self) -> u64 {
+        self.next_connection.fetch_add(1, Ordering::Relaxed)
+    }
+
+    pub async fn subscribe(&self, claims: &SessionClaims, connection: u64, tx: mpsc::UnboundedSender<WsServerMessage>) {
+        let mut subscribers = self.subscribers.write().await;
+        let list = subscribers.entry(claims.environment.clone()).or_default();
+        list.retain(|s| s.connection != connection);
+        list.push(Subscriber {
+            connection,
+            session_id: claims.session_id.clone(),
+            pubkey: claims.pubkey.clone(),
+            expires_at: claims.expires_at,
+            tx,
+        });
+    }
+
+    pub async fn unsubscribe(&self, env: &str, connection: u64) {
+        let mut subscribers = self.subscribers.write().await;
+        if let Some(list) = subscribers.get_mut(env) {
+            list.retain(|s| s.connection != connection);
+            if list.is_empty() {
+                subscribers.remove(env);
+            }
+        }
+    }
+
+    /// Chiude le iscrizioni fatte con una sessione revocata
+    pub async fn revoke_session(&self, session_id: &str) {
+        let mut subscribers = self.subscribers.write().await;
+        for list in subscribers.values_mut() {
+            list.retain(|s| s.session_id != session_id);
+        }
+        subscribers.retain(|_, list| !list.is_empty());
+    }
+
+    /// Chiude le iscrizioni all'ambiente di chi non è più tra `members`
+    pub async fn retain_members(&self, env: &str, members: &[&str]) {
+        let mut subscribers = self.subscribers.write().await;
+        if let Some(list) = subscribers.get_mut(env) {
+            list.retain(|s| members.contains(&s.pubkey.as_str()));
+            if list.is_empty() {
+                subscribers.remove(env);
+            }
+        }
+    }
+
+    /// Rimuove tutte le iscrizioni di una connessione chiusa
+    pub async fn disconnect(&self, connection: u64) {
+        let mut subscribers = self.subscribers.write().await;
+        for list in subscribers.values_mut() {
+            list.retain(|s| s.connection != connection);
+        }
+        subscribers.retain(|_, list| !list.is_empty());
+    }
+
+    /// Invia un evento agli iscritti dell'ambiente; le iscrizioni scadute o
+    /// di connessioni chiuse vengono rimosse
+    pub async fn publish(&self, env: &str, event: VaultEvent) {
+        let mut subscribers = self.subscribers.write().await;
+        let Some(list) = subscribers.get_mut(env) else {
+            return;
+        };
+        let now = current_timestamp();
+        let message = WsServerMessage::VaultEvent {
+            environment: env.to_string(),
+            event,
+        };
+        list.retain(|s| s.expires_at > now && s.tx.send(message.clone()).is_ok());
+        if list.is_empty() {
+            subscribers.remove(env);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn claims(session_id: &str, pubkey: &str) -> SessionClaims {
+        SessionClaims {
+            session_id: session_id.to_string(),
+            pubkey: pubkey.to_string(),
+            environment: "team".to_string(),
+            issued_at: 0,
+            expires_at: u64::MAX,
+        }
+    }
+
+    #[tokio::test]
+    async fn revoked_sessions_and_former_members_stop_receiving() {
+        let hub = EventHub::default();
+        let (alice_tx, mut alice) = mpsc::unbounded_channel();
+        let (bob_tx, mut bob) = mpsc::unbounded_channel();
+        hub.subscribe(&claims("s1", "alice"), 1, alice_tx).await;
+        hub.subscribe(&claims("s2", "bob"), 2, bob_tx).await;
+
+        hub.revoke_session("s1").await;
+        hub.publish("team", VaultEvent::MetadataRevision { revision: 1 }).await;
+        assert!(alice.try_recv().is_err());
+        assert!(bob.try_recv().is_ok());
+
+        hub.retain_members("team", &["alice"]).await;
+        hub.publish("team", VaultEvent::MetadataRevision { revision: 2 }).await;
+        assert!(bob.try_recv().is_err());
+    }
+}
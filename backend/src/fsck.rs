@@ -0,0 +1,304 @@
+use axum::body::Bytes;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::io::ErrorKind;
+
+use crate::capability;
+use crate::crypto::current_timestamp;
+use crate::erasure;
+use crate::gateway;
+use crate::inbox;
+use crate::journal;
+use crate::links;
+use crate::replication;
+use crate::share;
+use crate::storage::BlobStore;
+use crate::team;
+use crate::trash::{self, TrashManager};
+use crate::types::FsckReport;
+use crate::upload;
+
+/// Insieme degli ID referenziati registrato dal client, salvato nello store
+const LIVE_SET: &str = "live.json";
+
+/// Blob di servizio che non sono mai garbage
+const RESERVED_KEYS: &[&str] = &[
+    "metadata.enc",
+    LIVE_SET,
+    share::OUTGOING_INDEX,
+    share::INCOMING_INDEX,
+    links::LINK_INDEX,
+    team::TEAM_MANIFEST,
+    team::TEAM_INDEX,
+    capability::CAPABILITY_INDEX,
+    erasure::SHARD_INDEX,
+    replication::REPLICATION_INDEX,
+    gateway::OBJECT_INDEX,
+];
+
+/// Configurazione del garbage collector nel file node.json
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct GcConfig {
+    /// Esegue la GC periodica (altrimenti solo su richiesta)
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// La GC periodica si limita a registrare il resoconto senza eliminare nulla
+    #[serde(default)]
+    pub dry_run: bool,
+    /// Intervallo tra due passate (secondi)
+    #[serde(default = "default_interval_secs")]
+    pub interval_secs: u64,
+    /// Un upload non completato senza attività da questo tempo è abbandonato (secondi)
+    #[serde(default = "default_stale_upload_secs")]
+    pub stale_upload_secs: u64,
+}
+
+impl Default for GcConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_enabled(),
+            dry_run: false,
+            interval_secs: default_interval_secs(),
+            stale_upload_secs: default_stale_upload_secs(),
+        }
+    }
+}
+
+fn default_enabled() -> bool { true }
+fn default_interval_secs() -> u64 { 24 * 3600 }
+fn default_stale_upload_secs() -> u64 { 7 * 24 * 3600 }
+
+/// ID di contenuti e anteprime ancora presenti nei metadata del client
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LiveSet {
+    pub ids: HashSet<String>,
+    pub registered_at: u64,
+}
+
+/// Salva l'insieme degli ID vivi di un ambiente
+pub async fn register_live_set(store: &dyn BlobStore, env: &str, ids: Vec<String>) -> std::io::Result<LiveSet> {
+    let live = LiveSet {
+        ids: ids.into_iter().collect(),
+        registered_at: current_timestamp(),
+    };
+    let data = serde_json::to_vec(&live).map_err(std::io::Error::other)?;
+    store.put(env, LIVE_SET, Bytes::from(data)).await?;
+    Ok(live)
+}
+
+/// Insieme degli ID vivi registrato dal client, se c'è
+pub async fn load_live_set(store: &dyn BlobStore, env: &str) -> std::io::Result<Option<LiveSet>> {
+    match store.get(env, LIVE_SET).await {
+        Ok(data) => serde_json::from_slice(&data).map(Some).map_err(std::io::Error::other),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Analizza un ambiente senza modificare nulla.
+///
+/// - upload senza meta.json, o completati il cui contenuto non esiste più
+///   né nello store, né nel cestino, né in shard sui peer
+/// - upload non completati senza attività da `stale_upload_secs`
+/// - anteprime di upload abbandonati, o non più referenziate dal client
+/// - blob non referenziati dal client (solo se ha registrato il live set;
+///   i blob scritti dopo la registrazione sono esclusi)
+/// - blob di lunghezza zero
+pub async fn scan(store: &dyn BlobStore, env: &str, config: &GcConfig) -> std::io::Result<FsckReport> {
+    let now = current_timestamp();
+    let blobs: HashMap<String, (u64, u64)> = store.list(env).await?
+        .into_iter()
+        .map(|b| (b.key, (b.size, b.modified)))
+        .collect();
+    let live = load_live_set(store, env).await?;
+    let sharded = erasure::sharded_ids(store, env).await?;
+    // Gli oggetti S3 non compaiono nei metadata del client
+    let objects = gateway::object_ids(store, env).await?;
+
+    let mut report = FsckReport {
+        environment: env.to_string(),
+        scanned_at: now,
+        live_set_registered_at: live.as_ref().map(|l| l.registered_at),
+        ..Default::default()
+    };
+
+    for orphan in upload::orphaned_uploads(env).await? {
+        report.reclaimable_bytes += orphan.bytes;
+        report.orphaned_uploads.push(orphan.name);
+    }
+
+    // Blob che un upload ancora attivo potrà referenziare
+    let mut pending = HashSet::new();
+    let mut previews = HashSet::new();
+    for staged in upload::list_uploads(env).await? {
+        match &staged.meta.item {
+            // Completato dopo la registrazione: il client potrebbe non averlo ancora nei metadata
+            Some(item) if live.as_ref().is_some_and(|l| staged.last_activity >= l.registered_at) => {
+                pending.insert(item.content_id.clone());
+                pending.extend(item.preview_id.clone());
+            }
+            Some(item) => {
+                previews.extend(item.preview_id.clone());
+                let present = |id: &str| {
+                    blobs.contains_key(id)
+                        || blobs.contains_key(&trash::trashed_key(id))
+                        || blobs.contains_key(&inbox::inbox_key(id))
+                        || sharded.contains(id)
+                };
+                if !present(&item.content_id) {
+                    report.orphaned_uploads.push(staged.file_id.clone());
+                }
+            }
+            None if now.saturating_sub(staged.last_activity) >= config.stale_upload_secs => {
+                report.reclaimable_bytes += staged.received_bytes;
+                report.stale_uploads.push(staged.file_id.clone());
+                if let Some(preview_id) = &staged.meta.preview_id {
+                    // Un upload anonimo mai completato ha l'anteprima ancora nell'inbox
+                    let key = if staged.meta.wrapped_key.is_some() { inbox::inbox_key(preview_id) } else { preview_id.clone() };
+                    if let Some((size, _)) = blobs.get(&key) {
+                        report.reclaimable_bytes += size;
+                        report.unreferenced_previews.push(key);
+                    }
+                }
+            }
+            None => {
+                pending.insert(staged.meta.content_id.clone());
+                pending.extend(staged.meta.preview_id.clone());
+            }
+        }
+    }
+
+    for (key, (size, modified)) in &blobs {
+        if RESERVED_KEYS.contains(&key.as_str())
+            || trash::is_trash_key(key)
+            || inbox::is_inbox_key(key)
+            || journal::is_journal_key(key)
+            || objects.contains(key)
+            || pending.contains(key)
+        {
+            continue;
+        }
+        if report.unreferenced_previews.contains(key) {
+            continue;
+        }
+        if *size == 0 {
+            report.zero_length_blobs.push(key.clone());
+            continue;
+        }
+        let Some(live) = &live else { continue };
+        if live.ids.contains(key) || *modified >= live.registered_at {
+            continue;
+        }
+        report.reclaimable_bytes += size;
+        if previews.contains(key) {
+            report.unreferenced_previews.push(key.clone());
+        } else {
+            report.unreferenced_blobs.push(key.clone());
+        }
+    }
+
+    report.orphaned_uploads.sort();
+    report.stale_uploads.sort();
+    report.unreferenced_previews.sort();
+    report.unreferenced_blobs.sort();
+    report.zero_length_blobs.sort();
+    Ok(report)
+}
+
+/// Esegue la GC di un ambiente. Le directory di staging e i blob vuoti vengono
+/// rimossi; anteprime e blob non referenziati passano dal cestino, così un
+/// live set sbagliato resta recuperabile per tutto il periodo di ritenzione.
+pub async fn collect(
+    store: &dyn BlobStore,
+    trash: &TrashManager,
+    env: &str,
+    config: &GcConfig,
+    dry_run: bool,
+) -> std::io::Result<FsckReport> {
+    let mut report = scan(store, env, config).await?;
+    report.dry_run = dry_run;
+    if dry_run {
+        return Ok(report);
+    }
+
+    for name in &report.orphaned_uploads {
+        if let Err(e) = upload::remove_upload(env, name).await {
+            tracing::warn!("GC: failed to remove {}/{}: {}", env, name, e);
+        }
+    }
+    for file_id in &report.stale_uploads {
+        if let Err(e) = upload::remove_upload(env, file_id).await {
+            tracing::warn!("GC: failed to remove upload {}/{}: {}", env, file_id, e);
+        }
+    }
+    for key in &report.zero_length_blobs {
+        if let Err(e) = store.delete(env, key).await {
+            tracing::warn!("GC: failed to delete {}/{}: {}", env, key, e);
+        }
+    }
+
+    let unreferenced: Vec<String> = report.unreferenced_previews.iter()
+        .chain(&report.unreferenced_blobs)
+        .cloned()
+        .collect();
+    if !unreferenced.is_empty() {
+        trash.trash(store, env, &unreferenced).await?;
+    }
+
+    report.applied = true;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::TempStore;
+    use crate::trash::TrashConfig;
+
+    async fn keys(store: &dyn BlobStore, env: &str) -> Vec<String> {
+        let mut keys: Vec<String> = store.list(env).await.unwrap().into_iter().map(|b| b.key).collect();
+        keys.sort();
+        keys
+    }
+
+    #[tokio::test]
+    async fn gc_only_collects_unreferenced_blobs() {
+        let store = TempStore::new();
+        let env = format!("fsck-test-{}", hex::encode(crate::crypto::random_bytes::<8>()));
+        let trash = TrashManager::new(TrashConfig::default());
+        let journal_key = format!("{:016x}.delta", 1);
+        assert!(journal::is_journal_key(&journal_key));
+
+        for key in ["metadata.enc", "live", "orphan", journal_key.as_str(), &trash::trashed_key("deleted")] {
+            store.put(&env, key, Bytes::from_static(b"data")).await.unwrap();
+        }
+        store.put(&env, "empty", Bytes::new()).await.unwrap();
+        // Registrato dopo le scritture: i blob sopra sono giudicabili
+        let live = LiveSet { ids: HashSet::from(["live".to_string()]), registered_at: current_timestamp() + 10 };
+        store.put(&env, LIVE_SET, Bytes::from(serde_json::to_vec(&live).unwrap())).await.unwrap();
+        let before = keys(&*store, &env).await;
+
+        let report = collect(&*store, &trash, &env, &GcConfig::default(), true).await.unwrap();
+        assert!(report.dry_run && !report.applied);
+        assert_eq!(report.unreferenced_blobs, ["orphan"]);
+        assert_eq!(report.zero_length_blobs, ["empty"]);
+        assert_eq!(report.reclaimable_bytes, 4);
+        assert_eq!(keys(&*store, &env).await, before);
+
+        let report = collect(&*store, &trash, &env, &GcConfig::default(), false).await.unwrap();
+        assert!(report.applied);
+        assert_eq!(report.unreferenced_blobs, ["orphan"]);
+        let mut expected = vec![
+            "live".to_string(),
+            LIVE_SET.to_string(),
+            "metadata.enc".to_string(),
+            journal_key,
+            trash::trashed_key("deleted"),
+            trash::trashed_key("orphan"),
+            "trash.json".to_string(),
+        ];
+        expected.sort();
+        assert_eq!(keys(&*store, &env).await, expected);
+        assert_eq!(trash.list(&*store, &env).await.unwrap().iter().map(|e| e.id.as_str()).collect::<Vec<_>>(), ["orphan"]);
+    }
+}
@@ -0,0 +1,299 @@
+use axum::body::Bytes;
+use serde::{Deserialize, Serialize};
+use std::io::ErrorKind;
+use tokio::sync::Mutex;
+
+use crate::crypto::current_timestamp;
+use crate::storage::BlobStore;
+use crate::types::{InboxItem, VaultItem};
+use crate::upload;
+
+/// Indice dell'inbox di un ambiente, salvato nello store accanto ai blob
+pub const INBOX_INDEX: &str = "inbox.json";
+
+/// Suffisso dei blob ricevuti e non ancora accettati
+const INBOX_SUFFIX: &str = ".inbox";
+
+/// Configurazione delle inbox nel file node.json
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct InboxConfig {
+    /// Accetta upload anonimi verso le inbox
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// Dimensione massima di un elemento (contenuto + anteprima, byte)
+    #[serde(default = "default_max_item_bytes")]
+    pub max_item_bytes: u64,
+    /// Spazio massimo di un'inbox, upload in corso compresi (byte)
+    #[serde(default = "default_max_inbox_bytes")]
+    pub max_inbox_bytes: u64,
+    /// Per quanto tempo un elemento non accettato resta nell'inbox (secondi)
+    #[serde(default = "default_retention_secs")]
+    pub retention_secs: u64,
+}
+
+impl Default for InboxConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_enabled(),
+            max_item_bytes: default_max_item_bytes(),
+            max_inbox_bytes: default_max_inbox_bytes(),
+            retention_secs: default_retention_secs(),
+        }
+    }
+}
+
+fn default_enabled() -> bool { true }
+fn default_max_item_bytes() -> u64 { 100 * 1024 * 1024 }
+fn default_max_inbox_bytes() -> u64 { 1024 * 1024 * 1024 }
+fn default_retention_secs() -> u64 { 14 * 24 * 3600 }
+
+/// Chiave di un blob ricevuto nell'inbox
+pub fn inbox_key(blob_id: &str) -> String {
+    format!("{}{}", blob_id, INBOX_SUFFIX)
+}
+
+/// Vero per le chiavi dello store che appartengono all'inbox
+pub fn is_inbox_key(key: &str) -> bool {
+    key == INBOX_INDEX || key.ends_with(INBOX_SUFFIX)
+}
+
+/// Motivi per cui un upload anonimo non viene accettato
+#[derive(Debug)]
+pub enum InboxError {
+    /// Inbox disattivate sul nodo
+    Disabled,
+    /// Destinatario senza vault sul nodo. Va risposto come a un upload
+    /// accettato, per non rivelare quali ambienti esistono
+    NoRecipient,
+    /// Elemento oltre `max_item_bytes`
+    TooLarge,
+    /// Inbox oltre `max_inbox_bytes`
+    Full,
+    Io(std::io::Error),
+}
+
+impl std::fmt::Display for InboxError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InboxError::Disabled => write!(f, "inboxes are disabled"),
+            InboxError::NoRecipient => write!(f, "inbox not found"),
+            InboxError::TooLarge => write!(f, "item exceeds the inbox size limit"),
+            InboxError::Full => write!(f, "inbox is full"),
+            InboxError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<std::io::Error> for InboxError {
+    fn from(e: std::io::Error) -> Self {
+        InboxError::Io(e)
+    }
+}
+
+/// Inbox per ambiente, dove chiunque può caricare contenuti cifrati per la
+/// pubkey del proprietario.
+///
+/// Gli upload anonimi passano dalla stessa staging area del vault; al termine
+/// i blob restano con il suffisso `.inbox` e vengono registrati in
+/// `inbox.json`. Il destinatario li accetta, e allora vengono rinominati come
+/// normali blob del vault, oppure li rifiuta; quelli non accettati entro il
+/// periodo di ritenzione vengono eliminati da `expire`.
+pub struct InboxManager {
+    config: InboxConfig,
+    /// Serializza le modifiche a inbox.json
+    lock: Mutex<()>,
+}
+
+impl InboxManager {
+    pub fn new(config: InboxConfig) -> Self {
+        Self {
+            config,
+            lock: Mutex::new(()),
+        }
+    }
+
+    async fn load(store: &dyn BlobStore, env: &str) -> std::io::Result<Vec<InboxItem>> {
+        match store.get(env, INBOX_INDEX).await {
+            Ok(data) => serde_json::from_slice(&data).map_err(std::io::Error::other),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn save(store: &dyn BlobStore, env: &str, items: &[InboxItem]) -> std::io::Result<()> {
+        if items.is_empty() {
+            return match store.delete(env, INBOX_INDEX).await {
+                Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            };
+        }
+        let data = serde_json::to_vec(items).map_err(std::io::Error::other)?;
+        store.put(env, INBOX_INDEX, Bytes::from(data)).await
+    }
+
+    /// Verifica che un nuovo upload di `bytes` possa entrare nell'inbox
+    /// dell'ambiente. Solo chi ha già un vault sul nodo ha un'inbox; i limiti
+    /// che non dipendono dall'ambiente vengono controllati prima.
+    pub async fn admit(&self, store: &dyn BlobStore, env: &str, bytes: u64) -> Result<(), InboxError> {
+        if !self.config.enabled {
+            return Err(InboxError::Disabled);
+        }
+        if bytes > self.config.max_item_bytes {
+            return Err(InboxError::TooLarge);
+        }
+        if store.stat(env, "metadata.enc").await.is_err() {
+            return Err(InboxError::NoRecipient);
+        }
+
+        let received: u64 = Self::load(store, env).await?.iter().map(|i| i.size).sum();
+        let in_flight: u64 = upload::list_uploads(env).await?
+            .iter()
+            .filter(|u| u.meta.wrapped_key.is_some() && u.meta.item.is_none())
+            .map(|u| u.meta.size)
+            .sum();
+        if received + in_flight + bytes > self.config.max_inbox_bytes {
+            return Err(InboxError::Full);
+        }
+        Ok(())
+    }
+
+    /// Registra un upload anonimo completato
+    pub async fn receive(
+        &self,
+        store: &dyn BlobStore,
+        env: &str,
+        item: VaultItem,
+        wrapped_key: Vec<u8>,
+        size: u64,
+    ) -> std::io::Result<InboxItem> {
+        let _guard = self.lock.lock().await;
+        let mut items = Self::load(store, env).await?;
+        if let Some(existing) = items.iter().find(|i| i.id == item.content_id) {
+            return Ok(existing.clone());
+        }
+
+        let now = current_timestamp();
+        let received = InboxItem {
+            id: item.content_id.clone(),
+            item,
+            wrapped_key,
+            size,
+            received_at: now,
+            expires_at: now + self.config.retention_secs,
+        };
+        items.push(received.clone());
+        Self::save(store, env, &items).await?;
+        Ok(received)
+    }
+
+    /// Elementi nell'inbox di un ambiente
+    pub async fn list(&self, store: &dyn BlobStore, env: &str) -> std::io::Result<Vec<InboxItem>> {
+        Self::load(store, env).await
+    }
+
+    /// Sposta gli elementi indicati nel vault e li restituisce, così il client
+    /// può aggiungerli ai propri metadata
+    pub async fn accept(&self, store: &dyn BlobStore, env: &str, ids: &[String]) -> std::io::Result<Vec<InboxItem>> {
+        let _guard = self.lock.lock().await;
+        let items = Self::load(store, env).await?;
+        let (selected, mut remaining): (Vec<_>, Vec<_>) = items.into_iter().partition(|i| ids.contains(&i.id));
+
+        let mut accepted = Vec::new();
+        for received in selected {
+            let mut result = Ok(());
+            for blob_id in std::iter::once(&received.item.content_id).chain(&received.item.preview_id) {
+                result = store.rename(env, &inbox_key(blob_id), blob_id).await;
+                if result.is_err() {
+                    break;
+                }
+            }
+            if let Err(e) = result {
+                tracing::warn!("Failed to accept {} from inbox: {}", received.id, e);
+                remaining.push(received);
+                continue;
+            }
+
+            // Il meta.json riscritto conta come attività recente: la GC non
+            // giudica il contenuto finché il client non aggiorna il live set
+            let files = upload::staging_files(env, &received.item.id);
+            if let Ok(mut meta) = upload::read_meta(&files).await {
+                meta.wrapped_key = None;
+                if let Err(e) = upload::write_meta(&files, &meta).await {
+                    tracing::warn!("Failed to update upload {} after accepting it: {}", received.item.id, e);
+                }
+            }
+            accepted.push(received);
+        }
+
+        Self::save(store, env, &remaining).await?;
+        Ok(accepted)
+    }
+
+    /// Elimina gli elementi indicati senza accettarli e restituisce gli ID eliminati
+    pub async fn reject(&self, store: &dyn BlobStore, env: &str, ids: &[String]) -> std::io::Result<Vec<String>> {
+        let _guard = self.lock.lock().await;
+        let items = Self::load(store, env).await?;
+        let (selected, remaining): (Vec<_>, Vec<_>) = items.into_iter().partition(|i| ids.contains(&i.id));
+
+        let rejected = Self::delete_items(store, env, selected).await;
+        Self::save(store, env, &remaining).await?;
+        Ok(rejected)
+    }
+
+    /// Elimina gli elementi non accettati in tempo; restituisce quanti ne ha eliminati
+    pub async fn expire(&self, store: &dyn BlobStore, env: &str, now: u64) -> std::io::Result<usize> {
+        let _guard = self.lock.lock().await;
+        let items = Self::load(store, env).await?;
+        if items.iter().all(|i| i.expires_at > now) {
+            return Ok(0);
+        }
+        let (expired, remaining): (Vec<_>, Vec<_>) = items.into_iter().partition(|i| i.expires_at <= now);
+
+        let deleted = Self::delete_items(store, env, expired).await;
+        Self::save(store, env, &remaining).await?;
+        Ok(deleted.len())
+    }
+
+    async fn delete_items(store: &dyn BlobStore, env: &str, items: Vec<InboxItem>) -> Vec<String> {
+        let mut deleted = Vec::new();
+        for received in items {
+            for blob_id in std::iter::once(&received.item.content_id).chain(&received.item.preview_id) {
+                if let Err(e) = store.delete(env, &inbox_key(blob_id)).await {
+                    if e.kind() != ErrorKind::NotFound {
+                        tracing::warn!("Failed to delete {} from inbox: {}", blob_id, e);
+                    }
+                }
+            }
+            if let Err(e) = upload::remove_upload(env, &received.item.id).await {
+                if e.kind() != ErrorKind::NotFound {
+                    tracing::warn!("Failed to remove upload {} of inbox item: {}", received.item.id, e);
+                }
+            }
+            deleted.push(received.id);
+        }
+        deleted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::TempStore;
+
+    #[tokio::test]
+    async fn admission_does_not_reveal_environments() {
+        let store = TempStore::new();
+        let env = format!("inbox-test-{}", hex::encode(crate::crypto::random_bytes::<8>()));
+        let inbox = InboxManager::new(InboxConfig { max_item_bytes: 100, ..Default::default() });
+
+        // I limiti uguali per tutti vengono prima del controllo sul vault
+        assert!(matches!(inbox.admit(&*store, &env, 101).await, Err(InboxError::TooLarge)));
+        assert!(matches!(inbox.admit(&*store, &env, 10).await, Err(InboxError::NoRecipient)));
+
+        store.put(&env, "metadata.enc", Bytes::from_static(b"meta")).await.unwrap();
+        inbox.admit(&*store, &env, 10).await.unwrap();
+
+        let disabled = InboxManager::new(InboxConfig { enabled: false, ..Default::default() });
+        assert!(matches!(disabled.admit(&*store, &env, 10).await, Err(InboxError::Disabled)));
+    }
+}
@@ -0,0 +1,88 @@
+use sha2::{Digest, Sha256};
+
+/// Un hash dichiarato dal client è valido se è SHA-256 in esadecimale
+pub fn is_valid_hash(hash: &str) -> bool {
+    hash.len() == 64 && hash.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Radice Merkle sugli hash dei chunk (foglie, in ordine).
+///
+/// Le foglie sono `SHA-256(0x00 || hash)` e i nodi interni
+/// `SHA-256(0x01 || sinistro || destro)`, così una foglia non può passare per
+/// un nodo interno. Un nodo senza fratello sale al livello successivo invariato.
+pub fn merkle_root(leaves: &[String]) -> Option<String> {
+    let mut level: Vec<[u8; 32]> = leaves
+        .iter()
+        .map(|h| Some(hash_node(0x00, &[&hex::decode(h).ok()?.try_into().ok()?])))
+        .collect::<Option<_>>()?;
+    if level.is_empty() {
+        return None;
+    }
+
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => hash_node(0x01, &[left, right]),
+                [single] => *single,
+                _ => unreachable!(),
+            })
+            .collect();
+    }
+
+    Some(hex::encode(level[0]))
+}
+
+fn hash_node(prefix: u8, parts: &[&[u8; 32]]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update([prefix]);
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher.finalize().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaf(data: &[u8]) -> String {
+        hex::encode(Sha256::digest(data))
+    }
+
+    #[test]
+    fn known_answers() {
+        let leaves = [leaf(b"a"), leaf(b"b"), leaf(b"c")];
+        assert_eq!(
+            merkle_root(&leaves[..1]).unwrap(),
+            "a23bd5b06da9048238a65b3f1d9d0b9e15fae3dde262688e6489aa4c763d1820"
+        );
+        assert_eq!(
+            merkle_root(&leaves[..2]).unwrap(),
+            "ad5ca6cddc0b27c6a83e332bf28011769236e6c6a1f786ebf7b5267b37a5bd22"
+        );
+        assert_eq!(
+            merkle_root(&leaves).unwrap(),
+            "cac3d448d4e20a2ad5eae1f500e63c2a7f9217cd14572ba7fd22e26dc1ec2648"
+        );
+    }
+
+    #[test]
+    fn leaf_is_not_an_internal_node() {
+        let leaves = [leaf(b"a"), leaf(b"b")];
+        let level: Vec<[u8; 32]> = leaves
+            .iter()
+            .map(|h| hash_node(0x00, &[&hex::decode(h).unwrap().try_into().unwrap()]))
+            .collect();
+        // La radice di due foglie, presentata come foglia, dà un'altra radice
+        let inner = hex::encode(hash_node(0x01, &[&level[0], &level[1]]));
+        assert_ne!(merkle_root(&[inner]), merkle_root(&leaves));
+    }
+
+    #[test]
+    fn rejects_empty_and_malformed_leaves() {
+        assert_eq!(merkle_root(&[]), None);
+        assert_eq!(merkle_root(&["zz".repeat(32)]), None);
+        assert_eq!(merkle_root(&["ab".into()]), None);
+    }
+}
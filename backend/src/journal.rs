@@ -0,0 +1,412 @@
+use axum::body::Bytes;
+use serde::{Deserialize, Serialize};
+use std::io::ErrorKind;
+use tokio::sync::Mutex;
+
+use crate::storage::BlobStore;
+use crate::types::{JournalEntry, JournalKind, JournalRecord, MetadataJournal};
+
+/// Copia dell'ultimo snapshot, per i client che leggono i metadata interi
+/// e per repliche, inbox e quote che ne controllano la presenza
+pub const METADATA_KEY: &str = "metadata.enc";
+
+/// Suffissi dei blob del journal: `{revisione:016x}{suffisso}`
+const DELTA_SUFFIX: &str = ".delta";
+const SNAPSHOT_SUFFIX: &str = ".snapshot";
+
+/// Configurazione del journal nel file node.json
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct JournalConfig {
+    /// Snapshot conservati con i delta successivi; le revisioni più vecchie
+    /// vengono eliminate a ogni nuovo snapshot
+    #[serde(default = "default_snapshots_kept")]
+    pub snapshots_kept: usize,
+}
+
+impl Default for JournalConfig {
+    fn default() -> Self {
+        Self { snapshots_kept: default_snapshots_kept() }
+    }
+}
+
+fn default_snapshots_kept() -> usize { 10 }
+
+/// Chiave del blob di una revisione
+fn journal_key(revision: u64, kind: JournalKind) -> String {
+    let suffix = match kind {
+        JournalKind::Delta => DELTA_SUFFIX,
+        JournalKind::Snapshot => SNAPSHOT_SUFFIX,
+    };
+    format!("{:016x}{}", revision, suffix)
+}
+
+fn parse_key(key: &str) -> Option<(u64, JournalKind)> {
+    let (revision, kind) = if let Some(revision) = key.strip_suffix(DELTA_SUFFIX) {
+        (revision, JournalKind::Delta)
+    } else {
+        (key.strip_suffix(SNAPSHOT_SUFFIX)?, JournalKind::Snapshot)
+    };
+    if revision.len() != 16 {
+        return None;
+    }
+    u64::from_str_radix(revision, 16).ok().map(|r| (r, kind))
+}
+
+/// Vero per le chiavi dello store che appartengono al journal dei metadata
+pub fn is_journal_key(key: &str) -> bool {
+    parse_key(key).is_some()
+}
+
+/// Motivi per cui una scrittura del journal viene rifiutata
+#[derive(Debug)]
+pub enum JournalError {
+    /// La revisione di partenza del client non è più l'ultima
+    Conflict { current: u64 },
+    /// Revisione mai scritta
+    NotFound(u64),
+    /// Revisione eliminata da una compattazione
+    Compacted { oldest: u64 },
+    Io(std::io::Error),
+}
+
+impl std::fmt::Display for JournalError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JournalError::Conflict { current } => write!(f, "stale base revision, current is {}", current),
+            JournalError::NotFound(revision) => write!(f, "revision {} does not exist", revision),
+            JournalError::Compacted { oldest } => write!(f, "revisions before {} were compacted", oldest),
+            JournalError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<std::io::Error> for JournalError {
+    fn from(e: std::io::Error) -> Self {
+        JournalError::Io(e)
+    }
+}
+
+/// Journal dei metadata cifrati di un ambiente. Ogni salvataggio aggiunge una
+/// revisione, delta o snapshot, con numero crescente; le scritture indicano la
+/// revisione da cui partono e vengono rifiutate se nel frattempo ne è stata
+/// aggiunta un'altra. Il server non legge i metadata: applicare i delta è
+/// compito del client. Si conservano solo gli ultimi `snapshots_kept`
+/// snapshot con i delta che li seguono.
+#[derive(Default)]
+pub struct JournalManager {
+    config: JournalConfig,
+    /// Serializza le scritture, così il controllo della revisione è atomico
+    lock: Mutex<()>,
+}
+
+impl JournalManager {
+    pub fn new(config: JournalConfig) -> Self {
+        Self {
+            config,
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Revisioni presenti nello store, in ordine. Un metadata.enc scritto
+    /// prima del journal diventa la revisione 1.
+    async fn load(store: &dyn BlobStore, env: &str) -> std::io::Result<Vec<JournalEntry>> {
+        let blobs = store.list(env).await?;
+        let mut entries: Vec<JournalEntry> = blobs.iter()
+            .filter_map(|b| {
+                parse_key(&b.key).map(|(revision, kind)| JournalEntry {
+                    revision,
+                    kind,
+                    size: b.size,
+                    created_at: b.modified,
+                })
+            })
+            .collect();
+        entries.sort_by_key(|e| e.revision);
+
+        if entries.is_empty() {
+            if let Some(legacy) = blobs.iter().find(|b| b.key == METADATA_KEY) {
+                let data = store.get(env, METADATA_KEY).await?;
+                store.put(env, &journal_key(1, JournalKind::Snapshot), data).await?;
+                entries.push(JournalEntry {
+                    revision: 1,
+                    kind: JournalKind::Snapshot,
+                    size: legacy.size,
+                    created_at: legacy.modified,
+                });
+            }
+        }
+        Ok(entries)
+    }
+
+    async fn read_entry(store: &dyn BlobStore, env: &str, entry: &JournalEntry) -> std::io::Result<Bytes> {
+        store.get(env, &journal_key(entry.revision, entry.kind)).await
+    }
+
+    /// Scrive una revisione; gli snapshot aggiornano anche metadata.enc
+    async fn write_entry(
+        store: &dyn BlobStore,
+        env: &str,
+        revision: u64,
+        kind: JournalKind,
+        data: Bytes,
+    ) -> std::io::Result<()> {
+        store.put(env, &journal_key(revision, kind), data.clone()).await?;
+        if kind == JournalKind::Snapshot {
+            store.put(env, METADATA_KEY, data).await?;
+        }
+        Ok(())
+    }
+
+    fn check_base(entries: &[JournalEntry], base: Option<u64>) -> Result<u64, JournalError> {
+        let current = entries.last().map(|e| e.revision).unwrap_or(0);
+        match base {
+            Some(base) if base != current => Err(JournalError::Conflict { current }),
+            _ => Ok(current),
+        }
+    }
+
+    /// Elimina le revisioni precedenti al più vecchio snapshot da conservare
+    async fn prune(&self, store: &dyn BlobStore, env: &str) -> std::io::Result<()> {
+        let entries = Self::load(store, env).await?;
+        let Some(oldest_kept) = entries.iter()
+            .filter(|e| e.kind == JournalKind::Snapshot)
+            .rev()
+            .nth(self.config.snapshots_kept.max(1) - 1)
+        else {
+            return Ok(());
+        };
+
+        for entry in entries.iter().take_while(|e| e.revision < oldest_kept.revision) {
+            match store.delete(env, &journal_key(entry.revision, entry.kind)).await {
+                Err(e) if e.kind() != ErrorKind::NotFound => {
+                    tracing::warn!("Cannot remove revision {} of {}: {}", entry.revision, env, e);
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Byte di una nuova revisione da addebitare alla quota: nulla per uno
+    /// snapshot che non supera l'ultimo, così un ambiente pieno può ancora
+    /// salvare cancellazioni
+    pub async fn charged_bytes(&self, store: &dyn BlobStore, env: &str, kind: JournalKind, len: u64) -> std::io::Result<u64> {
+        if kind == JournalKind::Delta {
+            return Ok(len);
+        }
+        let _guard = self.lock.lock().await;
+        let entries = Self::load(store, env).await?;
+        match entries.iter().rev().find(|e| e.kind == JournalKind::Snapshot) {
+            Some(last) if len <= last.size => Ok(0),
+            _ => Ok(len),
+        }
+    }
+
+    /// Revisioni di un ambiente, dalla più vecchia ancora presente
+    pub async fn history(&self, store: &dyn BlobStore, env: &str) -> std::io::Result<Vec<JournalEntry>> {
+        let _guard = self.lock.lock().await;
+        Self::load(store, env).await
+    }
+
+    /// Ultima revisione del journal e ultimo snapshot con la sua revisione,
+    /// per i client che non usano i delta
+    pub async fn snapshot(&self, store: &dyn BlobStore, env: &str) -> std::io::Result<(u64, Option<(u64, Bytes)>)> {
+        let _guard = self.lock.lock().await;
+        let entries = Self::load(store, env).await?;
+        let head = entries.last().map(|e| e.revision).unwrap_or(0);
+        match entries.iter().rev().find(|e| e.kind == JournalKind::Snapshot) {
+            Some(entry) => Ok((head, Some((entry.revision, Self::read_entry(store, env, entry).await?)))),
+            None => Ok((head, None)),
+        }
+    }
+
+    /// Revisioni successive a `since`. Se tra queste c'è uno snapshot si parte
+    /// dall'ultimo, perché quelle precedenti non servono più al client.
+    pub async fn read(&self, store: &dyn BlobStore, env: &str, since: u64) -> std::io::Result<MetadataJournal> {
+        let _guard = self.lock.lock().await;
+        let entries = Self::load(store, env).await?;
+        let revision = entries.last().map(|e| e.revision).unwrap_or(0);
+
+        let mut tail: Vec<&JournalEntry> = entries.iter().filter(|e| e.revision > since).collect();
+        if let Some(start) = tail.iter().rposition(|e| e.kind == JournalKind::Snapshot) {
+            tail.drain(..start);
+        }
+
+        let mut records = Vec::new();
+        for entry in tail {
+            records.push(JournalRecord {
+                revision: entry.revision,
+                kind: entry.kind,
+                data: Self::read_entry(store, env, entry).await?.to_vec(),
+            });
+        }
+        Ok(MetadataJournal { revision, entries: records })
+    }
+
+    /// Aggiunge una revisione. Con `base` la scrittura riesce solo se è ancora
+    /// l'ultima revisione; senza, si accoda comunque (vecchi client).
+    pub async fn append(
+        &self,
+        store: &dyn BlobStore,
+        env: &str,
+        base: Option<u64>,
+        kind: JournalKind,
+        data: Bytes,
+    ) -> Result<u64, JournalError> {
+        let _guard = self.lock.lock().await;
+        let entries = Self::load(store, env).await?;
+        let revision = Self::check_base(&entries, base)? + 1;
+        Self::write_entry(store, env, revision, kind, data).await?;
+        if kind == JournalKind::Snapshot {
+            self.prune(store, env).await?;
+        }
+        Ok(revision)
+    }
+
+    /// Sostituisce la storia con uno snapshot scritto dal client: le revisioni
+    /// precedenti vengono eliminate e non si possono più ripristinare
+    pub async fn compact(&self, store: &dyn BlobStore, env: &str, base: u64, data: Bytes) -> Result<u64, JournalError> {
+        let _guard = self.lock.lock().await;
+        let entries = Self::load(store, env).await?;
+        let revision = Self::check_base(&entries, Some(base))? + 1;
+        Self::write_entry(store, env, revision, JournalKind::Snapshot, data).await?;
+
+        for entry in &entries {
+            match store.delete(env, &journal_key(entry.revision, entry.kind)).await {
+                Err(e) if e.kind() != ErrorKind::NotFound => {
+                    tracing::warn!("Cannot remove revision {} of {}: {}", entry.revision, env, e);
+                }
+                _ => {}
+            }
+        }
+        Ok(revision)
+    }
+
+    /// Riporta i metadata allo stato di `target` senza riscrivere la storia:
+    /// lo snapshot da cui dipende `target` e i delta successivi vengono
+    /// copiati in coda come nuove revisioni. Restituisce l'ultima.
+    pub async fn rollback(&self, store: &dyn BlobStore, env: &str, base: u64, target: u64) -> Result<u64, JournalError> {
+        let _guard = self.lock.lock().await;
+        let entries = Self::load(store, env).await?;
+        let mut revision = Self::check_base(&entries, Some(base))?;
+
+        let Some(end) = entries.iter().position(|e| e.revision == target) else {
+            return match entries.first() {
+                Some(oldest) if target < oldest.revision => Err(JournalError::Compacted { oldest: oldest.revision }),
+                _ => Err(JournalError::NotFound(target)),
+            };
+        };
+        let start = entries[..=end].iter().rposition(|e| e.kind == JournalKind::Snapshot);
+
+        // Senza snapshot i delta partono da un vault vuoto
+        if start.is_none() {
+            revision += 1;
+            Self::write_entry(store, env, revision, JournalKind::Snapshot, Bytes::new()).await?;
+        }
+        for entry in &entries[start.unwrap_or(0)..=end] {
+            let data = Self::read_entry(store, env, entry).await?;
+            revision += 1;
+            Self::write_entry(store, env, revision, entry.kind, data).await?;
+        }
+        self.prune(store, env).await?;
+        Ok(revision)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::TempStore;
+
+    const ENV: &str = "env";
+
+    fn revisions(journal: &MetadataJournal) -> Vec<(u64, JournalKind, &[u8])> {
+        journal.entries.iter().map(|e| (e.revision, e.kind, e.data.as_slice())).collect()
+    }
+
+    async fn append(journal: &JournalManager, store: &TempStore, base: Option<u64>, kind: JournalKind, data: &'static [u8]) -> u64 {
+        journal.append(&**store, ENV, base, kind, Bytes::from_static(data)).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn read_folds_from_the_last_snapshot() {
+        let (store, journal) = (TempStore::new(), JournalManager::default());
+        append(&journal, &store, Some(0), JournalKind::Snapshot, b"s1").await;
+        append(&journal, &store, Some(1), JournalKind::Delta, b"d2").await;
+        append(&journal, &store, Some(2), JournalKind::Snapshot, b"s3").await;
+        append(&journal, &store, Some(3), JournalKind::Delta, b"d4").await;
+
+        let all = journal.read(&*store, ENV, 0).await.unwrap();
+        assert_eq!(all.revision, 4);
+        assert_eq!(revisions(&all), [(3, JournalKind::Snapshot, &b"s3"[..]), (4, JournalKind::Delta, &b"d4"[..])]);
+
+        let tail = journal.read(&*store, ENV, 3).await.unwrap();
+        assert_eq!(revisions(&tail), [(4, JournalKind::Delta, &b"d4"[..])]);
+        assert!(journal.read(&*store, ENV, 4).await.unwrap().entries.is_empty());
+
+        // metadata.enc segue l'ultimo snapshot
+        assert_eq!(store.get(ENV, METADATA_KEY).await.unwrap(), Bytes::from_static(b"s3"));
+        assert_eq!(journal.snapshot(&*store, ENV).await.unwrap(), (4, Some((3, Bytes::from_static(b"s3")))));
+    }
+
+    #[tokio::test]
+    async fn stale_base_is_a_conflict() {
+        let (store, journal) = (TempStore::new(), JournalManager::default());
+        append(&journal, &store, Some(0), JournalKind::Snapshot, b"s1").await;
+        let stale = journal.append(&*store, ENV, Some(0), JournalKind::Delta, Bytes::from_static(b"d")).await;
+        assert!(matches!(stale, Err(JournalError::Conflict { current: 1 })));
+        // Senza base si accoda comunque
+        assert_eq!(append(&journal, &store, None, JournalKind::Delta, b"d2").await, 2);
+    }
+
+    #[tokio::test]
+    async fn legacy_metadata_becomes_revision_one() {
+        let (store, journal) = (TempStore::new(), JournalManager::default());
+        store.put(ENV, METADATA_KEY, Bytes::from_static(b"legacy")).await.unwrap();
+        let read = journal.read(&*store, ENV, 0).await.unwrap();
+        assert_eq!(revisions(&read), [(1, JournalKind::Snapshot, &b"legacy"[..])]);
+    }
+
+    #[tokio::test]
+    async fn compact_and_rollback() {
+        let (store, journal) = (TempStore::new(), JournalManager::default());
+        append(&journal, &store, Some(0), JournalKind::Delta, b"d1").await;
+        append(&journal, &store, Some(1), JournalKind::Delta, b"d2").await;
+
+        // Senza snapshot il rollback parte da un vault vuoto
+        assert_eq!(journal.rollback(&*store, ENV, 2, 1).await.unwrap(), 4);
+        let read = journal.read(&*store, ENV, 2).await.unwrap();
+        assert_eq!(revisions(&read), [(3, JournalKind::Snapshot, &b""[..]), (4, JournalKind::Delta, &b"d1"[..])]);
+
+        assert_eq!(journal.compact(&*store, ENV, 4, Bytes::from_static(b"s5")).await.unwrap(), 5);
+        let history = journal.history(&*store, ENV).await.unwrap();
+        assert_eq!(history.iter().map(|e| e.revision).collect::<Vec<_>>(), [5]);
+        assert!(matches!(journal.rollback(&*store, ENV, 5, 2).await, Err(JournalError::Compacted { oldest: 5 })));
+        assert!(matches!(journal.rollback(&*store, ENV, 5, 9).await, Err(JournalError::NotFound(9))));
+    }
+
+    #[tokio::test]
+    async fn keeps_only_the_last_snapshots() {
+        let store = TempStore::new();
+        let journal = JournalManager::new(JournalConfig { snapshots_kept: 2 });
+        append(&journal, &store, Some(0), JournalKind::Snapshot, b"s1").await;
+        append(&journal, &store, Some(1), JournalKind::Delta, b"d2").await;
+        append(&journal, &store, Some(2), JournalKind::Snapshot, b"s3").await;
+        append(&journal, &store, Some(3), JournalKind::Delta, b"d4").await;
+        append(&journal, &store, Some(4), JournalKind::Snapshot, b"s5").await;
+
+        let history = journal.history(&*store, ENV).await.unwrap();
+        assert_eq!(history.iter().map(|e| e.revision).collect::<Vec<_>>(), [3, 4, 5]);
+        assert!(matches!(journal.rollback(&*store, ENV, 5, 1).await, Err(JournalError::Compacted { oldest: 3 })));
+    }
+
+    #[tokio::test]
+    async fn smaller_snapshots_are_not_charged() {
+        let (store, journal) = (TempStore::new(), JournalManager::default());
+        assert_eq!(journal.charged_bytes(&*store, ENV, JournalKind::Snapshot, 4).await.unwrap(), 4);
+        append(&journal, &store, Some(0), JournalKind::Snapshot, b"four").await;
+        assert_eq!(journal.charged_bytes(&*store, ENV, JournalKind::Snapshot, 3).await.unwrap(), 0);
+        assert_eq!(journal.charged_bytes(&*store, ENV, JournalKind::Snapshot, 5).await.unwrap(), 5);
+        assert_eq!(journal.charged_bytes(&*store, ENV, JournalKind::Delta, 1).await.unwrap(), 1);
+    }
+}
@@ -0,0 +1,239 @@
+use argon2::password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use axum::body::Bytes;
+use serde::{Deserialize, Serialize};
+use std::io::ErrorKind;
+use tokio::sync::Mutex;
+
+use crate::storage::BlobStore;
+use crate::types::ShareLink;
+
+/// Link pubblici di un ambiente, salvati nello store accanto ai blob
+pub const LINK_INDEX: &str = "links.json";
+
+/// Errori nell'uso di un link pubblico
+#[derive(Debug)]
+pub enum LinkError {
+    /// Link inesistente o revocato
+    NotFound,
+    /// Link scaduto o con i download esauriti
+    Gone,
+    /// Password mancante o errata
+    BadPassword,
+    Io(std::io::Error),
+}
+
+impl std::fmt::Display for LinkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LinkError::NotFound => write!(f, "link not found"),
+            LinkError::Gone => write!(f, "link expired or exhausted"),
+            LinkError::BadPassword => write!(f, "wrong link password"),
+            LinkError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<std::io::Error> for LinkError {
+    fn from(e: std::io::Error) -> Self {
+        LinkError::Io(e)
+    }
+}
+
+/// Link con l'hash della password, così come viene salvato nell'indice
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct LinkRecord {
+    #[serde(flatten)]
+    link: ShareLink,
+    /// Hash Argon2 (formato PHC) della password, se il link ne ha una
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    password_hash: Option<String>,
+}
+
+/// Un link non è più utilizzabile se è scaduto o ha esaurito i download
+fn is_spent(link: &ShareLink, now: u64) -> bool {
+    link.expires_at.is_some_and(|t| t <= now) || link.max_downloads.is_some_and(|max| link.downloads >= max)
+}
+
+/// Link pubblici verso un contenuto del vault per chi non ha un account sul nodo.
+///
+/// Il link porta solo a ciphertext: la chiave viaggia nel fragment dell'URL e
+/// non arriva mai al nodo. Scadenza e numero massimo di download sono
+/// verificati a ogni richiesta; i link esauriti vengono rimossi da `expire`.
+#[derive(Default)]
+pub struct LinkManager {
+    /// Serializza le modifiche a links.json
+    lock: Mutex<()>,
+}
+
+impl LinkManager {
+    async fn load(store: &dyn BlobStore, env: &str) -> std::io::Result<Vec<LinkRecord>> {
+        match store.get(env, LINK_INDEX).await {
+            Ok(data) => serde_json::from_slice(&data).map_err(std::io::Error::other),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn save(store: &dyn BlobStore, env: &str, records: &[LinkRecord]) -> std::io::Result<()> {
+        if records.is_empty() {
+            return match store.delete(env, LINK_INDEX).await {
+                Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            };
+        }
+        let data = serde_json::to_vec(records).map_err(std::io::Error::other)?;
+        store.put(env, LINK_INDEX, Bytes::from(data)).await
+    }
+
+    /// Crea un link verso un contenuto dell'ambiente
+    pub async fn create(
+        &self,
+        store: &dyn BlobStore,
+        env: &str,
+        link: ShareLink,
+        password: Option<String>,
+    ) -> std::io::Result<ShareLink> {
+        let password_hash = match password {
+            Some(password) => Some(
+                tokio::task::spawn_blocking(move || {
+                    let salt = SaltString::generate(&mut OsRng);
+                    Argon2::default()
+                        .hash_password(password.as_bytes(), &salt)
+                        .map(|h| h.to_string())
+                        .map_err(|e| std::io::Error::other(e.to_string()))
+                })
+                .await
+                .map_err(std::io::Error::other)??,
+            ),
+            None => None,
+        };
+
+        let _guard = self.lock.lock().await;
+        let mut records = Self::load(store, env).await?;
+        records.push(LinkRecord { link: link.clone(), password_hash });
+        Self::save(store, env, &records).await?;
+        Ok(link)
+    }
+
+    /// Link ancora utilizzabili di un ambiente
+    pub async fn list(&self, store: &dyn BlobStore, env: &str, now: u64) -> std::io::Result<Vec<ShareLink>> {
+        Ok(Self::load(store, env).await?
+            .into_iter()
+            .map(|r| r.link)
+            .filter(|l| !is_spent(l, now))
+            .collect())
+    }
+
+    /// Revoca i link indicati e restituisce gli ID revocati
+    pub async fn revoke(&self, store: &dyn BlobStore, env: &str, ids: &[String]) -> std::io::Result<Vec<String>> {
+        let _guard = self.lock.lock().await;
+        let records = Self::load(store, env).await?;
+        let (revoked, remaining): (Vec<_>, Vec<_>) = records.into_iter().partition(|r| ids.contains(&r.link.id));
+        Self::save(store, env, &remaining).await?;
+        Ok(revoked.into_iter().map(|r| r.link.id).collect())
+    }
+
+    /// Elimina i link scaduti o esauriti; restituisce quanti ne ha rimossi
+    pub async fn expire(&self, store: &dyn BlobStore, env: &str, now: u64) -> std::io::Result<usize> {
+        let _guard = self.lock.lock().await;
+        let records = Self::load(store, env).await?;
+        let before = records.len();
+        let remaining: Vec<_> = records.into_iter().filter(|r| !is_spent(&r.link, now)).collect();
+        if remaining.len() != before {
+            Self::save(store, env, &remaining).await?;
+        }
+        Ok(before - remaining.len())
+    }
+
+    /// Autorizza una richiesta su un link senza consumare download
+    pub async fn authorize(
+        &self,
+        store: &dyn BlobStore,
+        env: &str,
+        id: &str,
+        password: Option<String>,
+        now: u64,
+    ) -> Result<ShareLink, LinkError> {
+        // La password si verifica fuori dal lock: Argon2 è volutamente lento
+        let record = Self::load(store, env).await?
+            .into_iter()
+            .find(|r| r.link.id == id)
+            .ok_or(LinkError::NotFound)?;
+        if is_spent(&record.link, now) {
+            return Err(LinkError::Gone);
+        }
+        if let Some(hash) = record.password_hash {
+            let password = password.ok_or(LinkError::BadPassword)?;
+            let valid = tokio::task::spawn_blocking(move || {
+                PasswordHash::new(&hash).is_ok_and(|h| Argon2::default().verify_password(password.as_bytes(), &h).is_ok())
+            })
+            .await
+            .map_err(std::io::Error::other)?;
+            if !valid {
+                return Err(LinkError::BadPassword);
+            }
+        }
+        Ok(record.link)
+    }
+
+    /// Consuma un download di un link già autorizzato, da restituire con
+    /// `release` se la richiesta poi non viene servita
+    pub async fn claim(&self, store: &dyn BlobStore, env: &str, id: &str, now: u64) -> Result<ShareLink, LinkError> {
+        let _guard = self.lock.lock().await;
+        let mut records = Self::load(store, env).await?;
+        let record = records.iter_mut().find(|r| r.link.id == id).ok_or(LinkError::NotFound)?;
+        if is_spent(&record.link, now) {
+            return Err(LinkError::Gone);
+        }
+        record.link.downloads += 1;
+        let link = record.link.clone();
+        Self::save(store, env, &records).await?;
+        Ok(link)
+    }
+
+    /// Restituisce un download consumato da `claim` per una richiesta non servita
+    pub async fn release(&self, store: &dyn BlobStore, env: &str, id: &str) -> std::io::Result<()> {
+        let _guard = self.lock.lock().await;
+        let mut records = Self::load(store, env).await?;
+        if let Some(record) = records.iter_mut().find(|r| r.link.id == id) {
+            record.link.downloads = record.link.downloads.saturating_sub(1);
+            Self::save(store, env, &records).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::TempStore;
+
+    fn link(max_downloads: Option<u32>) -> ShareLink {
+        ShareLink {
+            id: "l1".to_string(),
+            content_id: "c1".to_string(),
+            created_at: 0,
+            expires_at: None,
+            max_downloads,
+            downloads: 0,
+            protected: true,
+        }
+    }
+
+    #[tokio::test]
+    async fn authorize_checks_the_password_without_counting() {
+        let (store, links) = (TempStore::new(), LinkManager::default());
+        links.create(&*store, "env", link(Some(1)), Some("secret".to_string())).await.unwrap();
+
+        assert!(matches!(links.authorize(&*store, "env", "l1", None, 0).await, Err(LinkError::BadPassword)));
+        assert!(matches!(links.authorize(&*store, "env", "l1", Some("nope".to_string()), 0).await, Err(LinkError::BadPassword)));
+        let authorized = links.authorize(&*store, "env", "l1", Some("secret".to_string()), 0).await.unwrap();
+        assert_eq!(authorized.downloads, 0);
+
+        assert_eq!(links.claim(&*store, "env", "l1", 0).await.unwrap().downloads, 1);
+        assert!(matches!(links.claim(&*store, "env", "l1", 0).await, Err(LinkError::Gone)));
+        links.release(&*store, "env", "l1").await.unwrap();
+        assert!(links.authorize(&*store, "env", "l1", Some("secret".to_string()), 0).await.is_ok());
+    }
+}
@@ -1,8 +1,9 @@
 use axum::{
+    body::Body,
     extract::{ws::{Message, WebSocket, WebSocketUpgrade}, Path, Query, State},
-    http::StatusCode,
-    response::{IntoResponse, Json},
-    routing::{get, post},
+    http::{header, HeaderMap, HeaderValue, Method, StatusCode, Uri},
+    response::{IntoResponse, Json, Response},
+    routing::{any, get, post},
     Router,
 };
 use futures_util::{SinkExt, StreamExt};
@@ -15,13 +16,50 @@ use tower_http::services::{ServeDir, ServeFile};
 use tower_http::trace::TraceLayer;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
+mod archive;
+mod catalog;
+mod capability;
 mod crypto;
 mod discovery;
+mod download;
+mod erasure;
+mod events;
+mod fsck;
+mod gateway;
+mod inbox;
+mod integrity;
+mod journal;
+mod links;
 mod onion;
+mod quota;
+mod replication;
+mod s3;
+mod session;
+mod share;
+mod storage;
+mod team;
+mod trash;
 mod types;
+mod upload;
 
+use archive::ArchiveError;
+use catalog::Catalog;
+use capability::{CapabilityError, CapabilityManager, Caveats, Operation};
 use discovery::DiscoveryManager;
+use erasure::{ErasureError, ErasureManager};
+use events::EventHub;
+use gateway::{ObjectIndex, S3Error, S3Object};
+use inbox::{InboxError, InboxManager};
+use journal::{JournalError, JournalManager};
+use links::{LinkError, LinkManager};
 use onion::OnionRouter;
+use quota::{QuotaError, QuotaManager};
+use replication::{ReplicationError, ReplicationManager};
+use session::{SessionError, SessionManager};
+use share::ShareManager;
+use storage::BlobStore;
+use team::{TeamError, TeamManager};
+use trash::TrashManager;
 use types::*;
 
 // ============== APP STATE ==============
@@ -35,6 +73,36 @@ pub struct AppState {
     pub onion_router: Arc<OnionRouter>,
     /// Nodi connessi come relay client (node_pubkey -> ConnectionState)
     pub relay_clients: Arc<RwLock<HashMap<String, RelayClientState>>>,
+    /// Sessioni autenticate per le API del vault
+    pub sessions: Arc<SessionManager>,
+    /// Storage dei blob del vault
+    pub store: Arc<dyn BlobStore>,
+    /// Quote di spazio e contabilità dell'uso
+    pub quotas: Arc<QuotaManager>,
+    /// Cestino dei blob eliminati
+    pub trash: Arc<TrashManager>,
+    /// Inbox per gli upload anonimi
+    pub inbox: Arc<InboxManager>,
+    /// Condivisioni di elementi verso altri utenti
+    pub shares: Arc<ShareManager>,
+    /// Link pubblici ai contenuti
+    pub links: Arc<LinkManager>,
+    /// Membri e ruoli degli ambienti di team
+    pub teams: Arc<TeamManager>,
+    /// Capability delegate con caveat
+    pub capabilities: Arc<CapabilityManager>,
+    /// Replica degli ambienti sui peer
+    pub replication: Arc<ReplicationManager>,
+    /// Contenuti distribuiti in shard sui peer conosciuti
+    pub erasure: Arc<ErasureManager>,
+    /// Journal versionato dei metadata
+    pub journal: Arc<JournalManager>,
+    /// Notifiche delle modifiche al vault via WebSocket
+    pub events: Arc<EventHub>,
+    /// Nomi degli oggetti scritti tramite l'endpoint S3
+    pub objects: Arc<ObjectIndex>,
+    /// Catalogo SQLite di elementi e sessioni di upload
+    pub catalog: Arc<Catalog>,
 }
 
 /// Stato di un nodo connesso come relay client
@@ -58,6 +126,28 @@ pub struct RemotePeerConnection {
 
 impl AppState {
     pub fn new(node: Node, discovery: DiscoveryManager, onion_router: OnionRouter) -> Self {
+        let sessions = SessionManager::new(node.pubkey.clone(), node.privkey.clone());
+        let store = storage::from_config(&node.storage);
+        let quotas = QuotaManager::new(node.quotas.clone());
+        let trash = TrashManager::new(node.trash.clone());
+        let inbox = InboxManager::new(node.inbox.clone());
+        let capabilities = CapabilityManager::new(&node.privkey);
+        let replication = ReplicationManager::new(
+            node.replication.clone(),
+            node.pubkey.clone(),
+            node.privkey.clone(),
+            node.peers.clone(),
+        );
+        let erasure = ErasureManager::new(node.erasure.clone(), node.pubkey.clone());
+        let journal = JournalManager::new(node.metadata_journal.clone());
+        let catalog_path = std::path::Path::new(upload::STAGING_ROOT).join(catalog::CATALOG_FILE);
+        let catalog = Catalog::open(&catalog_path)
+            .or_else(|e| {
+                tracing::error!("Cannot open catalog {}: {}; keeping it in memory", catalog_path.display(), e);
+                Catalog::in_memory()
+            })
+            .expect("in-memory catalog");
+
         Self {
             node: Arc::new(RwLock::new(node)),
             connections: Arc::new(RwLock::new(HashMap::new())),
@@ -65,6 +155,21 @@ impl AppState {
             discovery: Arc::new(discovery),
             onion_router: Arc::new(onion_router),
             relay_clients: Arc::new(RwLock::new(HashMap::new())),
+            sessions: Arc::new(sessions),
+            store,
+            quotas: Arc::new(quotas),
+            trash: Arc::new(trash),
+            inbox: Arc::new(inbox),
+            shares: Arc::new(ShareManager::default()),
+            links: Arc::new(LinkManager::default()),
+            teams: Arc::new(TeamManager::default()),
+            capabilities: Arc::new(capabilities),
+            replication: Arc::new(replication),
+            erasure: Arc::new(erasure),
+            journal: Arc::new(journal),
+            events: Arc::new(EventHub::default()),
+            objects: Arc::new(ObjectIndex::default()),
+            catalog: Arc::new(catalog),
         }
     }
 
@@ -104,6 +209,15 @@ impl AppState {
                 ping_interval: 30,
                 relay_mode: false,
                 relay_node: None,
+                storage: storage::StorageConfig::default(),
+                quotas: quota::QuotaConfig::default(),
+                trash: trash::TrashConfig::default(),
+                gc: fsck::GcConfig::default(),
+                inbox: inbox::InboxConfig::default(),
+                replication: replication::ReplicationConfig::default(),
+                erasure: erasure::ErasureConfig::default(),
+                s3_gateway: gateway::GatewayConfig::default(),
+                metadata_journal: journal::JournalConfig::default(),
                 listen_port: 0,
                 public_port: 0,
             };
@@ -319,6 +433,247 @@ impl AppState {
         Err(last_error)
     }
 
+    /// Permanently deletes trash entries past their retention in every environment
+    pub async fn expire_trash(&self) {
+        let envs = match upload::environments().await {
+            Ok(envs) => envs,
+            Err(e) => {
+                tracing::warn!("Trash expiry: cannot list environments: {}", e);
+                return;
+            }
+        };
+
+        let now = crypto::current_timestamp();
+        for env in envs {
+            match self.trash.expire(self.store.as_ref(), &env, now).await {
+                Ok(0) => {}
+                Ok(n) => {
+                    tracing::info!("Expired {} trash entries in {}", n, env);
+                    self.quotas.invalidate(&env).await;
+                    self.catalog.invalidate(&env).await;
+                }
+                Err(e) => tracing::warn!("Trash expiry failed for {}: {}", env, e),
+            }
+        }
+    }
+
+    /// Deletes inbox items nobody accepted in time, in every environment
+    pub async fn expire_inbox(&self) {
+        let envs = match upload::environments().await {
+            Ok(envs) => envs,
+            Err(e) => {
+                tracing::warn!("Inbox expiry: cannot list environments: {}", e);
+                return;
+            }
+        };
+
+        let now = crypto::current_timestamp();
+        for env in envs {
+            match self.inbox.expire(self.store.as_ref(), &env, now).await {
+                Ok(0) => {}
+                Ok(n) => {
+                    self.quotas.invalidate(&env).await;
+                    self.catalog.invalidate(&env).await;
+                    tracing::info!("Expired {} inbox items in {}", n, env);
+                }
+                Err(e) => tracing::warn!("Inbox expiry failed for {}: {}", env, e),
+            }
+        }
+    }
+
+    /// Deletes S3 multipart uploads that were never completed, in every environment
+    pub async fn expire_multipart_uploads(&self) {
+        let envs = match upload::environments().await {
+            Ok(envs) => envs,
+            Err(e) => {
+                tracing::warn!("Multipart expiry: cannot list environments: {}", e);
+                return;
+            }
+        };
+
+        let ttl = self.node.read().await.s3_gateway.multipart_ttl_secs;
+        let now = crypto::current_timestamp();
+        for env in envs {
+            match gateway::expire_multipart(&env, now, ttl).await {
+                Ok(0) => {}
+                Ok(n) => {
+                    self.quotas.invalidate(&env).await;
+                    tracing::info!("Expired {} abandoned S3 uploads in {}", n, env);
+                }
+                Err(e) => tracing::warn!("Multipart expiry failed for {}: {}", env, e),
+            }
+        }
+    }
+
+    /// Drops expired share grants in every environment
+    pub async fn expire_shares(&self) {
+        let envs = match upload::environments().await {
+            Ok(envs) => envs,
+            Err(e) => {
+                tracing::warn!("Share expiry: cannot list environments: {}", e);
+                return;
+            }
+        };
+
+        let now = crypto::current_timestamp();
+        for env in envs {
+            match self.shares.expire(self.store.as_ref(), &env, now).await {
+                Ok(0) => {}
+                Ok(n) => tracing::info!("Expired {} share grants in {}", n, env),
+                Err(e) => tracing::warn!("Share expiry failed for {}: {}", env, e),
+            }
+        }
+    }
+
+    /// Drops expired and exhausted public links in every environment
+    pub async fn expire_links(&self) {
+        let envs = match upload::environments().await {
+            Ok(envs) => envs,
+            Err(e) => {
+                tracing::warn!("Link expiry: cannot list environments: {}", e);
+                return;
+            }
+        };
+
+        let now = crypto::current_timestamp();
+        for env in envs {
+            match self.links.expire(self.store.as_ref(), &env, now).await {
+                Ok(0) => {}
+                Ok(n) => tracing::info!("Expired {} public links in {}", n, env),
+                Err(e) => tracing::warn!("Link expiry failed for {}: {}", env, e),
+            }
+        }
+    }
+
+    /// Drops expired capabilities from every environment's index
+    pub async fn expire_capabilities(&self) {
+        let envs = match upload::environments().await {
+            Ok(envs) => envs,
+            Err(e) => {
+                tracing::warn!("Capability expiry: cannot list environments: {}", e);
+                return;
+            }
+        };
+
+        let now = crypto::current_timestamp();
+        for env in envs {
+            match self.capabilities.expire(self.store.as_ref(), &env, now).await {
+                Ok(0) => {}
+                Ok(n) => tracing::info!("Expired {} capabilities in {}", n, env),
+                Err(e) => tracing::warn!("Capability expiry failed for {}: {}", env, e),
+            }
+        }
+    }
+
+    /// Pushes every replicated environment to its peers and recovers lost blobs
+    pub async fn sync_replicas(&self) {
+        let envs = match upload::environments().await {
+            Ok(envs) => envs,
+            Err(e) => {
+                tracing::warn!("Replication: cannot list environments: {}", e);
+                return;
+            }
+        };
+
+        for env in envs {
+            match self.replication.sync(self.store.as_ref(), &env).await {
+                Ok(0) => {}
+                Ok(n) => {
+                    tracing::info!("Recovered {} blobs of {} from replicas", n, env);
+                    self.quotas.invalidate(&env).await;
+                }
+                Err(e) => tracing::warn!("Replication failed for {}: {}", env, e),
+            }
+        }
+    }
+
+    /// Challenges every replica to prove it still holds a sample of the blobs
+    /// pushed to it and checks a sample of the shards; failing peers lose
+    /// trust and get the blobs again, failing shards move to other peers
+    pub async fn audit_replicas(&self) {
+        let envs = match upload::environments().await {
+            Ok(envs) => envs,
+            Err(e) => {
+                tracing::warn!("Audit: cannot list environments: {}", e);
+                return;
+            }
+        };
+
+        for env in envs {
+            match self.replication.audit(self.store.as_ref(), &self.discovery, &env).await {
+                Ok(0) => {}
+                Ok(n) => tracing::warn!("{} blobs of {} failed their audit and were re-replicated", n, env),
+                Err(e) => tracing::warn!("Audit failed for {}: {}", env, e),
+            }
+            match self.erasure.audit(self.store.as_ref(), &self.replication, &self.discovery, &env).await {
+                Ok(0) => {}
+                Ok(n) => tracing::warn!("{} shards of {} failed their audit", n, env),
+                Err(e) => tracing::warn!("Shard audit failed for {}: {}", env, e),
+            }
+        }
+    }
+
+    /// Spreads new content over known peers as shards and re-shards content
+    /// whose holders left, in every environment
+    pub async fn distribute_shards(&self) {
+        let envs = match upload::environments().await {
+            Ok(envs) => envs,
+            Err(e) => {
+                tracing::warn!("Sharding: cannot list environments: {}", e);
+                return;
+            }
+        };
+
+        let peers = self.discovery.get_known_peers().await;
+        for env in envs {
+            match self.erasure.distribute(self.store.as_ref(), &self.replication, &env, &peers).await {
+                Ok(0) => {}
+                Ok(n) => {
+                    tracing::info!("Updated the shards of {} items in {}", n, env);
+                    self.quotas.invalidate(&env).await;
+                }
+                Err(e) => tracing::warn!("Sharding failed for {}: {}", env, e),
+            }
+        }
+    }
+
+    /// Runs the garbage collector over every environment
+    pub async fn run_gc(&self, config: &fsck::GcConfig) {
+        let envs = match upload::environments().await {
+            Ok(envs) => envs,
+            Err(e) => {
+                tracing::warn!("GC: cannot list environments: {}", e);
+                return;
+            }
+        };
+
+        for env in envs {
+            match fsck::collect(self.store.as_ref(), &self.trash, &env, config, config.dry_run).await {
+                Ok(report) => {
+                    let found = report.orphaned_uploads.len()
+                        + report.stale_uploads.len()
+                        + report.unreferenced_previews.len()
+                        + report.unreferenced_blobs.len()
+                        + report.zero_length_blobs.len();
+                    if found > 0 {
+                        tracing::info!(
+                            "GC {}: {} issues, {} bytes reclaimable{}",
+                            env,
+                            found,
+                            report.reclaimable_bytes,
+                            if report.dry_run { " (dry run)" } else { "" }
+                        );
+                    }
+                    if report.applied {
+                        self.quotas.invalidate(&env).await;
+                        self.catalog.invalidate(&env).await;
+                    }
+                }
+                Err(e) => tracing::warn!("GC failed for {}: {}", env, e),
+            }
+        }
+    }
+
     /// Check connectivity to configured peers (supports multiple protocols)
     pub async fn check_peer_connectivity(&self) {
         let peers_to_check: Vec<_> = {
@@ -349,13 +704,13 @@ impl AppState {
                 PeerProtocol::Arson => {
                     // Check via TCP connection
                     let addr = format!("{}:{}", peer.address, peer.arson_port);
-                    match tokio::time::timeout(
-                        std::time::Duration::from_secs(5),
-                        tokio::net::TcpStream::connect(&addr)
-                    ).await {
-                        Ok(Ok(_)) => true,
-                        _ => false,
-                    }
+                    matches!(
+                        tokio::time::timeout(
+                            std::time::Duration::from_secs(5),
+                            tokio::net::TcpStream::connect(&addr)
+                        ).await,
+                        Ok(Ok(_))
+                    )
                 },
             };
             
@@ -377,6 +732,85 @@ pub struct RelayMessage {
 
 // ============== MAIN ==============
 
+/// `export <environment> [archive.tar]` and `import <archive.tar> <environment>`
+/// against this node's store, for backups and migrations without a session
+async fn run_cli(args: &[String]) -> i32 {
+    const USAGE: &str = "usage: vault-backend export <environment> [archive.tar]\n       vault-backend import <archive.tar> <environment>";
+    const CONFIG_PATH: &str = "config/node.json";
+
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let (command, env, path) = match args.as_slice() {
+        ["export", env] => ("export", *env, format!("vault-{}.tar", env)),
+        ["export", env, path] => ("export", *env, path.to_string()),
+        ["import", path, env] => ("import", *env, path.to_string()),
+        _ => {
+            eprintln!("{}", USAGE);
+            return 2;
+        }
+    };
+    if !is_valid_blob_id(env) {
+        eprintln!("Invalid environment: {}", env);
+        return 2;
+    }
+    if !std::path::Path::new(CONFIG_PATH).exists() {
+        eprintln!("No node configured in this directory ({} not found)", CONFIG_PATH);
+        return 1;
+    }
+    let state = match AppState::load_or_create(CONFIG_PATH).await {
+        Ok(state) => state,
+        Err(e) => {
+            eprintln!("Failed to load the node: {}", e);
+            return 1;
+        }
+    };
+
+    if command == "export" {
+        let file = match tokio::fs::File::create(&path).await {
+            Ok(file) => file,
+            Err(e) => {
+                eprintln!("Cannot create {}: {}", path, e);
+                return 1;
+            }
+        };
+        let writer = tokio::io::BufWriter::new(file);
+        return match archive::export_environment(state.store.as_ref(), &state.erasure, &state.replication, env, writer).await {
+            Ok(()) => {
+                println!("Exported {} to {}", env, path);
+                0
+            }
+            Err(e) => {
+                eprintln!("Export failed: {}", e);
+                1
+            }
+        };
+    }
+
+    let file = match tokio::fs::File::open(&path).await {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("Cannot open {}: {}", path, e);
+            return 1;
+        }
+    };
+    let staged = match archive::stage(state.store.as_ref(), &state.quotas, env, tokio::io::BufReader::new(file)).await {
+        Ok(staged) => staged,
+        Err(e) => {
+            eprintln!("Import failed: {}", e);
+            return 1;
+        }
+    };
+    match archive::commit(state.store.as_ref(), &state.quotas, env, staged).await {
+        Ok(report) => {
+            println!("{}", serde_json::to_string_pretty(&report).unwrap_or_default());
+            i32::from(!report.conflicts.is_empty() || !report.corrupted.is_empty() || !report.missing.is_empty())
+        }
+        Err(e) => {
+            eprintln!("Import failed: {}", e);
+            1
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() {
     tracing_subscriber::registry()
@@ -386,6 +820,12 @@ async fn main() {
         .with(tracing_subscriber::fmt::layer())
         .init();
 
+    // Offline export/import subcommands instead of running the node
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        std::process::exit(run_cli(&args).await);
+    }
+
     let state = AppState::load_or_create("config/node.json")
         .await
         .expect("Failed to initialize state");
@@ -415,12 +855,67 @@ async fn main() {
     // Start TCP listener for P2P/Onion routing (Arson protocol)
     let onion_router = state.onion_router.clone();
     let discovery = state.discovery.clone();
+    let replication = state.replication.clone();
+    let store = state.store.clone();
     
     tokio::spawn(async move {
-        start_tcp_listener(arson_port, onion_router, discovery).await;
+        start_tcp_listener(arson_port, onion_router, discovery, replication, store).await;
     });
     println!("🔌 Arson TCP listener started on port {}", arson_port);
 
+    // Start trash, share grant, public link and abandoned upload expiry
+    let trash_state = state.clone();
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(trash_state.trash.sweep_interval()).await;
+            trash_state.expire_trash().await;
+            trash_state.expire_inbox().await;
+            trash_state.expire_shares().await;
+            trash_state.expire_links().await;
+            trash_state.expire_capabilities().await;
+            trash_state.expire_multipart_uploads().await;
+        }
+    });
+
+    // Start scheduled garbage collection
+    let gc_config = state.node.read().await.gc.clone();
+    if gc_config.enabled {
+        let gc_state = state.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(std::time::Duration::from_secs(gc_config.interval_secs.max(60))).await;
+                gc_state.run_gc(&gc_config).await;
+            }
+        });
+    }
+
+    // Start replication to peers
+    let replication_state = state.clone();
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(replication_state.replication.sync_interval()).await;
+            replication_state.sync_replicas().await;
+        }
+    });
+
+    // Start proof-of-retrievability audits of the replicas
+    let audit_state = state.clone();
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(audit_state.replication.audit_interval()).await;
+            audit_state.audit_replicas().await;
+        }
+    });
+
+    // Start erasure-coded distribution over known peers
+    let erasure_state = state.clone();
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(erasure_state.erasure.interval()).await;
+            erasure_state.distribute_shards().await;
+        }
+    });
+
     // Start peer connectivity checker
     let check_state = state.clone();
     tokio::spawn(async move {
@@ -430,10 +925,29 @@ async fn main() {
         }
     });
 
+    // Start the S3-compatible endpoint for backup tools
+    let gateway_config = state.node.read().await.s3_gateway.clone();
+    if gateway_config.enabled {
+        let addr = SocketAddr::from(([0, 0, 0, 0], gateway_config.port));
+        match TcpListener::bind(addr).await {
+            Ok(listener) => {
+                let app = s3_routes(state.clone()).layer(TraceLayer::new_for_http());
+                tokio::spawn(async move {
+                    if let Err(e) = axum::serve(listener, app).await {
+                        tracing::error!("S3 gateway stopped: {}", e);
+                    }
+                });
+                println!("🪣 S3 gateway listening on http://{}", addr);
+            }
+            Err(e) => tracing::error!("Cannot bind the S3 gateway to {}: {}", addr, e),
+        }
+    }
+
     let cors = CorsLayer::new()
         .allow_origin(Any)
         .allow_methods(Any)
-        .allow_headers(Any);
+        .allow_headers(Any)
+        .expose_headers(Any);
 
     // Serve static files from frontend/build with SPA fallback
     let static_dir = std::env::var("STATIC_DIR").unwrap_or_else(|_| "../frontend/build".to_string());
@@ -448,13 +962,58 @@ async fn main() {
 
     let api_routes = Router::new()
         // Vault API routes
+        .route("/api/auth/challenge", post(session_challenge_handler))
+        .route("/api/auth/session", post(open_session_handler))
+        .route("/api/auth/logout", post(logout_handler))
         .route("/api/start_upload", post(start_upload_handler))
         .route("/api/upload_chunk", post(upload_chunk_handler))
+        .route("/api/upload_status/{file_id}", get(upload_status_handler))
         .route("/api/finish_upload", post(finish_upload_handler))
+        .route("/api/uploads", get(list_uploads_handler))
+        .route("/api/items", get(list_items_handler))
+        .route("/api/search", post(search_handler))
         .route("/api/get_file/{env}/{file_id}", get(get_file_handler))
         .route("/api/get_preview/{env}/{file_id}", get(get_preview_handler))
         .route("/api/metadata/{env}", get(get_metadata_handler).post(save_metadata_handler))
+        .route("/api/metadata/{env}/journal", get(read_journal_handler).post(append_journal_handler))
+        .route("/api/metadata/{env}/history", get(metadata_history_handler))
+        .route("/api/metadata/{env}/rollback", post(rollback_metadata_handler))
+        .route("/api/metadata/{env}/compact", post(compact_metadata_handler))
         .route("/api/delete_files", post(delete_files_handler))
+        .route("/api/items/copy", post(copy_items_handler))
+        .route("/api/items/move", post(move_items_handler))
+        .route("/api/usage", get(usage_handler))
+        .route("/api/trash", get(list_trash_handler))
+        .route("/api/trash/restore", post(restore_trash_handler))
+        .route("/api/trash/purge", post(purge_trash_handler))
+        .route("/api/inbox", get(list_inbox_handler))
+        .route("/api/inbox/accept", post(accept_inbox_handler))
+        .route("/api/inbox/reject", post(reject_inbox_handler))
+        .route("/api/inbox/start", post(inbox_start_handler))
+        .route("/api/inbox/chunk", post(inbox_chunk_handler))
+        .route("/api/inbox/finish", post(inbox_finish_handler))
+        .route("/api/shares", get(list_shares_handler).post(create_share_handler))
+        .route("/api/shares/incoming", get(incoming_shares_handler))
+        .route("/api/shares/revoke", post(revoke_shares_handler))
+        .route("/api/links", get(list_links_handler).post(create_link_handler))
+        .route("/api/links/revoke", post(revoke_links_handler))
+        .route("/api/public/{env}/{link_id}", get(public_link_handler))
+        .route("/api/teams", get(list_teams_handler).post(create_team_handler))
+        .route("/api/teams/members", post(change_membership_handler))
+        .route("/api/teams/{env}", get(get_team_handler))
+        .route("/api/capabilities", get(list_capabilities_handler).post(create_capability_handler))
+        .route("/api/capabilities/revoke", post(revoke_capabilities_handler))
+        .route("/api/s3/credentials", post(create_s3_credentials_handler))
+        .route("/api/replication", get(list_replicas_handler).post(configure_replication_handler))
+        .route("/api/replication/sync", post(sync_replication_handler))
+        .route("/api/replication/audit", post(audit_replication_handler))
+        .route("/api/shards", get(list_shards_handler))
+        .route("/api/fsck", get(fsck_handler))
+        .route("/api/gc", post(gc_handler))
+        .route("/api/gc/live", post(register_live_handler))
+        .route("/api/export", get(export_handler))
+        .route("/api/import", post(import_handler))
+        .route("/api/archive", post(archive_items_handler))
         // P2P routes
         .route("/p2p/ws", get(ws_handler))
         .route("/p2p/info", get(node_info_handler))
@@ -493,6 +1052,8 @@ async fn start_tcp_listener(
     port: u16,
     onion_router: Arc<OnionRouter>,
     discovery: Arc<DiscoveryManager>,
+    replication: Arc<ReplicationManager>,
+    store: Arc<dyn BlobStore>,
 ) {
     let addr = SocketAddr::from(([0, 0, 0, 0], port));
     
@@ -511,9 +1072,11 @@ async fn start_tcp_listener(
             Ok((stream, peer_addr)) => {
                 let router = onion_router.clone();
                 let disc = discovery.clone();
+                let replication = replication.clone();
+                let store = store.clone();
                 
                 tokio::spawn(async move {
-                    if let Err(e) = handle_tcp_connection(stream, router, disc, peer_addr).await {
+                    if let Err(e) = handle_tcp_connection(stream, router, disc, replication, store, peer_addr).await {
                         tracing::debug!("TCP connection error from {}: {}", peer_addr, e);
                     }
                 });
@@ -529,6 +1092,8 @@ async fn handle_tcp_connection(
     mut stream: tokio::net::TcpStream,
     onion_router: Arc<OnionRouter>,
     discovery: Arc<DiscoveryManager>,
+    replication: Arc<ReplicationManager>,
+    store: Arc<dyn BlobStore>,
     peer_addr: SocketAddr,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     use tokio::io::{AsyncReadExt, AsyncWriteExt};
@@ -603,6 +1168,22 @@ async fn handle_tcp_connection(
                 stream.write_all(&response_bytes).await?;
             }
         }
+        NodePacket::Replication(message) => {
+            // Richiesta di replica da un peer configurato
+            let response = replication.handle(store.as_ref(), &discovery, message).await;
+            let response_bytes = bincode::serialize(&NodePacket::Replication(response))?;
+            let len = response_bytes.len() as u32;
+            stream.write_all(&len.to_be_bytes()).await?;
+            stream.write_all(&response_bytes).await?;
+        }
+        NodePacket::Audit(message) => {
+            // Sfida su una replica conservata per il mittente
+            let response = replication.handle_audit(store.as_ref(), &discovery, message).await;
+            let response_bytes = bincode::serialize(&NodePacket::Audit(response))?;
+            let len = response_bytes.len() as u32;
+            stream.write_all(&len.to_be_bytes()).await?;
+            stream.write_all(&response_bytes).await?;
+        }
         NodePacket::OnionResponse(_) => {
             // Le risposte vengono gestite dal chiamante
             tracing::debug!("Received unexpected OnionResponse");
@@ -613,183 +1194,2517 @@ async fn handle_tcp_connection(
     Ok(())
 }
 
-// ============== VAULT HANDLERS ==============
-
-async fn start_upload_handler(
-    Json(req): Json<StartUploadRequest>,
-) -> Result<Json<StartUploadResponse>, StatusCode> {
-    use rand::Rng;
-
-    let environment = req.session_token.split('_').next().unwrap_or("personal");
-    let vault_dir = format!("vault_data/{}", environment);
-
-    std::fs::create_dir_all(&vault_dir).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    let mut rng = rand::thread_rng();
-    let file_id: String = (0..16).map(|_| format!("{:x}", rng.gen_range(0..16))).collect();
-    let content_id: String = (0..32).map(|_| format!("{:x}", rng.gen_range(0..16))).collect();
-
-    let temp_dir = format!("{}/{}_chunks", vault_dir, file_id);
-    std::fs::create_dir_all(&temp_dir).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    let mut preview_id = None;
-
-    if let (Some(preview), Some(preview_nonce)) = (&req.preview, &req.preview_nonce) {
-        let pid: String = (0..32).map(|_| format!("{:x}", rng.gen_range(0..16))).collect();
-        let preview_path = format!("{}/{}", vault_dir, pid);
+// ============== SESSION HANDLERS ==============
 
-        let mut file_data = Vec::with_capacity(preview_nonce.len() + preview.len());
-        file_data.extend_from_slice(preview_nonce);
-        file_data.extend_from_slice(preview);
+async fn session_challenge_handler(
+    State(state): State<AppState>,
+    Json(req): Json<SessionChallengeRequest>,
+) -> Json<SessionChallenge> {
+    Json(state.sessions.issue_challenge(&req.pubkey).await)
+}
 
-        std::fs::write(&preview_path, file_data).ok();
-        preview_id = Some(pid);
+async fn open_session_handler(
+    State(state): State<AppState>,
+    Json(req): Json<OpenSessionRequest>,
+) -> Result<Json<SessionResponse>, StatusCode> {
+    if req.environment.as_deref().is_some_and(|env| !team::is_team_env(env)) {
+        return Err(StatusCode::BAD_REQUEST);
     }
+    let (session_token, claims) = state.sessions
+        .open_session(&req.challenge_id, &req.signature, req.environment)
+        .await
+        .map_err(session_error_status)?;
 
-    let meta = serde_json::json!({
-        "encrypted_name": req.encrypted_name,
-        "name_nonce": req.name_nonce,
-        "item_type": req.item_type,
-        "nonce": req.nonce,
-        "total_chunks": req.total_chunks,
-        "content_id": content_id,
-        "preview_id": preview_id
-    });
-    std::fs::write(format!("{}/meta.json", temp_dir), meta.to_string())
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    Ok(Json(StartUploadResponse { file_id }))
-}
+    let Some(role) = member_role(&state, &claims, &claims.environment).await? else {
+        state.sessions.revoke(&claims).await;
+        return Err(StatusCode::FORBIDDEN);
+    };
 
-#[derive(Deserialize)]
-struct UploadChunkQuery {
-    token: String,
-    file_id: String,
-    chunk: usize,
+    Ok(Json(SessionResponse {
+        session_token,
+        environment: claims.environment,
+        role,
+        expires_at: claims.expires_at,
+    }))
 }
 
-async fn upload_chunk_handler(
-    Query(params): Query<UploadChunkQuery>,
-    body: axum::body::Bytes,
+async fn logout_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<TokenQuery>,
 ) -> Result<Json<serde_json::Value>, StatusCode> {
-    let environment = params.token.split('_').next().unwrap_or("personal");
-    let chunk_path = format!(
-        "vault_data/{}/{}_chunks/{}.chunk",
-        environment, params.file_id, params.chunk
-    );
-
-    std::fs::write(&chunk_path, &body).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let token = request_token(&headers, query.token.as_deref()).ok_or(StatusCode::UNAUTHORIZED)?;
+    let claims = state.sessions.validate(&token).await.map_err(session_error_status)?;
+    state.sessions.revoke(&claims).await;
+    state.events.revoke_session(&claims.session_id).await;
 
     Ok(Json(serde_json::json!({ "success": true })))
 }
 
-async fn finish_upload_handler(
-    Json(req): Json<FinishUploadRequest>,
-) -> Result<Json<UploadResult>, StatusCode> {
-    use std::io::Write;
+fn session_error_status(e: SessionError) -> StatusCode {
+    match e {
+        SessionError::Internal(msg) => {
+            tracing::error!("Session error: {}", msg);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+        _ => StatusCode::UNAUTHORIZED,
+    }
+}
 
-    let environment = req.session_token.split('_').next().unwrap_or("personal");
-    let vault_dir = format!("vault_data/{}", environment);
-    let temp_dir = format!("{}/{}_chunks", vault_dir, req.file_id);
+fn link_error_status(e: LinkError) -> StatusCode {
+    match e {
+        LinkError::NotFound => StatusCode::NOT_FOUND,
+        LinkError::Gone => StatusCode::GONE,
+        LinkError::BadPassword => StatusCode::UNAUTHORIZED,
+        LinkError::Io(e) => {
+            tracing::error!("Link lookup failed: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
 
-    let meta_str = std::fs::read_to_string(format!("{}/meta.json", temp_dir))
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    let meta: serde_json::Value =
-        serde_json::from_str(&meta_str).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+fn team_error_status(e: TeamError) -> StatusCode {
+    match e {
+        TeamError::NotFound => StatusCode::NOT_FOUND,
+        TeamError::Forbidden => StatusCode::FORBIDDEN,
+        TeamError::Conflict => StatusCode::CONFLICT,
+        TeamError::Invalid => StatusCode::BAD_REQUEST,
+        TeamError::BadSignature => StatusCode::UNAUTHORIZED,
+        TeamError::Io(e) => {
+            tracing::error!("Team update failed: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
 
-    let total_chunks = meta["total_chunks"].as_u64().unwrap_or(0) as usize;
-    let content_id = meta["content_id"].as_str().unwrap_or("").to_string();
+fn capability_error_status(e: CapabilityError) -> StatusCode {
+    match e {
+        CapabilityError::BadCaveat => StatusCode::BAD_REQUEST,
+        CapabilityError::Io(e) => {
+            tracing::error!("Capability lookup failed: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+        _ => StatusCode::UNAUTHORIZED,
+    }
+}
 
-    let file_path = format!("{}/{}", vault_dir, content_id);
-    let mut output = std::fs::File::create(&file_path).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+fn inbox_error_status(e: InboxError) -> StatusCode {
+    match e {
+        InboxError::Disabled | InboxError::NoRecipient => StatusCode::NOT_FOUND,
+        InboxError::TooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+        InboxError::Full => StatusCode::INSUFFICIENT_STORAGE,
+        InboxError::Io(e) => {
+            tracing::error!("Inbox check failed: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
 
-    let mut total_size = 0usize;
-    for i in 0..total_chunks {
-        let chunk_path = format!("{}/{}.chunk", temp_dir, i);
-        let chunk_data = std::fs::read(&chunk_path).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-        total_size += chunk_data.len();
-        output.write_all(&chunk_data).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+fn replication_error_status(e: ReplicationError) -> StatusCode {
+    match e {
+        ReplicationError::UnknownPeer(_) => StatusCode::BAD_REQUEST,
+        ReplicationError::Io(e) => {
+            tracing::error!("Replication index update failed: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
     }
+}
 
-    std::fs::remove_dir_all(&temp_dir).ok();
+fn journal_error_status(e: JournalError) -> StatusCode {
+    match e {
+        JournalError::Conflict { .. } => StatusCode::CONFLICT,
+        JournalError::NotFound(_) => StatusCode::NOT_FOUND,
+        JournalError::Compacted { .. } => StatusCode::GONE,
+        JournalError::Io(e) => {
+            tracing::error!("Metadata journal update failed: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
 
-    let item = VaultItem {
-        id: req.file_id,
-        encrypted_name: serde_json::from_value(meta["encrypted_name"].clone()).unwrap_or_default(),
-        name_nonce: serde_json::from_value(meta["name_nonce"].clone()).unwrap_or_default(),
-        item_type: meta["item_type"].as_str().unwrap_or("document").to_string(),
-        size: total_size,
-        nonce: serde_json::from_value(meta["nonce"].clone()).unwrap_or_default(),
-        content_id,
-        preview_id: meta["preview_id"].as_str().map(|s| s.to_string()),
-    };
+fn archive_error_status(e: ArchiveError) -> StatusCode {
+    match e {
+        ArchiveError::Invalid(reason) => {
+            tracing::warn!("Rejected archive: {}", reason);
+            StatusCode::BAD_REQUEST
+        }
+        ArchiveError::Quota(e) => quota_error_status(e),
+        ArchiveError::Io(e) => {
+            tracing::error!("Archive import failed: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
 
-    Ok(Json(UploadResult {
-        success: true,
-        item: Some(item),
-    }))
+fn erasure_error_status(e: ErasureError) -> StatusCode {
+    match e {
+        ErasureError::Unavailable { .. } => StatusCode::SERVICE_UNAVAILABLE,
+        ErasureError::Io(e) => {
+            tracing::error!("Shard reconstruction failed: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
 }
 
-async fn get_file_handler(Path((env, file_id)): Path<(String, String)>) -> Result<axum::body::Bytes, StatusCode> {
-    let file_path = format!("vault_data/{}/{}", env, file_id);
-    let data = std::fs::read(&file_path).map_err(|_| StatusCode::NOT_FOUND)?;
-    Ok(axum::body::Bytes::from(data))
+fn quota_error_status(e: QuotaError) -> StatusCode {
+    match e {
+        QuotaError::EnvironmentFull { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+        QuotaError::NodeFull { .. } => StatusCode::INSUFFICIENT_STORAGE,
+        QuotaError::Io(e) => {
+            tracing::error!("Quota check failed: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
 }
 
-async fn get_preview_handler(Path((env, file_id)): Path<(String, String)>) -> Result<axum::body::Bytes, StatusCode> {
-    let preview_path = format!("vault_data/{}/{}", env, file_id);
-    let data = std::fs::read(&preview_path).map_err(|_| StatusCode::NOT_FOUND)?;
-    Ok(axum::body::Bytes::from(data))
+#[derive(Deserialize)]
+struct TokenQuery {
+    token: Option<String>,
 }
 
-async fn get_metadata_handler(Path(env): Path<String>) -> Result<axum::body::Bytes, StatusCode> {
-    let vault_dir = format!("vault_data/{}", env);
-    let metadata_path = format!("{}/metadata.enc", vault_dir);
+/// Token from `Authorization: Bearer ...`, falling back to the `token` query param
+fn request_token(headers: &HeaderMap, query_token: Option<&str>) -> Option<String> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .or(query_token)
+        .map(|t| t.trim().to_string())
+        .filter(|t| !t.is_empty())
+}
 
-    std::fs::create_dir_all(&vault_dir).ok();
+/// The caller's current role in an environment. Membership is checked on
+/// every request, so a removed team member loses access immediately.
+async fn member_role(state: &AppState, claims: &SessionClaims, env: &str) -> Result<Option<Role>, StatusCode> {
+    state.teams.role(state.store.as_ref(), env, &claims.pubkey).await.map_err(|e| {
+        tracing::error!("Failed to read team manifest for {}: {}", env, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
 
-    if std::path::Path::new(&metadata_path).exists() {
-        let data = std::fs::read(&metadata_path).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-        Ok(axum::body::Bytes::from(data))
-    } else {
-        Ok(axum::body::Bytes::new())
+/// Validates a session token or a capability token. Capabilities carry the
+/// issuer's pubkey in their claims and come back with their caveats.
+async fn authenticate(state: &AppState, token: &str) -> Result<(SessionClaims, Option<Caveats>), StatusCode> {
+    if token.starts_with(capability::TOKEN_PREFIX) {
+        let (claims, caveats) = state.capabilities
+            .verify(state.store.as_ref(), token, crypto::current_timestamp())
+            .await
+            .map_err(capability_error_status)?;
+        return Ok((claims, Some(caveats)));
     }
+    let claims = state.sessions.validate(token).await.map_err(session_error_status)?;
+    Ok((claims, None))
 }
 
-async fn save_metadata_handler(
-    Path(env): Path<String>,
-    body: axum::body::Bytes,
-) -> Result<Json<serde_json::Value>, StatusCode> {
-    let vault_dir = format!("vault_data/{}", env);
-    let metadata_path = format!("{}/metadata.enc", vault_dir);
-
-    std::fs::create_dir_all(&vault_dir).ok();
-    std::fs::write(&metadata_path, &body).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+/// Validates a token and checks that the caller's role in the token's
+/// environment allows `op`
+async fn authorize_session(state: &AppState, token: &str, op: Operation) -> Result<SessionClaims, StatusCode> {
+    authorize_scoped(state, token, op, &[], 0).await
+}
 
-    Ok(Json(serde_json::json!({ "success": true })))
+/// Like `authorize_session`, for requests that touch specific blobs or upload
+/// `bytes`: a capability's caveats must allow both
+async fn authorize_scoped(
+    state: &AppState,
+    token: &str,
+    op: Operation,
+    content: &[String],
+    bytes: u64,
+) -> Result<SessionClaims, StatusCode> {
+    let (claims, caveats) = authenticate(state, token).await?;
+    if caveats.is_some_and(|c| !c.permits(op, content, bytes)) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    if member_role(state, &claims, &claims.environment).await?.is_none_or(|r| r < op.role()) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    Ok(claims)
 }
 
-#[derive(Deserialize)]
-struct DeleteFilesRequest {
-    session_token: String,
-    file_ids: Vec<String>,
+/// Validates a token against the environment named in the request path
+async fn authorize_env(state: &AppState, token: Option<String>, env: &str, op: Operation) -> Result<SessionClaims, StatusCode> {
+    let token = token.ok_or(StatusCode::UNAUTHORIZED)?;
+    let claims = authorize_session(state, &token, op).await?;
+    if claims.environment != env {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    Ok(claims)
 }
 
-async fn delete_files_handler(Json(req): Json<DeleteFilesRequest>) -> Result<Json<serde_json::Value>, StatusCode> {
-    let environment = req.session_token.split('_').next().unwrap_or("personal");
-    let vault_dir = format!("vault_data/{}", environment);
+/// Sessions may follow their environment's changes over the WebSocket;
+/// capabilities are scoped to specific content and may not
+async fn authorize_events(state: &AppState, token: &str) -> Result<SessionClaims, StatusCode> {
+    let (claims, caveats) = authenticate(state, token).await?;
+    if caveats.is_some() || member_role(state, &claims, &claims.environment).await?.is_none() {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    Ok(claims)
+}
 
-    for id in req.file_ids {
-        let path = format!("{}/{}", vault_dir, id);
-        if std::path::Path::new(&path).exists() {
-            std::fs::remove_file(&path).ok();
+/// Like `authorize_env`, but also admits a recipient holding a share grant
+/// for this blob of the environment. Capabilities never reach other environments.
+async fn authorize_blob(state: &AppState, token: Option<String>, env: &str, blob_id: &str) -> Result<SessionClaims, StatusCode> {
+    let token = token.ok_or(StatusCode::UNAUTHORIZED)?;
+    let (claims, caveats) = authenticate(state, &token).await?;
+    if claims.environment == env {
+        if caveats.is_some_and(|c| !c.permits(Operation::Read, &[blob_id.to_string()], 0)) {
+            return Err(StatusCode::FORBIDDEN);
         }
+        if member_role(state, &claims, env).await?.is_none() {
+            return Err(StatusCode::FORBIDDEN);
+        }
+        return Ok(claims);
+    }
+    if caveats.is_some() {
+        return Err(StatusCode::FORBIDDEN);
     }
 
-    Ok(Json(serde_json::json!({ "success": true })))
+    let allowed = state.shares
+        .allows(state.store.as_ref(), &claims.environment, env, blob_id, crypto::current_timestamp())
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to read share grants for {}: {}", claims.environment, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    if !allowed {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    Ok(claims)
 }
 
-// ============== P2P HANDLERS ==============
+/// Blob and upload IDs are server-generated hex strings; reject anything else
+/// so path parameters cannot escape the environment directory
+fn is_valid_blob_id(id: &str) -> bool {
+    !id.is_empty() && id.len() <= 64 && id.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+// ============== VAULT HANDLERS ==============
+
+async fn start_upload_handler(
+    State(state): State<AppState>,
+    Json(req): Json<StartUploadRequest>,
+) -> Result<Json<StartUploadResponse>, StatusCode> {
+    let preview_len = req.upload.preview.as_ref().map_or(0, |p| p.len() as u64);
+    let claims = authorize_scoped(&state, &req.session_token, Operation::Upload, &[], req.upload.size + preview_len).await?;
+
+    let file_id = stage_upload(&state, &claims.environment, req.upload, None).await?;
+    Ok(Json(StartUploadResponse { file_id }))
+}
+
+/// Checks the layout of a new upload; returns its normalized chunk hashes and
+/// search tokens
+fn validate_upload(req: &NewUpload) -> Result<(Vec<String>, Vec<String>), StatusCode> {
+    if req.chunk_hashes.len() != req.total_chunks || !req.chunk_hashes.iter().all(|h| integrity::is_valid_hash(h)) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    // The content is preallocated, so the layout must be consistent up front
+    if req.chunk_size == 0
+        || req.chunk_size > upload::MAX_CHUNK_BYTES
+        || req.size.div_ceil(req.chunk_size) != req.total_chunks as u64
+    {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let chunk_hashes = req.chunk_hashes.iter().map(|h| h.to_ascii_lowercase()).collect();
+    let search_tokens = catalog::normalize_search_tokens(&req.search_tokens, catalog::MAX_ITEM_TOKENS)
+        .ok_or(StatusCode::BAD_REQUEST)?;
+    Ok((chunk_hashes, search_tokens))
+}
+
+/// Random ID of a staged upload
+fn new_upload_id() -> String {
+    use rand::Rng;
+
+    let mut rng = rand::thread_rng();
+    (0..16).map(|_| format!("{:x}", rng.gen_range(0..16))).collect()
+}
+
+/// Validates the layout of a new upload, stores its preview and allocates the
+/// staging files; returns the upload ID. Inbox uploads carry the recipient's
+/// wrapped key and keep their blobs under the inbox suffix until accepted.
+async fn stage_upload(
+    state: &AppState,
+    env: &str,
+    req: NewUpload,
+    wrapped_key: Option<Vec<u8>>,
+) -> Result<String, StatusCode> {
+    use rand::Rng;
+
+    let (chunk_hashes, search_tokens) = validate_upload(&req)?;
+
+    // Only the preview is written now; each chunk reserves its own bytes.
+    // Inbox bytes have their own limit and stay out of the recipient's quota.
+    let preview_len = req.preview.as_ref().map_or(0, |p| p.len() as u64);
+    let _reservation = if wrapped_key.is_some() {
+        state.quotas.reserve_inbox(state.store.as_ref(), env, req.size + preview_len).await
+    } else {
+        state.quotas.reserve(state.store.as_ref(), env, req.size + preview_len).await
+    }
+    .map_err(quota_error_status)?;
+
+    let (file_id, content_id, preview_id) = {
+        let mut rng = rand::thread_rng();
+        let file_id = new_upload_id();
+        let content_id: String = (0..32).map(|_| format!("{:x}", rng.gen_range(0..16))).collect();
+        let preview_id: String = (0..32).map(|_| format!("{:x}", rng.gen_range(0..16))).collect();
+        (file_id, content_id, preview_id)
+    };
+
+    let mut stored_preview = None;
+
+    if let (Some(preview), Some(preview_nonce)) = (&req.preview, &req.preview_nonce) {
+        let mut file_data = Vec::with_capacity(preview_nonce.len() + preview.len());
+        file_data.extend_from_slice(preview_nonce);
+        file_data.extend_from_slice(preview);
+
+        let key = if wrapped_key.is_some() { inbox::inbox_key(&preview_id) } else { preview_id.clone() };
+        match state.store.put(env, &key, file_data.into()).await {
+            Ok(()) => stored_preview = Some(preview_id),
+            Err(e) => tracing::warn!("Failed to store preview: {}", e),
+        }
+        state.quotas.invalidate(env).await;
+    }
+
+    let meta = UploadMeta {
+        encrypted_name: req.encrypted_name,
+        name_nonce: req.name_nonce,
+        item_type: req.item_type,
+        nonce: req.nonce,
+        total_chunks: req.total_chunks,
+        chunk_hashes,
+        size: req.size,
+        chunk_size: req.chunk_size,
+        content_id,
+        preview_id: stored_preview,
+        created_at: crypto::current_timestamp(),
+        item: None,
+        // An anonymous sender cannot know the recipient's index key
+        search_tokens: if wrapped_key.is_some() { Vec::new() } else { search_tokens },
+        wrapped_key,
+    };
+    let files = upload::staging_files(env, &file_id);
+    upload::create(&files, &meta).await.map_err(|e| {
+        tracing::error!("Failed to allocate upload {}: {}", file_id, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    state.catalog.record_upload(env, &file_id, &meta).await;
+
+    Ok(file_id)
+}
+
+#[derive(Deserialize)]
+struct UploadChunkQuery {
+    token: Option<String>,
+    file_id: String,
+    chunk: usize,
+}
+
+/// Stores one chunk, streaming the request body straight to its offset in the
+/// preallocated content file. Chunks may arrive in any order and can be
+/// re-sent; a re-sent chunk replaces the previous copy. A chunk whose SHA-256
+/// does not match the hash declared at start is rejected with 422, and a
+/// chunk already being written by another request with 409.
+async fn upload_chunk_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(params): Query<UploadChunkQuery>,
+    body: axum::body::Body,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let token = request_token(&headers, params.token.as_deref()).ok_or(StatusCode::UNAUTHORIZED)?;
+    let claims = authorize_session(&state, &token, Operation::Upload).await?;
+
+    store_chunk(&state, &claims.environment, &params.file_id, params.chunk, &headers, body, false).await
+}
+
+/// Streams one chunk of a vault or inbox upload to its offset in the content file
+async fn store_chunk(
+    state: &AppState,
+    env: &str,
+    file_id: &str,
+    chunk: usize,
+    headers: &HeaderMap,
+    body: axum::body::Body,
+    inbox: bool,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    if !is_valid_blob_id(file_id) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    // The declared length is needed up front for the quota check
+    let content_length: u64 = headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok())
+        .ok_or(StatusCode::LENGTH_REQUIRED)?;
+    if content_length > upload::MAX_CHUNK_BYTES {
+        return Err(StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    let files = upload::staging_files(env, file_id);
+    let meta = upload::read_meta(&files).await.map_err(|_| StatusCode::NOT_FOUND)?;
+
+    if meta.wrapped_key.is_some() != inbox {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    if meta.item.is_some() {
+        return Err(StatusCode::CONFLICT);
+    }
+    if chunk >= meta.total_chunks {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let (_, chunk_len) = upload::chunk_range(&meta, chunk);
+    if content_length != chunk_len {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    // A re-sent chunk adds no bytes over the previous copy
+    let (received, _) = upload::received_chunks(&files, &meta).await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let reservation = if received.binary_search(&chunk).is_err() {
+        let reservation = if inbox {
+            state.quotas.reserve_inbox(state.store.as_ref(), env, chunk_len).await
+        } else {
+            state.quotas.reserve(state.store.as_ref(), env, chunk_len).await
+        }
+        .map_err(quota_error_status)?;
+        Some(reservation)
+    } else {
+        None
+    };
+
+    let expected_hash = meta.chunk_hashes.get(chunk).ok_or(StatusCode::BAD_REQUEST)?;
+    upload::write_chunk(&files, &meta, chunk, body.into_data_stream(), expected_hash)
+        .await
+        .map_err(|e| match e {
+            upload::ChunkError::HashMismatch => StatusCode::UNPROCESSABLE_ENTITY,
+            upload::ChunkError::TooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            upload::ChunkError::Busy => StatusCode::CONFLICT,
+            e => {
+                tracing::error!("Failed to write chunk {} of {}: {}", chunk, file_id, e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        })?;
+    if let Some(reservation) = reservation {
+        state.quotas.commit(reservation).await;
+    }
+    state.catalog.record_chunk(env, file_id, chunk).await;
+
+    if !inbox {
+        let received_chunks = received.len() + usize::from(received.binary_search(&chunk).is_err());
+        state.events.publish(env, VaultEvent::UploadProgress {
+            file_id: file_id.to_string(),
+            received_chunks,
+            total_chunks: meta.total_chunks,
+        }).await;
+    }
+
+    Ok(Json(serde_json::json!({ "success": true, "chunk": chunk })))
+}
+
+/// Reports which chunks of an upload have been received so a client can resume
+async fn upload_status_handler(
+    State(state): State<AppState>,
+    Path(file_id): Path<String>,
+    headers: HeaderMap,
+    Query(query): Query<TokenQuery>,
+) -> Result<Json<UploadStatus>, StatusCode> {
+    let token = request_token(&headers, query.token.as_deref()).ok_or(StatusCode::UNAUTHORIZED)?;
+    let claims = authorize_session(&state, &token, Operation::Upload).await?;
+    if !is_valid_blob_id(&file_id) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let files = upload::staging_files(&claims.environment, &file_id);
+    if upload::read_meta(&files).await.map_err(|_| StatusCode::NOT_FOUND)?.wrapped_key.is_some() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let status = upload::status(&files, &file_id).await.map_err(|_| StatusCode::NOT_FOUND)?;
+
+    Ok(Json(status))
+}
+
+#[derive(Deserialize)]
+struct ListItemsQuery {
+    token: Option<String>,
+    /// Only items of this type
+    #[serde(rename = "type")]
+    item_type: Option<String>,
+    #[serde(default)]
+    state: ItemState,
+    #[serde(default)]
+    offset: u64,
+    limit: Option<u64>,
+}
+
+/// Pages through the items of the session's environment, newest first, with
+/// the count and size of the whole selection
+async fn list_items_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<ListItemsQuery>,
+) -> Result<Json<ItemPage>, StatusCode> {
+    let token = request_token(&headers, query.token.as_deref()).ok_or(StatusCode::UNAUTHORIZED)?;
+    let claims = authorize_session(&state, &token, Operation::Read).await?;
+    let limit = query.limit.unwrap_or(catalog::DEFAULT_PAGE).clamp(1, catalog::MAX_PAGE);
+
+    let page = async {
+        state.catalog.ensure_indexed(state.store.as_ref(), &claims.environment).await?;
+        state.catalog.items(&claims.environment, query.state, query.item_type.as_deref(), query.offset, limit).await
+    }.await;
+    page.map(Json).map_err(|e| {
+        tracing::error!("Failed to list items of {}: {}", claims.environment, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+#[derive(Deserialize)]
+struct SearchRequest {
+    session_token: String,
+    /// Blind index tokens computed by the client from the search terms
+    tokens: Vec<String>,
+    /// Require every token (default) or at least one
+    #[serde(default = "default_true")]
+    match_all: bool,
+    #[serde(default)]
+    state: ItemState,
+    #[serde(default)]
+    offset: u64,
+    limit: Option<u64>,
+}
+
+/// Matches blind index tokens against those attached to the items at upload
+/// and returns the IDs of the matching items, best matches first. The server
+/// only compares opaque tokens and never sees the terms behind them.
+async fn search_handler(
+    State(state): State<AppState>,
+    Json(req): Json<SearchRequest>,
+) -> Result<Json<SearchPage>, StatusCode> {
+    let claims = authorize_session(&state, &req.session_token, Operation::Read).await?;
+    let tokens = catalog::normalize_search_tokens(&req.tokens, catalog::MAX_QUERY_TOKENS)
+        .filter(|tokens| !tokens.is_empty())
+        .ok_or(StatusCode::BAD_REQUEST)?;
+    let limit = req.limit.unwrap_or(catalog::DEFAULT_PAGE).clamp(1, catalog::MAX_PAGE);
+
+    let page = async {
+        state.catalog.ensure_indexed(state.store.as_ref(), &claims.environment).await?;
+        state.catalog.search(&claims.environment, &tokens, req.match_all, req.state, req.offset, limit).await
+    }.await;
+    page.map(Json).map_err(|e| {
+        tracing::error!("Search in {} failed: {}", claims.environment, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+#[derive(Deserialize)]
+struct ListUploadsQuery {
+    token: Option<String>,
+    /// Include finished uploads as well
+    #[serde(default)]
+    completed: bool,
+}
+
+/// Upload sessions of the session's environment with their chunk status, so
+/// a client can find the uploads it still has to resume
+async fn list_uploads_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<ListUploadsQuery>,
+) -> Result<Json<Vec<UploadSession>>, StatusCode> {
+    let token = request_token(&headers, query.token.as_deref()).ok_or(StatusCode::UNAUTHORIZED)?;
+    let claims = authorize_session(&state, &token, Operation::Upload).await?;
+
+    let sessions = async {
+        state.catalog.ensure_indexed(state.store.as_ref(), &claims.environment).await?;
+        state.catalog.uploads(&claims.environment, query.completed).await
+    }.await;
+    sessions.map(Json).map_err(|e| {
+        tracing::error!("Failed to list uploads of {}: {}", claims.environment, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+/// Moves the completed content file into the store. Finishing an already
+/// finished upload returns the same item; missing chunks are reported with 409.
+async fn finish_upload_handler(
+    State(state): State<AppState>,
+    Json(req): Json<FinishUploadRequest>,
+) -> Result<Json<UploadResult>, (StatusCode, Json<serde_json::Value>)> {
+    let claims = authorize_session(&state, &req.session_token, Operation::Upload).await.map_err(error_json)?;
+
+    let item = complete_upload(&state, &claims.environment, &req.file_id, false).await?;
+    Ok(Json(UploadResult {
+        success: true,
+        item: Some(item),
+    }))
+}
+
+/// Moves the content file of a vault or inbox upload into the store once every
+/// chunk has arrived, and records the resulting item
+async fn complete_upload(
+    state: &AppState,
+    env: &str,
+    file_id: &str,
+    inbox: bool,
+) -> Result<VaultItem, (StatusCode, Json<serde_json::Value>)> {
+    if !is_valid_blob_id(file_id) {
+        return Err(error_json(StatusCode::BAD_REQUEST));
+    }
+
+    let files = upload::staging_files(env, file_id);
+
+    let mut meta = upload::read_meta(&files).await.map_err(|_| error_json(StatusCode::NOT_FOUND))?;
+
+    if meta.wrapped_key.is_some() != inbox {
+        return Err(error_json(StatusCode::NOT_FOUND));
+    }
+    if let Some(item) = meta.item {
+        return Ok(item);
+    }
+
+    // No chunk may be written between the checks below and the move to the store
+    let _guard = upload::begin_finish(&files).map_err(|_| error_json(StatusCode::CONFLICT))?;
+    let (received, _) = upload::received_chunks(&files, &meta)
+        .await
+        .map_err(|_| error_json(StatusCode::INTERNAL_SERVER_ERROR))?;
+    let missing = upload::missing_chunks(&received, meta.total_chunks);
+    if !missing.is_empty() {
+        return Err((StatusCode::CONFLICT, Json(serde_json::json!({
+            "success": false,
+            "error": format!("{} of {} chunks missing", missing.len(), meta.total_chunks),
+            "missing_chunks": missing,
+        }))));
+    }
+
+    // A content file that is already gone was moved by a finish interrupted
+    // before recording the item
+    let key = if inbox { inbox::inbox_key(&meta.content_id) } else { meta.content_id.clone() };
+    let moved = tokio::fs::metadata(&files.content).await.is_err()
+        && state.store.stat(env, &key).await.is_ok();
+    if !moved {
+        // Chunks were verified as they arrived; the staged file may have
+        // changed on disk since, and must match before it becomes the content
+        let corrupted = upload::verify_chunks(&files, &meta).await.map_err(|e| {
+            tracing::error!("Failed to verify upload {}: {}", file_id, e);
+            error_json(StatusCode::INTERNAL_SERVER_ERROR)
+        })?;
+        if !corrupted.is_empty() {
+            return Err((StatusCode::CONFLICT, Json(serde_json::json!({
+                "success": false,
+                "error": format!("{} of {} chunks failed verification", corrupted.len(), meta.total_chunks),
+                "missing_chunks": corrupted,
+            }))));
+        }
+
+        let stored = async {
+            upload::seal(&files).await?;
+            state.store.put_file(env, &key, &files.content).await
+        }.await;
+        stored.map_err(|e| {
+            tracing::error!("Failed to store content {}: {}", meta.content_id, e);
+            error_json(StatusCode::INTERNAL_SERVER_ERROR)
+        })?;
+    }
+    state.quotas.invalidate(env).await;
+
+    let item = VaultItem {
+        id: file_id.to_string(),
+        encrypted_name: meta.encrypted_name.clone(),
+        name_nonce: meta.name_nonce.clone(),
+        item_type: meta.item_type.clone(),
+        size: meta.size as usize,
+        nonce: meta.nonce.clone(),
+        content_id: meta.content_id.clone(),
+        preview_id: meta.preview_id.clone(),
+        merkle_root: integrity::merkle_root(&meta.chunk_hashes),
+        chunk_hashes: meta.chunk_hashes.clone(),
+        chunk_size: (meta.total_chunks > 0).then(|| upload::chunk_range(&meta, 0).1 as usize),
+        search_tokens: meta.search_tokens.clone(),
+    };
+
+    if let Some(wrapped_key) = &meta.wrapped_key {
+        let mut size = meta.size;
+        if let Some(preview_id) = &meta.preview_id {
+            size += state.store.stat(env, &inbox::inbox_key(preview_id)).await.map_or(0, |s| s.size);
+        }
+        state.inbox
+            .receive(state.store.as_ref(), env, item.clone(), wrapped_key.clone(), size)
+            .await
+            .map_err(|e| {
+                tracing::error!("Failed to record inbox item {}: {}", meta.content_id, e);
+                error_json(StatusCode::INTERNAL_SERVER_ERROR)
+            })?;
+        state.quotas.invalidate(env).await;
+    }
+
+    // Keep meta.json as a record of the finished upload so finish is idempotent
+    meta.item = Some(item.clone());
+    upload::write_meta(&files, &meta).await.map_err(|_| error_json(StatusCode::INTERNAL_SERVER_ERROR))?;
+    upload::remove_bitmap(&files).await;
+    let item_state = if inbox { ItemState::Inbox } else { ItemState::Active };
+    state.catalog.record_item(env, &item, meta.created_at, item_state).await;
+
+    if !inbox {
+        state.events.publish(env, VaultEvent::ItemAdded { item: Box::new(item.clone()) }).await;
+    }
+
+    Ok(item)
+}
+
+/// JSON error body for handlers that report details on failure
+fn error_json(status: StatusCode) -> (StatusCode, Json<serde_json::Value>) {
+    (status, Json(serde_json::json!({
+        "success": false,
+        "error": status.canonical_reason().unwrap_or("error"),
+    })))
+}
+
+/// Serves a content blob to the environment's owner, or to the recipient of a
+/// share grant covering it
+async fn get_file_handler(
+    State(state): State<AppState>,
+    Path((env, file_id)): Path<(String, String)>,
+    headers: HeaderMap,
+    Query(query): Query<TokenQuery>,
+) -> Result<Response, StatusCode> {
+    if !is_valid_blob_id(&file_id) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    authorize_blob(&state, request_token(&headers, query.token.as_deref()), &env, &file_id).await?;
+
+    serve_content(&state, &env, &file_id, &headers).await
+}
+
+/// Serves a content blob, rebuilding it from its shards when it only lives
+/// on peers
+async fn serve_content(state: &AppState, env: &str, content_id: &str, headers: &HeaderMap) -> Result<Response, StatusCode> {
+    match download::serve_blob(state.store.as_ref(), env, content_id, headers).await {
+        Err(StatusCode::NOT_FOUND) => {}
+        response => return response,
+    }
+
+    match state.erasure.read(state.store.as_ref(), &state.replication, env, content_id).await {
+        Ok(Some(data)) => download::serve_bytes(data, content_id, headers).await,
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            tracing::warn!("Cannot rebuild {}/{}: {}", env, content_id, e);
+            Err(erasure_error_status(e))
+        }
+    }
+}
+
+async fn get_preview_handler(
+    State(state): State<AppState>,
+    Path((env, file_id)): Path<(String, String)>,
+    headers: HeaderMap,
+    Query(query): Query<TokenQuery>,
+) -> Result<Response, StatusCode> {
+    if !is_valid_blob_id(&file_id) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    authorize_blob(&state, request_token(&headers, query.token.as_deref()), &env, &file_id).await?;
+
+    download::serve_blob(state.store.as_ref(), &env, &file_id, &headers).await
+}
+
+/// Header with the latest journal revision, to be passed back as `base`
+const METADATA_REVISION_HEADER: &str = "x-metadata-revision";
+/// Header with the revision of the snapshot returned by `get_metadata_handler`
+const METADATA_SNAPSHOT_HEADER: &str = "x-metadata-snapshot-revision";
+
+#[derive(Deserialize)]
+struct MetadataQuery {
+    token: Option<String>,
+    /// Revision the client's change is based on; stale bases are rejected with 409
+    base: Option<u64>,
+}
+
+/// Latest metadata snapshot. `X-Metadata-Revision` is the latest journal
+/// revision; when it differs from `X-Metadata-Snapshot-Revision`, deltas were
+/// written after the snapshot and the client must fold them from the journal
+/// before saving on top of it.
+async fn get_metadata_handler(
+    State(state): State<AppState>,
+    Path(env): Path<String>,
+    headers: HeaderMap,
+    Query(query): Query<TokenQuery>,
+) -> Result<Response, StatusCode> {
+    authorize_env(&state, request_token(&headers, query.token.as_deref()), &env, Operation::Read).await?;
+
+    let (head, snapshot) = state.journal.snapshot(state.store.as_ref(), &env).await.map_err(|e| {
+        tracing::error!("Failed to read metadata of {}: {}", env, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let (revision, data) = snapshot.unwrap_or((0, axum::body::Bytes::new()));
+    Ok((
+        [
+            (METADATA_REVISION_HEADER, head.to_string()),
+            (METADATA_SNAPSHOT_HEADER, revision.to_string()),
+        ],
+        data,
+    ).into_response())
+}
+
+/// Reserves quota for a new journal revision; snapshots that do not grow the
+/// metadata are free, so a full environment can still save deletions
+async fn reserve_journal_bytes(
+    state: &AppState,
+    env: &str,
+    kind: JournalKind,
+    body: &axum::body::Bytes,
+) -> Result<Option<quota::Reservation>, StatusCode> {
+    let charged = state.journal.charged_bytes(state.store.as_ref(), env, kind, body.len() as u64).await.map_err(|e| {
+        tracing::error!("Failed to read the metadata journal of {}: {}", env, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    if charged == 0 {
+        return Ok(None);
+    }
+    state.quotas.reserve(state.store.as_ref(), env, charged).await.map(Some).map_err(quota_error_status)
+}
+
+/// Saves a full metadata snapshot as a new journal revision. Without `base`
+/// the snapshot is appended unconditionally, as older clients expect.
+async fn save_metadata_handler(
+    State(state): State<AppState>,
+    Path(env): Path<String>,
+    headers: HeaderMap,
+    Query(query): Query<MetadataQuery>,
+    body: axum::body::Bytes,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    authorize_env(&state, request_token(&headers, query.token.as_deref()), &env, Operation::Write).await?;
+
+    let _reservation = reserve_journal_bytes(&state, &env, JournalKind::Snapshot, &body).await?;
+    let revision = state.journal
+        .append(state.store.as_ref(), &env, query.base, JournalKind::Snapshot, body)
+        .await
+        .map_err(journal_error_status)?;
+    state.quotas.invalidate(&env).await;
+    state.events.publish(&env, VaultEvent::MetadataRevision { revision }).await;
+
+    Ok(Json(serde_json::json!({ "success": true, "revision": revision })))
+}
+
+#[derive(Deserialize)]
+struct JournalQuery {
+    token: Option<String>,
+    /// Last revision the client has applied
+    #[serde(default)]
+    since: u64,
+}
+
+/// Revisions the client needs to catch up from `since`: the latest snapshot
+/// when one was written since, then the deltas after it
+async fn read_journal_handler(
+    State(state): State<AppState>,
+    Path(env): Path<String>,
+    headers: HeaderMap,
+    Query(query): Query<JournalQuery>,
+) -> Result<Json<MetadataJournal>, StatusCode> {
+    authorize_env(&state, request_token(&headers, query.token.as_deref()), &env, Operation::Read).await?;
+
+    let journal = state.journal.read(state.store.as_ref(), &env, query.since).await.map_err(|e| {
+        tracing::error!("Failed to read the metadata journal of {}: {}", env, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(Json(journal))
+}
+
+/// Appends an encrypted delta on top of revision `base`
+async fn append_journal_handler(
+    State(state): State<AppState>,
+    Path(env): Path<String>,
+    headers: HeaderMap,
+    Query(query): Query<MetadataQuery>,
+    body: axum::body::Bytes,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    authorize_env(&state, request_token(&headers, query.token.as_deref()), &env, Operation::Write).await?;
+    let base = query.base.ok_or(StatusCode::BAD_REQUEST)?;
+
+    let _reservation = reserve_journal_bytes(&state, &env, JournalKind::Delta, &body).await?;
+    let revision = state.journal
+        .append(state.store.as_ref(), &env, Some(base), JournalKind::Delta, body)
+        .await
+        .map_err(journal_error_status)?;
+    state.quotas.invalidate(&env).await;
+    state.events.publish(&env, VaultEvent::MetadataRevision { revision }).await;
+
+    Ok(Json(serde_json::json!({ "success": true, "revision": revision })))
+}
+
+/// Revisions still in the journal, oldest first
+async fn metadata_history_handler(
+    State(state): State<AppState>,
+    Path(env): Path<String>,
+    headers: HeaderMap,
+    Query(query): Query<TokenQuery>,
+) -> Result<Json<Vec<JournalEntry>>, StatusCode> {
+    authorize_env(&state, request_token(&headers, query.token.as_deref()), &env, Operation::Read).await?;
+
+    let history = state.journal.history(state.store.as_ref(), &env).await.map_err(|e| {
+        tracing::error!("Failed to read the metadata journal of {}: {}", env, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(Json(history))
+}
+
+#[derive(Deserialize)]
+struct RollbackQuery {
+    token: Option<String>,
+    base: u64,
+    /// Revision whose state is restored
+    to: u64,
+}
+
+/// Restores the metadata as of revision `to` by appending new revisions;
+/// history after `to` stays available
+async fn rollback_metadata_handler(
+    State(state): State<AppState>,
+    Path(env): Path<String>,
+    headers: HeaderMap,
+    Query(query): Query<RollbackQuery>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    authorize_env(&state, request_token(&headers, query.token.as_deref()), &env, Operation::Admin).await?;
+
+    let revision = state.journal
+        .rollback(state.store.as_ref(), &env, query.base, query.to)
+        .await
+        .map_err(journal_error_status)?;
+    state.quotas.invalidate(&env).await;
+    state.events.publish(&env, VaultEvent::MetadataRevision { revision }).await;
+
+    Ok(Json(serde_json::json!({ "success": true, "revision": revision })))
+}
+
+/// Replaces the journal with a snapshot of the state at `base`, written by
+/// the client; earlier revisions can no longer be rolled back to
+async fn compact_metadata_handler(
+    State(state): State<AppState>,
+    Path(env): Path<String>,
+    headers: HeaderMap,
+    Query(query): Query<MetadataQuery>,
+    body: axum::body::Bytes,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    authorize_env(&state, request_token(&headers, query.token.as_deref()), &env, Operation::Admin).await?;
+    let base = query.base.ok_or(StatusCode::BAD_REQUEST)?;
+
+    let revision = state.journal
+        .compact(state.store.as_ref(), &env, base, body)
+        .await
+        .map_err(journal_error_status)?;
+    state.quotas.invalidate(&env).await;
+    state.events.publish(&env, VaultEvent::MetadataRevision { revision }).await;
+
+    Ok(Json(serde_json::json!({ "success": true, "revision": revision })))
+}
+
+#[derive(Deserialize)]
+struct DeleteFilesRequest {
+    session_token: String,
+    file_ids: Vec<String>,
+}
+
+/// Moves content and preview blobs to the environment's trash; IDs that do
+/// not exist are reported back in `not_found`
+async fn delete_files_handler(
+    State(state): State<AppState>,
+    Json(req): Json<DeleteFilesRequest>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let claims = authorize_scoped(&state, &req.session_token, Operation::Write, &req.file_ids, 0).await?;
+
+    let (ids, mut invalid): (Vec<String>, Vec<String>) = req.file_ids.into_iter().partition(|id| is_valid_blob_id(id));
+    // Sharded content comes back first so it can go through the trash like any blob
+    state.erasure
+        .unshard(state.store.as_ref(), &state.replication, &claims.environment, &ids)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to unshard files before deleting them: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    let mut outcome = state.trash.trash(state.store.as_ref(), &claims.environment, &ids).await.map_err(|e| {
+        tracing::error!("Failed to move files to trash: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    state.quotas.invalidate(&claims.environment).await;
+    state.catalog.set_state(&claims.environment, &outcome.trashed, ItemState::Trashed).await;
+    if !outcome.trashed.is_empty() {
+        state.events.publish(&claims.environment, VaultEvent::ItemsDeleted { ids: outcome.trashed.clone() }).await;
+    }
+    outcome.not_found.append(&mut invalid);
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "trashed": outcome.trashed,
+        "not_found": outcome.not_found,
+    })))
+}
+
+#[derive(Deserialize)]
+struct TransferRequest {
+    session_token: String,
+    /// Environment the items come from
+    from: String,
+    /// Environment receiving the copies
+    to: String,
+    items: Vec<TransferItem>,
+}
+
+/// An item to copy, with the name the client encrypted again for the destination.
+/// The content is linked as is, so it stays encrypted with the source key
+#[derive(Deserialize)]
+struct TransferItem {
+    /// Item ID in the source environment
+    id: String,
+    encrypted_name: Vec<u8>,
+    name_nonce: Vec<u8>,
+    /// Blind index tokens computed with the destination's index key
+    #[serde(default)]
+    search_tokens: Vec<String>,
+}
+
+/// Copies items into another environment the caller belongs to, without
+/// re-uploading them. Content and preview are linked rather than copied, so a
+/// copy costs no extra disk; the copies get fresh IDs in the destination.
+async fn copy_items_handler(
+    State(state): State<AppState>,
+    Json(req): Json<TransferRequest>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    transfer_items(&state, req, false).await.map(Json)
+}
+
+/// Like copy, then removes the items from the source environment. The linked
+/// blobs stay on disk for the destination.
+async fn move_items_handler(
+    State(state): State<AppState>,
+    Json(req): Json<TransferRequest>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    if req.from == req.to {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    transfer_items(&state, req, true).await.map(Json)
+}
+
+/// Links the blobs of finished items from one environment into another and
+/// records them there as finished uploads. Items that do not exist are
+/// reported in `not_found`, those whose blobs could not be linked in `failed`.
+async fn transfer_items(state: &AppState, req: TransferRequest, remove_source: bool) -> Result<serde_json::Value, StatusCode> {
+    let (claims, caveats) = authenticate(state, &req.session_token).await?;
+    // Capabilities never reach other environments
+    if caveats.is_some() {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let source_op = if remove_source { Operation::Write } else { Operation::Read };
+    if member_role(state, &claims, &req.from).await?.is_none_or(|r| r < source_op.role())
+        || member_role(state, &claims, &req.to).await?.is_none_or(|r| r < Operation::Upload.role())
+    {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    // A copy must share the blobs, never duplicate them
+    if !state.store.can_link() {
+        return Err(StatusCode::NOT_IMPLEMENTED);
+    }
+    let (from, to) = (req.from.as_str(), req.to.as_str());
+
+    // Only finished vault uploads can be copied; inbox items must be accepted first
+    let mut sources = Vec::new();
+    let mut not_found = Vec::new();
+    for transfer in req.items {
+        let search_tokens = catalog::normalize_search_tokens(&transfer.search_tokens, catalog::MAX_ITEM_TOKENS)
+            .ok_or(StatusCode::BAD_REQUEST)?;
+        let meta = if is_valid_blob_id(&transfer.id) {
+            upload::read_meta(&upload::staging_files(from, &transfer.id)).await.ok()
+        } else {
+            None
+        };
+        match meta {
+            Some(UploadMeta { item: Some(item), wrapped_key: None, .. }) => sources.push((item, transfer, search_tokens)),
+            _ => not_found.push(transfer.id),
+        }
+    }
+
+    // Sharded content comes back first so it can be linked
+    let content_ids: Vec<String> = sources.iter().map(|(item, ..)| item.content_id.clone()).collect();
+    state.erasure
+        .unshard(state.store.as_ref(), &state.replication, from, &content_ids)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to unshard files before copying them: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    // A content in the trash is gone; a missing preview is simply not copied
+    let mut found = Vec::new();
+    let mut bytes = 0;
+    for (item, transfer, search_tokens) in sources {
+        let Ok(content) = state.store.stat(from, &item.content_id).await else {
+            not_found.push(transfer.id);
+            continue;
+        };
+        let preview = match &item.preview_id {
+            Some(preview_id) => state.store.stat(from, preview_id).await.ok(),
+            None => None,
+        };
+        bytes += content.size + preview.as_ref().map_or(0, |p| p.size);
+        found.push((item, transfer, search_tokens, preview.is_some()));
+    }
+    let _reservation = state.quotas.reserve(state.store.as_ref(), to, bytes).await.map_err(quota_error_status)?;
+
+    let now = crypto::current_timestamp();
+    let mut transferred = Vec::new();
+    let mut failed = Vec::new();
+    let mut removed = Vec::new();
+    for (item, transfer, search_tokens, with_preview) in found {
+        let copy = VaultItem {
+            id: hex::encode(crypto::random_bytes::<8>()),
+            encrypted_name: transfer.encrypted_name,
+            name_nonce: transfer.name_nonce,
+            content_id: hex::encode(crypto::random_bytes::<16>()),
+            preview_id: with_preview.then(|| hex::encode(crypto::random_bytes::<16>())),
+            search_tokens,
+            ..item.clone()
+        };
+        if let Err(e) = link_item(state, from, &item, to, &copy).await {
+            tracing::warn!("Failed to copy {} from {} to {}: {}", item.id, from, to, e);
+            failed.push(transfer.id);
+            continue;
+        }
+        state.catalog.record_item(to, &copy, now, ItemState::Active).await;
+        state.events.publish(to, VaultEvent::ItemAdded { item: Box::new(copy.clone()) }).await;
+        transferred.push(TransferredItem { source_id: item.id.clone(), item: copy });
+
+        if remove_source {
+            remove_item(state, from, &item).await;
+            removed.push(item.content_id);
+        }
+    }
+    state.quotas.invalidate(to).await;
+    if !removed.is_empty() {
+        state.quotas.invalidate(from).await;
+        state.events.publish(from, VaultEvent::ItemsDeleted { ids: removed }).await;
+    }
+
+    Ok(serde_json::json!({
+        "success": true,
+        "items": transferred,
+        "not_found": not_found,
+        "failed": failed,
+    }))
+}
+
+/// Links the content and preview of `item` under the IDs of `copy` and records
+/// the copy as a finished upload of the destination. Nothing is left behind on failure.
+async fn link_item(state: &AppState, from: &str, item: &VaultItem, to: &str, copy: &VaultItem) -> std::io::Result<()> {
+    let mut blobs = vec![(item.content_id.as_str(), copy.content_id.as_str())];
+    if let (Some(source), Some(target)) = (&item.preview_id, &copy.preview_id) {
+        blobs.push((source.as_str(), target.as_str()));
+    }
+
+    let mut linked = Vec::new();
+    let mut result = Ok(());
+    for (source, target) in blobs {
+        result = state.store.link(from, source, to, target).await;
+        if result.is_err() {
+            break;
+        }
+        linked.push(target);
+    }
+    if result.is_ok() {
+        result = upload::record_item(to, copy).await.map(|_| ());
+    }
+
+    if result.is_err() {
+        for target in linked {
+            state.store.delete(to, target).await.ok();
+        }
+    }
+    result
+}
+
+/// Removes a moved item from its source environment: only the source's links
+/// to the blobs go, the data stays for the copy
+async fn remove_item(state: &AppState, env: &str, item: &VaultItem) {
+    for blob_id in std::iter::once(&item.content_id).chain(&item.preview_id) {
+        if let Err(e) = state.store.delete(env, blob_id).await {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                tracing::warn!("Failed to remove {} after moving it: {}", blob_id, e);
+            }
+        }
+    }
+    if let Err(e) = upload::remove_upload(env, &item.id).await {
+        tracing::warn!("Failed to remove upload {} after moving it: {}", item.id, e);
+    }
+    state.catalog.remove_upload(env, &item.id).await;
+}
+
+#[derive(Deserialize)]
+struct TrashRequest {
+    session_token: String,
+    /// Trash entry IDs; for purge, omitted means empty the whole trash
+    ids: Option<Vec<String>>,
+}
+
+async fn list_trash_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<TokenQuery>,
+) -> Result<Json<Vec<TrashEntry>>, StatusCode> {
+    let token = request_token(&headers, query.token.as_deref()).ok_or(StatusCode::UNAUTHORIZED)?;
+    let claims = authorize_session(&state, &token, Operation::Read).await?;
+
+    let entries = state.trash.list(state.store.as_ref(), &claims.environment).await.map_err(|e| {
+        tracing::error!("Failed to read trash: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(Json(entries))
+}
+
+/// Moves trash entries back; the returned items are re-added to the client's metadata
+async fn restore_trash_handler(
+    State(state): State<AppState>,
+    Json(req): Json<TrashRequest>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let claims = authorize_session(&state, &req.session_token, Operation::Write).await?;
+    let ids = req.ids.ok_or(StatusCode::BAD_REQUEST)?;
+
+    let restored = state.trash.restore(state.store.as_ref(), &claims.environment, &ids).await.map_err(|e| {
+        tracing::error!("Failed to restore from trash: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    state.quotas.invalidate(&claims.environment).await;
+    let content_ids: Vec<String> = restored.iter().map(|entry| entry.id.clone()).collect();
+    state.catalog.set_state(&claims.environment, &content_ids, ItemState::Active).await;
+    for item in restored.iter().filter_map(|entry| entry.item.clone()) {
+        state.events.publish(&claims.environment, VaultEvent::ItemAdded { item: Box::new(item) }).await;
+    }
+
+    Ok(Json(serde_json::json!({ "success": true, "restored": restored })))
+}
+
+async fn purge_trash_handler(
+    State(state): State<AppState>,
+    Json(req): Json<TrashRequest>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let claims = authorize_session(&state, &req.session_token, Operation::Admin).await?;
+
+    let purged = state.trash
+        .purge(state.store.as_ref(), &claims.environment, req.ids.as_deref())
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to purge trash: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    state.quotas.invalidate(&claims.environment).await;
+    state.catalog.remove_items(&claims.environment, &purged).await;
+
+    Ok(Json(serde_json::json!({ "success": true, "purged": purged })))
+}
+
+/// Starts an anonymous upload into the inbox of the vault owning
+/// `recipient_pubkey`. The content is encrypted with a fresh key, sent
+/// wrapped for the recipient; chunks and finish follow on the inbox routes.
+/// A pubkey without a vault gets an upload ID like any other, which then
+/// behaves like an expired upload, so senders cannot probe for vaults.
+async fn inbox_start_handler(
+    State(state): State<AppState>,
+    Json(req): Json<InboxUploadRequest>,
+) -> Result<Json<InboxUploadResponse>, StatusCode> {
+    if req.wrapped_key.is_empty() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    validate_upload(&req.upload)?;
+    let environment = session::environment_for_pubkey(&req.recipient_pubkey);
+    let preview_len = req.upload.preview.as_ref().map_or(0, |p| p.len() as u64);
+    match state.inbox.admit(state.store.as_ref(), &environment, req.upload.size + preview_len).await {
+        Ok(()) => {}
+        Err(InboxError::NoRecipient) => {
+            return Ok(Json(InboxUploadResponse { file_id: new_upload_id(), environment }));
+        }
+        Err(e) => return Err(inbox_error_status(e)),
+    }
+
+    let file_id = stage_upload(&state, &environment, req.upload, Some(req.wrapped_key)).await?;
+    Ok(Json(InboxUploadResponse { file_id, environment }))
+}
+
+#[derive(Deserialize)]
+struct InboxChunkQuery {
+    environment: String,
+    file_id: String,
+    chunk: usize,
+}
+
+/// Same as `upload_chunk`, for an anonymous inbox upload
+async fn inbox_chunk_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(params): Query<InboxChunkQuery>,
+    body: axum::body::Body,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    if !is_valid_blob_id(&params.environment) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    store_chunk(&state, &params.environment, &params.file_id, params.chunk, &headers, body, true).await
+}
+
+/// Completes an anonymous inbox upload. The sender only gets the content ID
+/// back; the item becomes visible to the recipient in their inbox.
+async fn inbox_finish_handler(
+    State(state): State<AppState>,
+    Json(req): Json<InboxFinishRequest>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+    if !is_valid_blob_id(&req.environment) {
+        return Err(error_json(StatusCode::BAD_REQUEST));
+    }
+    let item = complete_upload(&state, &req.environment, &req.file_id, true).await?;
+
+    Ok(Json(serde_json::json!({ "success": true, "id": item.content_id })))
+}
+
+/// Items waiting in the session's inbox
+async fn list_inbox_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<TokenQuery>,
+) -> Result<Json<Vec<InboxItem>>, StatusCode> {
+    let token = request_token(&headers, query.token.as_deref()).ok_or(StatusCode::UNAUTHORIZED)?;
+    let claims = authorize_session(&state, &token, Operation::Read).await?;
+
+    let items = state.inbox.list(state.store.as_ref(), &claims.environment).await.map_err(|e| {
+        tracing::error!("Failed to read inbox: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(Json(items))
+}
+
+#[derive(Deserialize)]
+struct InboxRequest {
+    session_token: String,
+    ids: Vec<String>,
+}
+
+/// Moves inbox items into the vault; the client re-keys the returned items
+/// with the unwrapped content keys and adds them to its metadata
+async fn accept_inbox_handler(
+    State(state): State<AppState>,
+    Json(req): Json<InboxRequest>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let claims = authorize_session(&state, &req.session_token, Operation::Write).await?;
+
+    // Accepted items leave the inbox limit and count against the quota
+    let items = state.inbox.list(state.store.as_ref(), &claims.environment).await.map_err(|e| {
+        tracing::error!("Failed to read inbox: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let bytes = items.iter().filter(|i| req.ids.contains(&i.id)).map(|i| i.size).sum();
+    let _reservation = state.quotas
+        .reserve_from_inbox(state.store.as_ref(), &claims.environment, bytes)
+        .await
+        .map_err(quota_error_status)?;
+
+    let accepted = state.inbox.accept(state.store.as_ref(), &claims.environment, &req.ids).await.map_err(|e| {
+        tracing::error!("Failed to accept inbox items: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    state.quotas.invalidate(&claims.environment).await;
+    let content_ids: Vec<String> = accepted.iter().map(|received| received.item.content_id.clone()).collect();
+    state.catalog.set_state(&claims.environment, &content_ids, ItemState::Active).await;
+
+    Ok(Json(serde_json::json!({ "success": true, "accepted": accepted })))
+}
+
+async fn reject_inbox_handler(
+    State(state): State<AppState>,
+    Json(req): Json<InboxRequest>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let claims = authorize_session(&state, &req.session_token, Operation::Write).await?;
+
+    let rejected = state.inbox.reject(state.store.as_ref(), &claims.environment, &req.ids).await.map_err(|e| {
+        tracing::error!("Failed to reject inbox items: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    state.quotas.invalidate(&claims.environment).await;
+    state.catalog.invalidate(&claims.environment).await;
+
+    Ok(Json(serde_json::json!({ "success": true, "rejected": rejected })))
+}
+
+/// Shares a content blob (and its preview) with another user's pubkey. The
+/// content key arrives already wrapped for the recipient.
+async fn create_share_handler(
+    State(state): State<AppState>,
+    Json(req): Json<CreateShareRequest>,
+) -> Result<Json<ShareGrant>, StatusCode> {
+    let claims = authorize_session(&state, &req.session_token, Operation::Admin).await?;
+
+    let now = crypto::current_timestamp();
+    let recipient_env = session::environment_for_pubkey(&req.recipient_pubkey);
+    if req.recipient_pubkey.is_empty()
+        || recipient_env == claims.environment
+        || req.wrapped_key.is_empty()
+        || !is_valid_blob_id(&req.content_id)
+        || req.preview_id.as_deref().is_some_and(|id| !is_valid_blob_id(id))
+        || req.expires_at.is_some_and(|t| t <= now)
+    {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    for blob_id in std::iter::once(&req.content_id).chain(&req.preview_id) {
+        state.store.stat(&claims.environment, blob_id).await.map_err(|_| StatusCode::NOT_FOUND)?;
+    }
+
+    let grant = ShareGrant {
+        id: hex::encode(crypto::random_bytes::<16>()),
+        owner_env: claims.environment.clone(),
+        owner_pubkey: claims.pubkey,
+        recipient_env,
+        recipient_pubkey: req.recipient_pubkey,
+        content_id: req.content_id,
+        preview_id: req.preview_id,
+        wrapped_key: req.wrapped_key,
+        encrypted_meta: req.encrypted_meta,
+        meta_nonce: req.meta_nonce,
+        created_at: now,
+        expires_at: req.expires_at,
+    };
+    state.shares.grant(state.store.as_ref(), grant.clone()).await.map_err(|e| {
+        tracing::error!("Failed to store share grant: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    state.quotas.invalidate(&grant.owner_env).await;
+    state.quotas.invalidate(&grant.recipient_env).await;
+
+    Ok(Json(grant))
+}
+
+/// Grants created by the session's environment that have not expired
+async fn list_shares_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<TokenQuery>,
+) -> Result<Json<Vec<ShareGrant>>, StatusCode> {
+    let token = request_token(&headers, query.token.as_deref()).ok_or(StatusCode::UNAUTHORIZED)?;
+    let claims = authorize_session(&state, &token, Operation::Admin).await?;
+
+    let grants = state.shares
+        .outgoing(state.store.as_ref(), &claims.environment, crypto::current_timestamp())
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to read share grants: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    Ok(Json(grants))
+}
+
+/// Grants received by the session's environment that have not expired
+async fn incoming_shares_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<TokenQuery>,
+) -> Result<Json<Vec<ShareGrant>>, StatusCode> {
+    let token = request_token(&headers, query.token.as_deref()).ok_or(StatusCode::UNAUTHORIZED)?;
+    let claims = authorize_session(&state, &token, Operation::Read).await?;
+
+    let grants = state.shares
+        .incoming(state.store.as_ref(), &claims.environment, crypto::current_timestamp())
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to read share grants: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    Ok(Json(grants))
+}
+
+#[derive(Deserialize)]
+struct RevokeSharesRequest {
+    session_token: String,
+    ids: Vec<String>,
+}
+
+/// Revokes grants created by the session's environment
+async fn revoke_shares_handler(
+    State(state): State<AppState>,
+    Json(req): Json<RevokeSharesRequest>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let claims = authorize_session(&state, &req.session_token, Operation::Admin).await?;
+
+    let revoked = state.shares.revoke(state.store.as_ref(), &claims.environment, &req.ids).await.map_err(|e| {
+        tracing::error!("Failed to revoke share grants: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    state.quotas.invalidate(&claims.environment).await;
+
+    Ok(Json(serde_json::json!({ "success": true, "revoked": revoked })))
+}
+
+/// Issues a public link to a content blob, with optional expiry, download
+/// limit and password
+async fn create_link_handler(
+    State(state): State<AppState>,
+    Json(req): Json<CreateLinkRequest>,
+) -> Result<Json<ShareLink>, StatusCode> {
+    let claims = authorize_session(&state, &req.session_token, Operation::Admin).await?;
+
+    let now = crypto::current_timestamp();
+    if !is_valid_blob_id(&req.content_id)
+        || req.expires_at.is_some_and(|t| t <= now)
+        || req.max_downloads == Some(0)
+        || req.password.as_deref().is_some_and(str::is_empty)
+    {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    state.store.stat(&claims.environment, &req.content_id).await.map_err(|_| StatusCode::NOT_FOUND)?;
+
+    let link = ShareLink {
+        id: hex::encode(crypto::random_bytes::<16>()),
+        content_id: req.content_id,
+        created_at: now,
+        expires_at: req.expires_at,
+        max_downloads: req.max_downloads,
+        downloads: 0,
+        protected: req.password.is_some(),
+    };
+    let link = state.links
+        .create(state.store.as_ref(), &claims.environment, link, req.password)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to store public link: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    state.quotas.invalidate(&claims.environment).await;
+
+    Ok(Json(link))
+}
+
+/// Public links of the session's environment that can still be used
+async fn list_links_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<TokenQuery>,
+) -> Result<Json<Vec<ShareLink>>, StatusCode> {
+    let token = request_token(&headers, query.token.as_deref()).ok_or(StatusCode::UNAUTHORIZED)?;
+    let claims = authorize_session(&state, &token, Operation::Admin).await?;
+
+    let links = state.links
+        .list(state.store.as_ref(), &claims.environment, crypto::current_timestamp())
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to read public links: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    Ok(Json(links))
+}
+
+#[derive(Deserialize)]
+struct RevokeLinksRequest {
+    session_token: String,
+    ids: Vec<String>,
+}
+
+async fn revoke_links_handler(
+    State(state): State<AppState>,
+    Json(req): Json<RevokeLinksRequest>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let claims = authorize_session(&state, &req.session_token, Operation::Admin).await?;
+
+    let revoked = state.links.revoke(state.store.as_ref(), &claims.environment, &req.ids).await.map_err(|e| {
+        tracing::error!("Failed to revoke public links: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    state.quotas.invalidate(&claims.environment).await;
+
+    Ok(Json(serde_json::json!({ "success": true, "revoked": revoked })))
+}
+
+/// Streams the ciphertext behind a public link to anyone holding it. The key
+/// travels in the URL fragment and never reaches the node; the password, if
+/// any, is sent as `X-Link-Password`. Requests that reach the first or the
+/// last byte count as a download, so a download split into ranges uses up
+/// the limit while resuming it in the middle does not.
+async fn public_link_handler(
+    State(state): State<AppState>,
+    Path((env, link_id)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    if !is_valid_blob_id(&env) || !is_valid_blob_id(&link_id) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    let password = headers
+        .get("x-link-password")
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string);
+    let now = crypto::current_timestamp();
+    let link = state.links
+        .authorize(state.store.as_ref(), &env, &link_id, password, now)
+        .await
+        .map_err(link_error_status)?;
+    // Shard-only content has no size without rebuilding it: always counts
+    let counts = match state.store.stat(&env, &link.content_id).await {
+        Ok(stat) => download::reaches_blob_edge(&headers, &link.content_id, stat.size),
+        Err(_) => true,
+    };
+    if counts {
+        state.links
+            .claim(state.store.as_ref(), &env, &link_id, now)
+            .await
+            .map_err(link_error_status)?;
+    }
+
+    let response = serve_content(&state, &env, &link.content_id, &headers).await;
+    if counts && !response.as_ref().is_ok_and(|r| r.status().is_success()) {
+        if let Err(e) = state.links.release(state.store.as_ref(), &env, &link_id).await {
+            tracing::warn!("Failed to release download on link {}: {}", link_id, e);
+        }
+    }
+    response
+}
+
+/// Creates a team environment with the caller as its only owner. The first
+/// change (version 1) must add the caller as owner and be signed by them.
+async fn create_team_handler(
+    State(state): State<AppState>,
+    Json(req): Json<MembershipRequest>,
+) -> Result<Json<TeamManifest>, StatusCode> {
+    let claims = state.sessions.validate(&req.session_token).await.map_err(session_error_status)?;
+
+    let manifest = state.teams
+        .create(state.store.as_ref(), req.change, &claims.pubkey, req.signature)
+        .await
+        .map_err(team_error_status)?;
+    Ok(Json(manifest))
+}
+
+/// Adds, re-roles or removes a member. The change must be signed by an owner
+/// and carry the version following the current manifest.
+async fn change_membership_handler(
+    State(state): State<AppState>,
+    Json(req): Json<MembershipRequest>,
+) -> Result<Json<TeamManifest>, StatusCode> {
+    let claims = state.sessions.validate(&req.session_token).await.map_err(session_error_status)?;
+
+    let manifest = state.teams
+        .apply(state.store.as_ref(), req.change, &claims.pubkey, req.signature)
+        .await
+        .map_err(team_error_status)?;
+    let members: Vec<&str> = manifest.members.iter().map(|m| m.pubkey.as_str()).collect();
+    state.events.retain_members(&manifest.environment, &members).await;
+    Ok(Json(manifest))
+}
+
+/// Teams the caller belongs to
+async fn list_teams_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<TokenQuery>,
+) -> Result<Json<Vec<TeamManifest>>, StatusCode> {
+    let token = request_token(&headers, query.token.as_deref()).ok_or(StatusCode::UNAUTHORIZED)?;
+    let claims = state.sessions.validate(&token).await.map_err(session_error_status)?;
+
+    let teams = state.teams.teams_of(state.store.as_ref(), &claims.pubkey).await.map_err(|e| {
+        tracing::error!("Failed to list teams: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(Json(teams))
+}
+
+/// Members of a team, with their wrapped keys and the signed change log
+async fn get_team_handler(
+    State(state): State<AppState>,
+    Path(env): Path<String>,
+    headers: HeaderMap,
+    Query(query): Query<TokenQuery>,
+) -> Result<Json<TeamManifest>, StatusCode> {
+    let token = request_token(&headers, query.token.as_deref()).ok_or(StatusCode::UNAUTHORIZED)?;
+    let claims = state.sessions.validate(&token).await.map_err(session_error_status)?;
+    if member_role(&state, &claims, &env).await?.is_none() {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let manifest = state.teams.manifest(state.store.as_ref(), &env).await.map_err(|e| {
+        tracing::error!("Failed to read team manifest for {}: {}", env, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    manifest.map(Json).ok_or(StatusCode::NOT_FOUND)
+}
+
+/// Validates a session token (never a capability, which could otherwise mint
+/// itself a copy without its caveats) and returns the caller's role
+async fn authorize_issuer(state: &AppState, token: &str) -> Result<(SessionClaims, Role), StatusCode> {
+    let claims = state.sessions.validate(token).await.map_err(session_error_status)?;
+    let role = member_role(state, &claims, &claims.environment).await?.ok_or(StatusCode::FORBIDDEN)?;
+    Ok((claims, role))
+}
+
+/// Mints a capability for the session's environment. It acts with the
+/// caller's role, narrowed by the caveats given here and any added later.
+async fn create_capability_handler(
+    State(state): State<AppState>,
+    Json(req): Json<CreateCapabilityRequest>,
+) -> Result<Json<CapabilityResponse>, StatusCode> {
+    let (claims, _) = authorize_issuer(&state, &req.session_token).await?;
+    if req.expires_at.is_some_and(|t| t <= crypto::current_timestamp()) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let (token, capability) = state.capabilities
+        .mint(state.store.as_ref(), &claims.environment, &claims.pubkey, req.label, req.caveats, req.expires_at)
+        .await
+        .map_err(capability_error_status)?;
+    state.quotas.invalidate(&claims.environment).await;
+
+    Ok(Json(CapabilityResponse { token, capability }))
+}
+
+/// Capabilities of the session's environment: owners see all of them,
+/// other members only those they issued
+async fn list_capabilities_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<TokenQuery>,
+) -> Result<Json<Vec<CapabilityInfo>>, StatusCode> {
+    let token = request_token(&headers, query.token.as_deref()).ok_or(StatusCode::UNAUTHORIZED)?;
+    let (claims, role) = authorize_issuer(&state, &token).await?;
+
+    let mut capabilities = state.capabilities
+        .list(state.store.as_ref(), &claims.environment, crypto::current_timestamp())
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to read capabilities: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    if role != Role::Owner {
+        capabilities.retain(|c| c.issuer == claims.pubkey);
+    }
+    Ok(Json(capabilities))
+}
+
+#[derive(Deserialize)]
+struct RevokeCapabilitiesRequest {
+    session_token: String,
+    ids: Vec<String>,
+}
+
+/// Revokes capabilities and every token attenuated from them. Owners may
+/// revoke any capability of the environment, other members their own.
+async fn revoke_capabilities_handler(
+    State(state): State<AppState>,
+    Json(req): Json<RevokeCapabilitiesRequest>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let (claims, role) = authorize_issuer(&state, &req.session_token).await?;
+    let issuer = (role != Role::Owner).then_some(claims.pubkey.as_str());
+
+    let revoked = state.capabilities
+        .revoke(state.store.as_ref(), &claims.environment, &req.ids, issuer)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to revoke capabilities: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    state.quotas.invalidate(&claims.environment).await;
+
+    Ok(Json(serde_json::json!({ "success": true, "revoked": revoked })))
+}
+
+/// Mints a capability and returns it as credentials for the S3 gateway: the
+/// access key names the capability and the secret is its signature, so the
+/// caveats apply to S3 requests and revoking the capability revokes the key
+async fn create_s3_credentials_handler(
+    State(state): State<AppState>,
+    Json(req): Json<CreateCapabilityRequest>,
+) -> Result<Json<S3CredentialsResponse>, StatusCode> {
+    let (claims, _) = authorize_issuer(&state, &req.session_token).await?;
+    if req.expires_at.is_some_and(|t| t <= crypto::current_timestamp()) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let (_, capability) = state.capabilities
+        .mint(state.store.as_ref(), &claims.environment, &claims.pubkey, req.label, req.caveats, req.expires_at)
+        .await
+        .map_err(capability_error_status)?;
+    state.quotas.invalidate(&claims.environment).await;
+
+    let (access_key_id, secret_access_key) = state.capabilities.access_key(&claims.environment, &capability);
+    Ok(Json(S3CredentialsResponse {
+        access_key_id,
+        secret_access_key,
+        bucket: claims.environment,
+        region: state.node.read().await.s3_gateway.region.clone(),
+        capability,
+    }))
+}
+
+/// Peers the session's environment is replicated to, with their sync state
+async fn list_replicas_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<TokenQuery>,
+) -> Result<Json<Vec<ReplicaInfo>>, StatusCode> {
+    let token = request_token(&headers, query.token.as_deref()).ok_or(StatusCode::UNAUTHORIZED)?;
+    let claims = authorize_session(&state, &token, Operation::Read).await?;
+
+    let replicas = state.replication.list(state.store.as_ref(), &claims.environment).await.map_err(|e| {
+        tracing::error!("Failed to read replication state: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(Json(replicas))
+}
+
+/// Sets the configured peers the environment is replicated to; an empty list
+/// stops replication, leaving existing copies on the peers
+async fn configure_replication_handler(
+    State(state): State<AppState>,
+    Json(req): Json<ReplicationRequest>,
+) -> Result<Json<Vec<ReplicaInfo>>, StatusCode> {
+    let claims = authorize_session(&state, &req.session_token, Operation::Admin).await?;
+
+    let replicas = state.replication
+        .configure(state.store.as_ref(), &claims.environment, &req.peers)
+        .await
+        .map_err(replication_error_status)?;
+    Ok(Json(replicas))
+}
+
+#[derive(Deserialize)]
+struct SyncReplicationRequest {
+    session_token: String,
+}
+
+/// Syncs the environment with its replicas now instead of waiting for the
+/// next scheduled pass
+async fn sync_replication_handler(
+    State(state): State<AppState>,
+    Json(req): Json<SyncReplicationRequest>,
+) -> Result<Json<Vec<ReplicaInfo>>, StatusCode> {
+    let claims = authorize_session(&state, &req.session_token, Operation::Admin).await?;
+    let env = &claims.environment;
+
+    let repaired = state.replication.sync(state.store.as_ref(), env).await.map_err(|e| {
+        tracing::error!("Replication failed for {}: {}", env, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    if repaired > 0 {
+        state.quotas.invalidate(env).await;
+    }
+
+    let replicas = state.replication.list(state.store.as_ref(), env).await.map_err(|e| {
+        tracing::error!("Failed to read replication state: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(Json(replicas))
+}
+
+/// Audits the environment's replicas now; the outcome of each is in the
+/// returned replica state
+async fn audit_replication_handler(
+    State(state): State<AppState>,
+    Json(req): Json<SyncReplicationRequest>,
+) -> Result<Json<Vec<ReplicaInfo>>, StatusCode> {
+    let claims = authorize_session(&state, &req.session_token, Operation::Admin).await?;
+    let env = &claims.environment;
+
+    state.replication.audit(state.store.as_ref(), &state.discovery, env).await.map_err(|e| {
+        tracing::error!("Audit failed for {}: {}", env, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    let replicas = state.replication.list(state.store.as_ref(), env).await.map_err(|e| {
+        tracing::error!("Failed to read replication state: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(Json(replicas))
+}
+
+/// Content of the session's environment that lives as shards on peers
+async fn list_shards_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<TokenQuery>,
+) -> Result<Json<Vec<ShardMap>>, StatusCode> {
+    let token = request_token(&headers, query.token.as_deref()).ok_or(StatusCode::UNAUTHORIZED)?;
+    let claims = authorize_session(&state, &token, Operation::Read).await?;
+
+    let maps = state.erasure.list(state.store.as_ref(), &claims.environment).await.map_err(|e| {
+        tracing::error!("Failed to read shard maps: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(Json(maps))
+}
+
+/// Streams the session's environment as a tar archive: metadata, journal,
+/// content and previews, then `manifest.json` with every blob's size and hash
+async fn export_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<TokenQuery>,
+) -> Result<Response, StatusCode> {
+    let token = request_token(&headers, query.token.as_deref()).ok_or(StatusCode::UNAUTHORIZED)?;
+    let claims = authorize_session(&state, &token, Operation::Admin).await?;
+
+    let env = claims.environment.clone();
+    let (writer, reader) = tokio::io::duplex(64 * 1024);
+    tokio::spawn(async move {
+        // A failed export ends the stream early, without the manifest, so an
+        // import of it is rejected rather than partial
+        let exported = archive::export_environment(state.store.as_ref(), &state.erasure, &state.replication, &env, writer).await;
+        if let Err(e) = exported {
+            tracing::warn!("Export of {} failed: {}", env, e);
+        }
+    });
+    Ok(archive_response(&format!("vault-{}.tar", claims.environment), reader))
+}
+
+#[derive(Deserialize)]
+struct ArchiveItemsRequest {
+    session_token: String,
+    /// Content IDs; previews are included with their content
+    ids: Vec<String>,
+}
+
+/// Selected items and their previews as one archive, in the export format
+async fn archive_items_handler(
+    State(state): State<AppState>,
+    Json(req): Json<ArchiveItemsRequest>,
+) -> Result<Response, StatusCode> {
+    let claims = authorize_scoped(&state, &req.session_token, Operation::Read, &req.ids, 0).await?;
+
+    let env = claims.environment.clone();
+    let (writer, reader) = tokio::io::duplex(64 * 1024);
+    tokio::spawn(async move {
+        let exported = archive::export_items(state.store.as_ref(), &state.erasure, &state.replication, &env, &req.ids, writer).await;
+        if let Err(e) = exported {
+            tracing::warn!("Archive of {} items from {} failed: {}", req.ids.len(), env, e);
+        }
+    });
+    Ok(archive_response("vault-items.tar", reader))
+}
+
+fn archive_response(filename: &str, reader: tokio::io::DuplexStream) -> Response {
+    (
+        [
+            (header::CONTENT_TYPE, "application/x-tar".to_string()),
+            (header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", filename)),
+        ],
+        axum::body::Body::from_stream(tokio_util::io::ReaderStream::new(reader)),
+    )
+        .into_response()
+}
+
+/// Restores an exported archive into the session's environment. Each blob is
+/// charged to the quota as it arrives and blobs are verified against the
+/// manifest before anything is written; existing blobs
+/// are never overwritten and differing ones are reported as conflicts.
+async fn import_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<TokenQuery>,
+    body: axum::body::Body,
+) -> Result<Json<ImportReport>, StatusCode> {
+    let token = request_token(&headers, query.token.as_deref()).ok_or(StatusCode::UNAUTHORIZED)?;
+    let claims = authorize_session(&state, &token, Operation::Admin).await?;
+    let env = &claims.environment;
+
+    let input = tokio_util::io::StreamReader::new(body.into_data_stream().map(|chunk| chunk.map_err(std::io::Error::other)));
+    let staged = archive::stage(state.store.as_ref(), &state.quotas, env, input).await.map_err(archive_error_status)?;
+    let report = archive::commit(state.store.as_ref(), &state.quotas, env, staged).await.map_err(|e| {
+        tracing::error!("Archive import into {} failed: {}", env, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    state.catalog.invalidate(env).await;
+
+    if report.imported.iter().any(|key| journal::is_journal_key(key)) {
+        if let Ok(Some(head)) = state.journal.history(state.store.as_ref(), env).await.map(|h| h.last().cloned()) {
+            state.events.publish(env, VaultEvent::MetadataRevision { revision: head.revision }).await;
+        }
+    }
+    Ok(Json(report))
+}
+
+/// Consistency report for the session's environment; changes nothing
+async fn fsck_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<TokenQuery>,
+) -> Result<Json<FsckReport>, StatusCode> {
+    let token = request_token(&headers, query.token.as_deref()).ok_or(StatusCode::UNAUTHORIZED)?;
+    let claims = authorize_session(&state, &token, Operation::Read).await?;
+    let config = state.node.read().await.gc.clone();
+
+    let report = fsck::scan(state.store.as_ref(), &claims.environment, &config).await.map_err(|e| {
+        tracing::error!("fsck failed for {}: {}", claims.environment, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(Json(report))
+}
+
+#[derive(Deserialize)]
+struct GcRequest {
+    session_token: String,
+    #[serde(default = "default_true")]
+    dry_run: bool,
+}
+
+fn default_true() -> bool { true }
+
+/// Runs the garbage collector on the session's environment (dry run unless asked otherwise)
+async fn gc_handler(
+    State(state): State<AppState>,
+    Json(req): Json<GcRequest>,
+) -> Result<Json<FsckReport>, StatusCode> {
+    let claims = authorize_session(&state, &req.session_token, Operation::Admin).await?;
+    let config = state.node.read().await.gc.clone();
+
+    let report = fsck::collect(state.store.as_ref(), &state.trash, &claims.environment, &config, req.dry_run)
+        .await
+        .map_err(|e| {
+            tracing::error!("GC failed for {}: {}", claims.environment, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    if report.applied {
+        state.quotas.invalidate(&claims.environment).await;
+        state.catalog.invalidate(&claims.environment).await;
+    }
+    Ok(Json(report))
+}
+
+#[derive(Deserialize)]
+struct LiveSetRequest {
+    session_token: String,
+    /// Content and preview IDs still referenced by the client's metadata
+    ids: Vec<String>,
+}
+
+/// Records which blobs the client still references, so GC can find the rest
+async fn register_live_handler(
+    State(state): State<AppState>,
+    Json(req): Json<LiveSetRequest>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let claims = authorize_session(&state, &req.session_token, Operation::Write).await?;
+
+    let live = fsck::register_live_set(state.store.as_ref(), &claims.environment, req.ids).await.map_err(|e| {
+        tracing::error!("Failed to store live set: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    state.quotas.invalidate(&claims.environment).await;
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "count": live.ids.len(),
+        "registered_at": live.registered_at,
+    })))
+}
+
+/// Space used by the session's environment, with its quota
+async fn usage_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<TokenQuery>,
+) -> Result<Json<UsageReport>, StatusCode> {
+    let token = request_token(&headers, query.token.as_deref()).ok_or(StatusCode::UNAUTHORIZED)?;
+    let claims = authorize_session(&state, &token, Operation::Read).await?;
+
+    let report = state.quotas.usage(state.store.as_ref(), &claims.environment).await.map_err(|e| {
+        tracing::error!("Failed to compute usage for {}: {}", claims.environment, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(report))
+}
+
+// ============== S3 GATEWAY ==============
+
+/// Routes of the S3-compatible endpoint. Only path-style addressing is
+/// supported: the bucket is the environment, `/{env}/{key}`.
+fn s3_routes(state: AppState) -> Router {
+    Router::new()
+        .route("/", any(s3_service_handler))
+        .route("/{bucket}", any(s3_bucket_handler))
+        .route("/{bucket}/", any(s3_bucket_handler))
+        .route("/{bucket}/{*key}", any(s3_object_handler))
+        .with_state(state)
+}
+
+/// Caller of an S3 request, acting through the capability its access key
+/// was minted from
+struct S3Caller {
+    request: gateway::SignedRequest,
+    claims: SessionClaims,
+    caveats: Caveats,
+}
+
+/// Verifies the SigV4 signature of a request with the secret of the
+/// capability named by its access key
+async fn authenticate_s3(state: &AppState, method: &Method, uri: &Uri, headers: &HeaderMap) -> Result<S3Caller, S3Error> {
+    let authorization = gateway::Authorization::parse(method, uri.path(), uri.query(), headers)?;
+    let now = crypto::current_timestamp();
+    let (secret, claims, caveats) = state.capabilities
+        .resolve_access_key(state.store.as_ref(), &authorization.access_key, now)
+        .await
+        .map_err(|e| match e {
+            CapabilityError::Io(e) => S3Error::internal(e),
+            CapabilityError::Expired => S3Error::new(StatusCode::FORBIDDEN, "ExpiredToken", "The provided credentials have expired."),
+            _ => S3Error::new(
+                StatusCode::FORBIDDEN,
+                "InvalidAccessKeyId",
+                "The AWS Access Key Id you provided does not exist in our records.",
+            ),
+        })?;
+    let config = state.node.read().await.s3_gateway.clone();
+    let request = authorization.verify(&secret, &config, now)?;
+    Ok(S3Caller { request, claims, caveats })
+}
+
+/// Checks that the caller's environment is the bucket before anything of it
+/// is read, so other environments' objects cannot be probed
+fn check_bucket(caller: &S3Caller, bucket: &str) -> Result<(), S3Error> {
+    if caller.claims.environment != bucket {
+        return Err(S3Error::access_denied());
+    }
+    Ok(())
+}
+
+/// Same checks as `authorize_scoped`: the capability's caveats and the
+/// issuer's current role in the environment
+async fn authorize_s3(state: &AppState, caller: &S3Caller, op: Operation, content: &[String], bytes: u64) -> Result<(), S3Error> {
+    if !caller.caveats.permits(op, content, bytes) {
+        return Err(S3Error::access_denied());
+    }
+    if member_role(state, &caller.claims, &caller.claims.environment).await?.is_none_or(|r| r < op.role()) {
+        return Err(S3Error::access_denied());
+    }
+    Ok(())
+}
+
+fn method_not_allowed() -> S3Error {
+    S3Error::new(StatusCode::METHOD_NOT_ALLOWED, "MethodNotAllowed", "The specified method is not allowed against this resource.")
+}
+
+/// ListBuckets: the only bucket is the credential's environment
+async fn s3_service_handler(
+    State(state): State<AppState>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+) -> Result<Response, S3Error> {
+    let caller = authenticate_s3(&state, &method, &uri, &headers).await?;
+    if method != Method::GET {
+        return Err(method_not_allowed());
+    }
+    authorize_s3(&state, &caller, Operation::Read, &[], 0).await?;
+    let body = gateway::list_buckets_xml(&caller.claims.environment, caller.claims.issued_at);
+    Ok(gateway::xml_response(StatusCode::OK, body))
+}
+
+async fn s3_bucket_handler(
+    State(state): State<AppState>,
+    Path(bucket): Path<String>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<Response, S3Error> {
+    let caller = authenticate_s3(&state, &method, &uri, &headers).await?;
+    check_bucket(&caller, &bucket)?;
+    let params: HashMap<String, String> = gateway::parse_query(uri.query()).into_iter().collect();
+
+    match method {
+        Method::HEAD => {
+            authorize_s3(&state, &caller, Operation::Read, &[], 0).await?;
+            Ok(StatusCode::OK.into_response())
+        }
+        // The environment exists already; clients that create their bucket first get it as is
+        Method::PUT => {
+            authorize_s3(&state, &caller, Operation::Upload, &[], 0).await?;
+            Ok(StatusCode::OK.into_response())
+        }
+        Method::GET if params.contains_key("location") => {
+            authorize_s3(&state, &caller, Operation::Read, &[], 0).await?;
+            let region = state.node.read().await.s3_gateway.region.clone();
+            Ok(gateway::xml_response(StatusCode::OK, gateway::location_xml(&region)))
+        }
+        Method::GET if params.contains_key("versioning") => {
+            authorize_s3(&state, &caller, Operation::Read, &[], 0).await?;
+            let body = "<VersioningConfiguration xmlns=\"http://s3.amazonaws.com/doc/2006-03-01/\"/>".to_string();
+            Ok(gateway::xml_response(StatusCode::OK, body))
+        }
+        Method::GET if params.contains_key("uploads") => {
+            authorize_s3(&state, &caller, Operation::Upload, &[], 0).await?;
+            let uploads = gateway::list_multipart(&bucket).await?;
+            let prefix = params.get("prefix").map(String::as_str).unwrap_or_default();
+            Ok(gateway::xml_response(StatusCode::OK, gateway::list_multipart_xml(&bucket, &uploads, prefix)))
+        }
+        Method::GET => {
+            authorize_s3(&state, &caller, Operation::Read, &[], 0).await?;
+            let objects = state.objects.list(state.store.as_ref(), &bucket).await?;
+            Ok(gateway::xml_response(StatusCode::OK, gateway::list_objects_xml(&bucket, &objects, &params)?))
+        }
+        Method::POST if params.contains_key("delete") => {
+            let data = gateway::read_small(&caller.request, body, 1024 * 1024).await?;
+            let (keys, quiet) = gateway::parse_delete(&data)?;
+            delete_s3_objects(&state, &caller, &bucket, &keys).await?;
+            Ok(gateway::xml_response(StatusCode::OK, gateway::delete_result_xml(&keys, quiet)))
+        }
+        Method::DELETE => Err(S3Error::new(
+            StatusCode::CONFLICT,
+            "BucketNotEmpty",
+            "Environments cannot be deleted through the S3 gateway.",
+        )),
+        _ => Err(method_not_allowed()),
+    }
+}
+
+async fn s3_object_handler(
+    State(state): State<AppState>,
+    Path((bucket, key)): Path<(String, String)>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<Response, S3Error> {
+    let caller = authenticate_s3(&state, &method, &uri, &headers).await?;
+    check_bucket(&caller, &bucket)?;
+    if !gateway::is_valid_key(&key) {
+        return Err(S3Error::invalid_argument("Invalid object key."));
+    }
+    let params: HashMap<String, String> = gateway::parse_query(uri.query()).into_iter().collect();
+    let upload_id = params.get("uploadId").map(String::as_str);
+
+    match (method, upload_id) {
+        (Method::GET | Method::HEAD, None) => get_s3_object(&state, &caller, &bucket, &key, &headers).await,
+        (Method::PUT, Some(_)) => {
+            if headers.contains_key("x-amz-copy-source") {
+                return Err(S3Error::not_implemented());
+            }
+            upload_s3_part(&state, &caller, &bucket, &key, &params, &headers, body).await
+        }
+        (Method::PUT, None) => match headers.get("x-amz-copy-source").and_then(|v| v.to_str().ok()) {
+            Some(source) => copy_s3_object(&state, &caller, &bucket, &key, source).await,
+            None => put_s3_object(&state, &caller, &bucket, &key, &headers, body).await,
+        },
+        (Method::POST, None) if params.contains_key("uploads") => {
+            authorize_s3(&state, &caller, Operation::Upload, &[], 0).await?;
+            let upload_id = gateway::create_multipart(&bucket, &key, &caller.claims.pubkey).await?;
+            Ok(gateway::xml_response(StatusCode::OK, gateway::initiate_multipart_xml(&bucket, &key, &upload_id)))
+        }
+        (Method::POST, Some(upload_id)) => complete_s3_multipart(&state, &caller, &bucket, &key, upload_id, body).await,
+        (Method::DELETE, Some(upload_id)) => {
+            gateway::read_multipart(&bucket, upload_id, &key, &caller.claims.pubkey).await?;
+            authorize_s3(&state, &caller, Operation::Upload, &[], 0).await?;
+            gateway::remove_multipart(&bucket, upload_id).await?;
+            state.quotas.invalidate(&bucket).await;
+            Ok(StatusCode::NO_CONTENT.into_response())
+        }
+        (Method::DELETE, None) => {
+            delete_s3_objects(&state, &caller, &bucket, std::slice::from_ref(&key)).await?;
+            Ok(StatusCode::NO_CONTENT.into_response())
+        }
+        (Method::GET, Some(_)) => Err(S3Error::not_implemented()),
+        _ => Err(method_not_allowed()),
+    }
+}
+
+/// Content blob behind an object name: an object written over S3, or any
+/// vault content addressed by its content ID
+async fn resolve_s3_object(state: &AppState, env: &str, key: &str) -> Result<(String, Option<S3Object>), S3Error> {
+    match state.objects.get(state.store.as_ref(), env, key).await? {
+        Some(object) => Ok((object.content_id.clone(), Some(object))),
+        None if is_valid_blob_id(key) => Ok((key.to_string(), None)),
+        None => Err(S3Error::no_such_key()),
+    }
+}
+
+async fn get_s3_object(state: &AppState, caller: &S3Caller, env: &str, key: &str, headers: &HeaderMap) -> Result<Response, S3Error> {
+    let (content_id, object) = resolve_s3_object(state, env, key).await?;
+    authorize_s3(state, caller, Operation::Read, std::slice::from_ref(&content_id), 0).await?;
+
+    let mut response = serve_content(state, env, &content_id, headers).await?;
+    // Vault content has no MD5; the multipart form keeps clients from checking it as one
+    let etag = match &object {
+        Some(object) => format!("\"{}\"", object.etag),
+        None => format!("\"{}-1\"", content_id),
+    };
+    let response_headers = response.headers_mut();
+    if let Ok(value) = HeaderValue::from_str(&etag) {
+        response_headers.insert(header::ETAG, value);
+    }
+    if let Some(object) = object {
+        let modified = std::time::UNIX_EPOCH + std::time::Duration::from_secs(object.modified);
+        if let Ok(value) = HeaderValue::from_str(&httpdate::fmt_http_date(modified)) {
+            response_headers.insert(header::LAST_MODIFIED, value);
+        }
+    }
+    Ok(response)
+}
+
+/// Moves a received body into the store under a new content ID and points
+/// the object name at it; the blob it replaces goes to the trash
+async fn store_s3_object(state: &AppState, env: &str, key: &str, received: gateway::ReceivedFile) -> Result<S3Object, S3Error> {
+    let content_id = hex::encode(crypto::random_bytes::<16>());
+    let stored = state.store.put_file(env, &content_id, &received.path).await;
+    let object = S3Object {
+        content_id,
+        size: received.size,
+        etag: received.etag.clone(),
+        modified: crypto::current_timestamp(),
+    };
+    received.discard().await;
+    stored?;
+
+    let previous = state.objects.insert(state.store.as_ref(), env, key, object.clone()).await?;
+    if let Some(previous) = previous {
+        trash_s3_blobs(state, env, vec![previous.content_id]).await?;
+    }
+    state.quotas.invalidate(env).await;
+    Ok(object)
+}
+
+async fn trash_s3_blobs(state: &AppState, env: &str, ids: Vec<String>) -> Result<(), S3Error> {
+    state.erasure
+        .unshard(state.store.as_ref(), &state.replication, env, &ids)
+        .await
+        .map_err(S3Error::internal)?;
+    state.trash.trash(state.store.as_ref(), env, &ids).await?;
+    Ok(())
+}
+
+/// Deletes objects written over S3. Names that do not exist, including vault
+/// content IDs, are ignored as S3 does for missing keys.
+async fn delete_s3_objects(state: &AppState, caller: &S3Caller, env: &str, keys: &[String]) -> Result<(), S3Error> {
+    let objects = state.objects.list(state.store.as_ref(), env).await?;
+    let content: Vec<String> = keys.iter()
+        .filter_map(|key| objects.get(key).map(|o| o.content_id.clone()))
+        .collect();
+    authorize_s3(state, caller, Operation::Write, &content, 0).await?;
+
+    let removed = state.objects.remove(state.store.as_ref(), env, keys).await?;
+    if !removed.is_empty() {
+        trash_s3_blobs(state, env, removed.into_iter().map(|(_, o)| o.content_id).collect()).await?;
+        state.quotas.invalidate(env).await;
+    }
+    Ok(())
+}
+
+/// Checks the declared length of an upload against the S3 limit and the
+/// caveats, and reserves it in the environment's quota
+async fn admit_s3_upload(
+    state: &AppState,
+    caller: &S3Caller,
+    env: &str,
+    headers: &HeaderMap,
+) -> Result<(u64, quota::Reservation), S3Error> {
+    let size = gateway::declared_length(headers, &caller.request.payload)?;
+    if size > gateway::MAX_OBJECT_BYTES {
+        return Err(S3Error::new(StatusCode::BAD_REQUEST, "EntityTooLarge", "Your proposed upload exceeds the maximum allowed size."));
+    }
+    authorize_s3(state, caller, Operation::Upload, &[], size).await?;
+    let reservation = state.quotas.reserve(state.store.as_ref(), env, size).await.map_err(quota_error_status)?;
+    Ok((size, reservation))
+}
+
+/// Receives exactly `size` bytes of the request body
+async fn receive_s3_body(caller: &S3Caller, env: &str, body: Body, size: u64) -> Result<gateway::ReceivedFile, S3Error> {
+    let received = gateway::receive(env, &caller.request, body, size).await?;
+    if received.size != size {
+        received.discard().await;
+        return Err(S3Error::new(StatusCode::BAD_REQUEST, "IncompleteBody", "You did not provide the number of bytes specified by the Content-Length HTTP header."));
+    }
+    Ok(received)
+}
+
+async fn put_s3_object(
+    state: &AppState,
+    caller: &S3Caller,
+    env: &str,
+    key: &str,
+    headers: &HeaderMap,
+    body: Body,
+) -> Result<Response, S3Error> {
+    let (size, _reservation) = admit_s3_upload(state, caller, env, headers).await?;
+    let received = receive_s3_body(caller, env, body, size).await?;
+    let object = store_s3_object(state, env, key, received).await?;
+    Ok(([(header::ETAG, format!("\"{}\"", object.etag))], StatusCode::OK).into_response())
+}
+
+/// Server-side copy within the environment (`x-amz-copy-source: /{env}/{key}`)
+async fn copy_s3_object(state: &AppState, caller: &S3Caller, env: &str, key: &str, source: &str) -> Result<Response, S3Error> {
+    let source = gateway::uri_decode(source.split('?').next().unwrap_or_default());
+    let (source_bucket, source_key) = source.trim_start_matches('/')
+        .split_once('/')
+        .ok_or_else(|| S3Error::invalid_argument("Copy Source must mention the source bucket and key."))?;
+    check_bucket(caller, source_bucket)?;
+
+    let (content_id, object) = resolve_s3_object(state, env, source_key).await?;
+    authorize_s3(state, caller, Operation::Read, std::slice::from_ref(&content_id), 0).await?;
+    let size = match &object {
+        Some(object) => object.size,
+        None => state.store.stat(env, &content_id).await.map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => S3Error::no_such_key(),
+            _ => S3Error::internal(e),
+        })?.size,
+    };
+    authorize_s3(state, caller, Operation::Upload, &[], size).await?;
+    let _reservation = state.quotas.reserve(state.store.as_ref(), env, size).await.map_err(quota_error_status)?;
+
+    let received = match state.store.open(env, &content_id, None).await {
+        Ok(reader) => gateway::receive_blob(env, reader).await?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            match state.erasure.read(state.store.as_ref(), &state.replication, env, &content_id).await {
+                Ok(Some(data)) => gateway::receive_blob(env, std::io::Cursor::new(data)).await?,
+                Ok(None) => return Err(S3Error::no_such_key()),
+                Err(e) => return Err(erasure_error_status(e).into()),
+            }
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let object = store_s3_object(state, env, key, received).await?;
+    Ok(gateway::xml_response(StatusCode::OK, gateway::copy_object_xml(&object.etag, object.modified)))
+}
+
+async fn upload_s3_part(
+    state: &AppState,
+    caller: &S3Caller,
+    env: &str,
+    key: &str,
+    params: &HashMap<String, String>,
+    headers: &HeaderMap,
+    body: Body,
+) -> Result<Response, S3Error> {
+    let upload_id = params.get("uploadId").map(String::as_str).unwrap_or_default();
+    let part_number = gateway::parse_part_number(params.get("partNumber").map(String::as_str).unwrap_or_default())?;
+    gateway::read_multipart(env, upload_id, key, &caller.claims.pubkey).await?;
+    let (size, _reservation) = admit_s3_upload(state, caller, env, headers).await?;
+    let received = receive_s3_body(caller, env, body, size).await?;
+    let etag = gateway::store_part(env, upload_id, part_number, received).await?;
+    // The part stays in the staging area, charged to the environment until completed or aborted
+    state.quotas.invalidate(env).await;
+    Ok(([(header::ETAG, format!("\"{}\"", etag))], StatusCode::OK).into_response())
+}
+
+async fn complete_s3_multipart(
+    state: &AppState,
+    caller: &S3Caller,
+    env: &str,
+    key: &str,
+    upload_id: &str,
+    body: Body,
+) -> Result<Response, S3Error> {
+    gateway::read_multipart(env, upload_id, key, &caller.claims.pubkey).await?;
+    authorize_s3(state, caller, Operation::Upload, &[], 0).await?;
+    let data = gateway::read_small(&caller.request, body, 1024 * 1024).await?;
+    let parts = gateway::parse_complete(&data)?;
+
+    // The parts were charged to the quota as they arrived
+    let received = gateway::assemble_multipart(env, upload_id, &parts).await?;
+    if let Err(e) = authorize_s3(state, caller, Operation::Upload, &[], received.size).await {
+        received.discard().await;
+        return Err(e);
+    }
+
+    let object = store_s3_object(state, env, key, received).await?;
+    if let Err(e) = gateway::remove_multipart(env, upload_id).await {
+        tracing::warn!("Cannot remove multipart upload {}/{}: {}", env, upload_id, e.message);
+    }
+    state.quotas.invalidate(env).await;
+    Ok(gateway::xml_response(StatusCode::OK, gateway::complete_multipart_xml(env, key, &object.etag)))
+}
+
+// ============== P2P HANDLERS ==============
 
 async fn ws_handler(ws: WebSocketUpgrade, State(state): State<AppState>) -> impl IntoResponse {
     ws.on_upgrade(move |socket| handle_socket(socket, state))
@@ -800,7 +3715,7 @@ async fn handle_socket(socket: WebSocket, state: AppState) {
     let (tx, mut rx) = mpsc::unbounded_channel::<WsServerMessage>();
 
     let mut client_pubkey: Option<String> = None;
-    let mut last_activity = std::time::Instant::now();
+    let connection = state.events.connection_id();
 
     // Send task with keep-alive
     let send_task = tokio::spawn(async move {
@@ -838,8 +3753,6 @@ async fn handle_socket(socket: WebSocket, state: AppState) {
         
         match tokio::time::timeout(timeout_duration, receive_future).await {
             Ok(Some(Ok(msg))) => {
-                last_activity = std::time::Instant::now();
-                
                 match msg {
                     Message::Text(text) => {
                         if let Ok(client_msg) = serde_json::from_str::<WsClientMessage>(&text) {
@@ -919,6 +3832,21 @@ async fn handle_socket(socket: WebSocket, state: AppState) {
                                     }
                                 }
 
+                                WsClientMessage::Subscribe { session_token } => {
+                                    let reply = match authorize_events(&state, &session_token).await {
+                                        Ok(claims) => {
+                                            state.events.subscribe(&claims, connection, tx.clone()).await;
+                                            WsServerMessage::Subscribed { environment: Some(claims.environment), error: None }
+                                        }
+                                        Err(status) => WsServerMessage::Subscribed { environment: None, error: Some(status.to_string()) },
+                                    };
+                                    let _ = tx.send(reply);
+                                }
+
+                                WsClientMessage::Unsubscribe { environment } => {
+                                    state.events.unsubscribe(&environment, connection).await;
+                                }
+
                                 WsClientMessage::RegisterAsNode { node, x25519_pubkey } => {
                                     // Register this connection as a relay client node
                                     let node_pubkey = node.node.pubkey.clone();
@@ -967,7 +3895,7 @@ async fn handle_socket(socket: WebSocket, state: AppState) {
                             }
                         }
                     }
-                    Message::Ping(data) => {
+                    Message::Ping(_) => {
                         // Respond to ping with pong
                         let _ = tx.send(WsServerMessage::Pong);
                     }
@@ -998,6 +3926,7 @@ async fn handle_socket(socket: WebSocket, state: AppState) {
     if let Some(pubkey) = client_pubkey {
         state.unregister_connection(&pubkey).await;
     }
+    state.events.disconnect(connection).await;
 
     send_task.abort();
 }
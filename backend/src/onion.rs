@@ -26,6 +26,7 @@ pub struct OnionRouter {
 
 /// Informazioni su un circuito attivo per le risposte
 #[derive(Debug, Clone)]
+#[allow(dead_code)]
 pub struct CircuitInfo {
     pub created_at: u64,
     pub return_key: [u8; 32],
@@ -317,6 +318,7 @@ impl OnionRouter {
 }
 
 /// Gestisce connessioni TCP in arrivo per onion routing
+#[allow(dead_code)]
 pub async fn handle_onion_connection(
     mut stream: TcpStream,
     router: Arc<OnionRouter>,
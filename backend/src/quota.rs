@@ -0,0 +1,369 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::Arc;
+use tokio::sync::{Mutex, RwLock};
+
+use crate::gateway;
+use crate::inbox;
+use crate::journal;
+use crate::storage::BlobStore;
+use crate::trash;
+use crate::types::UsageReport;
+use crate::upload;
+
+/// Limiti di spazio nel file node.json (byte). Assente = nessun limite
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct QuotaConfig {
+    /// Limite di default per ogni ambiente
+    #[serde(default)]
+    pub env_bytes: Option<u64>,
+    /// Limiti specifici per ambiente (id ambiente -> byte), prevalgono sul default
+    #[serde(default)]
+    pub environments: HashMap<String, u64>,
+    /// Tetto complessivo per tutti gli ambienti del nodo
+    #[serde(default)]
+    pub node_bytes: Option<u64>,
+}
+
+#[derive(Debug)]
+pub enum QuotaError {
+    /// L'ambiente supererebbe la propria quota
+    EnvironmentFull { used: u64, limit: u64 },
+    /// Il nodo supererebbe il tetto complessivo
+    NodeFull { used: u64, limit: u64 },
+    Io(std::io::Error),
+}
+
+impl fmt::Display for QuotaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QuotaError::EnvironmentFull { used, limit } => {
+                write!(f, "environment quota exceeded ({} of {} bytes used)", used, limit)
+            }
+            QuotaError::NodeFull { used, limit } => {
+                write!(f, "node storage full ({} of {} bytes used)", used, limit)
+            }
+            QuotaError::Io(e) => write!(f, "usage accounting failed: {}", e),
+        }
+    }
+}
+
+impl From<std::io::Error> for QuotaError {
+    fn from(e: std::io::Error) -> Self {
+        QuotaError::Io(e)
+    }
+}
+
+/// Byte di un ambiente, con a parte quelli dell'inbox
+#[derive(Debug, Clone, Copy, Default)]
+struct EnvBytes {
+    vault: u64,
+    inbox: u64,
+}
+
+impl EnvBytes {
+    fn add(&mut self, inbox: bool, bytes: u64) {
+        if inbox {
+            self.inbox += bytes;
+        } else {
+            self.vault += bytes;
+        }
+    }
+}
+
+/// Destinazione dei byte di una prenotazione
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Target {
+    Vault,
+    Inbox,
+    FromInbox,
+}
+
+/// Applica le quote. I byte dei blob già nello store e quelli in staging
+/// (upload in corso, corpi e parti ricevuti dall'endpoint S3) vengono messi
+/// in cache per ambiente, così il controllo di un chunk non rilegge lo store né la staging
+/// area; la cache va invalidata dopo ogni scrittura o cancellazione di blob.
+/// Lo spazio di una scrittura viene prenotato prima di scriverla: controllo e
+/// prenotazione sono atomici, quindi scritture concorrenti non possono
+/// superare insieme la quota.
+///
+/// I byte dell'inbox (upload anonimi e blob non ancora accettati) non contano
+/// nella quota dell'ambiente, che ha un limite proprio in `InboxConfig`, ma
+/// contano nel tetto del nodo.
+pub struct QuotaManager {
+    config: QuotaConfig,
+    stored: RwLock<HashMap<String, EnvBytes>>,
+    staged: RwLock<HashMap<String, EnvBytes>>,
+    /// Byte prenotati da scritture non ancora concluse, per ambiente e per
+    /// destinazione (vero = inbox)
+    reserved: Arc<std::sync::Mutex<HashMap<(String, bool), u64>>>,
+    /// Serializza controllo e prenotazione
+    admission: Mutex<()>,
+}
+
+/// Spazio prenotato per una scrittura, rilasciato quando viene eliminata.
+/// Va tenuta finché i byte scritti non sono visibili nella cache, cioè fino
+/// a `QuotaManager::commit` o all'invalidazione dell'ambiente.
+pub struct Reservation {
+    reserved: Arc<std::sync::Mutex<HashMap<(String, bool), u64>>>,
+    key: (String, bool),
+    bytes: u64,
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        let mut reserved = self.reserved.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(bytes) = reserved.get_mut(&self.key) {
+            *bytes = bytes.saturating_sub(self.bytes);
+            if *bytes == 0 {
+                reserved.remove(&self.key);
+            }
+        }
+    }
+}
+
+impl QuotaManager {
+    pub fn new(config: QuotaConfig) -> Self {
+        Self {
+            config,
+            stored: RwLock::new(HashMap::new()),
+            staged: RwLock::new(HashMap::new()),
+            reserved: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            admission: Mutex::new(()),
+        }
+    }
+
+    /// Quota dell'ambiente, se configurata
+    pub fn limit_for(&self, env: &str) -> Option<u64> {
+        self.config.environments.get(env).copied().or(self.config.env_bytes)
+    }
+
+    /// Da chiamare dopo ogni modifica ai blob di un ambiente
+    pub async fn invalidate(&self, env: &str) {
+        self.stored.write().await.remove(env);
+        self.staged.write().await.remove(env);
+    }
+
+    async fn stored_bytes(&self, store: &dyn BlobStore, env: &str) -> std::io::Result<EnvBytes> {
+        if let Some(bytes) = self.stored.read().await.get(env) {
+            return Ok(*bytes);
+        }
+        let mut bytes = EnvBytes::default();
+        for blob in store.list(env).await? {
+            bytes.add(inbox::is_inbox_key(&blob.key), blob.size);
+        }
+        self.stored.write().await.insert(env.to_string(), bytes);
+        Ok(bytes)
+    }
+
+    async fn staged_bytes(&self, env: &str) -> std::io::Result<EnvBytes> {
+        if let Some(bytes) = self.staged.read().await.get(env) {
+            return Ok(*bytes);
+        }
+        let mut bytes = EnvBytes { vault: gateway::staged_bytes(env).await?, inbox: 0 };
+        for staged in upload::list_uploads(env).await? {
+            bytes.add(staged.meta.wrapped_key.is_some(), staged.received_bytes);
+        }
+        self.staged.write().await.insert(env.to_string(), bytes);
+        Ok(bytes)
+    }
+
+    fn reserved_bytes(&self, env: Option<&str>) -> u64 {
+        let reserved = self.reserved.lock().unwrap_or_else(|e| e.into_inner());
+        match env {
+            Some(env) => reserved.get(&(env.to_string(), false)).copied().unwrap_or(0),
+            None => reserved.values().sum(),
+        }
+    }
+
+    async fn env_bytes(&self, store: &dyn BlobStore, env: &str) -> std::io::Result<EnvBytes> {
+        let (stored, staged) = (self.stored_bytes(store, env).await?, self.staged_bytes(env).await?);
+        Ok(EnvBytes { vault: stored.vault + staged.vault, inbox: stored.inbox + staged.inbox })
+    }
+
+    /// Spazio usato da tutti gli ambienti del nodo, inbox comprese: quelli
+    /// con blob nello store e quelli con soli upload in staging
+    async fn node_bytes(&self, store: &dyn BlobStore) -> std::io::Result<u64> {
+        let mut envs: HashSet<String> = store.environments().await?.into_iter().collect();
+        envs.extend(upload::environments().await?);
+        let mut total = 0;
+        for env in envs {
+            let bytes = self.env_bytes(store, &env).await?;
+            total += bytes.vault + bytes.inbox;
+        }
+        Ok(total)
+    }
+
+    /// Prenota `additional` byte se stanno nella quota dell'ambiente e nel
+    /// tetto del nodo, contando anche le prenotazioni ancora aperte
+    pub async fn reserve(&self, store: &dyn BlobStore, env: &str, additional: u64) -> Result<Reservation, QuotaError> {
+        self.admit(store, env, Target::Vault, additional).await
+    }
+
+    /// Come `reserve`, per byte destinati all'inbox dell'ambiente: contano
+    /// solo nel tetto del nodo (il limite dell'inbox lo applica `InboxManager`)
+    pub async fn reserve_inbox(&self, store: &dyn BlobStore, env: &str, additional: u64) -> Result<Reservation, QuotaError> {
+        self.admit(store, env, Target::Inbox, additional).await
+    }
+
+    /// Prenota nella quota dell'ambiente i byte di elementi accettati
+    /// dall'inbox, che sono già nello store e quindi nel tetto del nodo
+    pub async fn reserve_from_inbox(&self, store: &dyn BlobStore, env: &str, additional: u64) -> Result<Reservation, QuotaError> {
+        self.admit(store, env, Target::FromInbox, additional).await
+    }
+
+    async fn admit(&self, store: &dyn BlobStore, env: &str, target: Target, additional: u64) -> Result<Reservation, QuotaError> {
+        let _admission = self.admission.lock().await;
+        if let (Target::Vault | Target::FromInbox, Some(limit)) = (target, self.limit_for(env)) {
+            let used = self.env_bytes(store, env).await?.vault + self.reserved_bytes(Some(env));
+            if used + additional > limit {
+                return Err(QuotaError::EnvironmentFull { used, limit });
+            }
+        }
+        if let (Target::Vault | Target::Inbox, Some(limit)) = (target, self.config.node_bytes) {
+            let used = self.node_bytes(store).await? + self.reserved_bytes(None);
+            if used + additional > limit {
+                return Err(QuotaError::NodeFull { used, limit });
+            }
+        }
+
+        let key = (env.to_string(), target == Target::Inbox);
+        *self.reserved.lock().unwrap_or_else(|e| e.into_inner()).entry(key.clone()).or_default() += additional;
+        Ok(Reservation {
+            reserved: self.reserved.clone(),
+            key,
+            bytes: additional,
+        })
+    }
+
+    /// Chiude la prenotazione di byte ora presenti nella staging area,
+    /// aggiornando la cache invece di invalidarla
+    pub async fn commit(&self, reservation: Reservation) {
+        let (env, inbox) = &reservation.key;
+        if let Some(bytes) = self.staged.write().await.get_mut(env) {
+            bytes.add(*inbox, reservation.bytes);
+        }
+    }
+
+    /// Resoconto dettagliato dello spazio usato da un ambiente
+    pub async fn usage(&self, store: &dyn BlobStore, env: &str) -> std::io::Result<UsageReport> {
+        let blobs: HashMap<String, u64> = store.list(env).await?
+            .into_iter()
+            .map(|b| (b.key, b.size))
+            .collect();
+        let stored_bytes: u64 = blobs.values().sum();
+        let inbox_bytes: u64 = blobs.iter().filter(|(k, _)| inbox::is_inbox_key(k)).map(|(_, size)| size).sum();
+        self.stored.write().await.insert(env.to_string(), EnvBytes { vault: stored_bytes - inbox_bytes, inbox: inbox_bytes });
+
+        let mut report = UsageReport {
+            environment: env.to_string(),
+            stored_bytes,
+            metadata_bytes: blobs.iter()
+                .filter(|(k, _)| *k == "metadata.enc" || journal::is_journal_key(k))
+                .map(|(_, size)| size)
+                .sum(),
+            trash_bytes: blobs.iter().filter(|(k, _)| trash::is_trash_key(k)).map(|(_, size)| size).sum(),
+            inbox_bytes,
+            quota_bytes: self.limit_for(env),
+            node_quota_bytes: self.config.node_bytes,
+            ..Default::default()
+        };
+
+        // Contenuti e anteprime si riconoscono dai meta.json degli upload
+        let mut previews = HashSet::new();
+        let mut staged_bytes = EnvBytes::default();
+        for staged in upload::list_uploads(env).await? {
+            staged_bytes.add(staged.meta.wrapped_key.is_some(), staged.received_bytes);
+            if let Some(preview_id) = staged.meta.preview_id {
+                previews.insert(preview_id);
+            }
+            match staged.meta.item {
+                Some(item) => {
+                    if let Some(size) = blobs.get(&item.content_id) {
+                        report.item_count += 1;
+                        report.content_bytes += size;
+                    }
+                }
+                None => report.in_flight_bytes += staged.received_bytes,
+            }
+        }
+        let gateway_bytes = gateway::staged_bytes(env).await?;
+        report.in_flight_bytes += gateway_bytes;
+        staged_bytes.vault += gateway_bytes;
+        self.staged.write().await.insert(env.to_string(), staged_bytes);
+        report.preview_bytes = previews.iter().filter_map(|id| blobs.get(id)).sum();
+        report.bytes_used = report.stored_bytes - report.inbox_bytes + staged_bytes.vault;
+
+        if self.config.node_bytes.is_some() {
+            report.node_bytes_used = Some(self.node_bytes(store).await?);
+        }
+
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::TempStore;
+    use axum::body::Bytes;
+
+    #[tokio::test]
+    async fn reservations_count_until_released() {
+        let store = TempStore::new();
+        let env = format!("quota-test-{}", hex::encode(crate::crypto::random_bytes::<8>()));
+        let quotas = QuotaManager::new(QuotaConfig { env_bytes: Some(100), ..Default::default() });
+        store.put(&env, "blob", Bytes::from_static(&[0; 20])).await.unwrap();
+
+        let first = quotas.reserve(&*store, &env, 60).await.unwrap();
+        assert!(matches!(
+            quotas.reserve(&*store, &env, 30).await,
+            Err(QuotaError::EnvironmentFull { used: 80, limit: 100 })
+        ));
+        drop(first);
+        let second = quotas.reserve(&*store, &env, 80).await.unwrap();
+
+        // Una prenotazione chiusa resta contata nella cache fino all'invalidazione
+        quotas.commit(second).await;
+        assert!(quotas.reserve(&*store, &env, 1).await.is_err());
+        quotas.invalidate(&env).await;
+        quotas.reserve(&*store, &env, 80).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn inbox_bytes_stay_out_of_the_quota() {
+        let store = TempStore::new();
+        let env = format!("quota-test-{}", hex::encode(crate::crypto::random_bytes::<8>()));
+        let quotas = QuotaManager::new(QuotaConfig { env_bytes: Some(100), ..Default::default() });
+        store.put(&env, &inbox::inbox_key("blob"), Bytes::from_static(&[0; 80])).await.unwrap();
+
+        let received = quotas.reserve_inbox(&*store, &env, 500).await.unwrap();
+        quotas.commit(received).await;
+        let _write = quotas.reserve(&*store, &env, 100).await.unwrap();
+
+        // Accettati, contano come gli altri blob
+        assert!(matches!(
+            quotas.reserve_from_inbox(&*store, &env, 80).await,
+            Err(QuotaError::EnvironmentFull { used: 100, limit: 100 })
+        ));
+    }
+
+    #[tokio::test]
+    async fn node_quota_counts_envs_without_staging() {
+        let store = TempStore::new();
+        let stored = format!("quota-test-{}", hex::encode(crate::crypto::random_bytes::<8>()));
+        let env = format!("quota-test-{}", hex::encode(crate::crypto::random_bytes::<8>()));
+        let quotas = QuotaManager::new(QuotaConfig { node_bytes: Some(100), ..Default::default() });
+
+        // Ambiente presente solo nello store, senza directory di staging
+        store.put(&stored, "blob", Bytes::from_static(&[0; 80])).await.unwrap();
+        assert!(upload::environments().await.unwrap().iter().all(|e| e != &stored));
+
+        assert!(matches!(
+            quotas.reserve(&*store, &env, 30).await,
+            Err(QuotaError::NodeFull { limit: 100, .. })
+        ));
+        quotas.reserve(&*store, &env, 20).await.unwrap();
+    }
+}
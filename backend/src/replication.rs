@@ -0,0 +1,863 @@
+use axum::body::Bytes;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+
+use crate::crypto::{self, current_timestamp};
+use crate::discovery::DiscoveryManager;
+use crate::erasure;
+use crate::fsck;
+use crate::journal;
+use crate::storage::{BlobReader, BlobStat, BlobStore};
+use crate::trash;
+use crate::types::{
+    AuditChallenge, AuditMessage, AuditProof, NodePacket, PeerConfig, PeerProtocol, ReplicaInfo, ReplicaRequest,
+    ReplicaResponse, ReplicationMessage,
+};
+use crate::upload;
+
+/// Peer su cui è replicato un ambiente, con lo stato di ciascuno
+pub const REPLICATION_INDEX: &str = "replication.json";
+
+const METADATA_KEY: &str = "metadata.enc";
+
+/// Dati trasferiti per pacchetto, ben sotto il limite di 10 MiB dell'Arson
+const TRANSFER_CHUNK: u64 = 4 * 1024 * 1024;
+
+/// Scarto massimo tra l'orologio del peer e il nostro nelle richieste firmate
+const MAX_CLOCK_SKEW: u64 = 300;
+
+/// Trust tolto a un peer per ogni blob che non dimostra di avere
+pub(crate) const AUDIT_PENALTY: u8 = 10;
+
+/// Limiti di una sfida accettata da un peer
+const MAX_AUDIT_RANGES: usize = 64;
+
+/// Sottodirectory di un ambiente con i blob in trasferimento
+const TRANSFER_DIR: &str = ".replica";
+
+/// Configurazione della replica nel file node.json
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ReplicationConfig {
+    /// Intervallo tra due sincronizzazioni periodiche (secondi)
+    #[serde(default = "default_interval_secs")]
+    pub interval_secs: u64,
+    /// Accetta le repliche inviate dai peer configurati
+    #[serde(default = "default_accept")]
+    pub accept: bool,
+    /// Spazio massimo di ogni ambiente replicato qui da un peer (byte)
+    #[serde(default)]
+    pub max_replica_bytes: Option<u64>,
+    /// Spazio massimo degli shard ricevuti da ogni peer scoperto ma non
+    /// configurato (byte); vale sempre, anche senza `max_replica_bytes`
+    #[serde(default = "default_max_discovered_shard_bytes")]
+    pub max_discovered_shard_bytes: u64,
+    /// Timeout di ogni scambio con un peer (secondi)
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u64,
+    /// Intervallo tra due audit delle repliche (secondi)
+    #[serde(default = "default_audit_interval_secs")]
+    pub audit_interval_secs: u64,
+    /// Blob sfidati per replica a ogni audit
+    #[serde(default = "default_audit_samples")]
+    pub audit_samples: usize,
+    /// Intervalli di byte sfidati per blob
+    #[serde(default = "default_audit_ranges")]
+    pub audit_ranges: usize,
+    /// Lunghezza di ogni intervallo sfidato (byte)
+    #[serde(default = "default_audit_range_bytes")]
+    pub audit_range_bytes: u64,
+}
+
+impl Default for ReplicationConfig {
+    fn default() -> Self {
+        Self {
+            interval_secs: default_interval_secs(),
+            accept: default_accept(),
+            max_replica_bytes: None,
+            max_discovered_shard_bytes: default_max_discovered_shard_bytes(),
+            timeout_secs: default_timeout_secs(),
+            audit_interval_secs: default_audit_interval_secs(),
+            audit_samples: default_audit_samples(),
+            audit_ranges: default_audit_ranges(),
+            audit_range_bytes: default_audit_range_bytes(),
+        }
+    }
+}
+
+fn default_interval_secs() -> u64 { 15 * 60 }
+fn default_accept() -> bool { true }
+fn default_timeout_secs() -> u64 { 30 }
+fn default_max_discovered_shard_bytes() -> u64 { 1024 * 1024 * 1024 }
+fn default_audit_interval_secs() -> u64 { 6 * 3600 }
+fn default_audit_samples() -> usize { 8 }
+fn default_audit_ranges() -> usize { 4 }
+fn default_audit_range_bytes() -> u64 { 4096 }
+
+type TransportError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Errori nella configurazione della replica
+#[derive(Debug)]
+pub enum ReplicationError {
+    /// Peer non configurato, o non raggiungibile con l'Arson
+    UnknownPeer(String),
+    Io(std::io::Error),
+}
+
+impl std::fmt::Display for ReplicationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReplicationError::UnknownPeer(pubkey) => write!(f, "peer {} is not configured for Arson", pubkey),
+            ReplicationError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<std::io::Error> for ReplicationError {
+    fn from(e: std::io::Error) -> Self {
+        ReplicationError::Io(e)
+    }
+}
+
+/// Chiavi che vengono replicate: metadata.enc, journal dei metadata,
+/// contenuti e anteprime. Indici, cestino e inbox restano locali.
+fn is_replicated_key(key: &str) -> bool {
+    key == METADATA_KEY
+        || journal::is_journal_key(key)
+        || (!key.is_empty() && key.len() <= 64 && key.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+/// Ambiente in cui un peer conserva la replica di `env` ricevuta da `origin`.
+/// Ha una lunghezza diversa dagli ambienti personali e di team, così non si
+/// confonde con essi.
+pub fn replica_environment(origin: &str, env: &str) -> String {
+    let digest = Sha256::digest(format!("vault-replica:{}:{}", origin, env).as_bytes());
+    hex::encode(&digest[..12])
+}
+
+fn transfer_path(env: &str, key: &str) -> PathBuf {
+    Path::new(upload::STAGING_ROOT).join(env).join(TRANSFER_DIR).join(format!("{}.part", key))
+}
+
+/// Replica degli ambienti sui peer di `Node.peers` attraverso l'Arson.
+///
+/// Il nodo di origine confronta periodicamente i propri blob con l'elenco di
+/// ciascuna replica: invia contenuti e anteprime mancanti e le nuove revisioni
+/// di metadata.enc, recupera dal peer i blob del live set persi in locale e
+/// rimuove dalla replica quelli che il client non referenzia più. I blob sono
+/// già cifrati dal client, quindi il peer li conserva senza poterli leggere.
+///
+/// Sul peer ogni richiesta deve essere firmata da un nodo configurato tra i
+/// suoi peer (per gli shard basta un peer scoperto); le repliche finiscono in
+/// ambienti separati per origine.
+pub struct ReplicationManager {
+    config: ReplicationConfig,
+    pubkey: String,
+    privkey: String,
+    /// Gli unici nodi verso cui replicare e da cui accettare repliche
+    peers: Vec<PeerConfig>,
+    /// Serializza le modifiche a replication.json
+    lock: Mutex<()>,
+    /// Una sola sincronizzazione alla volta, periodica o richiesta dal client
+    sync_lock: Mutex<()>,
+    /// Nonce delle richieste firmate ricevute, con il loro timestamp, finché
+    /// sono dentro la finestra di `MAX_CLOCK_SKEW`
+    seen_nonces: std::sync::Mutex<HashMap<(String, String), u64>>,
+}
+
+impl ReplicationManager {
+    pub fn new(config: ReplicationConfig, pubkey: String, privkey: String, peers: Vec<PeerConfig>) -> Self {
+        Self {
+            config,
+            pubkey,
+            privkey,
+            peers,
+            lock: Mutex::new(()),
+            sync_lock: Mutex::new(()),
+            seen_nonces: std::sync::Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn sync_interval(&self) -> Duration {
+        Duration::from_secs(self.config.interval_secs.max(10))
+    }
+
+    pub fn audit_interval(&self) -> Duration {
+        Duration::from_secs(self.config.audit_interval_secs.max(10))
+    }
+
+    async fn load(store: &dyn BlobStore, env: &str) -> std::io::Result<Vec<ReplicaInfo>> {
+        match store.get(env, REPLICATION_INDEX).await {
+            Ok(data) => serde_json::from_slice(&data).map_err(std::io::Error::other),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn save(store: &dyn BlobStore, env: &str, replicas: &[ReplicaInfo]) -> std::io::Result<()> {
+        if replicas.is_empty() {
+            return match store.delete(env, REPLICATION_INDEX).await {
+                Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            };
+        }
+        let data = serde_json::to_vec(replicas).map_err(std::io::Error::other)?;
+        store.put(env, REPLICATION_INDEX, Bytes::from(data)).await
+    }
+
+    /// Repliche di un ambiente con il loro stato
+    pub async fn list(&self, store: &dyn BlobStore, env: &str) -> std::io::Result<Vec<ReplicaInfo>> {
+        Self::load(store, env).await
+    }
+
+    /// Imposta i peer su cui replicare l'ambiente. Lo stato dei peer già
+    /// presenti viene mantenuto; le copie sui peer rimossi restano dove sono.
+    pub async fn configure(&self, store: &dyn BlobStore, env: &str, peers: &[String]) -> Result<Vec<ReplicaInfo>, ReplicationError> {
+        for pubkey in peers {
+            if *pubkey == self.pubkey || self.arson_peer(pubkey).is_none() {
+                return Err(ReplicationError::UnknownPeer(pubkey.clone()));
+            }
+        }
+
+        let _guard = self.lock.lock().await;
+        let mut existing = Self::load(store, env).await?;
+        let now = current_timestamp();
+        let mut replicas: Vec<ReplicaInfo> = Vec::new();
+        for pubkey in peers {
+            if replicas.iter().any(|r| r.peer == *pubkey) {
+                continue;
+            }
+            let replica = match existing.iter().position(|r| r.peer == *pubkey) {
+                Some(i) => existing.swap_remove(i),
+                None => ReplicaInfo {
+                    peer: pubkey.clone(),
+                    name: self.arson_peer(pubkey).and_then(|p| p.name.clone()),
+                    added_at: now,
+                    ..Default::default()
+                },
+            };
+            replicas.push(replica);
+        }
+        Self::save(store, env, &replicas).await?;
+        Ok(replicas)
+    }
+
+    fn arson_peer(&self, pubkey: &str) -> Option<&PeerConfig> {
+        self.peers.iter().find(|p| p.pubkey == pubkey && p.protocol == PeerProtocol::Arson)
+    }
+
+    /// Sincronizza un ambiente con tutte le sue repliche e restituisce quanti
+    /// blob persi in locale sono stati recuperati. Gli errori di un peer
+    /// finiscono nel suo stato e non fermano gli altri.
+    pub async fn sync(&self, store: &dyn BlobStore, env: &str) -> std::io::Result<usize> {
+        let _sync = self.sync_lock.lock().await;
+        let replicas = Self::load(store, env).await?;
+        if replicas.is_empty() {
+            return Ok(0);
+        }
+
+        let mut repaired = 0;
+        let mut results = Vec::new();
+        for mut replica in replicas {
+            let before = replica.repaired;
+            replica.last_attempt = Some(current_timestamp());
+            match self.sync_replica(store, env, &mut replica).await {
+                Ok(()) => {
+                    replica.last_sync = replica.last_attempt;
+                    replica.last_error = None;
+                }
+                Err(e) => {
+                    tracing::warn!("Replication of {} to {} failed: {}", env, replica.name.as_deref().unwrap_or(&replica.peer), e);
+                    replica.last_error = Some(e.to_string());
+                }
+            }
+            repaired += replica.repaired - before;
+            results.push(replica);
+        }
+
+        self.store_results(store, env, &results).await?;
+        Ok(repaired)
+    }
+
+    /// Salva lo stato delle repliche dopo una sincronizzazione o un audit;
+    /// il client può aver cambiato i peer nel frattempo
+    async fn store_results(&self, store: &dyn BlobStore, env: &str, results: &[ReplicaInfo]) -> std::io::Result<()> {
+        let _guard = self.lock.lock().await;
+        let mut current = Self::load(store, env).await?;
+        for replica in &mut current {
+            if let Some(result) = results.iter().find(|r| r.peer == replica.peer) {
+                *replica = result.clone();
+            }
+        }
+        Self::save(store, env, &current).await
+    }
+
+    /// Sfida ogni replica dell'ambiente su un campione di blob già
+    /// sincronizzati, chiedendo gli hash di intervalli casuali con un nonce
+    /// nuovo. Chi non dimostra di avere un blob perde trust nel
+    /// `DiscoveryManager` e lo riceve di nuovo. Restituisce quanti blob non
+    /// hanno superato la verifica.
+    pub async fn audit(&self, store: &dyn BlobStore, discovery: &DiscoveryManager, env: &str) -> std::io::Result<usize> {
+        let _sync = self.sync_lock.lock().await;
+        let replicas = Self::load(store, env).await?;
+        if replicas.is_empty() {
+            return Ok(0);
+        }
+        // metadata.enc cambia spesso: si verificano solo contenuti e anteprime
+        let local: Vec<BlobStat> = store.list(env).await?
+            .into_iter()
+            .filter(|b| b.key != METADATA_KEY && is_replicated_key(&b.key) && b.size > 0)
+            .collect();
+
+        let mut failed = 0;
+        let mut results = Vec::new();
+        for mut replica in replicas {
+            // Solo i blob già presenti all'ultima sincronizzazione riuscita
+            let Some(synced_at) = replica.last_sync else {
+                results.push(replica);
+                continue;
+            };
+            let candidates: Vec<&BlobStat> = local.iter().filter(|b| b.modified < synced_at).collect();
+            match self.audit_replica(store, discovery, env, &mut replica, &candidates).await {
+                Ok(n) => failed += n,
+                Err(e) => {
+                    tracing::warn!("Audit of {} on {} failed: {}", env, replica.name.as_deref().unwrap_or(&replica.peer), e);
+                    replica.last_error = Some(format!("audit: {}", e));
+                }
+            }
+            replica.last_audit = Some(current_timestamp());
+            results.push(replica);
+        }
+
+        self.store_results(store, env, &results).await?;
+        Ok(failed)
+    }
+
+    async fn audit_replica(
+        &self,
+        store: &dyn BlobStore,
+        discovery: &DiscoveryManager,
+        env: &str,
+        replica: &mut ReplicaInfo,
+        candidates: &[&BlobStat],
+    ) -> Result<usize, TransportError> {
+        let peer = self.arson_peer(&replica.peer)
+            .ok_or_else(|| format!("peer {} is no longer configured", replica.peer))?;
+        let peer = format!("{}:{}", peer.address, peer.port);
+
+        let challenges: Vec<(AuditChallenge, u64)> = {
+            let mut rng = rand::thread_rng();
+            candidates
+                .choose_multiple(&mut rng, self.config.audit_samples)
+                .map(|blob| {
+                    let length = self.config.audit_range_bytes.clamp(1, blob.size);
+                    let ranges = (0..self.config.audit_ranges.clamp(1, MAX_AUDIT_RANGES))
+                        .map(|_| (rng.gen_range(0..=blob.size - length), length))
+                        .collect();
+                    let challenge = AuditChallenge {
+                        environment: env.to_string(),
+                        key: blob.key.clone(),
+                        nonce: crypto::random_bytes(),
+                        ranges,
+                    };
+                    (challenge, blob.size)
+                })
+                .collect()
+        };
+
+        let mut failed = 0;
+        for (challenge, size) in challenges {
+            let expected = match Self::prove(store, env, &challenge).await {
+                Ok(hashes) => hashes,
+                // Eliminato nel frattempo
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            let key = challenge.key.clone();
+            match self.challenge(&peer, challenge).await? {
+                AuditProof::Hashes(hashes) if hashes == expected => {
+                    replica.audits_passed += 1;
+                    continue;
+                }
+                AuditProof::Hashes(_) => tracing::warn!("Peer {} holds a corrupted copy of {}/{}", replica.peer, env, key),
+                AuditProof::Error(e) => tracing::warn!("Peer {} cannot prove {}/{}: {}", replica.peer, env, key, e),
+            }
+
+            failed += 1;
+            replica.audits_failed += 1;
+            discovery.penalize(&replica.peer, AUDIT_PENALTY).await;
+            let reader = store.open(env, &key, None).await?;
+            self.push(&peer, env, &key, size, reader).await?;
+        }
+        Ok(failed)
+    }
+
+    /// Hash di `nonce || intervallo` per ogni intervallo della sfida
+    async fn prove(store: &dyn BlobStore, env: &str, challenge: &AuditChallenge) -> std::io::Result<Vec<String>> {
+        let mut hashes = Vec::new();
+        for &(offset, length) in &challenge.ranges {
+            let mut data = Vec::new();
+            store.open(env, &challenge.key, Some((offset, length))).await?.read_to_end(&mut data).await?;
+            let mut hasher = Sha256::new();
+            hasher.update(challenge.nonce);
+            hasher.update(&data);
+            hashes.push(hex::encode(hasher.finalize()));
+        }
+        Ok(hashes)
+    }
+
+    async fn sync_replica(&self, store: &dyn BlobStore, env: &str, replica: &mut ReplicaInfo) -> Result<(), TransportError> {
+        let peer = self.arson_peer(&replica.peer)
+            .ok_or_else(|| format!("peer {} is no longer configured", replica.peer))?;
+        let peer = format!("{}:{}", peer.address, peer.port);
+
+        let local: HashMap<String, u64> = store.list(env).await?
+            .into_iter()
+            .map(|b| (b.key, b.size))
+            .collect();
+        let mut remote: HashMap<String, u64> = match self.exchange(&peer, ReplicaRequest::List { environment: env.to_string() }).await? {
+            ReplicaResponse::Blobs(blobs) => blobs.into_iter().collect(),
+            _ => return Err("unexpected response to list".into()),
+        };
+        let live = fsck::load_live_set(store, env).await?;
+        // I contenuti distribuiti in shard mancano in locale di proposito
+        let sharded = erasure::sharded_ids(store, env).await?;
+        let trashed = |key: &str| local.contains_key(&trash::trashed_key(key)) || sharded.contains(key);
+
+        // Blob persi in locale: metadata.enc, il journal se manca per intero
+        // (altrimenti le revisioni assenti sono state compattate) e ciò che il
+        // client referenzia ancora
+        let journal_lost = !local.keys().any(|key| journal::is_journal_key(key));
+        let mut lost: Vec<&String> = remote.keys()
+            .filter(|key| !local.contains_key(*key) && !trashed(key))
+            .filter(|key| {
+                *key == METADATA_KEY
+                    || (journal_lost && journal::is_journal_key(key))
+                    || live.as_ref().is_some_and(|l| l.ids.contains(*key))
+            })
+            .collect();
+        lost.sort();
+        for key in lost {
+            self.fetch(store, &peer, env, key).await?;
+            tracing::info!("Recovered {}/{} from replica on {}", env, key, replica.peer);
+            replica.repaired += 1;
+        }
+
+        let mut outgoing: Vec<(&String, u64)> = Vec::new();
+        let mut metadata = None;
+        for (key, size) in local.iter().filter(|(key, _)| is_replicated_key(key)) {
+            if key == METADATA_KEY {
+                let data = store.get(env, key).await?;
+                let revision = hex::encode(Sha256::digest(&data));
+                if remote.get(key) != Some(size) || replica.metadata_revision.as_ref() != Some(&revision) {
+                    metadata = Some((data, revision));
+                }
+            } else if remote.get(key) != Some(size) {
+                outgoing.push((key, *size));
+            }
+        }
+        outgoing.sort();
+        replica.pending = outgoing.len() + usize::from(metadata.is_some());
+
+        for (key, size) in outgoing {
+            let reader = match store.open(env, key, None).await {
+                Ok(reader) => reader,
+                // Eliminato nel frattempo
+                Err(e) if e.kind() == ErrorKind::NotFound => {
+                    replica.pending -= 1;
+                    continue;
+                }
+                Err(e) => return Err(e.into()),
+            };
+            self.push(&peer, env, key, size, reader).await?;
+            remote.insert(key.clone(), size);
+            replica.pending -= 1;
+        }
+
+        // metadata.enc per ultimo, così sul peer non referenzia blob mancanti
+        if let Some((data, revision)) = metadata {
+            let size = data.len() as u64;
+            self.push(&peer, env, METADATA_KEY, size, Box::pin(std::io::Cursor::new(data))).await?;
+            remote.insert(METADATA_KEY.to_string(), size);
+            replica.metadata_revision = Some(revision);
+            replica.pending -= 1;
+        }
+
+        // Senza live set non si sa cosa il client abbia eliminato: si tiene tutto
+        if let Some(live) = live.filter(|_| local.contains_key(METADATA_KEY)) {
+            let stale: Vec<String> = remote.keys()
+                .filter(|key| *key != METADATA_KEY && !local.contains_key(*key) && !trashed(key) && !live.ids.contains(*key))
+                .cloned()
+                .collect();
+            if !stale.is_empty() {
+                self.remove(&peer, env, stale.clone()).await?;
+                for key in &stale {
+                    remote.remove(key);
+                }
+            }
+        }
+
+        replica.blobs = remote.len();
+        replica.bytes = remote.values().sum();
+        Ok(())
+    }
+
+    /// Invia un blob a un peer in porzioni da `TRANSFER_CHUNK`
+    pub async fn push(&self, peer: &str, env: &str, key: &str, total: u64, mut reader: BlobReader) -> Result<(), TransportError> {
+        let mut offset = 0;
+        loop {
+            let mut data = Vec::new();
+            (&mut reader).take(TRANSFER_CHUNK.min(total - offset)).read_to_end(&mut data).await?;
+            if data.is_empty() && offset < total {
+                return Err(format!("{} changed while being replicated", key).into());
+            }
+            let len = data.len() as u64;
+            let request = ReplicaRequest::Put { environment: env.to_string(), key: key.to_string(), offset, total, data };
+            self.exchange(peer, request).await?;
+            offset += len;
+            if offset >= total {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Scarica un blob da un peer interamente in memoria
+    pub async fn download(&self, peer: &str, env: &str, key: &str) -> Result<Vec<u8>, TransportError> {
+        let mut result = Vec::new();
+        loop {
+            let request = ReplicaRequest::Get { environment: env.to_string(), key: key.to_string(), offset: result.len() as u64, length: TRANSFER_CHUNK };
+            let (total, data) = match self.exchange(peer, request).await? {
+                ReplicaResponse::Data { total, data } => (total, data),
+                _ => return Err("unexpected response to get".into()),
+            };
+            let done = data.is_empty();
+            result.extend(data);
+            if result.len() as u64 >= total || done {
+                return Ok(result);
+            }
+        }
+    }
+
+    /// Elimina blob da un peer
+    pub async fn remove(&self, peer: &str, env: &str, keys: Vec<String>) -> Result<(), TransportError> {
+        self.exchange(peer, ReplicaRequest::Delete { environment: env.to_string(), keys }).await?;
+        Ok(())
+    }
+
+    async fn fetch(&self, store: &dyn BlobStore, peer: &str, env: &str, key: &str) -> Result<(), TransportError> {
+        let path = transfer_path(env, key);
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let mut file = tokio::fs::File::create(&path).await?;
+
+        let mut offset = 0;
+        loop {
+            let request = ReplicaRequest::Get { environment: env.to_string(), key: key.to_string(), offset, length: TRANSFER_CHUNK };
+            let (total, data) = match self.exchange(peer, request).await? {
+                ReplicaResponse::Data { total, data } => (total, data),
+                _ => return Err("unexpected response to get".into()),
+            };
+            file.write_all(&data).await?;
+            offset += data.len() as u64;
+            if offset >= total || data.is_empty() {
+                break;
+            }
+        }
+        file.flush().await?;
+        drop(file);
+
+        store.put_file(env, key, &path).await?;
+        Ok(())
+    }
+
+    /// Invia una richiesta firmata a un peer e ne attende la risposta
+    async fn exchange(&self, addr: &str, request: ReplicaRequest) -> Result<ReplicaResponse, TransportError> {
+        let timestamp = current_timestamp();
+        let nonce = hex::encode(crypto::random_bytes::<16>());
+        let signature = crypto::sign_data(&self.privkey, &bincode::serialize(&(timestamp, &nonce, &request))?)?;
+        let packet = NodePacket::Replication(ReplicationMessage::Request {
+            origin: self.pubkey.clone(),
+            timestamp,
+            nonce,
+            signature,
+            request,
+        });
+
+        match self.send(addr, &packet).await? {
+            NodePacket::Replication(ReplicationMessage::Response(ReplicaResponse::Error(e))) => Err(e.into()),
+            NodePacket::Replication(ReplicationMessage::Response(response)) => Ok(response),
+            _ => Err("unexpected packet in reply".into()),
+        }
+    }
+
+    /// Invia una sfida di audit firmata; una prova con errore non è un
+    /// errore di trasporto ma un audit fallito
+    async fn challenge(&self, addr: &str, challenge: AuditChallenge) -> Result<AuditProof, TransportError> {
+        let timestamp = current_timestamp();
+        let nonce = hex::encode(crypto::random_bytes::<16>());
+        let signature = crypto::sign_data(&self.privkey, &bincode::serialize(&(timestamp, &nonce, &challenge))?)?;
+        let packet = NodePacket::Audit(AuditMessage::Challenge {
+            origin: self.pubkey.clone(),
+            timestamp,
+            nonce,
+            signature,
+            challenge,
+        });
+
+        match self.send(addr, &packet).await? {
+            NodePacket::Audit(AuditMessage::Proof(proof)) => Ok(proof),
+            _ => Err("unexpected packet in reply".into()),
+        }
+    }
+
+    /// Un pacchetto su una nuova connessione Arson, e la risposta
+    async fn send(&self, addr: &str, packet: &NodePacket) -> Result<NodePacket, TransportError> {
+        tokio::time::timeout(Duration::from_secs(self.config.timeout_secs), async {
+            let mut stream = TcpStream::connect(addr).await?;
+            let data = bincode::serialize(packet)?;
+            stream.write_all(&(data.len() as u32).to_be_bytes()).await?;
+            stream.write_all(&data).await?;
+            stream.flush().await?;
+
+            let mut len_buf = [0u8; 4];
+            stream.read_exact(&mut len_buf).await?;
+            let len = u32::from_be_bytes(len_buf) as usize;
+            if len > 10 * 1024 * 1024 {
+                return Err::<NodePacket, TransportError>("Packet too large".into());
+            }
+            let mut data = vec![0u8; len];
+            stream.read_exact(&mut data).await?;
+            Ok(bincode::deserialize(&data)?)
+        })
+        .await
+        .map_err(|_| format!("{} timed out", addr))?
+    }
+
+    /// Gestisce una richiesta di replica ricevuta da un altro nodo. Gli shard
+    /// vengono accettati anche dai peer scoperti, non solo da quelli
+    /// configurati, entro `max_discovered_shard_bytes` per peer.
+    pub async fn handle(&self, store: &dyn BlobStore, discovery: &DiscoveryManager, message: ReplicationMessage) -> ReplicationMessage {
+        let response = match message {
+            ReplicationMessage::Request { origin, timestamp, nonce, signature, request } => {
+                let trusted = self.is_trusted(discovery, &origin, request.environment()).await;
+                match self.authenticate(&origin, trusted, timestamp, &nonce, &signature, &request) {
+                    Ok(()) => self.serve(store, &origin, request).await.unwrap_or_else(|e| {
+                        tracing::warn!("Replication request from {} failed: {}", origin, e);
+                        ReplicaResponse::Error(e.to_string())
+                    }),
+                    Err(e) => {
+                        tracing::warn!("Rejected replication request from {}: {}", origin, e);
+                        ReplicaResponse::Error(e.to_string())
+                    }
+                }
+            }
+            ReplicationMessage::Response(_) => ReplicaResponse::Error("unexpected response".to_string()),
+        };
+        ReplicationMessage::Response(response)
+    }
+
+    /// Risponde a una sfida di audit sulla replica ricevuta da `origin`
+    pub async fn handle_audit(&self, store: &dyn BlobStore, discovery: &DiscoveryManager, message: AuditMessage) -> AuditMessage {
+        let proof = match message {
+            AuditMessage::Challenge { origin, timestamp, nonce, signature, challenge } => {
+                let trusted = self.is_trusted(discovery, &origin, &challenge.environment).await;
+                let valid = is_replicated_key(&challenge.key)
+                    && challenge.ranges.len() <= MAX_AUDIT_RANGES
+                    && challenge.ranges.iter().all(|&(_, length)| length <= TRANSFER_CHUNK);
+                match self.authenticate(&origin, trusted, timestamp, &nonce, &signature, &challenge) {
+                    Ok(()) if valid => {
+                        let replica = replica_environment(&origin, &challenge.environment);
+                        match Self::prove(store, &replica, &challenge).await {
+                            Ok(hashes) => AuditProof::Hashes(hashes),
+                            Err(e) => AuditProof::Error(e.to_string()),
+                        }
+                    }
+                    Ok(()) => AuditProof::Error("invalid challenge".to_string()),
+                    Err(e) => {
+                        tracing::warn!("Rejected audit challenge from {}: {}", origin, e);
+                        AuditProof::Error(e.to_string())
+                    }
+                }
+            }
+            AuditMessage::Proof(_) => AuditProof::Error("unexpected proof".to_string()),
+        };
+        AuditMessage::Proof(proof)
+    }
+
+    async fn is_trusted(&self, discovery: &DiscoveryManager, origin: &str, environment: &str) -> bool {
+        self.peers.iter().any(|p| p.pubkey == origin)
+            || (environment == erasure::SHARD_ENVIRONMENT && discovery.is_known(origin).await)
+    }
+
+    fn authenticate<T: Serialize>(
+        &self,
+        origin: &str,
+        trusted: bool,
+        timestamp: u64,
+        nonce: &str,
+        signature: &str,
+        request: &T,
+    ) -> Result<(), TransportError> {
+        if !self.config.accept {
+            return Err("replication is disabled on this node".into());
+        }
+        if !trusted {
+            return Err("origin is not a configured peer".into());
+        }
+        let now = current_timestamp();
+        if now.abs_diff(timestamp) > MAX_CLOCK_SKEW {
+            return Err("request timestamp out of range".into());
+        }
+        if !crypto::verify_signature(origin, signature, &bincode::serialize(&(timestamp, nonce, request))?)? {
+            return Err("invalid signature".into());
+        }
+
+        // Dopo la finestra il timestamp basta da solo a rifiutare la richiesta
+        let mut seen = self.seen_nonces.lock().unwrap_or_else(|e| e.into_inner());
+        seen.retain(|_, &mut t| now.abs_diff(t) <= MAX_CLOCK_SKEW);
+        if seen.insert((origin.to_string(), nonce.to_string()), timestamp).is_some() {
+            return Err("replayed request".into());
+        }
+        Ok(())
+    }
+
+    /// Spazio concesso a `origin` per la replica di `environment`: i peer
+    /// scoperti possono scrivere solo shard, entro un limite sempre attivo
+    fn replica_limit(&self, origin: &str, environment: &str) -> Option<u64> {
+        if self.peers.iter().any(|p| p.pubkey == origin) || environment != erasure::SHARD_ENVIRONMENT {
+            return self.config.max_replica_bytes;
+        }
+        let limit = self.config.max_discovered_shard_bytes;
+        Some(self.config.max_replica_bytes.map_or(limit, |max| max.min(limit)))
+    }
+
+    async fn serve(&self, store: &dyn BlobStore, origin: &str, request: ReplicaRequest) -> Result<ReplicaResponse, TransportError> {
+        match request {
+            ReplicaRequest::List { environment } => {
+                let replica = replica_environment(origin, &environment);
+                let blobs = store.list(&replica).await?
+                    .into_iter()
+                    .filter(|b| is_replicated_key(&b.key))
+                    .map(|b| (b.key, b.size))
+                    .collect();
+                Ok(ReplicaResponse::Blobs(blobs))
+            }
+            ReplicaRequest::Put { environment, key, offset, total, data } => {
+                if !is_replicated_key(&key) || offset + data.len() as u64 > total {
+                    return Err("invalid blob transfer".into());
+                }
+                let replica = replica_environment(origin, &environment);
+                let path = transfer_path(&replica, &key);
+
+                let mut file = if offset == 0 {
+                    if let Some(limit) = self.replica_limit(origin, &environment) {
+                        let used: u64 = store.list(&replica).await?
+                            .iter()
+                            .filter(|b| b.key != key)
+                            .map(|b| b.size)
+                            .sum();
+                        if used + total > limit {
+                            return Err("replica quota exceeded".into());
+                        }
+                    }
+                    if let Some(parent) = path.parent() {
+                        tokio::fs::create_dir_all(parent).await?;
+                    }
+                    tokio::fs::File::create(&path).await?
+                } else {
+                    let file = tokio::fs::OpenOptions::new().append(true).open(&path).await?;
+                    if file.metadata().await?.len() != offset {
+                        return Err("blob transfer out of sequence".into());
+                    }
+                    file
+                };
+                file.write_all(&data).await?;
+                file.flush().await?;
+                drop(file);
+
+                if offset + data.len() as u64 == total {
+                    store.put_file(&replica, &key, &path).await?;
+                }
+                Ok(ReplicaResponse::Ok)
+            }
+            ReplicaRequest::Get { environment, key, offset, length } => {
+                if !is_replicated_key(&key) {
+                    return Err("invalid blob key".into());
+                }
+                let replica = replica_environment(origin, &environment);
+                let total = store.stat(&replica, &key).await?.size;
+                let length = length.min(TRANSFER_CHUNK).min(total.saturating_sub(offset));
+
+                let mut data = Vec::new();
+                if length > 0 {
+                    store.open(&replica, &key, Some((offset, length))).await?.read_to_end(&mut data).await?;
+                }
+                Ok(ReplicaResponse::Data { total, data })
+            }
+            ReplicaRequest::Delete { environment, keys } => {
+                let replica = replica_environment(origin, &environment);
+                for key in keys.iter().filter(|k| is_replicated_key(k)) {
+                    match store.delete(&replica, key).await {
+                        Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+                        _ => {}
+                    }
+                }
+                Ok(ReplicaResponse::Ok)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signed_requests_cannot_be_replayed() {
+        let (pubkey, privkey) = crypto::generate_keypair().unwrap();
+        let manager = ReplicationManager::new(ReplicationConfig::default(), String::new(), String::new(), Vec::new());
+        let request = ReplicaRequest::List { environment: "env".to_string() };
+        let timestamp = current_timestamp();
+        let sign = |nonce: &str| crypto::sign_data(&privkey, &bincode::serialize(&(timestamp, nonce, &request)).unwrap()).unwrap();
+
+        let signature = sign("n1");
+        assert!(manager.authenticate(&pubkey, true, timestamp, "n1", &signature, &request).is_ok());
+        assert!(manager.authenticate(&pubkey, true, timestamp, "n1", &signature, &request).is_err());
+        // Il nonce è firmato: non si può sostituire
+        assert!(manager.authenticate(&pubkey, true, timestamp, "n2", &signature, &request).is_err());
+        assert!(manager.authenticate(&pubkey, true, timestamp, "n2", &sign("n2"), &request).is_ok());
+
+        let stale = timestamp - MAX_CLOCK_SKEW - 1;
+        let signature = crypto::sign_data(&privkey, &bincode::serialize(&(stale, "n3", &request)).unwrap()).unwrap();
+        assert!(manager.authenticate(&pubkey, true, stale, "n3", &signature, &request).is_err());
+    }
+
+    #[test]
+    fn discovered_peers_have_a_shard_limit() {
+        let peer = PeerConfig {
+            name: None,
+            pubkey: "configured".to_string(),
+            address: "127.0.0.1".to_string(),
+            port: 3000,
+            protocol: PeerProtocol::Arson,
+        };
+        let config = ReplicationConfig { max_discovered_shard_bytes: 100, ..ReplicationConfig::default() };
+        let manager = ReplicationManager::new(config, String::new(), String::new(), vec![peer]);
+
+        assert_eq!(manager.replica_limit("configured", erasure::SHARD_ENVIRONMENT), None);
+        assert_eq!(manager.replica_limit("discovered", erasure::SHARD_ENVIRONMENT), Some(100));
+
+        let config = ReplicationConfig { max_replica_bytes: Some(50), max_discovered_shard_bytes: 100, ..ReplicationConfig::default() };
+        let manager = ReplicationManager::new(config, String::new(), String::new(), Vec::new());
+        assert_eq!(manager.replica_limit("discovered", erasure::SHARD_ENVIRONMENT), Some(50));
+    }
+}
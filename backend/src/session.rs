@@ -0,0 +1,323 @@
+use crate::crypto::{current_timestamp, random_bytes, sign_data, verify_signature};
+use crate::types::{SessionChallenge, SessionClaims};
+use base64::{engine::general_purpose, Engine as _};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+/// Validità di una challenge non ancora firmata (secondi)
+const CHALLENGE_TTL: u64 = 120;
+/// Validità di un token di sessione (secondi)
+const SESSION_TTL: u64 = 12 * 3600;
+
+/// Errori di autenticazione delle sessioni vault
+#[derive(Debug, Clone, PartialEq)]
+pub enum SessionError {
+    /// Challenge sconosciuta, già usata o scaduta
+    UnknownChallenge,
+    /// La firma della challenge non corrisponde alla pubkey
+    BadSignature,
+    /// Token malformato o con firma del nodo non valida
+    InvalidToken,
+    /// Token scaduto o revocato
+    Expired,
+    /// Errore interno (firma del token non riuscita)
+    Internal(String),
+}
+
+impl std::fmt::Display for SessionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SessionError::UnknownChallenge => write!(f, "unknown or expired challenge"),
+            SessionError::BadSignature => write!(f, "challenge signature does not match pubkey"),
+            SessionError::InvalidToken => write!(f, "invalid session token"),
+            SessionError::Expired => write!(f, "session expired or revoked"),
+            SessionError::Internal(e) => write!(f, "session error: {}", e),
+        }
+    }
+}
+
+/// Challenge emessa e in attesa di firma
+#[derive(Debug, Clone)]
+struct PendingChallenge {
+    pubkey: String,
+    challenge: String,
+    expires_at: u64,
+}
+
+/// Gestisce le sessioni autenticate per le API del vault.
+///
+/// Il client dimostra il possesso di una pubkey firmando una challenge emessa
+/// dal server; in cambio riceve un token firmato con la chiave RSA del nodo,
+/// legato all'ambiente derivato da quella pubkey.
+pub struct SessionManager {
+    /// Chiave pubblica del nodo (verifica dei token)
+    node_pubkey: String,
+    /// Chiave privata del nodo (firma dei token)
+    node_privkey: String,
+    /// Challenge in attesa (challenge_id -> challenge)
+    challenges: Arc<RwLock<HashMap<String, PendingChallenge>>>,
+    /// Sessioni revocate prima della scadenza (session_id -> expires_at)
+    revoked: Arc<RwLock<HashMap<String, u64>>>,
+}
+
+impl SessionManager {
+    /// Crea un nuovo SessionManager con le chiavi del nodo
+    pub fn new(node_pubkey: String, node_privkey: String) -> Self {
+        Self {
+            node_pubkey,
+            node_privkey,
+            challenges: Arc::new(RwLock::new(HashMap::new())),
+            revoked: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    /// Emette una challenge che il client deve firmare con la propria chiave
+    pub async fn issue_challenge(&self, pubkey: &str) -> SessionChallenge {
+        let now = current_timestamp();
+        let challenge_id = hex::encode(random_bytes::<16>());
+        let expires_at = now + CHALLENGE_TTL;
+        let challenge = format!("vault-session:{}:{}", challenge_id, expires_at);
+
+        let mut challenges = self.challenges.write().await;
+        challenges.retain(|_, c| c.expires_at > now);
+        challenges.insert(challenge_id.clone(), PendingChallenge {
+            pubkey: pubkey.to_string(),
+            challenge: challenge.clone(),
+            expires_at,
+        });
+
+        SessionChallenge {
+            challenge_id,
+            challenge,
+            expires_at,
+        }
+    }
+
+    /// Verifica la firma di una challenge e apre una sessione, legata
+    /// all'ambiente personale o a quello indicato (un team: l'appartenenza è
+    /// verificata dal chiamante). Ogni challenge può essere usata una sola volta.
+    pub async fn open_session(
+        &self,
+        challenge_id: &str,
+        signature: &str,
+        environment: Option<String>,
+    ) -> Result<(String, SessionClaims), SessionError> {
+        let pending = self.challenges.write().await
+            .remove(challenge_id)
+            .ok_or(SessionError::UnknownChallenge)?;
+
+        let now = current_timestamp();
+        if pending.expires_at <= now {
+            return Err(SessionError::UnknownChallenge);
+        }
+
+        let valid = verify_signature(&pending.pubkey, signature, pending.challenge.as_bytes())
+            .unwrap_or(false);
+        if !valid {
+            return Err(SessionError::BadSignature);
+        }
+
+        let claims = SessionClaims {
+            session_id: hex::encode(random_bytes::<16>()),
+            environment: environment.unwrap_or_else(|| environment_for_pubkey(&pending.pubkey)),
+            pubkey: pending.pubkey,
+            issued_at: now,
+            expires_at: now + SESSION_TTL,
+        };
+        let token = self.sign_claims(&claims)?;
+
+        Ok((token, claims))
+    }
+
+    /// Valida un token di sessione e ne restituisce i claims
+    pub async fn validate(&self, token: &str) -> Result<SessionClaims, SessionError> {
+        let (payload_b64, sig_b64) = token.split_once('.').ok_or(SessionError::InvalidToken)?;
+
+        let payload = general_purpose::URL_SAFE_NO_PAD.decode(payload_b64)
+            .map_err(|_| SessionError::InvalidToken)?;
+        let sig = general_purpose::URL_SAFE_NO_PAD.decode(sig_b64)
+            .map_err(|_| SessionError::InvalidToken)?;
+
+        let valid = verify_signature(
+            &self.node_pubkey,
+            &general_purpose::STANDARD.encode(sig),
+            &payload,
+        ).unwrap_or(false);
+        if !valid {
+            return Err(SessionError::InvalidToken);
+        }
+
+        let claims: SessionClaims = serde_json::from_slice(&payload)
+            .map_err(|_| SessionError::InvalidToken)?;
+
+        if claims.expires_at <= current_timestamp() {
+            return Err(SessionError::Expired);
+        }
+        if self.revoked.read().await.contains_key(&claims.session_id) {
+            return Err(SessionError::Expired);
+        }
+
+        Ok(claims)
+    }
+
+    /// Revoca una sessione prima della sua scadenza naturale
+    pub async fn revoke(&self, claims: &SessionClaims) {
+        let now = current_timestamp();
+        let mut revoked = self.revoked.write().await;
+        revoked.retain(|_, &mut expires_at| expires_at > now);
+        revoked.insert(claims.session_id.clone(), claims.expires_at);
+    }
+
+    /// Firma i claims con la chiave del nodo: `base64url(json).base64url(firma)`
+    fn sign_claims(&self, claims: &SessionClaims) -> Result<String, SessionError> {
+        let payload = serde_json::to_vec(claims)
+            .map_err(|e| SessionError::Internal(e.to_string()))?;
+        let sig_b64 = sign_data(&self.node_privkey, &payload)
+            .map_err(|e| SessionError::Internal(e.to_string()))?;
+        let sig = general_purpose::STANDARD.decode(sig_b64)
+            .map_err(|e| SessionError::Internal(e.to_string()))?;
+
+        Ok(format!(
+            "{}.{}",
+            general_purpose::URL_SAFE_NO_PAD.encode(payload),
+            general_purpose::URL_SAFE_NO_PAD.encode(sig)
+        ))
+    }
+}
+
+/// Deriva l'ID dell'ambiente personale da una pubkey (stessa regola del client:
+/// primi 8 byte di SHA-256 della pubkey base64, in esadecimale)
+pub fn environment_for_pubkey(pubkey: &str) -> String {
+    let hash = Sha256::digest(pubkey.as_bytes());
+    hex::encode(&hash[..8])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::generate_keypair;
+    use std::sync::OnceLock;
+
+    /// Chiavi RSA (pubkey, privkey) del nodo e del client, generate una volta sola
+    fn keys() -> &'static [(String, String); 2] {
+        static KEYS: OnceLock<[(String, String); 2]> = OnceLock::new();
+        KEYS.get_or_init(|| std::array::from_fn(|_| generate_keypair().unwrap()))
+    }
+
+    fn manager() -> SessionManager {
+        let (pubkey, privkey) = keys()[0].clone();
+        SessionManager::new(pubkey, privkey)
+    }
+
+    /// Challenge emessa per il client e già firmata
+    async fn signed_challenge(sessions: &SessionManager) -> (SessionChallenge, String) {
+        let (pubkey, privkey) = &keys()[1];
+        let challenge = sessions.issue_challenge(pubkey).await;
+        let signature = sign_data(privkey, challenge.challenge.as_bytes()).unwrap();
+        (challenge, signature)
+    }
+
+    #[tokio::test]
+    async fn challenges_are_single_use() {
+        let sessions = manager();
+        let (challenge, signature) = signed_challenge(&sessions).await;
+
+        let (token, claims) = sessions.open_session(&challenge.challenge_id, &signature, None).await.unwrap();
+        assert_eq!(claims.environment, environment_for_pubkey(&keys()[1].0));
+        assert_eq!(sessions.validate(&token).await.unwrap().session_id, claims.session_id);
+        assert_eq!(
+            sessions.open_session(&challenge.challenge_id, &signature, None).await.unwrap_err(),
+            SessionError::UnknownChallenge
+        );
+    }
+
+    #[tokio::test]
+    async fn challenges_expire() {
+        let sessions = manager();
+        let issued = current_timestamp();
+        let (challenge, signature) = signed_challenge(&sessions).await;
+        assert!((issued + CHALLENGE_TTL..=current_timestamp() + CHALLENGE_TTL).contains(&challenge.expires_at));
+
+        sessions.challenges.write().await.get_mut(&challenge.challenge_id).unwrap().expires_at = current_timestamp();
+        assert_eq!(
+            sessions.open_session(&challenge.challenge_id, &signature, None).await.unwrap_err(),
+            SessionError::UnknownChallenge
+        );
+    }
+
+    #[tokio::test]
+    async fn bad_signatures_are_rejected() {
+        let sessions = manager();
+        let (challenge, _) = signed_challenge(&sessions).await;
+
+        // Firmata dalla chiave del nodo invece che da quella del client
+        let signature = sign_data(&keys()[0].1, challenge.challenge.as_bytes()).unwrap();
+        assert_eq!(
+            sessions.open_session(&challenge.challenge_id, &signature, None).await.unwrap_err(),
+            SessionError::BadSignature
+        );
+
+        let (challenge, _) = signed_challenge(&sessions).await;
+        let signature = sign_data(&keys()[1].1, b"another text").unwrap();
+        assert_eq!(
+            sessions.open_session(&challenge.challenge_id, &signature, None).await.unwrap_err(),
+            SessionError::BadSignature
+        );
+    }
+
+    #[tokio::test]
+    async fn edited_tokens_are_rejected() {
+        let sessions = manager();
+        let (challenge, signature) = signed_challenge(&sessions).await;
+        let (token, mut claims) = sessions.open_session(&challenge.challenge_id, &signature, None).await.unwrap();
+        let (payload, sig) = token.split_once('.').unwrap();
+
+        claims.environment = "0000000000000000".to_string();
+        let edited = general_purpose::URL_SAFE_NO_PAD.encode(serde_json::to_vec(&claims).unwrap());
+        let mut bad_sig = general_purpose::URL_SAFE_NO_PAD.decode(sig).unwrap();
+        bad_sig[0] ^= 1;
+        let bad_sig = general_purpose::URL_SAFE_NO_PAD.encode(bad_sig);
+
+        for forged in [
+            format!("{}.{}", edited, sig),
+            format!("{}.{}", payload, bad_sig),
+            payload.to_string(),
+            format!("{}.", payload),
+        ] {
+            assert_eq!(sessions.validate(&forged).await.unwrap_err(), SessionError::InvalidToken);
+        }
+
+        // Un token firmato da un altro nodo non vale qui
+        let (pubkey, privkey) = keys()[1].clone();
+        let other = SessionManager::new(pubkey, privkey);
+        assert_eq!(other.validate(&token).await.unwrap_err(), SessionError::InvalidToken);
+    }
+
+    #[tokio::test]
+    async fn sessions_expire() {
+        let sessions = manager();
+        let (challenge, signature) = signed_challenge(&sessions).await;
+        let (_, mut claims) = sessions.open_session(&challenge.challenge_id, &signature, None).await.unwrap();
+        assert_eq!(claims.expires_at - claims.issued_at, SESSION_TTL);
+
+        claims.issued_at -= SESSION_TTL;
+        claims.expires_at -= SESSION_TTL;
+        let expired = sessions.sign_claims(&claims).unwrap();
+        assert_eq!(sessions.validate(&expired).await.unwrap_err(), SessionError::Expired);
+    }
+
+    #[tokio::test]
+    async fn revoked_sessions_are_refused() {
+        let sessions = manager();
+        let (challenge, signature) = signed_challenge(&sessions).await;
+        let (token, claims) = sessions.open_session(&challenge.challenge_id, &signature, None).await.unwrap();
+        let (challenge, signature) = signed_challenge(&sessions).await;
+        let (other, _) = sessions.open_session(&challenge.challenge_id, &signature, None).await.unwrap();
+
+        sessions.revoke(&claims).await;
+        assert_eq!(sessions.validate(&token).await.unwrap_err(), SessionError::Expired);
+        sessions.validate(&other).await.unwrap();
+    }
+}
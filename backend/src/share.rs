@@ -0,0 +1,223 @@
+use axum::body::Bytes;
+use std::io::ErrorKind;
+use tokio::sync::Mutex;
+
+use crate::storage::BlobStore;
+use crate::types::ShareGrant;
+
+/// Condivisioni create dal proprietario, salvate nel suo ambiente
+pub const OUTGOING_INDEX: &str = "shares_out.json";
+
+/// Condivisioni ricevute, salvate nell'ambiente del destinatario
+pub const INCOMING_INDEX: &str = "shares_in.json";
+
+/// Una condivisione è scaduta quando ha una scadenza già raggiunta
+pub fn is_expired(grant: &ShareGrant, now: u64) -> bool {
+    grant.expires_at.is_some_and(|t| t <= now)
+}
+
+/// Condivisioni di elementi del vault verso la pubkey di un altro utente.
+///
+/// Ogni grant è registrato due volte: nell'indice del proprietario, per
+/// elencarlo e revocarlo, e in quello del destinatario, che è il riferimento
+/// per autorizzare l'accesso ai blob. La revoca rimuove prima la copia del
+/// destinatario, così un grant revocato non concede più nulla anche se la
+/// seconda scrittura fallisce. Il server non vede mai la chiave in chiaro:
+/// riceve solo quella già cifrata per il destinatario.
+#[derive(Default)]
+pub struct ShareManager {
+    /// Serializza le modifiche agli indici
+    lock: Mutex<()>,
+}
+
+impl ShareManager {
+    async fn load(store: &dyn BlobStore, env: &str, index: &str) -> std::io::Result<Vec<ShareGrant>> {
+        match store.get(env, index).await {
+            Ok(data) => serde_json::from_slice(&data).map_err(std::io::Error::other),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn save(store: &dyn BlobStore, env: &str, index: &str, grants: &[ShareGrant]) -> std::io::Result<()> {
+        if grants.is_empty() {
+            return match store.delete(env, index).await {
+                Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            };
+        }
+        let data = serde_json::to_vec(grants).map_err(std::io::Error::other)?;
+        store.put(env, index, Bytes::from(data)).await
+    }
+
+    /// Registra un nuovo grant in entrambi gli indici
+    pub async fn grant(&self, store: &dyn BlobStore, grant: ShareGrant) -> std::io::Result<()> {
+        let _guard = self.lock.lock().await;
+
+        let mut outgoing = Self::load(store, &grant.owner_env, OUTGOING_INDEX).await?;
+        outgoing.push(grant.clone());
+        Self::save(store, &grant.owner_env, OUTGOING_INDEX, &outgoing).await?;
+
+        let mut incoming = Self::load(store, &grant.recipient_env, INCOMING_INDEX).await?;
+        incoming.push(grant.clone());
+        Self::save(store, &grant.recipient_env, INCOMING_INDEX, &incoming).await
+    }
+
+    /// Grant ancora validi creati da un ambiente
+    pub async fn outgoing(&self, store: &dyn BlobStore, env: &str, now: u64) -> std::io::Result<Vec<ShareGrant>> {
+        let mut grants = Self::load(store, env, OUTGOING_INDEX).await?;
+        grants.retain(|g| !is_expired(g, now));
+        Ok(grants)
+    }
+
+    /// Grant ancora validi ricevuti da un ambiente
+    pub async fn incoming(&self, store: &dyn BlobStore, env: &str, now: u64) -> std::io::Result<Vec<ShareGrant>> {
+        let mut grants = Self::load(store, env, INCOMING_INDEX).await?;
+        grants.retain(|g| !is_expired(g, now));
+        Ok(grants)
+    }
+
+    /// Revoca i grant indicati del proprietario e restituisce gli ID revocati;
+    /// gli ID di altri ambienti vengono ignorati
+    pub async fn revoke(&self, store: &dyn BlobStore, owner_env: &str, ids: &[String]) -> std::io::Result<Vec<String>> {
+        let _guard = self.lock.lock().await;
+        let outgoing = Self::load(store, owner_env, OUTGOING_INDEX).await?;
+        let (revoked, remaining): (Vec<_>, Vec<_>) = outgoing.into_iter().partition(|g| ids.contains(&g.id));
+
+        Self::remove_incoming(store, &revoked).await?;
+        Self::save(store, owner_env, OUTGOING_INDEX, &remaining).await?;
+        Ok(revoked.into_iter().map(|g| g.id).collect())
+    }
+
+    /// Elimina dagli indici di un ambiente i grant scaduti; restituisce quanti ne ha rimossi
+    pub async fn expire(&self, store: &dyn BlobStore, env: &str, now: u64) -> std::io::Result<usize> {
+        let _guard = self.lock.lock().await;
+
+        let outgoing = Self::load(store, env, OUTGOING_INDEX).await?;
+        let (expired, remaining): (Vec<_>, Vec<_>) = outgoing.into_iter().partition(|g| is_expired(g, now));
+        if !expired.is_empty() {
+            Self::remove_incoming(store, &expired).await?;
+            Self::save(store, env, OUTGOING_INDEX, &remaining).await?;
+        }
+
+        // Copie del destinatario rimaste da un proprietario che non è più sul nodo
+        let incoming = Self::load(store, env, INCOMING_INDEX).await?;
+        let before = incoming.len();
+        let incoming: Vec<_> = incoming.into_iter().filter(|g| !is_expired(g, now)).collect();
+        if incoming.len() != before {
+            Self::save(store, env, INCOMING_INDEX, &incoming).await?;
+        }
+
+        Ok(expired.len() + before - incoming.len())
+    }
+
+    /// Toglie i grant indicati dagli indici dei rispettivi destinatari
+    async fn remove_incoming(store: &dyn BlobStore, grants: &[ShareGrant]) -> std::io::Result<()> {
+        let mut recipients: Vec<&str> = grants.iter().map(|g| g.recipient_env.as_str()).collect();
+        recipients.sort_unstable();
+        recipients.dedup();
+
+        for env in recipients {
+            let mut incoming = Self::load(store, env, INCOMING_INDEX).await?;
+            incoming.retain(|g| !grants.iter().any(|r| r.id == g.id));
+            Self::save(store, env, INCOMING_INDEX, &incoming).await?;
+        }
+        Ok(())
+    }
+
+    /// Vero se l'ambiente del destinatario ha un grant valido per un blob del proprietario
+    pub async fn allows(
+        &self,
+        store: &dyn BlobStore,
+        recipient_env: &str,
+        owner_env: &str,
+        blob_id: &str,
+        now: u64,
+    ) -> std::io::Result<bool> {
+        Ok(self.incoming(store, recipient_env, now).await?.iter().any(|g| {
+            g.owner_env == owner_env
+                && (g.content_id == blob_id || g.preview_id.as_deref() == Some(blob_id))
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::random_bytes;
+    use crate::storage::TempStore;
+
+    fn grant(owner_env: &str, recipient_env: &str, content_id: &str, expires_at: Option<u64>) -> ShareGrant {
+        ShareGrant {
+            id: hex::encode(random_bytes::<8>()),
+            owner_env: owner_env.to_string(),
+            owner_pubkey: "owner".to_string(),
+            recipient_env: recipient_env.to_string(),
+            recipient_pubkey: "recipient".to_string(),
+            content_id: content_id.to_string(),
+            preview_id: Some(format!("{}-preview", content_id)),
+            wrapped_key: b"wrapped".to_vec(),
+            encrypted_meta: Vec::new(),
+            meta_nonce: Vec::new(),
+            created_at: 0,
+            expires_at,
+        }
+    }
+
+    fn envs() -> (String, String) {
+        (hex::encode(random_bytes::<8>()), hex::encode(random_bytes::<8>()))
+    }
+
+    #[tokio::test]
+    async fn grants_cover_only_their_blobs() {
+        let store = TempStore::new();
+        let shares = ShareManager::default();
+        let (owner, recipient) = envs();
+        shares.grant(&*store, grant(&owner, &recipient, "a", None)).await.unwrap();
+
+        assert!(shares.allows(&*store, &recipient, &owner, "a", 0).await.unwrap());
+        assert!(shares.allows(&*store, &recipient, &owner, "a-preview", 0).await.unwrap());
+        assert!(!shares.allows(&*store, &recipient, &owner, "b", 0).await.unwrap());
+        // Lo stesso ID in un altro ambiente, o chiesto da un altro destinatario
+        assert!(!shares.allows(&*store, &recipient, &recipient, "a", 0).await.unwrap());
+        assert!(!shares.allows(&*store, &owner, &owner, "a", 0).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn expired_grants_allow_nothing() {
+        let store = TempStore::new();
+        let shares = ShareManager::default();
+        let (owner, recipient) = envs();
+        shares.grant(&*store, grant(&owner, &recipient, "a", Some(100))).await.unwrap();
+        shares.grant(&*store, grant(&owner, &recipient, "b", None)).await.unwrap();
+
+        assert!(shares.allows(&*store, &recipient, &owner, "a", 99).await.unwrap());
+        assert!(!shares.allows(&*store, &recipient, &owner, "a", 100).await.unwrap());
+        assert_eq!(shares.outgoing(&*store, &owner, 100).await.unwrap().len(), 1);
+
+        assert_eq!(shares.expire(&*store, &owner, 100).await.unwrap(), 1);
+        let incoming = shares.incoming(&*store, &recipient, 0).await.unwrap();
+        assert_eq!(incoming.iter().map(|g| g.content_id.as_str()).collect::<Vec<_>>(), ["b"]);
+    }
+
+    #[tokio::test]
+    async fn revoked_grants_allow_nothing() {
+        let store = TempStore::new();
+        let shares = ShareManager::default();
+        let (owner, recipient) = envs();
+        let revoked = grant(&owner, &recipient, "a", None);
+        let kept = grant(&owner, &recipient, "b", None);
+        shares.grant(&*store, revoked.clone()).await.unwrap();
+        shares.grant(&*store, kept.clone()).await.unwrap();
+
+        // Solo il proprietario può revocare
+        assert!(shares.revoke(&*store, &recipient, std::slice::from_ref(&revoked.id)).await.unwrap().is_empty());
+        assert!(shares.allows(&*store, &recipient, &owner, "a", 0).await.unwrap());
+
+        assert_eq!(shares.revoke(&*store, &owner, std::slice::from_ref(&revoked.id)).await.unwrap(), vec![revoked.id]);
+        assert!(!shares.allows(&*store, &recipient, &owner, "a", 0).await.unwrap());
+        assert!(!shares.allows(&*store, &recipient, &owner, "a-preview", 0).await.unwrap());
+        assert!(shares.allows(&*store, &recipient, &owner, "b", 0).await.unwrap());
+        assert_eq!(shares.outgoing(&*store, &owner, 0).await.unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,291 @@
+use async_trait::async_trait;
+use axum::body::Bytes;
+use serde::{Deserialize, Serialize};
+use std::io::{ErrorKind, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt};
+
+use crate::s3::{S3BlobStore, S3Config};
+
+/// Lettore asincrono sul contenuto (o su un intervallo) di un blob
+pub type BlobReader = Pin<Box<dyn AsyncRead + Send>>;
+
+/// Informazioni su un blob memorizzato
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlobStat {
+    pub key: String,
+    pub size: u64,
+    /// Ultima modifica (unix timestamp, secondi)
+    pub modified: u64,
+}
+
+/// Archivio dei blob del vault (contenuti cifrati, anteprime, metadata.enc).
+///
+/// Ogni blob è identificato da ambiente + chiave. Gli upload a chunk restano
+/// su disco locale finché non sono completi; solo il blob finale passa dallo store.
+#[async_trait]
+pub trait BlobStore: Send + Sync {
+    /// Scrive (o sovrascrive) un blob piccolo, interamente in memoria
+    async fn put(&self, env: &str, key: &str, data: Bytes) -> std::io::Result<()>;
+
+    /// Sposta nello store un file locale già completo; `src` non va più usato dopo la chiamata
+    async fn put_file(&self, env: &str, key: &str, src: &Path) -> std::io::Result<()>;
+
+    /// Legge un blob intero
+    async fn get(&self, env: &str, key: &str) -> std::io::Result<Bytes> {
+        let mut data = Vec::new();
+        self.open(env, key, None).await?.read_to_end(&mut data).await?;
+        Ok(Bytes::from(data))
+    }
+
+    /// Apre un blob in streaming; `range` è (inizio, lunghezza) in byte
+    async fn open(&self, env: &str, key: &str, range: Option<(u64, u64)>) -> std::io::Result<BlobReader>;
+
+    /// Dimensione e data di modifica di un blob
+    async fn stat(&self, env: &str, key: &str) -> std::io::Result<BlobStat>;
+
+    /// Sposta un blob sotto un'altra chiave dello stesso ambiente
+    async fn rename(&self, env: &str, from: &str, to: &str) -> std::io::Result<()>;
+
+    /// Rende un blob disponibile anche sotto un'altra chiave, di norma in un
+    /// altro ambiente, senza far passare i dati dal nodo. Le due chiavi restano
+    /// indipendenti: eliminarne una non tocca l'altra.
+    async fn link(&self, from_env: &str, from: &str, to_env: &str, to: &str) -> std::io::Result<()>;
+
+    /// Se `link` condivide i dati; gli store che dovrebbero copiarli restituiscono
+    /// Unsupported da `link`
+    fn can_link(&self) -> bool {
+        true
+    }
+
+    /// Elimina un blob; un blob inesistente restituisce NotFound
+    async fn delete(&self, env: &str, key: &str) -> std::io::Result<()>;
+
+    /// Elenca i blob di un ambiente
+    async fn list(&self, env: &str) -> std::io::Result<Vec<BlobStat>>;
+
+    /// Ambienti che hanno almeno un blob (o una directory) nello store
+    async fn environments(&self) -> std::io::Result<Vec<String>>;
+}
+
+/// Configurazione dello storage nel file node.json
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "backend", rename_all = "lowercase")]
+pub enum StorageConfig {
+    /// Directory locale, layout `{root}/{env}/{key}`
+    Fs {
+        #[serde(default = "default_fs_root")]
+        root: String,
+    },
+    /// Object storage compatibile S3 (AWS, MinIO, ...)
+    S3(S3Config),
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        StorageConfig::Fs { root: default_fs_root() }
+    }
+}
+
+fn default_fs_root() -> String { "vault_data".to_string() }
+
+/// Crea lo store indicato dalla configurazione
+pub fn from_config(config: &StorageConfig) -> Arc<dyn BlobStore> {
+    match config {
+        StorageConfig::Fs { root } => Arc::new(FsBlobStore::new(root)),
+        StorageConfig::S3(s3) => Arc::new(S3BlobStore::new(s3.clone())),
+    }
+}
+
+/// Store su filesystem: il layout storico `vault_data/{env}/{key}`
+pub struct FsBlobStore {
+    root: PathBuf,
+}
+
+impl FsBlobStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    fn path(&self, env: &str, key: &str) -> PathBuf {
+        self.root.join(env).join(key)
+    }
+}
+
+#[async_trait]
+impl BlobStore for FsBlobStore {
+    async fn put(&self, env: &str, key: &str, data: Bytes) -> std::io::Result<()> {
+        let path = self.path(env, key);
+        tokio::fs::create_dir_all(self.root.join(env)).await?;
+
+        let tmp = path.with_extension("tmp");
+        tokio::fs::write(&tmp, &data).await?;
+        tokio::fs::rename(&tmp, &path).await
+    }
+
+    async fn put_file(&self, env: &str, key: &str, src: &Path) -> std::io::Result<()> {
+        tokio::fs::create_dir_all(self.root.join(env)).await?;
+        let path = self.path(env, key);
+
+        // Rename se siamo sullo stesso filesystem, altrimenti copia
+        if tokio::fs::rename(src, &path).await.is_err() {
+            tokio::fs::copy(src, &path).await?;
+            tokio::fs::remove_file(src).await.ok();
+        }
+        Ok(())
+    }
+
+    async fn open(&self, env: &str, key: &str, range: Option<(u64, u64)>) -> std::io::Result<BlobReader> {
+        let mut file = tokio::fs::File::open(self.path(env, key)).await?;
+        match range {
+            Some((start, len)) => {
+                file.seek(SeekFrom::Start(start)).await?;
+                Ok(Box::pin(file.take(len)))
+            }
+            None => Ok(Box::pin(file)),
+        }
+    }
+
+    async fn stat(&self, env: &str, key: &str) -> std::io::Result<BlobStat> {
+        let meta = tokio::fs::metadata(self.path(env, key)).await?;
+        if !meta.is_file() {
+            return Err(ErrorKind::NotFound.into());
+        }
+        Ok(BlobStat {
+            key: key.to_string(),
+            size: meta.len(),
+            modified: unix_mtime(&meta),
+        })
+    }
+
+    async fn rename(&self, env: &str, from: &str, to: &str) -> std::io::Result<()> {
+        tokio::fs::rename(self.path(env, from), self.path(env, to)).await
+    }
+
+    /// Hard link: i dati sono condivisi e il disco si libera solo quando
+    /// viene eliminato l'ultimo nome. Senza hard link (altro filesystem) copia.
+    async fn link(&self, from_env: &str, from: &str, to_env: &str, to: &str) -> std::io::Result<()> {
+        tokio::fs::create_dir_all(self.root.join(to_env)).await?;
+        let (src, dst) = (self.path(from_env, from), self.path(to_env, to));
+
+        match tokio::fs::hard_link(&src, &dst).await {
+            Err(e) if e.kind() != ErrorKind::NotFound && e.kind() != ErrorKind::AlreadyExists => {
+                tracing::debug!("Hard link {} failed ({}), copying", dst.display(), e);
+                let tmp = dst.with_extension("tmp");
+                tokio::fs::copy(&src, &tmp).await?;
+                tokio::fs::rename(&tmp, &dst).await
+            }
+            result => result,
+        }
+    }
+
+    async fn delete(&self, env: &str, key: &str) -> std::io::Result<()> {
+        tokio::fs::remove_file(self.path(env, key)).await
+    }
+
+    async fn list(&self, env: &str) -> std::io::Result<Vec<BlobStat>> {
+        let mut result = Vec::new();
+        let mut entries = match tokio::fs::read_dir(self.root.join(env)).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(result),
+            Err(e) => return Err(e),
+        };
+
+        while let Some(entry) = entries.next_entry().await? {
+            let meta = entry.metadata().await?;
+            // Le directory sono upload in corso, non blob
+            if !meta.is_file() {
+                continue;
+            }
+            result.push(BlobStat {
+                key: entry.file_name().to_string_lossy().to_string(),
+                size: meta.len(),
+                modified: unix_mtime(&meta),
+            });
+        }
+        Ok(result)
+    }
+
+    async fn environments(&self) -> std::io::Result<Vec<String>> {
+        let mut result = Vec::new();
+        let mut entries = match tokio::fs::read_dir(&self.root).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(result),
+            Err(e) => return Err(e),
+        };
+
+        while let Some(entry) = entries.next_entry().await? {
+            if entry.file_type().await?.is_dir() {
+                result.push(entry.file_name().to_string_lossy().to_string());
+            }
+        }
+        Ok(result)
+    }
+}
+
+fn unix_mtime(meta: &std::fs::Metadata) -> u64 {
+    meta.modified()
+        .ok()
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Store su una directory temporanea, rimossa alla fine del test
+#[cfg(test)]
+pub struct TempStore {
+    store: FsBlobStore,
+}
+
+#[cfg(test)]
+impl TempStore {
+    pub fn new() -> Self {
+        let root = std::env::temp_dir().join(format!("vault-test-{}", hex::encode(crate::crypto::random_bytes::<8>())));
+        Self { store: FsBlobStore::new(root) }
+    }
+}
+
+#[cfg(test)]
+impl std::ops::Deref for TempStore {
+    type Target = FsBlobStore;
+
+    fn deref(&self) -> &FsBlobStore {
+        &self.store
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempStore {
+    fn drop(&mut self) {
+        std::fs::remove_dir_all(&self.store.root).ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn delete_in_one_env_keeps_the_linked_copy() {
+        let store = TempStore::new();
+        store.put("personal", "content", Bytes::from_static(b"ciphertext")).await.unwrap();
+        store.link("personal", "content", "team", "copy").await.unwrap();
+
+        store.delete("personal", "content").await.unwrap();
+        assert_eq!(store.stat("personal", "content").await.unwrap_err().kind(), ErrorKind::NotFound);
+        assert_eq!(store.get("team", "copy").await.unwrap(), Bytes::from_static(b"ciphertext"));
+
+        store.delete("team", "copy").await.unwrap();
+        assert!(store.list("team").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn link_of_a_missing_blob_is_not_found() {
+        let store = TempStore::new();
+        let err = store.link("personal", "missing", "team", "copy").await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+        assert!(store.list("team").await.unwrap().is_empty());
+    }
+}
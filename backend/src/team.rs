@@ -0,0 +1,410 @@
+use axum::body::Bytes;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use tokio::sync::{Mutex, RwLock};
+
+use crate::crypto::{current_timestamp, verify_signature};
+use crate::session::environment_for_pubkey;
+use crate::storage::BlobStore;
+use crate::types::{MembershipChange, Role, SignedChange, TeamManifest, TeamMember};
+
+/// Manifest dei membri, salvato nell'ambiente di team
+pub const TEAM_MANIFEST: &str = "team.json";
+
+/// Team di cui un utente fa parte, salvato nel suo ambiente personale
+pub const TEAM_INDEX: &str = "teams.json";
+
+/// Lunghezza (esadecimale) dell'ID di un ambiente di team. Gli ambienti
+/// personali ne hanno 16, quindi un team non può mai prendere il posto
+/// dell'ambiente personale di qualcuno.
+pub const TEAM_ENV_LEN: usize = 32;
+
+/// Errori nella gestione dei membri di un team
+#[derive(Debug)]
+pub enum TeamError {
+    NotFound,
+    /// Chi firma non è owner del team
+    Forbidden,
+    /// Versione non consecutiva o team già esistente
+    Conflict,
+    /// Modifica non applicabile (ID non valido, membro inesistente, ultimo owner...)
+    Invalid,
+    BadSignature,
+    Io(std::io::Error),
+}
+
+impl std::fmt::Display for TeamError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TeamError::NotFound => write!(f, "team not found"),
+            TeamError::Forbidden => write!(f, "signer is not an owner"),
+            TeamError::Conflict => write!(f, "team version conflict"),
+            TeamError::Invalid => write!(f, "invalid membership change"),
+            TeamError::BadSignature => write!(f, "membership change signature is invalid"),
+            TeamError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<std::io::Error> for TeamError {
+    fn from(e: std::io::Error) -> Self {
+        TeamError::Io(e)
+    }
+}
+
+/// Vero per gli ID che possono identificare un ambiente di team
+pub fn is_team_env(env: &str) -> bool {
+    env.len() == TEAM_ENV_LEN && env.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Payload firmato dall'owner per una modifica dei membri:
+/// `vault-team:{env}:{versione}:{sha256(pubkey)}:{ruolo|remove}:{sha256(wrapped_key)}`
+pub fn signing_payload(change: &MembershipChange) -> String {
+    let role = match change.role {
+        Some(Role::Owner) => "owner",
+        Some(Role::Writer) => "writer",
+        Some(Role::Reader) => "reader",
+        None => "remove",
+    };
+    format!(
+        "vault-team:{}:{}:{}:{}:{}",
+        change.environment,
+        change.version,
+        hex::encode(Sha256::digest(change.pubkey.as_bytes())),
+        role,
+        hex::encode(Sha256::digest(&change.wrapped_key)),
+    )
+}
+
+/// Ambienti di team con membri e ruoli.
+///
+/// Ogni modifica ai membri è firmata da un owner con la propria chiave e
+/// registrata nel log del manifest, così i membri possono verificare da sé
+/// lo storico invece di fidarsi del nodo. I ruoli vengono controllati a ogni
+/// richiesta: i manifest restano in cache e vengono aggiornati solo qui.
+#[derive(Default)]
+pub struct TeamManager {
+    /// Manifest letti dallo store (None: l'ambiente non è un team)
+    cache: RwLock<HashMap<String, Option<TeamManifest>>>,
+    /// Serializza le modifiche ai manifest e agli indici
+    lock: Mutex<()>,
+}
+
+impl TeamManager {
+    async fn load_json<T: serde::de::DeserializeOwned>(store: &dyn BlobStore, env: &str, key: &str) -> std::io::Result<Option<T>> {
+        match store.get(env, key).await {
+            Ok(data) => serde_json::from_slice(&data).map(Some).map_err(std::io::Error::other),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Manifest di un ambiente di team, se esiste
+    pub async fn manifest(&self, store: &dyn BlobStore, env: &str) -> std::io::Result<Option<TeamManifest>> {
+        if !is_team_env(env) {
+            return Ok(None);
+        }
+        if let Some(cached) = self.cache.read().await.get(env) {
+            return Ok(cached.clone());
+        }
+        let manifest: Option<TeamManifest> = Self::load_json(store, env, TEAM_MANIFEST).await?;
+        self.cache.write().await.insert(env.to_string(), manifest.clone());
+        Ok(manifest)
+    }
+
+    /// Ruolo di una pubkey in un ambiente: owner del proprio ambiente personale,
+    /// il ruolo di membro in un team, nessuno altrove
+    pub async fn role(&self, store: &dyn BlobStore, env: &str, pubkey: &str) -> std::io::Result<Option<Role>> {
+        if environment_for_pubkey(pubkey) == env {
+            return Ok(Some(Role::Owner));
+        }
+        Ok(self.manifest(store, env).await?.and_then(|m| {
+            m.members.iter().find(|member| member.pubkey == pubkey).map(|member| member.role)
+        }))
+    }
+
+    /// Team di cui fa parte una pubkey
+    pub async fn teams_of(&self, store: &dyn BlobStore, pubkey: &str) -> std::io::Result<Vec<TeamManifest>> {
+        let index: Vec<String> = Self::load_json(store, &environment_for_pubkey(pubkey), TEAM_INDEX).await?.unwrap_or_default();
+        let mut teams = Vec::new();
+        for env in index {
+            if let Some(manifest) = self.manifest(store, &env).await? {
+                if manifest.members.iter().any(|m| m.pubkey == pubkey) {
+                    teams.push(manifest);
+                }
+            }
+        }
+        Ok(teams)
+    }
+
+    /// Crea un team con chi firma come unico owner (versione 1)
+    pub async fn create(
+        &self,
+        store: &dyn BlobStore,
+        change: MembershipChange,
+        signer: &str,
+        signature: String,
+    ) -> Result<TeamManifest, TeamError> {
+        if !is_team_env(&change.environment)
+            || change.version != 1
+            || change.pubkey != signer
+            || change.role != Some(Role::Owner)
+            || change.wrapped_key.is_empty()
+        {
+            return Err(TeamError::Invalid);
+        }
+        Self::verify(&change, signer, &signature)?;
+
+        let _guard = self.lock.lock().await;
+        let env = change.environment.clone();
+        if self.manifest(store, &env).await?.is_some() || !store.list(&env).await?.is_empty() {
+            return Err(TeamError::Conflict);
+        }
+
+        let now = current_timestamp();
+        let manifest = TeamManifest {
+            environment: env.clone(),
+            version: 1,
+            members: vec![TeamMember {
+                pubkey: signer.to_string(),
+                role: Role::Owner,
+                wrapped_key: change.wrapped_key.clone(),
+                added_at: now,
+            }],
+            log: vec![SignedChange {
+                change,
+                signer: signer.to_string(),
+                signature,
+                applied_at: now,
+            }],
+        };
+        self.save(store, &manifest).await?;
+        Self::update_index(store, signer, &env, true).await?;
+        Ok(manifest)
+    }
+
+    /// Applica una modifica firmata da un owner del team
+    pub async fn apply(
+        &self,
+        store: &dyn BlobStore,
+        change: MembershipChange,
+        signer: &str,
+        signature: String,
+    ) -> Result<TeamManifest, TeamError> {
+        let _guard = self.lock.lock().await;
+        let mut manifest = self.manifest(store, &change.environment).await?.ok_or(TeamError::NotFound)?;
+
+        if !manifest.members.iter().any(|m| m.pubkey == signer && m.role == Role::Owner) {
+            return Err(TeamError::Forbidden);
+        }
+        if change.version != manifest.version + 1 {
+            return Err(TeamError::Conflict);
+        }
+        Self::verify(&change, signer, &signature)?;
+
+        let now = current_timestamp();
+        let existing = manifest.members.iter().position(|m| m.pubkey == change.pubkey);
+        match (change.role, existing) {
+            (None, Some(i)) => {
+                manifest.members.remove(i);
+            }
+            (None, None) => return Err(TeamError::Invalid),
+            (Some(role), Some(i)) => {
+                let member = &mut manifest.members[i];
+                member.role = role;
+                if !change.wrapped_key.is_empty() {
+                    member.wrapped_key = change.wrapped_key.clone();
+                }
+            }
+            (Some(_), None) if change.wrapped_key.is_empty() || change.pubkey.is_empty() => {
+                return Err(TeamError::Invalid);
+            }
+            (Some(role), None) => manifest.members.push(TeamMember {
+                pubkey: change.pubkey.clone(),
+                role,
+                wrapped_key: change.wrapped_key.clone(),
+                added_at: now,
+            }),
+        }
+        if !manifest.members.iter().any(|m| m.role == Role::Owner) {
+            return Err(TeamError::Invalid);
+        }
+
+        let member = change.pubkey.clone();
+        let added = change.role.is_some();
+        manifest.version = change.version;
+        manifest.log.push(SignedChange {
+            change,
+            signer: signer.to_string(),
+            signature,
+            applied_at: now,
+        });
+        self.save(store, &manifest).await?;
+        Self::update_index(store, &member, &manifest.environment, added).await?;
+        Ok(manifest)
+    }
+
+    fn verify(change: &MembershipChange, signer: &str, signature: &str) -> Result<(), TeamError> {
+        let payload = signing_payload(change);
+        if verify_signature(signer, signature, payload.as_bytes()).unwrap_or(false) {
+            Ok(())
+        } else {
+            Err(TeamError::BadSignature)
+        }
+    }
+
+    async fn save(&self, store: &dyn BlobStore, manifest: &TeamManifest) -> std::io::Result<()> {
+        let data = serde_json::to_vec(manifest).map_err(std::io::Error::other)?;
+        store.put(&manifest.environment, TEAM_MANIFEST, Bytes::from(data)).await?;
+        self.cache.write().await.insert(manifest.environment.clone(), Some(manifest.clone()));
+        Ok(())
+    }
+
+    /// Aggiunge o toglie un team dall'indice personale di un membro
+    async fn update_index(store: &dyn BlobStore, pubkey: &str, team_env: &str, member: bool) -> std::io::Result<()> {
+        let env = environment_for_pubkey(pubkey);
+        let mut index: Vec<String> = Self::load_json(store, &env, TEAM_INDEX).await?.unwrap_or_default();
+        let present = index.iter().any(|e| e == team_env);
+        if present == member {
+            return Ok(());
+        }
+        if member {
+            index.push(team_env.to_string());
+        } else {
+            index.retain(|e| e != team_env);
+        }
+        let data = serde_json::to_vec(&index).map_err(std::io::Error::other)?;
+        store.put(&env, TEAM_INDEX, Bytes::from(data)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::{generate_keypair, random_bytes, sign_data};
+    use crate::storage::TempStore;
+    use std::sync::OnceLock;
+
+    /// Coppie di chiavi RSA (pubkey, privkey), generate una volta sola
+    fn keys() -> &'static [(String, String); 3] {
+        static KEYS: OnceLock<[(String, String); 3]> = OnceLock::new();
+        KEYS.get_or_init(|| std::array::from_fn(|_| generate_keypair().unwrap()))
+    }
+
+    fn change(env: &str, version: u64, pubkey: &str, role: Option<Role>) -> MembershipChange {
+        MembershipChange {
+            environment: env.to_string(),
+            version,
+            pubkey: pubkey.to_string(),
+            role,
+            wrapped_key: if role.is_some() { b"wrapped".to_vec() } else { Vec::new() },
+        }
+    }
+
+    fn sign(change: &MembershipChange, privkey: &str) -> String {
+        sign_data(privkey, signing_payload(change).as_bytes()).unwrap()
+    }
+
+    /// Team appena creato con la prima chiave come owner
+    async fn team(store: &dyn BlobStore, teams: &TeamManager) -> String {
+        let env = hex::encode(random_bytes::<16>());
+        let (owner, owner_key) = &keys()[0];
+        let first = change(&env, 1, owner, Some(Role::Owner));
+        let signature = sign(&first, owner_key);
+        teams.create(store, first, owner, signature).await.unwrap();
+        env
+    }
+
+    #[tokio::test]
+    async fn only_owners_change_members() {
+        let store = TempStore::new();
+        let teams = TeamManager::default();
+        let env = team(&*store, &teams).await;
+        let [(owner, owner_key), (writer, writer_key), (other, other_key)] = keys();
+
+        let add = change(&env, 2, writer, Some(Role::Writer));
+        let signature = sign(&add, owner_key);
+        teams.apply(&*store, add, owner, signature).await.unwrap();
+        assert_eq!(teams.role(&*store, &env, writer).await.unwrap(), Some(Role::Writer));
+
+        let promote = change(&env, 3, other, Some(Role::Owner));
+        for (signer, key) in [(writer, writer_key), (other, other_key)] {
+            let signature = sign(&promote, key);
+            assert!(matches!(
+                teams.apply(&*store, promote.clone(), signer, signature).await,
+                Err(TeamError::Forbidden)
+            ));
+        }
+        assert_eq!(teams.role(&*store, &env, other).await.unwrap(), None);
+        assert_eq!(teams.manifest(&*store, &env).await.unwrap().unwrap().version, 2);
+    }
+
+    #[tokio::test]
+    async fn stale_versions_conflict() {
+        let store = TempStore::new();
+        let teams = TeamManager::default();
+        let env = team(&*store, &teams).await;
+        let [(owner, owner_key), (writer, _), _] = keys();
+
+        for version in [1, 3] {
+            let add = change(&env, version, writer, Some(Role::Writer));
+            let signature = sign(&add, owner_key);
+            assert!(matches!(teams.apply(&*store, add, owner, signature).await, Err(TeamError::Conflict)));
+        }
+
+        // Un team esistente non può essere ricreato
+        let again = change(&env, 1, owner, Some(Role::Owner));
+        let signature = sign(&again, owner_key);
+        assert!(matches!(teams.create(&*store, again, owner, signature).await, Err(TeamError::Conflict)));
+    }
+
+    #[tokio::test]
+    async fn forged_changes_are_rejected() {
+        let store = TempStore::new();
+        let teams = TeamManager::default();
+        let env = team(&*store, &teams).await;
+        let [(owner, owner_key), (writer, _), (_, other_key)] = keys();
+
+        // Firmata da un'altra chiave a nome dell'owner
+        let add = change(&env, 2, writer, Some(Role::Writer));
+        let signature = sign(&add, other_key);
+        assert!(matches!(teams.apply(&*store, add.clone(), owner, signature).await, Err(TeamError::BadSignature)));
+
+        // wrapped_key diversa da quella firmata
+        let signature = sign(&add, owner_key);
+        let swapped = MembershipChange { wrapped_key: b"attacker".to_vec(), ..add.clone() };
+        assert!(matches!(teams.apply(&*store, swapped, owner, signature.clone()).await, Err(TeamError::BadSignature)));
+
+        // Ruolo diverso da quello firmato
+        let promoted = MembershipChange { role: Some(Role::Owner), ..add.clone() };
+        assert!(matches!(teams.apply(&*store, promoted, owner, signature.clone()).await, Err(TeamError::BadSignature)));
+
+        assert!(matches!(teams.apply(&*store, add, owner, "not-a-signature".to_string()).await, Err(TeamError::BadSignature)));
+        assert_eq!(teams.manifest(&*store, &env).await.unwrap().unwrap().members.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn the_last_owner_stays() {
+        let store = TempStore::new();
+        let teams = TeamManager::default();
+        let env = team(&*store, &teams).await;
+        let [(owner, owner_key), (second, _), _] = keys();
+
+        for role in [None, Some(Role::Writer), Some(Role::Reader)] {
+            let demote = change(&env, 2, owner, role);
+            let signature = sign(&demote, owner_key);
+            assert!(matches!(teams.apply(&*store, demote, owner, signature).await, Err(TeamError::Invalid)));
+        }
+        assert_eq!(teams.role(&*store, &env, owner).await.unwrap(), Some(Role::Owner));
+
+        // Con un secondo owner si può lasciare il ruolo
+        let add = change(&env, 2, second, Some(Role::Owner));
+        let signature = sign(&add, owner_key);
+        teams.apply(&*store, add, owner, signature).await.unwrap();
+        let demote = change(&env, 3, owner, Some(Role::Writer));
+        let signature = sign(&demote, owner_key);
+        teams.apply(&*store, demote, owner, signature).await.unwrap();
+        assert_eq!(teams.role(&*store, &env, owner).await.unwrap(), Some(Role::Writer));
+    }
+}
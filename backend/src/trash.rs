@@ -0,0 +1,315 @@
+use axum::body::Bytes;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::io::ErrorKind;
+use tokio::sync::Mutex;
+
+use crate::crypto::current_timestamp;
+use crate::storage::BlobStore;
+use crate::types::{TrashEntry, VaultItem};
+use crate::upload;
+
+/// Indice del cestino di un ambiente, salvato nello store accanto ai blob
+const TRASH_INDEX: &str = "trash.json";
+
+/// Suffisso dei blob spostati nel cestino
+const TRASHED_SUFFIX: &str = ".trashed";
+
+/// Configurazione del cestino nel file node.json
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TrashConfig {
+    /// Per quanto tempo un elemento resta nel cestino prima di essere eliminato (secondi)
+    #[serde(default = "default_retention_secs")]
+    pub retention_secs: u64,
+    /// Intervallo tra due passate di pulizia degli elementi scaduti (secondi)
+    #[serde(default = "default_sweep_interval_secs")]
+    pub sweep_interval_secs: u64,
+}
+
+impl Default for TrashConfig {
+    fn default() -> Self {
+        Self {
+            retention_secs: default_retention_secs(),
+            sweep_interval_secs: default_sweep_interval_secs(),
+        }
+    }
+}
+
+fn default_retention_secs() -> u64 { 30 * 24 * 3600 }
+fn default_sweep_interval_secs() -> u64 { 3600 }
+
+/// Chiave di un blob nel cestino
+pub fn trashed_key(blob_id: &str) -> String {
+    format!("{}{}", blob_id, TRASHED_SUFFIX)
+}
+
+/// Vero per le chiavi dello store che appartengono al cestino
+pub fn is_trash_key(key: &str) -> bool {
+    key == TRASH_INDEX || key.ends_with(TRASHED_SUFFIX)
+}
+
+/// Esito di una cancellazione
+#[derive(Debug, Default)]
+pub struct TrashOutcome {
+    pub trashed: Vec<String>,
+    pub not_found: Vec<String>,
+}
+
+/// Cestino per ambiente: i blob eliminati vengono rinominati con il suffisso
+/// `.trashed` e registrati in `trash.json`, da cui possono essere ripristinati
+/// o eliminati definitivamente. Gli elementi oltre il periodo di ritenzione
+/// vengono eliminati da `expire`.
+pub struct TrashManager {
+    config: TrashConfig,
+    /// Serializza le modifiche a trash.json
+    lock: Mutex<()>,
+}
+
+impl TrashManager {
+    pub fn new(config: TrashConfig) -> Self {
+        Self {
+            config,
+            lock: Mutex::new(()),
+        }
+    }
+
+    pub fn sweep_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.config.sweep_interval_secs.max(1))
+    }
+
+    async fn load(store: &dyn BlobStore, env: &str) -> std::io::Result<Vec<TrashEntry>> {
+        match store.get(env, TRASH_INDEX).await {
+            Ok(data) => serde_json::from_slice(&data).map_err(std::io::Error::other),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn save(store: &dyn BlobStore, env: &str, entries: &[TrashEntry]) -> std::io::Result<()> {
+        if entries.is_empty() {
+            return match store.delete(env, TRASH_INDEX).await {
+                Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            };
+        }
+        let data = serde_json::to_vec(entries).map_err(std::io::Error::other)?;
+        store.put(env, TRASH_INDEX, Bytes::from(data)).await
+    }
+
+    /// Elementi nel cestino di un ambiente
+    pub async fn list(&self, store: &dyn BlobStore, env: &str) -> std::io::Result<Vec<TrashEntry>> {
+        Self::load(store, env).await
+    }
+
+    /// Sposta i blob indicati nel cestino. Un contenuto porta con sé la propria
+    /// anteprima e il VaultItem registrato all'upload, così può essere ripristinato
+    /// per intero; gli ID inesistenti vengono riportati in `not_found`.
+    pub async fn trash(&self, store: &dyn BlobStore, env: &str, ids: &[String]) -> std::io::Result<TrashOutcome> {
+        let _guard = self.lock.lock().await;
+        let mut entries = Self::load(store, env).await?;
+        let now = current_timestamp();
+
+        let items: HashMap<String, VaultItem> = upload::list_uploads(env).await?
+            .into_iter()
+            .filter_map(|u| u.meta.item)
+            .map(|item| (item.content_id.clone(), item))
+            .collect();
+        // Anteprime che partono già insieme al proprio contenuto
+        let with_content: HashSet<&str> = ids.iter()
+            .filter_map(|id| items.get(id))
+            .filter_map(|item| item.preview_id.as_deref())
+            .collect();
+
+        let mut outcome = TrashOutcome::default();
+        for id in ids {
+            if with_content.contains(id.as_str()) {
+                continue;
+            }
+            let item = items.get(id).cloned();
+            let mut candidates = vec![id.clone()];
+            if let Some(preview_id) = item.as_ref().and_then(|i| i.preview_id.clone()) {
+                candidates.push(preview_id);
+            }
+
+            let mut blobs = Vec::new();
+            let mut size = 0;
+            for blob_id in candidates {
+                let Ok(stat) = store.stat(env, &blob_id).await else {
+                    continue;
+                };
+                // Un rename fallito lascia il blob dov'era: lo si riporta come non trovato
+                if let Err(e) = store.rename(env, &blob_id, &trashed_key(&blob_id)).await {
+                    tracing::warn!("Failed to move {} to trash: {}", blob_id, e);
+                    continue;
+                }
+                size += stat.size;
+                blobs.push(blob_id);
+            }
+
+            if blobs.first() == Some(id) {
+                outcome.trashed.push(id.clone());
+            } else {
+                outcome.not_found.push(id.clone());
+            }
+            if blobs.is_empty() {
+                continue;
+            }
+            entries.push(TrashEntry {
+                id: blobs[0].clone(),
+                blobs,
+                item,
+                size,
+                deleted_at: now,
+                expires_at: now + self.config.retention_secs,
+            });
+        }
+
+        Self::save(store, env, &entries).await?;
+        Ok(outcome)
+    }
+
+    /// Riporta gli elementi indicati fuori dal cestino e li restituisce
+    pub async fn restore(&self, store: &dyn BlobStore, env: &str, ids: &[String]) -> std::io::Result<Vec<TrashEntry>> {
+        let _guard = self.lock.lock().await;
+        let entries = Self::load(store, env).await?;
+        let (selected, mut remaining): (Vec<_>, Vec<_>) = entries.into_iter().partition(|e| ids.contains(&e.id));
+
+        let mut restored = Vec::new();
+        for entry in selected {
+            let mut result = Ok(());
+            for blob_id in &entry.blobs {
+                result = store.rename(env, &trashed_key(blob_id), blob_id).await;
+                if result.is_err() {
+                    break;
+                }
+            }
+            match result {
+                Ok(()) => restored.push(entry),
+                Err(e) => {
+                    tracing::warn!("Failed to restore {} from trash: {}", entry.id, e);
+                    remaining.push(entry);
+                }
+            }
+        }
+
+        Self::save(store, env, &remaining).await?;
+        Ok(restored)
+    }
+
+    /// Elimina definitivamente gli elementi indicati (tutti se `ids` è None)
+    pub async fn purge(&self, store: &dyn BlobStore, env: &str, ids: Option<&[String]>) -> std::io::Result<Vec<String>> {
+        let _guard = self.lock.lock().await;
+        let entries = Self::load(store, env).await?;
+        let (selected, remaining): (Vec<_>, Vec<_>) = entries
+            .into_iter()
+            .partition(|e| ids.is_none_or(|ids| ids.contains(&e.id)));
+
+        let purged = Self::delete_entries(store, env, selected).await;
+        Self::save(store, env, &remaining).await?;
+        Ok(purged)
+    }
+
+    /// Elimina gli elementi con ritenzione scaduta; restituisce quanti ne ha eliminati
+    pub async fn expire(&self, store: &dyn BlobStore, env: &str, now: u64) -> std::io::Result<usize> {
+        let _guard = self.lock.lock().await;
+        let entries = Self::load(store, env).await?;
+        if entries.iter().all(|e| e.expires_at > now) {
+            return Ok(0);
+        }
+        let (expired, remaining): (Vec<_>, Vec<_>) = entries.into_iter().partition(|e| e.expires_at <= now);
+
+        let purged = Self::delete_entries(store, env, expired).await;
+        Self::save(store, env, &remaining).await?;
+        Ok(purged.len())
+    }
+
+    async fn delete_entries(store: &dyn BlobStore, env: &str, entries: Vec<TrashEntry>) -> Vec<String> {
+        let mut purged = Vec::new();
+        for entry in entries {
+            for blob_id in &entry.blobs {
+                if let Err(e) = store.delete(env, &trashed_key(blob_id)).await {
+                    if e.kind() != ErrorKind::NotFound {
+                        tracing::warn!("Failed to purge {} from trash: {}", blob_id, e);
+                    }
+                }
+            }
+            purged.push(entry.id);
+        }
+        purged
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::TempStore;
+
+    fn env() -> String {
+        format!("trash-test-{}", hex::encode(crate::crypto::random_bytes::<8>()))
+    }
+
+    async fn keys(store: &dyn BlobStore, env: &str) -> Vec<String> {
+        let mut keys: Vec<String> = store.list(env).await.unwrap().into_iter().map(|b| b.key).collect();
+        keys.sort();
+        keys
+    }
+
+    #[tokio::test]
+    async fn trash_and_restore_rename_blobs() {
+        let store = TempStore::new();
+        let env = env();
+        let trash = TrashManager::new(TrashConfig::default());
+        store.put(&env, "a", Bytes::from_static(b"content")).await.unwrap();
+
+        let ids = ["a".to_string(), "missing".to_string()];
+        let outcome = trash.trash(&*store, &env, &ids).await.unwrap();
+        assert_eq!(outcome.trashed, ["a"]);
+        assert_eq!(outcome.not_found, ["missing"]);
+        assert_eq!(keys(&*store, &env).await, [trashed_key("a"), TRASH_INDEX.to_string()]);
+        let entries = trash.list(&*store, &env).await.unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].size, 7);
+
+        let restored = trash.restore(&*store, &env, &ids[..1]).await.unwrap();
+        assert_eq!(restored.len(), 1);
+        assert_eq!(keys(&*store, &env).await, ["a"]);
+        assert_eq!(&store.get(&env, "a").await.unwrap()[..], b"content");
+    }
+
+    #[tokio::test]
+    async fn purge_deletes_blob_and_entry() {
+        let store = TempStore::new();
+        let env = env();
+        let trash = TrashManager::new(TrashConfig::default());
+        for id in ["a", "b"] {
+            store.put(&env, id, Bytes::from_static(b"content")).await.unwrap();
+        }
+        trash.trash(&*store, &env, &["a".to_string(), "b".to_string()]).await.unwrap();
+
+        assert_eq!(trash.purge(&*store, &env, Some(&["a".to_string()])).await.unwrap(), ["a"]);
+        assert_eq!(keys(&*store, &env).await, [trashed_key("b"), TRASH_INDEX.to_string()]);
+        assert_eq!(trash.list(&*store, &env).await.unwrap().iter().map(|e| e.id.as_str()).collect::<Vec<_>>(), ["b"]);
+
+        assert_eq!(trash.purge(&*store, &env, None).await.unwrap(), ["b"]);
+        assert!(keys(&*store, &env).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn expiry_removes_only_entries_past_retention() {
+        let store = TempStore::new();
+        let env = env();
+        let short = TrashManager::new(TrashConfig { retention_secs: 100, ..Default::default() });
+        let long = TrashManager::new(TrashConfig { retention_secs: 1000, ..Default::default() });
+        for id in ["a", "b"] {
+            store.put(&env, id, Bytes::from_static(b"content")).await.unwrap();
+        }
+        short.trash(&*store, &env, &["a".to_string()]).await.unwrap();
+        long.trash(&*store, &env, &["b".to_string()]).await.unwrap();
+        let deleted_at = short.list(&*store, &env).await.unwrap()[0].deleted_at;
+
+        assert_eq!(short.expire(&*store, &env, deleted_at + 99).await.unwrap(), 0);
+        assert_eq!(short.expire(&*store, &env, deleted_at + 100).await.unwrap(), 1);
+        assert_eq!(keys(&*store, &env).await, [trashed_key("b"), TRASH_INDEX.to_string()]);
+        assert_eq!(short.list(&*store, &env).await.unwrap().iter().map(|e| e.id.as_str()).collect::<Vec<_>>(), ["b"]);
+    }
+}
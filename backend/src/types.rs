@@ -1,6 +1,16 @@
 use serde::{Deserialize, Serialize};
 use tokio::sync::mpsc;
 
+use crate::erasure::ErasureConfig;
+use crate::gateway::GatewayConfig;
+use crate::fsck::GcConfig;
+use crate::inbox::InboxConfig;
+use crate::journal::JournalConfig;
+use crate::quota::QuotaConfig;
+use crate::replication::ReplicationConfig;
+use crate::storage::StorageConfig;
+use crate::trash::TrashConfig;
+
 // ============== VAULT TYPES ==============
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
@@ -13,18 +23,47 @@ pub struct VaultItem {
     pub nonce: Vec<u8>,
     pub content_id: String,
     pub preview_id: Option<String>,
+    /// Radice Merkle degli hash SHA-256 dei chunk cifrati
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub merkle_root: Option<String>,
+    /// Hash SHA-256 di ogni chunk, per verificare il download chunk per chunk
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub chunk_hashes: Vec<String>,
+    /// Dimensione di ogni chunk tranne l'ultimo
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunk_size: Option<usize>,
+    /// Token dell'indice cieco (HMAC calcolati dal client), per la ricerca
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub search_tokens: Vec<String>,
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct StartUploadRequest {
     pub session_token: String,
+    #[serde(flatten)]
+    pub upload: NewUpload,
+}
+
+/// Descrizione di un nuovo upload, comune al vault e alle inbox
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct NewUpload {
     pub encrypted_name: Vec<u8>,
     pub name_nonce: Vec<u8>,
     pub item_type: String,
     pub nonce: Vec<u8>,
     pub total_chunks: usize,
+    /// SHA-256 (hex) di ciascun chunk, uno per chunk
+    pub chunk_hashes: Vec<String>,
+    /// Dimensione totale del contenuto (byte): il file viene allocato subito
+    pub size: u64,
+    /// Dimensione di ogni chunk tranne l'ultimo; il chunk `i` inizia a `i * chunk_size`
+    pub chunk_size: u64,
     pub preview: Option<Vec<u8>>,
     pub preview_nonce: Option<Vec<u8>>,
+    /// Token dell'indice cieco: HMAC con una chiave del client di parole del
+    /// nome, tag e tipo. Il server li confronta senza poterli invertire
+    #[serde(default)]
+    pub search_tokens: Vec<String>,
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
@@ -44,6 +83,552 @@ pub struct UploadResult {
     pub item: Option<VaultItem>,
 }
 
+/// Stato persistente di un upload (meta.json nella directory dei chunk)
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct UploadMeta {
+    pub encrypted_name: Vec<u8>,
+    pub name_nonce: Vec<u8>,
+    pub item_type: String,
+    pub nonce: Vec<u8>,
+    pub total_chunks: usize,
+    #[serde(default)]
+    pub chunk_hashes: Vec<String>,
+    pub size: u64,
+    pub chunk_size: u64,
+    pub content_id: String,
+    pub preview_id: Option<String>,
+    #[serde(default)]
+    pub created_at: u64,
+    /// Presente quando l'upload è stato completato: finish_upload lo restituisce di nuovo
+    #[serde(default)]
+    pub item: Option<VaultItem>,
+    /// Solo per gli upload anonimi verso un'inbox: chiave del contenuto
+    /// cifrata per il destinatario. Viene tolta quando l'elemento è accettato.
+    #[serde(default)]
+    pub wrapped_key: Option<Vec<u8>>,
+    /// Token dell'indice cieco, copiati nell'item al completamento
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub search_tokens: Vec<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct UploadStatus {
+    pub file_id: String,
+    pub total_chunks: usize,
+    pub received_chunks: Vec<usize>,
+    pub missing_chunks: Vec<usize>,
+    pub received_bytes: u64,
+    pub complete: bool,
+    pub item: Option<VaultItem>,
+}
+
+/// Elemento nel cestino di un ambiente
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct TrashEntry {
+    /// ID del blob eliminato (di norma il content_id)
+    pub id: String,
+    /// Blob spostati nel cestino: contenuto ed eventuale anteprima
+    pub blobs: Vec<String>,
+    /// Item registrato all'upload, per reinserirlo nei metadata al ripristino
+    pub item: Option<VaultItem>,
+    pub size: u64,
+    pub deleted_at: u64,
+    pub expires_at: u64,
+}
+
+/// Tipo di una revisione del journal dei metadata
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum JournalKind {
+    /// Modifica cifrata da applicare alla revisione precedente
+    Delta,
+    /// Stato completo dei metadata; vuoto equivale a un vault vuoto
+    Snapshot,
+}
+
+/// Revisione del journal dei metadata di un ambiente
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct JournalEntry {
+    pub revision: u64,
+    pub kind: JournalKind,
+    pub size: u64,
+    pub created_at: u64,
+}
+
+/// Revisione del journal con i dati cifrati, letta dal client
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct JournalRecord {
+    pub revision: u64,
+    pub kind: JournalKind,
+    pub data: Vec<u8>,
+}
+
+/// Revisioni necessarie a un client per aggiornarsi all'ultima
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct MetadataJournal {
+    /// Ultima revisione del journal (0 se vuoto)
+    pub revision: u64,
+    /// Da applicare in ordine: se la prima è uno snapshot, sostituisce lo
+    /// stato del client
+    pub entries: Vec<JournalRecord>,
+}
+
+/// Blob contenuto in un archivio di export
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ArchiveBlob {
+    pub key: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+/// Ultima voce di un archivio: elenco dei blob con i loro hash e item degli
+/// upload, per ricostruire l'ambiente altrove
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ArchiveManifest {
+    pub version: u32,
+    /// Ambiente esportato
+    pub environment: String,
+    pub created_at: u64,
+    pub blobs: Vec<ArchiveBlob>,
+    pub items: Vec<VaultItem>,
+}
+
+/// Esito dell'import di un archivio
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct ImportReport {
+    pub environment: String,
+    pub source_environment: String,
+    /// Blob scritti nello store
+    pub imported: Vec<String>,
+    /// Già presenti con lo stesso contenuto
+    pub identical: Vec<String>,
+    /// Già presenti con un contenuto diverso: non sovrascritti
+    pub conflicts: Vec<String>,
+    /// Hash o dimensione diversi da quelli del manifest
+    pub corrupted: Vec<String>,
+    /// Nel manifest ma non nell'archivio
+    pub missing: Vec<String>,
+    /// Item registrati tra gli upload completati
+    pub items: usize,
+}
+
+/// Contenuto ricevuto nell'inbox di un ambiente, in attesa di essere accettato
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct InboxItem {
+    /// content_id del contenuto ricevuto
+    pub id: String,
+    /// Item dell'upload; il nome è cifrato con la chiave del contenuto
+    pub item: VaultItem,
+    /// Chiave del contenuto cifrata con la pubkey RSA del destinatario
+    pub wrapped_key: Vec<u8>,
+    /// Contenuto e anteprima (byte)
+    pub size: u64,
+    pub received_at: u64,
+    pub expires_at: u64,
+}
+
+/// Upload anonimo verso l'inbox del proprietario di una pubkey
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct InboxUploadRequest {
+    pub recipient_pubkey: String,
+    pub wrapped_key: Vec<u8>,
+    #[serde(flatten)]
+    pub upload: NewUpload,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct InboxUploadResponse {
+    pub file_id: String,
+    /// Ambiente del destinatario, da indicare nelle richieste successive
+    pub environment: String,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct InboxFinishRequest {
+    pub environment: String,
+    pub file_id: String,
+}
+
+/// Condivisione di un elemento del vault verso la pubkey di un altro utente
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ShareGrant {
+    pub id: String,
+    pub owner_env: String,
+    pub owner_pubkey: String,
+    pub recipient_env: String,
+    pub recipient_pubkey: String,
+    pub content_id: String,
+    pub preview_id: Option<String>,
+    /// Chiave del contenuto cifrata con la pubkey RSA del destinatario
+    pub wrapped_key: Vec<u8>,
+    /// Nome, tipo e nonce dell'elemento cifrati con la chiave del contenuto (opachi per il nodo)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub encrypted_meta: Vec<u8>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub meta_nonce: Vec<u8>,
+    pub created_at: u64,
+    /// Scadenza (unix timestamp); senza scadenza vale fino alla revoca
+    pub expires_at: Option<u64>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct CreateShareRequest {
+    pub session_token: String,
+    pub recipient_pubkey: String,
+    pub content_id: String,
+    pub preview_id: Option<String>,
+    pub wrapped_key: Vec<u8>,
+    #[serde(default)]
+    pub encrypted_meta: Vec<u8>,
+    #[serde(default)]
+    pub meta_nonce: Vec<u8>,
+    pub expires_at: Option<u64>,
+}
+
+/// Link pubblico a un contenuto del vault, utilizzabile senza account sul nodo
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ShareLink {
+    pub id: String,
+    pub content_id: String,
+    pub created_at: u64,
+    /// Scadenza (unix timestamp); senza scadenza vale fino alla revoca
+    pub expires_at: Option<u64>,
+    pub max_downloads: Option<u32>,
+    pub downloads: u32,
+    /// Il download richiede una password
+    pub protected: bool,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct CreateLinkRequest {
+    pub session_token: String,
+    pub content_id: String,
+    pub expires_at: Option<u64>,
+    pub max_downloads: Option<u32>,
+    pub password: Option<String>,
+}
+
+/// Ruolo di un membro in un ambiente di team, in ordine crescente di permessi
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Reader,
+    Writer,
+    Owner,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct TeamMember {
+    pub pubkey: String,
+    pub role: Role,
+    /// Chiave dell'ambiente cifrata con la pubkey RSA del membro
+    pub wrapped_key: Vec<u8>,
+    pub added_at: u64,
+}
+
+/// Modifica di un membro: aggiunta, cambio di ruolo o rimozione (`role` assente)
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct MembershipChange {
+    pub environment: String,
+    /// Versione del manifest dopo la modifica: deve seguire quella corrente
+    pub version: u64,
+    pub pubkey: String,
+    pub role: Option<Role>,
+    /// Obbligatoria per un nuovo membro; vuota per mantenere quella esistente
+    #[serde(default)]
+    pub wrapped_key: Vec<u8>,
+}
+
+/// Modifica firmata da un owner, conservata nel log del team
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct SignedChange {
+    pub change: MembershipChange,
+    pub signer: String,
+    /// Firma RSA (base64) del payload canonico della modifica
+    pub signature: String,
+    pub applied_at: u64,
+}
+
+/// Membri di un ambiente di team, con lo storico delle modifiche firmate
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct TeamManifest {
+    pub environment: String,
+    pub version: u64,
+    pub members: Vec<TeamMember>,
+    pub log: Vec<SignedChange>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct MembershipRequest {
+    pub session_token: String,
+    pub change: MembershipChange,
+    pub signature: String,
+}
+
+/// Stato di sincronizzazione di un ambiente verso un peer
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct ReplicaInfo {
+    /// Pubkey del peer (tra quelli in `Node.peers`)
+    pub peer: String,
+    pub name: Option<String>,
+    pub added_at: u64,
+    pub last_attempt: Option<u64>,
+    /// Ultima sincronizzazione completata senza errori
+    pub last_sync: Option<u64>,
+    pub last_error: Option<String>,
+    /// Blob presenti sul peer dopo l'ultimo tentativo
+    pub blobs: usize,
+    pub bytes: u64,
+    /// Blob ancora da inviare
+    pub pending: usize,
+    /// SHA-256 dell'ultima revisione di metadata.enc inviata
+    pub metadata_revision: Option<String>,
+    /// Blob persi in locale e recuperati da questo peer
+    pub repaired: usize,
+    #[serde(default)]
+    pub last_audit: Option<u64>,
+    /// Blob per cui il peer ha dimostrato di avere il contenuto
+    #[serde(default)]
+    pub audits_passed: u64,
+    /// Blob mancanti o alterati sul peer, poi inviati di nuovo
+    #[serde(default)]
+    pub audits_failed: u64,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ReplicationRequest {
+    pub session_token: String,
+    /// Peer su cui replicare l'ambiente; una lista vuota disattiva la replica
+    pub peers: Vec<String>,
+}
+
+/// Posizione di uno shard su un peer
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ShardLocation {
+    pub index: usize,
+    /// Chiave dello shard nello store del peer
+    pub key: String,
+    /// SHA-256 del contenuto dello shard
+    pub hash: String,
+    pub peer: String,
+    pub address: String,
+    pub port: u16,
+}
+
+/// Contenuto distribuito sui peer in shard Reed–Solomon, con il VaultItem
+/// registrato all'upload
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ShardMap {
+    /// ID del contenuto
+    pub id: String,
+    pub item: VaultItem,
+    pub size: u64,
+    /// Shard necessari per ricostruire il contenuto (k)
+    pub data_shards: usize,
+    pub parity_shards: usize,
+    pub shard_size: u64,
+    pub shards: Vec<ShardLocation>,
+    pub created_at: u64,
+    pub updated_at: u64,
+}
+
+/// Resoconto di fsck/GC per un ambiente
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct FsckReport {
+    pub environment: String,
+    pub scanned_at: u64,
+    /// Quando il client ha registrato gli ID vivi; senza live set i blob non vengono giudicati
+    pub live_set_registered_at: Option<u64>,
+    /// Upload senza meta.json, o completati il cui contenuto non esiste più
+    pub orphaned_uploads: Vec<String>,
+    /// Upload non completati e inattivi oltre la soglia
+    pub stale_uploads: Vec<String>,
+    pub unreferenced_previews: Vec<String>,
+    pub unreferenced_blobs: Vec<String>,
+    pub zero_length_blobs: Vec<String>,
+    pub reclaimable_bytes: u64,
+    pub dry_run: bool,
+    /// Vero se le correzioni sono state applicate
+    pub applied: bool,
+}
+
+/// Spazio usato da un ambiente (byte)
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct UsageReport {
+    pub environment: String,
+    /// Blob nello store + upload in corso, esclusa l'inbox: è il valore
+    /// confrontato con la quota
+    pub bytes_used: u64,
+    /// Tutti i blob nello store (contenuti, anteprime, metadata.enc)
+    pub stored_bytes: u64,
+    pub content_bytes: u64,
+    pub preview_bytes: u64,
+    pub metadata_bytes: u64,
+    /// Chunk ricevuti da upload non ancora completati
+    pub in_flight_bytes: u64,
+    /// Blob nel cestino, in attesa di scadenza
+    pub trash_bytes: u64,
+    /// Contenuti ricevuti nell'inbox e non ancora accettati (fuori quota)
+    pub inbox_bytes: u64,
+    pub item_count: usize,
+    pub quota_bytes: Option<u64>,
+    pub node_bytes_used: Option<u64>,
+    pub node_quota_bytes: Option<u64>,
+}
+
+// ============== SESSION TYPES ==============
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct SessionChallengeRequest {
+    pub pubkey: String,
+}
+
+/// Challenge da firmare (RSASSA-PKCS1-v1_5 / SHA-256) con la chiave del client
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct SessionChallenge {
+    pub challenge_id: String,
+    pub challenge: String,
+    pub expires_at: u64,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct OpenSessionRequest {
+    pub challenge_id: String,
+    pub signature: String,
+    /// Ambiente di team a cui legare la sessione (default: quello personale)
+    #[serde(default)]
+    pub environment: Option<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct SessionResponse {
+    pub session_token: String,
+    pub environment: String,
+    /// Ruolo nell'ambiente della sessione (owner per quello personale)
+    pub role: Role,
+    pub expires_at: u64,
+}
+
+/// Contenuto firmato dal nodo dentro un token di sessione
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct SessionClaims {
+    pub session_id: String,
+    pub pubkey: String,
+    pub environment: String,
+    pub issued_at: u64,
+    pub expires_at: u64,
+}
+
+/// Capability emessa per un ambiente, come compare nell'indice (senza firma)
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct CapabilityInfo {
+    pub id: String,
+    /// Pubkey di chi l'ha emessa: la capability non può superarne il ruolo
+    pub issuer: String,
+    pub label: String,
+    /// Caveat aggiunti all'emissione; chi la riceve può aggiungerne altri
+    pub caveats: Vec<String>,
+    pub created_at: u64,
+    pub expires_at: Option<u64>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct CreateCapabilityRequest {
+    pub session_token: String,
+    #[serde(default)]
+    pub label: String,
+    /// Caveat nella forma `chiave = valore`
+    #[serde(default)]
+    pub caveats: Vec<String>,
+    pub expires_at: Option<u64>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct CapabilityResponse {
+    pub token: String,
+    pub capability: CapabilityInfo,
+}
+
+/// Credenziali per l'endpoint S3, derivate da una capability appena emessa
+#[derive(Serialize, Debug)]
+pub struct S3CredentialsResponse {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    /// Bucket da indicare al client: l'ambiente della sessione
+    pub bucket: String,
+    pub region: String,
+    pub capability: CapabilityInfo,
+}
+
+/// Stato di un elemento nel catalogo
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ItemState {
+    /// Contenuto nel vault (anche se distribuito in shard)
+    #[default]
+    Active,
+    /// Contenuto nel cestino
+    Trashed,
+    /// Upload anonimo in attesa di essere accettato
+    Inbox,
+}
+
+/// Elemento del catalogo di un ambiente
+#[derive(Clone, Serialize, Debug)]
+pub struct CatalogItem {
+    #[serde(flatten)]
+    pub item: VaultItem,
+    pub state: ItemState,
+    /// Avvio dell'upload che ha prodotto l'elemento (unix timestamp)
+    pub created_at: u64,
+    /// Percorso da cui scaricare l'anteprima, se presente
+    pub preview_url: Option<String>,
+}
+
+/// Una pagina di elementi del catalogo, con i totali dell'intera selezione
+#[derive(Clone, Serialize, Debug)]
+pub struct ItemPage {
+    pub items: Vec<CatalogItem>,
+    pub total: u64,
+    pub total_bytes: u64,
+    pub offset: u64,
+    pub limit: u64,
+}
+
+/// Una pagina di risultati della ricerca sull'indice cieco
+#[derive(Clone, Serialize, Debug)]
+pub struct SearchPage {
+    /// ID degli elementi, prima quelli con più token in comune con la ricerca
+    pub ids: Vec<String>,
+    pub total: u64,
+    pub offset: u64,
+    pub limit: u64,
+}
+
+/// Elemento copiato in un altro ambiente
+#[derive(Clone, Serialize, Debug)]
+pub struct TransferredItem {
+    /// ID dell'elemento nell'ambiente di origine
+    pub source_id: String,
+    /// Elemento creato nell'ambiente di destinazione
+    pub item: VaultItem,
+}
+
+/// Sessione di upload registrata nel catalogo
+#[derive(Clone, Serialize, Debug)]
+pub struct UploadSession {
+    pub file_id: String,
+    pub content_id: String,
+    pub item_type: String,
+    pub size: u64,
+    pub total_chunks: usize,
+    pub received_chunks: Vec<usize>,
+    pub received_bytes: u64,
+    pub started_at: u64,
+    pub last_activity: u64,
+    pub completed_at: Option<u64>,
+}
+
 // ============== NODE TYPES ==============
 
 /// Protocollo di connessione per peer
@@ -99,6 +684,33 @@ pub struct Node {
     /// Nodo relay a cui connettersi (pubkey del peer in peers[])
     #[serde(default)]
     pub relay_node: Option<String>,
+    /// Backend di storage per i blob del vault (default: filesystem in vault_data/)
+    #[serde(default)]
+    pub storage: StorageConfig,
+    /// Quote di spazio per ambiente e per nodo (default: nessun limite)
+    #[serde(default)]
+    pub quotas: QuotaConfig,
+    /// Ritenzione del cestino (default: 30 giorni)
+    #[serde(default)]
+    pub trash: TrashConfig,
+    /// Garbage collector periodico (default: ogni 24 ore)
+    #[serde(default)]
+    pub gc: GcConfig,
+    /// Inbox per upload anonimi (default: attive, 100 MiB per elemento)
+    #[serde(default)]
+    pub inbox: InboxConfig,
+    /// Replica degli ambienti sui peer (default: ogni 15 minuti)
+    #[serde(default)]
+    pub replication: ReplicationConfig,
+    /// Distribuzione dei contenuti in shard sui peer (default: disattivata)
+    #[serde(default)]
+    pub erasure: ErasureConfig,
+    /// Endpoint compatibile S3 per strumenti di backup (default: disattivato)
+    #[serde(default)]
+    pub s3_gateway: GatewayConfig,
+    /// Storia dei metadata conservata (default: ultimi 10 snapshot)
+    #[serde(default)]
+    pub metadata_journal: JournalConfig,
     // Campi legacy per retrocompatibilità
     #[serde(default, skip_serializing)]
     pub listen_port: u16,
@@ -225,6 +837,9 @@ pub enum WsClientMessage {
     RelayMessage { to_pubkey: String, message_type: String, payload: Vec<u8> },
     /// Conferma ricezione messaggio
     MessageAck { to_pubkey: String, message_id: String },
+    /// Iscrive la connessione agli eventi dell'ambiente della sessione
+    Subscribe { session_token: String },
+    Unsubscribe { environment: String },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -241,6 +856,23 @@ pub enum WsServerMessage {
     NodeRegistered { success: bool },
     /// Conferma ricezione messaggio (ACK)
     MessageAck { from_pubkey: String, message_id: String },
+    /// Esito di una Subscribe
+    Subscribed { environment: Option<String>, error: Option<String> },
+    /// Modifica a un ambiente a cui la connessione è iscritta
+    VaultEvent { environment: String, event: VaultEvent },
+}
+
+/// Modifiche al vault notificate alle connessioni iscritte
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum VaultEvent {
+    /// Upload completato o elemento ripristinato dal cestino
+    ItemAdded { item: Box<VaultItem> },
+    /// Contenuti spostati nel cestino
+    ItemsDeleted { ids: Vec<String> },
+    /// Nuova revisione del journal dei metadata
+    MetadataRevision { revision: u64 },
+    /// Chunk ricevuto per un upload in corso
+    UploadProgress { file_id: String, received_chunks: usize, total_chunks: usize },
 }
 
 // ============== CONNECTION STATE ==============
@@ -375,4 +1007,108 @@ pub enum NodePacket {
     OnionResponse(OnionResponse),
     /// Messaggio di discovery
     Discovery(DiscoveryMessage),
+    /// Replica dei vault tra nodi
+    Replication(ReplicationMessage),
+    /// Verifica che un peer conservi ancora i blob replicati
+    Audit(AuditMessage),
+}
+
+// ============== REPLICATION TYPES ==============
+
+/// Sfida di proof-of-retrievability e relativa prova
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum AuditMessage {
+    /// Sfida firmata dal nodo di origine, come le richieste di replica
+    Challenge {
+        origin: String,
+        timestamp: u64,
+        /// Valore casuale usato una sola volta, contro la ripetizione della sfida
+        nonce: String,
+        /// Firma di `bincode((timestamp, nonce, challenge))` con la chiave del nodo
+        signature: String,
+        challenge: AuditChallenge,
+    },
+    Proof(AuditProof),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditChallenge {
+    pub environment: String,
+    pub key: String,
+    /// Nonce nuovo per ogni sfida, così le prove non si possono precalcolare
+    pub nonce: [u8; 16],
+    /// Intervalli (inizio, lunghezza) del blob da dimostrare
+    pub ranges: Vec<(u64, u64)>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum AuditProof {
+    /// SHA-256 di `nonce || byte dell'intervallo` per ogni intervallo, in ordine
+    Hashes(Vec<String>),
+    Error(String),
+}
+
+/// Messaggio di replica tra nodi: una richiesta per connessione, seguita
+/// dalla risposta
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ReplicationMessage {
+    /// Richiesta del nodo che replica i propri ambienti
+    Request {
+        /// Pubkey del nodo di origine, che deve essere tra i peer configurati
+        origin: String,
+        timestamp: u64,
+        /// Valore casuale usato una sola volta: una richiesta intercettata non
+        /// può essere ripetuta entro la finestra del timestamp
+        nonce: String,
+        /// Firma di `bincode((timestamp, nonce, request))` con la chiave del nodo
+        signature: String,
+        request: ReplicaRequest,
+    },
+    Response(ReplicaResponse),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ReplicaRequest {
+    /// Blob presenti nella replica di un ambiente
+    List { environment: String },
+    /// Porzione di un blob, scritta in sequenza a partire da `offset`
+    Put {
+        environment: String,
+        key: String,
+        offset: u64,
+        total: u64,
+        data: Vec<u8>,
+    },
+    /// Porzione di un blob della replica, per recuperare quelli persi
+    Get {
+        environment: String,
+        key: String,
+        offset: u64,
+        length: u64,
+    },
+    Delete {
+        environment: String,
+        keys: Vec<String>,
+    },
+}
+
+impl ReplicaRequest {
+    /// Ambiente del nodo di origine a cui si riferisce la richiesta
+    pub fn environment(&self) -> &str {
+        match self {
+            ReplicaRequest::List { environment }
+            | ReplicaRequest::Put { environment, .. }
+            | ReplicaRequest::Get { environment, .. }
+            | ReplicaRequest::Delete { environment, .. } => environment,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ReplicaResponse {
+    /// Chiave e dimensione dei blob della replica
+    Blobs(Vec<(String, u64)>),
+    Data { total: u64, data: Vec<u8> },
+    Ok,
+    Error(String),
 }
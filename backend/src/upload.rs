@@ -0,0 +1,526 @@
+use axum::body::Bytes;
+use futures_util::{Stream, StreamExt};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::sync::{LazyLock, Mutex};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, BufWriter};
+
+use crate::crypto::current_timestamp;
+use crate::types::{UploadMeta, UploadStatus, VaultItem};
+
+/// Directory locale degli upload in corso, indipendente dal backend di storage
+pub const STAGING_ROOT: &str = "vault_data";
+
+/// Dimensione massima di un singolo chunk
+pub const MAX_CHUNK_BYTES: u64 = 16 * 1024 * 1024;
+
+/// Sottodirectory di un ambiente con i file degli upload in corso
+const UPLOADS_DIR: &str = ".uploads";
+
+/// Errori nella scrittura dei chunk
+#[derive(Debug)]
+pub enum ChunkError {
+    /// Lo SHA-256 del chunk non corrisponde a quello dichiarato
+    HashMismatch,
+    /// Il corpo della richiesta supera la lunghezza del chunk
+    TooLarge,
+    /// Lo stesso chunk è già in scrittura da un'altra richiesta
+    Busy,
+    Io(std::io::Error),
+}
+
+impl std::fmt::Display for ChunkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChunkError::HashMismatch => write!(f, "chunk hash mismatch"),
+            ChunkError::TooLarge => write!(f, "chunk larger than expected"),
+            ChunkError::Busy => write!(f, "chunk is already being written"),
+            ChunkError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<std::io::Error> for ChunkError {
+    fn from(e: std::io::Error) -> Self {
+        ChunkError::Io(e)
+    }
+}
+
+/// Directory di staging degli upload di un ambiente
+pub fn staging_dir(env: &str) -> PathBuf {
+    Path::new(STAGING_ROOT).join(env).join(UPLOADS_DIR)
+}
+
+/// File di staging di un upload: meta.json, contenuto preallocato in cui i
+/// chunk vengono scritti alla loro posizione, e bitmap dei chunk ricevuti
+/// (un byte per chunk, così ogni chunk aggiorna solo il proprio senza lock)
+pub struct StagingFiles {
+    pub meta: PathBuf,
+    pub content: PathBuf,
+    pub bitmap: PathBuf,
+}
+
+/// Percorsi dei file di staging di un upload
+pub fn staging_files(env: &str, file_id: &str) -> StagingFiles {
+    let dir = staging_dir(env);
+    StagingFiles {
+        meta: dir.join(format!("{}.json", file_id)),
+        content: dir.join(format!("{}.part", file_id)),
+        bitmap: dir.join(format!("{}.bitmap", file_id)),
+    }
+}
+
+/// Legge meta.json di un upload
+pub async fn read_meta(files: &StagingFiles) -> std::io::Result<UploadMeta> {
+    let data = tokio::fs::read(&files.meta).await?;
+    serde_json::from_slice(&data).map_err(std::io::Error::other)
+}
+
+/// Scrive meta.json in modo atomico (file temporaneo + rename)
+pub async fn write_meta(files: &StagingFiles, meta: &UploadMeta) -> std::io::Result<()> {
+    let data = serde_json::to_vec(meta).map_err(std::io::Error::other)?;
+    let tmp = files.meta.with_extension("json.tmp");
+    tokio::fs::write(&tmp, data).await?;
+    tokio::fs::rename(tmp, &files.meta).await
+}
+
+/// Crea i file di staging di un nuovo upload. Il contenuto viene allocato
+/// subito alla dimensione finale; meta.json è scritto per ultimo, così un
+/// upload senza meta.json è sempre un avvio interrotto.
+pub async fn create(files: &StagingFiles, meta: &UploadMeta) -> std::io::Result<()> {
+    if let Some(dir) = files.meta.parent() {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+    let content = tokio::fs::File::create(&files.content).await?;
+    content.set_len(meta.size).await?;
+    tokio::fs::write(&files.bitmap, vec![0u8; meta.total_chunks]).await?;
+    write_meta(files, meta).await
+}
+
+/// Posizione e lunghezza di un chunk nel contenuto
+pub fn chunk_range(meta: &UploadMeta, index: usize) -> (u64, u64) {
+    let offset = index as u64 * meta.chunk_size;
+    (offset, meta.chunk_size.min(meta.size.saturating_sub(offset)))
+}
+
+/// Segna un chunk come ricevuto (o no) nella bitmap
+async fn mark_received(files: &StagingFiles, index: usize, received: bool) -> std::io::Result<()> {
+    let mut bitmap = tokio::fs::OpenOptions::new().write(true).open(&files.bitmap).await?;
+    bitmap.seek(SeekFrom::Start(index as u64)).await?;
+    bitmap.write_all(&[received as u8]).await?;
+    bitmap.flush().await
+}
+
+/// Upload con chunk in scrittura o in fase di completamento
+enum Writing {
+    Chunks(HashSet<usize>),
+    Finishing,
+}
+
+/// Scritture in corso per contenuto: due invii dello stesso chunk, o un
+/// chunk e il completamento dello stesso upload, non possono sovrapporsi
+static WRITING: LazyLock<Mutex<HashMap<PathBuf, Writing>>> = LazyLock::new(Default::default);
+
+/// Riserva la scrittura di un chunk (o dell'intero upload, senza indice)
+/// finché non viene rilasciata (drop)
+pub struct WriteGuard {
+    content: PathBuf,
+    index: Option<usize>,
+}
+
+impl WriteGuard {
+    fn acquire(files: &StagingFiles, index: Option<usize>) -> Option<Self> {
+        let mut writing = WRITING.lock().unwrap_or_else(|e| e.into_inner());
+        match (writing.get_mut(&files.content), index) {
+            (None, Some(i)) => {
+                writing.insert(files.content.clone(), Writing::Chunks(HashSet::from([i])));
+            }
+            (None, None) => {
+                writing.insert(files.content.clone(), Writing::Finishing);
+            }
+            (Some(Writing::Chunks(chunks)), Some(i)) => {
+                if !chunks.insert(i) {
+                    return None;
+                }
+            }
+            _ => return None,
+        }
+        Some(Self { content: files.content.clone(), index })
+    }
+}
+
+impl Drop for WriteGuard {
+    fn drop(&mut self) {
+        let mut writing = WRITING.lock().unwrap_or_else(|e| e.into_inner());
+        if let (Some(Writing::Chunks(chunks)), Some(i)) = (writing.get_mut(&self.content), self.index) {
+            chunks.remove(&i);
+            if !chunks.is_empty() {
+                return;
+            }
+        }
+        writing.remove(&self.content);
+    }
+}
+
+/// Riserva un upload per il completamento: fallisce con `Busy` se un chunk
+/// è in scrittura, e i chunk inviati finché la guardia è attiva vengono
+/// rifiutati allo stesso modo
+pub fn begin_finish(files: &StagingFiles) -> Result<WriteGuard, ChunkError> {
+    WriteGuard::acquire(files, None).ok_or(ChunkError::Busy)
+}
+
+/// Scrive un chunk ricevuto in streaming direttamente alla sua posizione nel
+/// contenuto, calcolandone l'hash mentre arriva. Il chunk viene tolto dalla
+/// bitmap prima di sovrascriverlo e segnato come ricevuto solo se è completo
+/// e corrisponde all'hash dichiarato.
+pub async fn write_chunk<S, E>(
+    files: &StagingFiles,
+    meta: &UploadMeta,
+    index: usize,
+    mut body: S,
+    expected_hash: &str,
+) -> Result<u64, ChunkError>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+    E: std::error::Error + Send + Sync + 'static,
+{
+    let _guard = WriteGuard::acquire(files, Some(index)).ok_or(ChunkError::Busy)?;
+    let (offset, len) = chunk_range(meta, index);
+
+    mark_received(files, index, false).await?;
+    let mut file = tokio::fs::OpenOptions::new().write(true).open(&files.content).await?;
+    file.seek(SeekFrom::Start(offset)).await?;
+    let mut file = BufWriter::new(file);
+    let mut hasher = Sha256::new();
+    let mut written = 0u64;
+
+    while let Some(data) = body.next().await {
+        let data = data.map_err(std::io::Error::other)?;
+        written += data.len() as u64;
+        if written > len {
+            return Err(ChunkError::TooLarge);
+        }
+        hasher.update(&data);
+        file.write_all(&data).await?;
+    }
+    file.flush().await?;
+
+    if written != len || hex::encode(hasher.finalize()) != expected_hash {
+        return Err(ChunkError::HashMismatch);
+    }
+    // I dati devono essere su disco prima che la bitmap li dichiari ricevuti
+    file.into_inner().sync_data().await?;
+    mark_received(files, index, true).await?;
+    Ok(written)
+}
+
+/// Indici dei chunk ricevuti (ordinati) e byte totali ricevuti
+pub async fn received_chunks(files: &StagingFiles, meta: &UploadMeta) -> std::io::Result<(Vec<usize>, u64)> {
+    let bitmap = tokio::fs::read(&files.bitmap).await?;
+    let received: Vec<usize> = (0..meta.total_chunks)
+        .filter(|&i| bitmap.get(i).is_some_and(|&b| b != 0))
+        .collect();
+    let bytes = received.iter().map(|&i| chunk_range(meta, i).1).sum();
+    Ok((received, bytes))
+}
+
+/// Indici dei chunk mancanti dato l'elenco (ordinato) di quelli ricevuti
+pub fn missing_chunks(received: &[usize], total_chunks: usize) -> Vec<usize> {
+    (0..total_chunks)
+        .filter(|i| received.binary_search(i).is_err())
+        .collect()
+}
+
+/// Ricalcola l'hash dei chunk del contenuto e toglie dalla bitmap quelli che
+/// non corrispondono più, da inviare di nuovo. Restituisce i loro indici.
+pub async fn verify_chunks(files: &StagingFiles, meta: &UploadMeta) -> std::io::Result<Vec<usize>> {
+    let mut file = tokio::fs::File::open(&files.content).await?;
+    let mut corrupted = Vec::new();
+    let mut buf = Vec::new();
+    for index in 0..meta.total_chunks {
+        let (_, len) = chunk_range(meta, index);
+        buf.resize(len as usize, 0);
+        file.read_exact(&mut buf).await?;
+        if meta.chunk_hashes.get(index).is_none_or(|h| hex::encode(Sha256::digest(&buf)) != *h) {
+            mark_received(files, index, false).await?;
+            corrupted.push(index);
+        }
+    }
+    Ok(corrupted)
+}
+
+/// Porta su disco il contenuto completo, pronto per essere spostato nello store
+pub async fn seal(files: &StagingFiles) -> std::io::Result<()> {
+    tokio::fs::File::open(&files.content).await?.sync_all().await
+}
+
+/// Rimuove la bitmap di un upload completato; meta.json resta come record
+pub async fn remove_bitmap(files: &StagingFiles) {
+    tokio::fs::remove_file(&files.bitmap).await.ok();
+}
+
+/// Stato corrente di un upload
+pub async fn status(files: &StagingFiles, file_id: &str) -> std::io::Result<UploadStatus> {
+    let meta = read_meta(files).await?;
+
+    if let Some(item) = meta.item {
+        return Ok(UploadStatus {
+            file_id: file_id.to_string(),
+            total_chunks: meta.total_chunks,
+            received_chunks: (0..meta.total_chunks).collect(),
+            missing_chunks: Vec::new(),
+            received_bytes: item.size as u64,
+            complete: true,
+            item: Some(item),
+        });
+    }
+
+    let (received, received_bytes) = received_chunks(files, &meta).await?;
+    let missing = missing_chunks(&received, meta.total_chunks);
+
+    Ok(UploadStatus {
+        file_id: file_id.to_string(),
+        total_chunks: meta.total_chunks,
+        received_chunks: received,
+        missing_chunks: missing,
+        received_bytes,
+        complete: false,
+        item: None,
+    })
+}
+
+/// Registra come upload completato un item arrivato da un archivio, così
+/// l'ambiente lo tratta come uno caricato qui. Falso se l'upload esiste già.
+pub async fn record_item(env: &str, item: &VaultItem) -> std::io::Result<bool> {
+    let files = staging_files(env, &item.id);
+    if tokio::fs::try_exists(&files.meta).await? {
+        return Ok(false);
+    }
+    tokio::fs::create_dir_all(staging_dir(env)).await?;
+    let meta = UploadMeta {
+        encrypted_name: item.encrypted_name.clone(),
+        name_nonce: item.name_nonce.clone(),
+        item_type: item.item_type.clone(),
+        nonce: item.nonce.clone(),
+        total_chunks: item.chunk_hashes.len(),
+        chunk_hashes: item.chunk_hashes.clone(),
+        size: item.size as u64,
+        chunk_size: item.chunk_size.unwrap_or(item.size) as u64,
+        content_id: item.content_id.clone(),
+        preview_id: item.preview_id.clone(),
+        created_at: current_timestamp(),
+        item: Some(item.clone()),
+        wrapped_key: None,
+        search_tokens: item.search_tokens.clone(),
+    };
+    write_meta(&files, &meta).await?;
+    Ok(true)
+}
+
+/// Un upload presente nella staging area di un ambiente
+pub struct StagedUpload {
+    pub file_id: String,
+    pub meta: UploadMeta,
+    /// Byte dei chunk ricevuti (zero per gli upload completati)
+    pub received_bytes: u64,
+    /// Ultima attività: avvio dell'upload o chunk più recente (unix timestamp)
+    pub last_activity: u64,
+}
+
+/// Upload senza un meta.json leggibile
+pub struct OrphanedUpload {
+    /// ID dell'upload, o nome di una directory `_chunks` del vecchio layout
+    pub name: String,
+    pub bytes: u64,
+}
+
+/// Sottodirectory di `dir` come (nome, percorso); una directory inesistente è vuota
+async fn subdirs(dir: &Path) -> std::io::Result<Vec<(String, PathBuf)>> {
+    let mut entries = match tokio::fs::read_dir(dir).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut dirs = Vec::new();
+    while let Some(entry) = entries.next_entry().await? {
+        if entry.file_type().await?.is_dir() {
+            dirs.push((entry.file_name().to_string_lossy().to_string(), entry.path()));
+        }
+    }
+    Ok(dirs)
+}
+
+/// File di staging di un ambiente raggruppati per upload: ID -> (byte, mtime più recente)
+async fn staged_ids(env: &str) -> std::io::Result<HashMap<String, (u64, u64)>> {
+    let mut ids: HashMap<String, (u64, u64)> = HashMap::new();
+    let mut entries = match tokio::fs::read_dir(staging_dir(env)).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(ids),
+        Err(e) => return Err(e),
+    };
+
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let Some((file_id, _)) = name.split_once('.') else {
+            continue;
+        };
+        let meta = entry.metadata().await?;
+        let group = ids.entry(file_id.to_string()).or_default();
+        group.0 += meta.len();
+        group.1 = group.1.max(unix_mtime(&meta));
+    }
+    Ok(ids)
+}
+
+/// Directory `{file_id}_chunks` lasciate dal layout precedente
+async fn legacy_dirs(env: &str) -> std::io::Result<Vec<(String, PathBuf)>> {
+    let mut dirs = subdirs(&Path::new(STAGING_ROOT).join(env)).await?;
+    dirs.retain(|(name, _)| name.ends_with("_chunks"));
+    Ok(dirs)
+}
+
+/// Tutti gli upload (in corso e completati) di un ambiente
+pub async fn list_uploads(env: &str) -> std::io::Result<Vec<StagedUpload>> {
+    let mut uploads = Vec::new();
+
+    for (file_id, (_, newest)) in staged_ids(env).await? {
+        let files = staging_files(env, &file_id);
+        let Ok(meta) = read_meta(&files).await else {
+            continue;
+        };
+        let received_bytes = if meta.item.is_none() {
+            received_chunks(&files, &meta).await?.1
+        } else {
+            0
+        };
+        uploads.push(StagedUpload {
+            file_id,
+            last_activity: newest.max(meta.created_at),
+            meta,
+            received_bytes,
+        });
+    }
+    Ok(uploads)
+}
+
+/// Upload senza meta.json leggibile (interrotti all'avvio o corrotti) e
+/// directory del vecchio layout a chunk separati
+pub async fn orphaned_uploads(env: &str) -> std::io::Result<Vec<OrphanedUpload>> {
+    let mut orphans = Vec::new();
+    for (file_id, (bytes, _)) in staged_ids(env).await? {
+        if read_meta(&staging_files(env, &file_id)).await.is_err() {
+            orphans.push(OrphanedUpload { name: file_id, bytes });
+        }
+    }
+    for (name, dir) in legacy_dirs(env).await? {
+        orphans.push(OrphanedUpload { name, bytes: dir_contents(&dir).await? });
+    }
+    Ok(orphans)
+}
+
+/// Rimuove tutti i file di staging di un upload (o una directory del vecchio layout)
+pub async fn remove_upload(env: &str, name: &str) -> std::io::Result<()> {
+    if name.ends_with("_chunks") {
+        return tokio::fs::remove_dir_all(Path::new(STAGING_ROOT).join(env).join(name)).await;
+    }
+    let files = staging_files(env, name);
+    for path in [files.content, files.bitmap, files.meta.with_extension("json.tmp"), files.meta] {
+        match tokio::fs::remove_file(&path).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Byte totali dei file in una directory
+async fn dir_contents(dir: &Path) -> std::io::Result<u64> {
+    let mut bytes = 0;
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        bytes += entry.metadata().await?.len();
+    }
+    Ok(bytes)
+}
+
+fn unix_mtime(meta: &std::fs::Metadata) -> u64 {
+    meta.modified()
+        .ok()
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_secs())
+}
+
+/// Ambienti che hanno una staging area sul nodo
+pub async fn environments() -> std::io::Result<Vec<String>> {
+    Ok(subdirs(Path::new(STAGING_ROOT)).await?.into_iter().map(|(name, _)| name).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_files() -> (PathBuf, StagingFiles) {
+        let dir = std::env::temp_dir().join(format!("upload-test-{}", hex::encode(crate::crypto::random_bytes::<8>())));
+        let files = StagingFiles {
+            meta: dir.join("f.json"),
+            content: dir.join("f.part"),
+            bitmap: dir.join("f.bitmap"),
+        };
+        (dir, files)
+    }
+
+    fn meta(chunks: &[&[u8]]) -> UploadMeta {
+        UploadMeta {
+            encrypted_name: Vec::new(),
+            name_nonce: Vec::new(),
+            item_type: "file".to_string(),
+            nonce: Vec::new(),
+            total_chunks: chunks.len(),
+            chunk_hashes: chunks.iter().map(|c| hex::encode(Sha256::digest(c))).collect(),
+            size: chunks.iter().map(|c| c.len() as u64).sum(),
+            chunk_size: chunks[0].len() as u64,
+            content_id: "c".to_string(),
+            preview_id: None,
+            created_at: 0,
+            item: None,
+            wrapped_key: None,
+            search_tokens: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn finishing_excludes_chunk_writes() {
+        let (_, files) = temp_files();
+        let chunk = WriteGuard::acquire(&files, Some(0)).unwrap();
+        assert!(WriteGuard::acquire(&files, Some(0)).is_none());
+        assert!(begin_finish(&files).is_err());
+
+        let other = WriteGuard::acquire(&files, Some(1)).unwrap();
+        drop(chunk);
+        assert!(begin_finish(&files).is_err());
+        drop(other);
+
+        let finish = begin_finish(&files).unwrap();
+        assert!(WriteGuard::acquire(&files, Some(0)).is_none());
+        drop(finish);
+        assert!(WriteGuard::acquire(&files, Some(0)).is_some());
+    }
+
+    #[tokio::test]
+    async fn verify_finds_chunks_changed_on_disk() {
+        let (dir, files) = temp_files();
+        let meta = meta(&[b"aaaa", b"bb"]);
+        create(&files, &meta).await.unwrap();
+        tokio::fs::write(&files.content, b"aaaabb").await.unwrap();
+        tokio::fs::write(&files.bitmap, [1, 1]).await.unwrap();
+        assert!(verify_chunks(&files, &meta).await.unwrap().is_empty());
+
+        tokio::fs::write(&files.content, b"aaaabx").await.unwrap();
+        assert_eq!(verify_chunks(&files, &meta).await.unwrap(), [1]);
+        assert_eq!(received_chunks(&files, &meta).await.unwrap().0, [0]);
+        tokio::fs::remove_dir_all(dir).await.unwrap();
+    }
+}
@@ -94,4 +94,474 @@ export const p2pApi = {
 	}
 };
 
+// Vault session API
+export const vaultApi = {
+	// Prove ownership of the identity key and obtain a signed session token,
+	// bound to the personal environment or to a team environment
+	async openSession(pubkeyB64, privkeyB64, environment = null) {
+		const challengeRes = await api.post('/api/auth/challenge', { pubkey: pubkeyB64 });
+		if (!challengeRes.ok) throw new Error('Session challenge failed');
+		const { challenge_id, challenge } = await challengeRes.json();
+
+		const signature = await signText(privkeyB64, challenge);
+
+		const sessionRes = await api.post('/api/auth/session', { challenge_id, signature, environment });
+		if (!sessionRes.ok) throw new Error('Session authentication failed');
+		return sessionRes.json();
+	},
+
+	async logout(sessionToken) {
+		return api.post('/api/auth/logout', {}, { headers: authHeaders(sessionToken) });
+	},
+
+	// Catalog: one page of items (newest first) with the total count and size;
+	// state is 'active', 'trashed' or 'inbox'
+	async listItems(sessionToken, { offset = 0, limit = 100, type = null, state = 'active' } = {}) {
+		const params = new URLSearchParams({ offset, limit, state });
+		if (type) params.set('type', type);
+		const res = await api.fetch(`/api/items?${params}`, { headers: authHeaders(sessionToken) });
+		if (!res.ok) throw new Error(`Items request failed: ${res.status}`);
+		return res.json();
+	},
+
+	// Items whose blind index tokens match the query tokens; returns item IDs,
+	// best matches first
+	async search(sessionToken, tokens, { matchAll = true, state = 'active', offset = 0, limit = 100 } = {}) {
+		const res = await api.post('/api/search', {
+			session_token: sessionToken, tokens, match_all: matchAll, state, offset, limit
+		});
+		if (!res.ok) throw new Error(`Search failed: ${res.status}`);
+		return res.json();
+	},
+
+	// Copy items to another environment without re-uploading them. Each entry
+	// is { id, encrypted_name, name_nonce, search_tokens } with the name and
+	// tokens already computed for the destination; the content is linked as is,
+	// still encrypted with the source key. Returns the new items.
+	async copyItems(sessionToken, from, to, items) {
+		const res = await api.post('/api/items/copy', { session_token: sessionToken, from, to, items });
+		if (!res.ok) throw new Error(`Copy failed: ${res.status}`);
+		return res.json();
+	},
+
+	// Same as copyItems, then removes the items from `from`
+	async moveItems(sessionToken, from, to, items) {
+		const res = await api.post('/api/items/move', { session_token: sessionToken, from, to, items });
+		if (!res.ok) throw new Error(`Move failed: ${res.status}`);
+		return res.json();
+	},
+
+	// Upload sessions with their received chunks, to resume interrupted uploads
+	async listUploads(sessionToken, completed = false) {
+		const res = await api.fetch(`/api/uploads?completed=${completed}`, { headers: authHeaders(sessionToken) });
+		if (!res.ok) throw new Error(`Uploads request failed: ${res.status}`);
+		return res.json();
+	},
+
+	// Trash: deleted items kept until their retention expires
+	async listTrash(sessionToken) {
+		const res = await api.fetch('/api/trash', { headers: authHeaders(sessionToken) });
+		if (!res.ok) throw new Error(`Trash request failed: ${res.status}`);
+		return res.json();
+	},
+
+	async restoreTrash(sessionToken, ids) {
+		const res = await api.post('/api/trash/restore', { session_token: sessionToken, ids });
+		if (!res.ok) throw new Error(`Restore failed: ${res.status}`);
+		return (await res.json()).restored;
+	},
+
+	// ids omitted empties the whole trash
+	async purgeTrash(sessionToken, ids = null) {
+		const res = await api.post('/api/trash/purge', { session_token: sessionToken, ids });
+		if (!res.ok) throw new Error(`Purge failed: ${res.status}`);
+		return (await res.json()).purged;
+	},
+
+	// Content and preview IDs still referenced by the vault metadata (used by server GC)
+	async registerLiveSet(sessionToken, ids) {
+		const res = await api.post('/api/gc/live', { session_token: sessionToken, ids });
+		if (!res.ok) throw new Error(`Live set registration failed: ${res.status}`);
+		return res.json();
+	},
+
+	// Metadata journal: revisions after `since` (a snapshot first if one was written since)
+	async readJournal(sessionToken, environment, since = 0) {
+		const res = await api.fetch(`/api/metadata/${environment}/journal?since=${since}`, { headers: authHeaders(sessionToken) });
+		if (!res.ok) throw new Error(`Journal request failed: ${res.status}`);
+		return res.json();
+	},
+
+	// Encrypted delta on top of revision `base` ({ name?, folders?, items?, removed? }); 409 means someone else wrote first
+	async appendDelta(sessionToken, environment, base, delta) {
+		const res = await api.postRaw(`/api/metadata/${environment}/journal?base=${base}`, delta, { headers: authHeaders(sessionToken) });
+		if (!res.ok) throw new Error(`Journal append failed: ${res.status}`);
+		return (await res.json()).revision;
+	},
+
+	async metadataHistory(sessionToken, environment) {
+		const res = await api.fetch(`/api/metadata/${environment}/history`, { headers: authHeaders(sessionToken) });
+		if (!res.ok) throw new Error(`History request failed: ${res.status}`);
+		return res.json();
+	},
+
+	async rollbackMetadata(sessionToken, environment, base, to) {
+		const res = await api.postRaw(`/api/metadata/${environment}/rollback?base=${base}&to=${to}`, null, { headers: authHeaders(sessionToken) });
+		if (!res.ok) throw new Error(`Rollback failed: ${res.status}`);
+		return (await res.json()).revision;
+	},
+
+	// Replaces the journal with a snapshot of the state at `base`
+	async compactMetadata(sessionToken, environment, base, snapshot) {
+		const res = await api.postRaw(`/api/metadata/${environment}/compact?base=${base}`, snapshot, { headers: authHeaders(sessionToken) });
+		if (!res.ok) throw new Error(`Compaction failed: ${res.status}`);
+		return (await res.json()).revision;
+	},
+
+	// Whole environment as a tar archive (metadata, content, previews and a manifest)
+	async exportVault(sessionToken) {
+		const res = await api.fetch('/api/export', { headers: authHeaders(sessionToken) });
+		if (!res.ok) throw new Error(`Export failed: ${res.status}`);
+		return res.blob();
+	},
+
+	// Restores an exported archive; conflicts and corrupted blobs are listed in the report
+	async importVault(sessionToken, archive) {
+		const res = await api.postRaw('/api/import', archive, { headers: authHeaders(sessionToken) });
+		if (!res.ok) throw new Error(`Import failed: ${res.status}`);
+		return res.json();
+	},
+
+	// Selected items (content IDs) and their previews as one archive
+	async archiveItems(sessionToken, ids) {
+		const res = await api.post('/api/archive', { session_token: sessionToken, ids });
+		if (!res.ok) throw new Error(`Archive failed: ${res.status}`);
+		return res.blob();
+	},
+
+	// Space used by the environment and its quota
+	async usage(sessionToken) {
+		const res = await api.fetch('/api/usage', { headers: authHeaders(sessionToken) });
+		if (!res.ok) throw new Error(`Usage request failed: ${res.status}`);
+		return res.json();
+	},
+
+	// Inbox: anonymous uploads addressed to this vault's pubkey
+	async listInbox(sessionToken) {
+		const res = await api.fetch('/api/inbox', { headers: authHeaders(sessionToken) });
+		if (!res.ok) throw new Error(`Inbox request failed: ${res.status}`);
+		return res.json();
+	},
+
+	// Accepted items come back with their wrapped keys, to be re-keyed into the metadata
+	async acceptInbox(sessionToken, ids) {
+		const res = await api.post('/api/inbox/accept', { session_token: sessionToken, ids });
+		if (!res.ok) throw new Error(`Accept failed: ${res.status}`);
+		return (await res.json()).accepted;
+	},
+
+	async rejectInbox(sessionToken, ids) {
+		const res = await api.post('/api/inbox/reject', { session_token: sessionToken, ids });
+		if (!res.ok) throw new Error(`Reject failed: ${res.status}`);
+		return (await res.json()).rejected;
+	},
+
+	// Share grants: wrappedKey is the content key already encrypted for the recipient's pubkey
+	async createShare(sessionToken, { recipientPubkey, contentId, previewId = null, wrappedKey, encryptedMeta = [], metaNonce = [], expiresAt = null }) {
+		const res = await api.post('/api/shares', {
+			session_token: sessionToken,
+			recipient_pubkey: recipientPubkey,
+			content_id: contentId,
+			preview_id: previewId,
+			wrapped_key: wrappedKey,
+			encrypted_meta: encryptedMeta,
+			meta_nonce: metaNonce,
+			expires_at: expiresAt
+		});
+		if (!res.ok) throw new Error(`Share failed: ${res.status}`);
+		return res.json();
+	},
+
+	async listShares(sessionToken) {
+		const res = await api.fetch('/api/shares', { headers: authHeaders(sessionToken) });
+		if (!res.ok) throw new Error(`Shares request failed: ${res.status}`);
+		return res.json();
+	},
+
+	// Grants from other users; their blobs are fetched from the owner's environment
+	async incomingShares(sessionToken) {
+		const res = await api.fetch('/api/shares/incoming', { headers: authHeaders(sessionToken) });
+		if (!res.ok) throw new Error(`Shares request failed: ${res.status}`);
+		return res.json();
+	},
+
+	async revokeShares(sessionToken, ids) {
+		const res = await api.post('/api/shares/revoke', { session_token: sessionToken, ids });
+		if (!res.ok) throw new Error(`Revoke failed: ${res.status}`);
+		return (await res.json()).revoked;
+	},
+
+	// Public links: the decryption key goes in the URL fragment, never to the node
+	async createLink(sessionToken, { contentId, expiresAt = null, maxDownloads = null, password = null }) {
+		const res = await api.post('/api/links', {
+			session_token: sessionToken,
+			content_id: contentId,
+			expires_at: expiresAt,
+			max_downloads: maxDownloads,
+			password
+		});
+		if (!res.ok) throw new Error(`Link creation failed: ${res.status}`);
+		return res.json();
+	},
+
+	async listLinks(sessionToken) {
+		const res = await api.fetch('/api/links', { headers: authHeaders(sessionToken) });
+		if (!res.ok) throw new Error(`Links request failed: ${res.status}`);
+		return res.json();
+	},
+
+	async revokeLinks(sessionToken, ids) {
+		const res = await api.post('/api/links/revoke', { session_token: sessionToken, ids });
+		if (!res.ok) throw new Error(`Revoke failed: ${res.status}`);
+		return (await res.json()).revoked;
+	},
+
+	linkUrl(environment, linkId, keyFragment) {
+		return `${window.location.origin}/api/public/${environment}/${linkId}#${keyFragment}`;
+	},
+
+	// Team environments: every membership change is signed by an owner
+	async listTeams(sessionToken) {
+		const res = await api.fetch('/api/teams', { headers: authHeaders(sessionToken) });
+		if (!res.ok) throw new Error(`Teams request failed: ${res.status}`);
+		return res.json();
+	},
+
+	async getTeam(sessionToken, environment) {
+		const res = await api.fetch(`/api/teams/${environment}`, { headers: authHeaders(sessionToken) });
+		if (!res.ok) throw new Error(`Team request failed: ${res.status}`);
+		return res.json();
+	},
+
+	// wrappedKey: the team key encrypted for the caller's own pubkey
+	async createTeam(sessionToken, pubkeyB64, privkeyB64, wrappedKey) {
+		const environment = toHex(crypto.getRandomValues(new Uint8Array(16)));
+		const change = { environment, version: 1, pubkey: pubkeyB64, role: 'owner', wrapped_key: wrappedKey };
+		return postMembership('/api/teams', sessionToken, privkeyB64, change);
+	},
+
+	// role null removes the member; version is the current manifest version + 1
+	async changeMembership(sessionToken, privkeyB64, { environment, version, pubkey, role, wrappedKey = [] }) {
+		const change = { environment, version, pubkey, role, wrapped_key: wrappedKey };
+		return postMembership('/api/teams/members', sessionToken, privkeyB64, change);
+	},
+
+	// Capabilities: delegated tokens for scripts and other people. Caveats are
+	// 'key = value' strings (access, content, max_bytes, expires).
+	async createCapability(sessionToken, { label = '', caveats = [], expiresAt = null } = {}) {
+		const res = await api.post('/api/capabilities', {
+			session_token: sessionToken,
+			label,
+			caveats,
+			expires_at: expiresAt
+		});
+		if (!res.ok) throw new Error(`Capability creation failed: ${res.status}`);
+		return res.json();
+	},
+
+	async listCapabilities(sessionToken) {
+		const res = await api.fetch('/api/capabilities', { headers: authHeaders(sessionToken) });
+		if (!res.ok) throw new Error(`Capabilities request failed: ${res.status}`);
+		return res.json();
+	},
+
+	async revokeCapabilities(sessionToken, ids) {
+		const res = await api.post('/api/capabilities/revoke', { session_token: sessionToken, ids });
+		if (!res.ok) throw new Error(`Revoke failed: ${res.status}`);
+		return (await res.json()).revoked;
+	},
+
+	// S3 access key and secret for backup tools; revoke them as a capability
+	async createS3Credentials(sessionToken, { label = '', caveats = [], expiresAt = null } = {}) {
+		const res = await api.post('/api/s3/credentials', {
+			session_token: sessionToken,
+			label,
+			caveats,
+			expires_at: expiresAt
+		});
+		if (!res.ok) throw new Error(`S3 credentials failed: ${res.status}`);
+		return res.json();
+	},
+
+	async listReplicas(sessionToken) {
+		const res = await api.fetch('/api/replication', { headers: authHeaders(sessionToken) });
+		if (!res.ok) throw new Error(`Replication request failed: ${res.status}`);
+		return res.json();
+	},
+
+	// peers: pubkeys from the node's configured peers; [] stops replication
+	async configureReplication(sessionToken, peers) {
+		const res = await api.post('/api/replication', { session_token: sessionToken, peers });
+		if (!res.ok) throw new Error(`Replication update failed: ${res.status}`);
+		return res.json();
+	},
+
+	async syncReplication(sessionToken) {
+		const res = await api.post('/api/replication/sync', { session_token: sessionToken });
+		if (!res.ok) throw new Error(`Replication sync failed: ${res.status}`);
+		return res.json();
+	}
+
+	async auditReplication(sessionToken) {
+		const res = await api.post('/api/replication/audit', { session_token: sessionToken });
+		if (!res.ok) throw new Error(`Replication audit failed: ${res.status}`);
+		return res.json();
+	}
+};
+
+// Anonymous drop-box uploads: no session needed. `upload` has the same fields
+// as start_upload; wrappedKey is the content key encrypted for the recipient.
+export const inboxApi = {
+	async start(recipientPubkey, wrappedKey, upload) {
+		const res = await api.post('/api/inbox/start', { recipient_pubkey: recipientPubkey, wrapped_key: wrappedKey, ...upload });
+		if (!res.ok) throw new Error(`Inbox upload failed: ${res.status}`);
+		return res.json();
+	},
+
+	async uploadChunk(environment, fileId, index, chunk) {
+		const res = await api.postRaw(`/api/inbox/chunk?environment=${environment}&file_id=${fileId}&chunk=${index}`, chunk);
+		if (!res.ok) throw new Error(`Chunk ${index} failed: ${res.status}`);
+		return res.json();
+	},
+
+	async finish(environment, fileId) {
+		const res = await api.post('/api/inbox/finish', { environment, file_id: fileId });
+		if (!res.ok) throw new Error(`Inbox upload failed: ${res.status}`);
+		return res.json();
+	}
+};
+
+// Narrows a capability token offline: each caveat extends the HMAC chain
+// with the previous signature as key, so it can be added but never removed
+export async function attenuateCapability(token, caveat) {
+	const encoded = token.slice('cap_'.length).replace(/-/g, '+').replace(/_/g, '/');
+	const body = JSON.parse(atob(encoded));
+	const key = await crypto.subtle.importKey('raw', fromHex(body.signature), { name: 'HMAC', hash: 'SHA-256' }, false, ['sign']);
+	const sig = await crypto.subtle.sign('HMAC', key, new TextEncoder().encode(caveat));
+	const next = { ...body, caveats: [...body.caveats, caveat], signature: toHex(sig) };
+	return 'cap_' + btoa(JSON.stringify(next)).replace(/\+/g, '-').replace(/\//g, '_').replace(/=+$/, '');
+}
+
+// PKCS#1 v1.5 signature with the identity key (generated for RSA-OAEP; the same key material signs)
+async function signText(privkeyB64, text) {
+	const privKeyBytes = Uint8Array.from(atob(privkeyB64), c => c.charCodeAt(0));
+	const signingKey = await crypto.subtle.importKey(
+		'pkcs8', privKeyBytes, { name: 'RSASSA-PKCS1-v1_5', hash: 'SHA-256' }, false, ['sign']
+	);
+	const sig = await crypto.subtle.sign('RSASSA-PKCS1-v1_5', signingKey, new TextEncoder().encode(text));
+	return btoa(String.fromCharCode(...new Uint8Array(sig)));
+}
+
+// Same payload the backend verifies for a membership change
+export async function teamSigningPayload(change) {
+	const pubkeyHash = await sha256Hex(new TextEncoder().encode(change.pubkey));
+	const keyHash = await sha256Hex(new Uint8Array(change.wrapped_key));
+	return `vault-team:${change.environment}:${change.version}:${pubkeyHash}:${change.role ?? 'remove'}:${keyHash}`;
+}
+
+async function postMembership(path, sessionToken, privkeyB64, change) {
+	const signature = await signText(privkeyB64, await teamSigningPayload(change));
+	const res = await api.post(path, { session_token: sessionToken, change, signature });
+	if (!res.ok) throw new Error(`Membership change failed: ${res.status}`);
+	return res.json();
+}
+
+export const authHeaders = (sessionToken) => ({ Authorization: `Bearer ${sessionToken}` });
+
 export default api;
+
+// Chunk integrity: SHA-256 per chunk and Merkle root (same tree as the backend)
+const toHex = (buf) => Array.from(new Uint8Array(buf), b => b.toString(16).padStart(2, '0')).join('');
+const fromHex = (hex) => new Uint8Array(hex.match(/../g).map(h => parseInt(h, 16)));
+
+// Blind index: HMAC-SHA256 of normalized terms under a key derived with
+// PBKDF2 from the identity's private key, salted with the environment, so
+// the server cannot brute-force a short PIN from the tokens it stores.
+const MAX_SEARCH_TOKENS = 256;
+const SEARCH_KEY_ITERATIONS = 600000;
+const searchKeys = new Map();
+
+function searchIndexKey(privkeyB64, environment) {
+	const cacheKey = `${environment}:${privkeyB64}`;
+	if (!searchKeys.has(cacheKey)) {
+		searchKeys.set(cacheKey, (async () => {
+			const encoder = new TextEncoder();
+			const secret = await crypto.subtle.importKey('raw', encoder.encode(privkeyB64), 'PBKDF2', false, ['deriveKey']);
+			return crypto.subtle.deriveKey(
+				{ name: 'PBKDF2', hash: 'SHA-256', salt: encoder.encode(`vault-search:${environment}`), iterations: SEARCH_KEY_ITERATIONS },
+				secret,
+				{ name: 'HMAC', hash: 'SHA-256', length: 256 },
+				false,
+				['sign']
+			);
+		})());
+	}
+	return searchKeys.get(cacheKey);
+}
+
+// Words of a filename or query, lowercased; single characters are dropped
+export function searchTerms(text) {
+	return text.toLowerCase().split(/[^\p{L}\p{N}]+/u).filter(w => w.length > 1);
+}
+
+// Tokens for an item at upload time, or for a query (pass the query as name)
+export async function blindIndexTokens(privkeyB64, environment, { name = '', tags = [], type = null } = {}) {
+	const terms = new Set(searchTerms(name));
+	for (const tag of tags) terms.add(`tag:${tag.toLowerCase()}`);
+	if (type) terms.add(`type:${type}`);
+	const key = await searchIndexKey(privkeyB64, environment);
+	const encoder = new TextEncoder();
+	return Promise.all([...terms].slice(0, MAX_SEARCH_TOKENS)
+		.map(async term => toHex(await crypto.subtle.sign('HMAC', key, encoder.encode(term)))));
+}
+
+export async function sha256Hex(bytes) {
+	return toHex(await crypto.subtle.digest('SHA-256', bytes));
+}
+
+// Leaves are SHA-256(0x00 || hash), internal nodes SHA-256(0x01 || left || right)
+export async function merkleRoot(hashes) {
+	let level = await Promise.all(hashes.map(async hash => {
+		const leaf = new Uint8Array(33);
+		leaf.set(fromHex(hash), 1);
+		return new Uint8Array(await crypto.subtle.digest('SHA-256', leaf));
+	}));
+	if (level.length === 0) return null;
+	while (level.length > 1) {
+		const next = [];
+		for (let i = 0; i < level.length; i += 2) {
+			if (i + 1 === level.length) { next.push(level[i]); continue; }
+			const node = new Uint8Array(65);
+			node[0] = 0x01;
+			node.set(level[i], 1);
+			node.set(level[i + 1], 33);
+			next.push(new Uint8Array(await crypto.subtle.digest('SHA-256', node)));
+		}
+		level = next;
+	}
+	return toHex(level[0]);
+}
+
+// Checks downloaded ciphertext chunk by chunk against the hashes recorded at upload
+export async function verifyChunks(bytes, item) {
+	if (!item.chunk_hashes || !item.chunk_size) return;
+	if (item.merkle_root && await merkleRoot(item.chunk_hashes) !== item.merkle_root) {
+		throw new Error('Chunk list does not match Merkle root');
+	}
+	for (let i = 0; i < item.chunk_hashes.length; i++) {
+		const chunk = bytes.subarray(i * item.chunk_size, Math.min((i + 1) * item.chunk_size, bytes.length));
+		if (await sha256Hex(chunk) !== item.chunk_hashes[i]) {
+			throw new Error(`Chunk ${i} failed integrity check`);
+		}
+	}
+}